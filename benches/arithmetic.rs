@@ -0,0 +1,41 @@
+/// Arithmetic-heavy scripts, run with `cargo bench`
+use std::io;
+
+use criterion::{criterion_group, criterion_main, Criterion};
+use rlox::interpreter::Interpreter;
+
+const SUM_LOOP: &str = "
+var sum = 0;
+for (var i = 0; i < 100000; i = i + 1) {
+    sum = sum + i * 2 - i / 2;
+}
+";
+
+const COMPARISONS: &str = "
+var count = 0;
+for (var i = 0; i < 100000; i = i + 1) {
+    if (i >= 500 and i <= 90000 and i != 1234) count = count + 1;
+}
+";
+
+const FIB: &str = "
+fun fib(n) {
+    if (n < 2) return n;
+    return fib(n - 1) + fib(n - 2);
+}
+fib(20);
+";
+
+fn run(source: &str) {
+    let mut interpreter = Interpreter::with_output(Box::new(io::sink()));
+    rlox::run_with(&mut interpreter, source).unwrap();
+}
+
+fn arithmetic(c: &mut Criterion) {
+    c.bench_function("sum loop", |b| b.iter(|| run(SUM_LOOP)));
+    c.bench_function("comparisons", |b| b.iter(|| run(COMPARISONS)));
+    c.bench_function("fib 20", |b| b.iter(|| run(FIB)));
+}
+
+criterion_group!(benches, arithmetic);
+criterion_main!(benches);
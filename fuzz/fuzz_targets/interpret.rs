@@ -0,0 +1,8 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use rlox::fuzz::FuzzLimits;
+
+fuzz_target!(|data: &[u8]| {
+    rlox::fuzz::fuzz_interpret_with_limits(data, FuzzLimits::default());
+});
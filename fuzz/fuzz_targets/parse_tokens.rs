@@ -0,0 +1,8 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use rlox::fuzz::TokenStream;
+
+fuzz_target!(|tokens: TokenStream| {
+    rlox::fuzz::fuzz_parse_tokens(tokens);
+});
@@ -0,0 +1,60 @@
+#ifndef RLOX_H
+#define RLOX_H
+
+#include <stdbool.h>
+#include <stddef.h>
+
+typedef struct Rlox Rlox;
+
+typedef enum {
+    RLOX_NIL,
+    RLOX_BOOL,
+    RLOX_NUMBER,
+    RLOX_STRING,
+    RLOX_OBJECT,
+    /* Returned by a native to raise a runtime error, with `string` as its
+     * message (a generic one if it's NULL). */
+    RLOX_ERROR,
+} RloxValueType;
+
+/* Only the field matching `kind` is meaningful. Strings passed to a native
+ * are valid until it returns. A string a native returns, including an error
+ * message, only has to stay valid until the native returns: rlox copies it
+ * and never frees it, so the host keeps ownership of its memory. */
+typedef struct {
+    RloxValueType kind;
+    bool boolean;
+    double number;
+    const char *string;
+} RloxValue;
+
+typedef RloxValue (*RloxNativeFn)(void *user_data, const RloxValue *args, size_t arg_count);
+
+Rlox *rlox_new(void);
+
+/* Returns 0 on success, 65 for static errors and 70 for runtime errors. */
+int rlox_run(Rlox *vm, const char *source);
+
+/* Returns 0 on success and -1 on invalid arguments. */
+int rlox_register_native(Rlox *vm, const char *name, size_t arity, RloxNativeFn function,
+                         void *user_data);
+
+void rlox_free(Rlox *vm);
+
+/* Extensions loaded by scripts with `loadExtension(path)` (the `plugins` feature)
+ * export both functions below. `rlox_extension_api_version` returns the
+ * RLOX_EXTENSION_API_VERSION they were built with, which must match rlox's.
+ * `rlox_extension_init` defines their natives through `api` and returns 0 on
+ * success. */
+#define RLOX_EXTENSION_API_VERSION 1
+
+typedef struct {
+    void *context;
+    int (*register_native)(void *context, const char *name, size_t arity, RloxNativeFn function,
+                           void *user_data);
+} RloxExtensionApi;
+
+unsigned int rlox_extension_api_version(void);
+int rlox_extension_init(const RloxExtensionApi *api);
+
+#endif
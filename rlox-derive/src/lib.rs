@@ -0,0 +1,239 @@
+/// Macros generating the `HostObject` glue for exposing Rust types to Lox scripts.
+/// Used through the `derive` feature of `rlox`, which re-exports them.
+use proc_macro::TokenStream;
+use proc_macro2::TokenStream as TokenStream2;
+use quote::{format_ident, quote};
+use syn::{
+    parse_macro_input, Data, DeriveInput, Fields, FnArg, ImplItem, ItemImpl, Pat, ReturnType, Type,
+};
+
+// Derives `HostObject` for a struct with named fields.
+// Every field is readable and writable from scripts unless marked `#[lox(skip)]`,
+// and `#[lox(readonly)]` rejects assignments. Methods come from the type's `HostMethods`
+// impl, usually generated by `#[lox_methods]`.
+#[proc_macro_derive(HostObject, attributes(lox))]
+pub fn derive_host_object(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    match host_object(&input) {
+        Ok(tokens) => tokens.into(),
+        Err(err) => err.to_compile_error().into(),
+    }
+}
+
+// Implements `HostMethods` for the `#[lox_method]` functions of an impl block.
+// Arguments are converted with `TryFrom<LiteralTypes>` and results with `Into<LiteralTypes>`;
+// a method returning `Result<_, String>` reports its error as a runtime error.
+#[proc_macro_attribute]
+pub fn lox_methods(_attr: TokenStream, item: TokenStream) -> TokenStream {
+    let mut item = parse_macro_input!(item as ItemImpl);
+    match host_methods(&mut item) {
+        Ok(tokens) => quote!(#item #tokens).into(),
+        Err(err) => err.to_compile_error().into(),
+    }
+}
+
+fn host_object(input: &DeriveInput) -> syn::Result<TokenStream2> {
+    let name = &input.ident;
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+
+    let fields = match &input.data {
+        Data::Struct(data) => match &data.fields {
+            Fields::Named(fields) => &fields.named,
+            _ => {
+                return Err(syn::Error::new_spanned(
+                    name,
+                    "HostObject can only be derived for structs with named fields",
+                ))
+            }
+        },
+        _ => {
+            return Err(syn::Error::new_spanned(
+                name,
+                "HostObject can only be derived for structs",
+            ))
+        }
+    };
+
+    let mut getters = Vec::new();
+    let mut setters = Vec::new();
+    for field in fields.iter() {
+        let (skip, readonly) = field_options(&field.attrs)?;
+        if skip {
+            continue;
+        }
+        let ident = field.ident.as_ref().unwrap();
+        let key = ident.to_string();
+        let ty = &field.ty;
+
+        getters.push(quote! {
+            #key => Some(::core::convert::Into::into(::core::clone::Clone::clone(&self.#ident))),
+        });
+        setters.push(if readonly {
+            quote! {
+                #key => Err(format!("Property {} is read-only.", #key)),
+            }
+        } else {
+            quote! {
+                #key => {
+                    self.#ident = <#ty as ::core::convert::TryFrom<::rlox::token::LiteralTypes>>::try_from(value)
+                        .map_err(|e| format!("Can't set {}: {}", #key, e))?;
+                    Ok(())
+                }
+            }
+        });
+    }
+
+    let type_name = name.to_string();
+    Ok(quote! {
+        impl #impl_generics ::rlox::lox_callable::HostObject for #name #ty_generics #where_clause {
+            fn type_name(&self) -> &str {
+                #type_name
+            }
+
+            fn get(&self, name: &str) -> Option<::rlox::token::LiteralTypes> {
+                match name {
+                    #(#getters)*
+                    _ => None,
+                }
+            }
+
+            fn set(
+                &mut self,
+                name: &str,
+                value: ::rlox::token::LiteralTypes,
+            ) -> Result<(), String> {
+                match name {
+                    #(#setters)*
+                    _ => Err(format!("Undefined property {}.", name)),
+                }
+            }
+
+            fn call_method(
+                &mut self,
+                name: &str,
+                arguments: &[::rlox::token::LiteralTypes],
+            ) -> Result<::rlox::token::LiteralTypes, String> {
+                ::rlox::lox_callable::HostMethods::call_method(self, name, arguments)
+            }
+        }
+    })
+}
+
+// Returns whether the field has `#[lox(skip)]` and `#[lox(readonly)]`
+fn field_options(attrs: &[syn::Attribute]) -> syn::Result<(bool, bool)> {
+    let mut skip = false;
+    let mut readonly = false;
+    for attr in attrs.iter().filter(|a| a.path().is_ident("lox")) {
+        attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident("skip") {
+                skip = true;
+                Ok(())
+            } else if meta.path.is_ident("readonly") {
+                readonly = true;
+                Ok(())
+            } else {
+                Err(meta.error("expected `skip` or `readonly`"))
+            }
+        })?;
+    }
+    Ok((skip, readonly))
+}
+
+fn host_methods(item: &mut ItemImpl) -> syn::Result<TokenStream2> {
+    let self_ty = &item.self_ty;
+    let (impl_generics, _, where_clause) = item.generics.split_for_impl();
+
+    let mut arms = Vec::new();
+    for impl_item in item.items.iter_mut() {
+        let method = match impl_item {
+            ImplItem::Fn(method) => method,
+            _ => continue,
+        };
+        let marked = method.attrs.len();
+        method.attrs.retain(|a| !a.path().is_ident("lox_method"));
+        if method.attrs.len() == marked {
+            continue;
+        }
+
+        if method.sig.receiver().is_none() {
+            return Err(syn::Error::new_spanned(
+                &method.sig,
+                "#[lox_method] functions must take self",
+            ));
+        }
+
+        let ident = &method.sig.ident;
+        let key = ident.to_string();
+        let mut conversions = Vec::new();
+        let mut names = Vec::new();
+        let typed = method.sig.inputs.iter().filter_map(|input| match input {
+            FnArg::Receiver(_) => None,
+            FnArg::Typed(arg) => Some(arg),
+        });
+        for (i, arg) in typed.enumerate() {
+            let ty = &arg.ty;
+            let label = match arg.pat.as_ref() {
+                Pat::Ident(p) => p.ident.to_string(),
+                _ => format!("argument {}", i),
+            };
+            let var = format_ident!("__arg{}", i);
+            conversions.push(quote! {
+                let #var = <#ty as ::core::convert::TryFrom<::rlox::token::LiteralTypes>>::try_from(
+                    ::core::clone::Clone::clone(&arguments[#i]),
+                )
+                .map_err(|e| format!("Invalid {} for {}: {}", #label, #key, e))?;
+            });
+            names.push(var);
+        }
+
+        let arity = names.len();
+        let call = quote!(self.#ident(#(#names),*));
+        let result = if returns_result(&method.sig.output) {
+            quote!(#call.map(::core::convert::Into::into))
+        } else {
+            quote!(Ok(::core::convert::Into::into(#call)))
+        };
+        arms.push(quote! {
+            #key => {
+                if arguments.len() != #arity {
+                    return Err(format!(
+                        "Expected {} arguments but got {}.",
+                        #arity,
+                        arguments.len()
+                    ));
+                }
+                #(#conversions)*
+                #result
+            }
+        });
+    }
+
+    Ok(quote! {
+        impl #impl_generics ::rlox::lox_callable::HostMethods for #self_ty #where_clause {
+            fn call_method(
+                &mut self,
+                name: &str,
+                arguments: &[::rlox::token::LiteralTypes],
+            ) -> Result<::rlox::token::LiteralTypes, String> {
+                match name {
+                    #(#arms)*
+                    _ => Err(format!("Undefined method {}.", name)),
+                }
+            }
+        }
+    })
+}
+
+fn returns_result(output: &ReturnType) -> bool {
+    match output {
+        ReturnType::Type(_, ty) => match ty.as_ref() {
+            Type::Path(path) => path
+                .path
+                .segments
+                .last()
+                .is_some_and(|segment| segment.ident == "Result"),
+            _ => false,
+        },
+        ReturnType::Default => false,
+    }
+}
@@ -0,0 +1,349 @@
+/// Prints syntax trees, either as Lisp-style expressions for debugging or back as Lox source.
+/// `for` loops come out as the `while` loops the parser turns them into.
+use std::rc::Rc;
+
+use crate::expr::{self, *};
+use crate::stmt::{self, *};
+use crate::token::{LiteralTypes, TokenType};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Form {
+    // `(+ 1 (* 2 3))`, showing exactly how the source was grouped
+    Lisp,
+    // Lox source laid out one statement per line, with blocks indented by two spaces
+    Source,
+}
+
+pub(crate) struct AstPrinter {
+    form: Form,
+    // Blocks the statement being printed is nested in
+    indent: usize,
+}
+
+impl AstPrinter {
+    pub fn new(form: Form) -> Self {
+        AstPrinter { form, indent: 0 }
+    }
+
+    pub fn print(&mut self, statements: &[Stmt]) -> String {
+        let mut text = String::new();
+        for statement in statements.iter() {
+            text.push_str(&self.stmt(statement));
+            text.push('\n');
+        }
+        text
+    }
+
+    fn stmt(&mut self, statement: &Stmt) -> String {
+        statement.accept(self)
+    }
+
+    fn expr(&mut self, expression: &Expr) -> String {
+        expression.accept(self)
+    }
+
+    fn parenthesize(&mut self, name: &str, exprs: &[&Expr]) -> String {
+        let mut text = format!("({}", name);
+        for expr in exprs.iter() {
+            text.push(' ');
+            text.push_str(&self.expr(expr));
+        }
+        text.push(')');
+        text
+    }
+
+    // Statements of a block or function body, one per line inside braces
+    fn body(&mut self, statements: &[Stmt]) -> String {
+        if self.form == Form::Lisp {
+            let parts: Vec<String> = statements.iter().map(|s| self.stmt(s)).collect();
+            return parts.join(" ");
+        }
+        if statements.is_empty() {
+            return "{}".to_string();
+        }
+
+        self.indent += 1;
+        let mut text = "{\n".to_string();
+        for statement in statements.iter() {
+            let line = self.stmt(statement);
+            text.push_str(&"  ".repeat(self.indent));
+            text.push_str(&line);
+            text.push('\n');
+        }
+        self.indent -= 1;
+        text.push_str(&"  ".repeat(self.indent));
+        text.push('}');
+        text
+    }
+
+    // Functions and methods, which are the same apart from the `fun` keyword
+    fn function(&mut self, function: &Function, keyword: &str) -> String {
+        let params: Vec<String> = function
+            .params
+            .iter()
+            .map(|p| p.lexeme.to_string())
+            .collect();
+        let body = self.body(&function.body);
+        // `async` goes before `fun`, or before a method's name
+        let (fun, keyword) = match function.is_async {
+            true => ("async fun", format!("async {}", keyword)),
+            false => ("fun", keyword.to_string()),
+        };
+        match self.form {
+            Form::Lisp if body.is_empty() => {
+                format!("({} {}({}))", fun, function.name.lexeme, params.join(" "))
+            }
+            Form::Lisp => format!(
+                "({} {}({}) {})",
+                fun,
+                function.name.lexeme,
+                params.join(" "),
+                body
+            ),
+            Form::Source => format!(
+                "{}{}({}) {}",
+                keyword,
+                function.name.lexeme,
+                params.join(", "),
+                body
+            ),
+        }
+    }
+}
+
+fn literal(value: &LiteralTypes) -> String {
+    match value {
+        LiteralTypes::Number(n) => n.to_string(),
+        LiteralTypes::String(s) => format!("\"{}\"", s),
+        LiteralTypes::Bool(b) => b.to_string(),
+        _ => "nil".to_string(),
+    }
+}
+
+impl stmt::Visitor<String> for AstPrinter {
+    fn visit_expression(&mut self, stmt: &Expression) -> String {
+        let expression = self.expr(&stmt.expression);
+        match self.form {
+            Form::Lisp => format!("(; {})", expression),
+            Form::Source => format!("{};", expression),
+        }
+    }
+
+    fn visit_print(&mut self, stmt: &Print) -> String {
+        let expression = self.expr(&stmt.expression);
+        match self.form {
+            Form::Lisp => format!("(print {})", expression),
+            Form::Source => format!("print {};", expression),
+        }
+    }
+
+    fn visit_var(&mut self, stmt: &Var) -> String {
+        let initializer = stmt.initializer.as_ref().map(|e| self.expr(e));
+        match (self.form, initializer) {
+            (Form::Lisp, Some(value)) => format!("(var {} = {})", stmt.name.lexeme, value),
+            (Form::Lisp, None) => format!("(var {})", stmt.name.lexeme),
+            (Form::Source, Some(value)) => format!("var {} = {};", stmt.name.lexeme, value),
+            (Form::Source, None) => format!("var {};", stmt.name.lexeme),
+        }
+    }
+
+    fn visit_block(&mut self, stmt: &Block) -> String {
+        let body = self.body(&stmt.statements);
+        match self.form {
+            Form::Lisp if body.is_empty() => "(block)".to_string(),
+            Form::Lisp => format!("(block {})", body),
+            Form::Source => body,
+        }
+    }
+
+    fn visit_if(&mut self, stmt: &If) -> String {
+        let condition = self.expr(&stmt.condition);
+        let then_branch = self.stmt(&stmt.then_branch);
+        let else_branch = stmt.else_branch.as_ref().map(|s| self.stmt(s));
+        match (self.form, else_branch) {
+            (Form::Lisp, Some(e)) => format!("(if-else {} {} {})", condition, then_branch, e),
+            (Form::Lisp, None) => format!("(if {} {})", condition, then_branch),
+            (Form::Source, Some(e)) => format!("if ({}) {} else {}", condition, then_branch, e),
+            (Form::Source, None) => format!("if ({}) {}", condition, then_branch),
+        }
+    }
+
+    // A `for` loop's initializer is in the block around it, so it prints back with none
+    fn visit_while(&mut self, stmt: &While) -> String {
+        let condition = self.expr(&stmt.condition);
+        let body = self.stmt(&stmt.body);
+        let increment = match stmt.increment.as_deref() {
+            Some(Stmt::Expression(increment)) => Some(self.expr(&increment.expression)),
+            _ => None,
+        };
+        match (self.form, increment) {
+            (Form::Lisp, Some(i)) => format!("(for {} {} {})", condition, i, body),
+            (Form::Lisp, None) => format!("(while {} {})", condition, body),
+            (Form::Source, Some(i)) => format!("for (; {}; {}) {}", condition, i, body),
+            (Form::Source, None) => format!("while ({}) {}", condition, body),
+        }
+    }
+
+    fn visit_break(&mut self, _stmt: &Break) -> String {
+        match self.form {
+            Form::Lisp => "(break)".to_string(),
+            Form::Source => "break;".to_string(),
+        }
+    }
+
+    fn visit_continue(&mut self, _stmt: &Continue) -> String {
+        match self.form {
+            Form::Lisp => "(continue)".to_string(),
+            Form::Source => "continue;".to_string(),
+        }
+    }
+
+    fn visit_function(&mut self, stmt: &Rc<Function>) -> String {
+        self.function(stmt, "fun ")
+    }
+
+    // A bare `return;` is parsed as returning `nil`, and printed back the same way
+    fn visit_return(&mut self, stmt: &Return) -> String {
+        let bare = matches!(
+            stmt.value.as_ref(),
+            Expr::Literal(Literal {
+                value: LiteralTypes::Nil,
+                ..
+            })
+        );
+        let value = self.expr(&stmt.value);
+        match (self.form, bare) {
+            (Form::Lisp, _) => format!("(return {})", value),
+            (Form::Source, true) => "return;".to_string(),
+            (Form::Source, false) => format!("return {};", value),
+        }
+    }
+
+    fn visit_class(&mut self, stmt: &Class) -> String {
+        let super_class = stmt
+            .super_class
+            .as_ref()
+            .map(|s| format!(" < {}", self.expr(s)))
+            .unwrap_or_default();
+
+        if self.form == Form::Lisp {
+            let mut text = format!("(class {}{}", stmt.name.lexeme, super_class);
+            for method in stmt.methods.iter() {
+                text.push(' ');
+                text.push_str(&self.stmt(method));
+            }
+            text.push(')');
+            return text;
+        }
+
+        let mut text = format!("class {}{} {{\n", stmt.name.lexeme, super_class);
+        self.indent += 1;
+        for method in stmt.methods.iter() {
+            if let Stmt::Function(method) = method {
+                let method = self.function(method, "");
+                text.push_str(&"  ".repeat(self.indent));
+                text.push_str(&method);
+                text.push('\n');
+            }
+        }
+        self.indent -= 1;
+        text.push_str(&"  ".repeat(self.indent));
+        text.push('}');
+        text
+    }
+}
+
+impl expr::Visitor<String> for AstPrinter {
+    fn visit_assignment(&mut self, expr: &Assignment) -> String {
+        let value = self.expr(&expr.value);
+        match self.form {
+            Form::Lisp => format!("(= {} {})", expr.name.lexeme, value),
+            Form::Source => format!("{} = {}", expr.name.lexeme, value),
+        }
+    }
+
+    fn visit_binary(&mut self, expr: &Binary) -> String {
+        match self.form {
+            Form::Lisp => self.parenthesize(&expr.operator.lexeme, &[&expr.left, &expr.right]),
+            Form::Source => {
+                let left = self.expr(&expr.left);
+                let right = self.expr(&expr.right);
+                format!("{} {} {}", left, expr.operator.lexeme, right)
+            }
+        }
+    }
+
+    fn visit_grouping(&mut self, expr: &Grouping) -> String {
+        match self.form {
+            Form::Lisp => self.parenthesize("group", &[&expr.expr]),
+            Form::Source => format!("({})", self.expr(&expr.expr)),
+        }
+    }
+
+    fn visit_literal(&self, expr: &Literal) -> String {
+        literal(&expr.value)
+    }
+
+    fn visit_logical(&mut self, expr: &Logical) -> String {
+        match self.form {
+            Form::Lisp => self.parenthesize(&expr.operator.lexeme, &[&expr.left, &expr.right]),
+            Form::Source => {
+                let left = self.expr(&expr.left);
+                let right = self.expr(&expr.right);
+                format!("{} {} {}", left, expr.operator.lexeme, right)
+            }
+        }
+    }
+
+    fn visit_unary(&mut self, expr: &Unary) -> String {
+        match self.form {
+            Form::Lisp => self.parenthesize(&expr.operator.lexeme, &[&expr.right]),
+            Form::Source if expr.operator.ttype == TokenType::Await => {
+                format!("await {}", self.expr(&expr.right))
+            }
+            Form::Source => format!("{}{}", expr.operator.lexeme, self.expr(&expr.right)),
+        }
+    }
+
+    fn visit_variable(&mut self, expr: &Variable) -> String {
+        expr.name.lexeme.to_string()
+    }
+
+    fn visit_call(&mut self, expr: &Call) -> String {
+        let callee = self.expr(&expr.callee);
+        let arguments: Vec<String> = expr.arguments.iter().map(|a| self.expr(a)).collect();
+        match self.form {
+            Form::Lisp if arguments.is_empty() => format!("(call {})", callee),
+            Form::Lisp => format!("(call {} {})", callee, arguments.join(" ")),
+            Form::Source => format!("{}({})", callee, arguments.join(", ")),
+        }
+    }
+
+    fn visit_get(&mut self, expr: &Get) -> String {
+        let object = self.expr(&expr.object);
+        match self.form {
+            Form::Lisp => format!("(. {} {})", object, expr.name.lexeme),
+            Form::Source => format!("{}.{}", object, expr.name.lexeme),
+        }
+    }
+
+    fn visit_set(&mut self, expr: &Set) -> String {
+        let object = self.expr(&expr.object);
+        let value = self.expr(&expr.value);
+        match self.form {
+            Form::Lisp => format!("(= (. {} {}) {})", object, expr.name.lexeme, value),
+            Form::Source => format!("{}.{} = {}", object, expr.name.lexeme, value),
+        }
+    }
+
+    fn visit_this(&mut self, _expr: &This) -> String {
+        "this".to_string()
+    }
+
+    fn visit_super(&mut self, expr: &Super) -> String {
+        match self.form {
+            Form::Lisp => format!("(super {})", expr.method.lexeme),
+            Form::Source => format!("super.{}", expr.method.lexeme),
+        }
+    }
+}
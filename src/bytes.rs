@@ -0,0 +1,354 @@
+/// Byte buffers, for binary files and network payloads that aren't UTF-8 text. `bytes(value)`
+/// makes one from a string, a list of numbers from 0 to 255 or a length, and `readBytes(path)`
+/// from a file. A buffer has a `length`, and `get(i)`, `set(i, byte)`, `slice(start, end)` and
+/// `toString()` methods. `readU8`, `readU32` and `readF64` read the number stored at an offset,
+/// and `writeU8`, `writeU32` and `writeF64` store one there. Like JavaScript's `DataView`, they
+/// take the byte order from an optional last argument, `true` for little endian, and are big
+/// endian without it. `base64Encode` and `hexEncode` turn a string's UTF-8 or a buffer into
+/// text, and `base64Decode` and `hexDecode` turn that text back into a buffer.
+use std::any::Any;
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use crate::interpreter::{Exit, Interpreter};
+use crate::lox_callable::{Callable, HostObject};
+use crate::token::LiteralTypes;
+
+// Longest buffer `bytes(length)` makes, so a huge length is a runtime error rather than the
+// process running out of memory
+pub const MAX_LENGTH: usize = 1 << 30;
+
+#[derive(Clone, Default)]
+pub struct Bytes {
+    pub data: Vec<u8>,
+}
+
+impl Bytes {
+    pub fn new(data: Vec<u8>) -> Self {
+        Bytes { data }
+    }
+
+    // Where the `N` bytes at an offset start, if they're all in the buffer
+    fn field(&self, offset: &LiteralTypes, size: usize) -> Result<usize, String> {
+        match index(offset, self.data.len())? {
+            start if start + size <= self.data.len() => Ok(start),
+            _ => Err("Index out of range.".to_string()),
+        }
+    }
+
+    fn read<const N: usize>(&self, offset: &LiteralTypes) -> Result<[u8; N], String> {
+        let start = self.field(offset, N)?;
+        let mut bytes = [0; N];
+        bytes.copy_from_slice(&self.data[start..start + N]);
+        Ok(bytes)
+    }
+
+    fn write<const N: usize>(
+        &mut self,
+        offset: &LiteralTypes,
+        bytes: [u8; N],
+    ) -> Result<LiteralTypes, String> {
+        let start = self.field(offset, N)?;
+        self.data[start..start + N].copy_from_slice(&bytes);
+        Ok(LiteralTypes::Nil)
+    }
+
+    // Index of a byte in the buffer
+    fn element(&self, value: &LiteralTypes) -> Result<usize, String> {
+        match index(value, self.data.len())? {
+            i if i == self.data.len() => Err("Index out of range.".to_string()),
+            i => Ok(i),
+        }
+    }
+
+    // The buffer as a value scripts can use
+    pub fn into_value(self) -> LiteralTypes {
+        LiteralTypes::Callable(Callable::Host(Rc::new(RefCell::new(self))))
+    }
+}
+
+// The data of a value if it's a byte buffer
+pub(crate) fn bytes(value: &LiteralTypes) -> Option<Vec<u8>> {
+    let LiteralTypes::Callable(Callable::Host(object)) = value else {
+        return None;
+    };
+    let object = object.borrow();
+    let bytes = object.as_any()?.downcast_ref::<Bytes>()?;
+    Some(bytes.data.clone())
+}
+
+// A whole number no greater than `limit`
+fn index(value: &LiteralTypes, limit: usize) -> Result<usize, String> {
+    match value {
+        LiteralTypes::Number(n) if n.fract() == 0.0 && *n >= 0.0 && *n <= limit as f64 => {
+            Ok(*n as usize)
+        }
+        LiteralTypes::Number(_) => Err("Index out of range.".to_string()),
+        _ => Err("Index must be a number.".to_string()),
+    }
+}
+
+// A buffer of `length` zeros
+fn zeroed(length: &LiteralTypes) -> Result<Vec<u8>, String> {
+    let length = index(length, MAX_LENGTH)
+        .map_err(|_| format!("Length must be a whole number from 0 to {}.", MAX_LENGTH))?;
+    let mut data = Vec::new();
+    data.try_reserve_exact(length)
+        .map_err(|_| format!("Not enough memory for {} bytes.", length))?;
+    data.resize(length, 0);
+    Ok(data)
+}
+
+fn byte(value: &LiteralTypes) -> Result<u8, String> {
+    match value {
+        LiteralTypes::Number(n) if n.fract() == 0.0 && (0.0..=255.0).contains(n) => Ok(*n as u8),
+        _ => Err("Byte must be a whole number from 0 to 255.".to_string()),
+    }
+}
+
+// Whether the optional byte order argument after `count` others asks for little endian
+fn little_endian(name: &str, arguments: &[LiteralTypes], count: usize) -> Result<bool, String> {
+    if arguments.len() != count + 1 {
+        return expect_arguments(name, arguments, count).map(|_| false);
+    }
+    match &arguments[count] {
+        LiteralTypes::Bool(little) => Ok(*little),
+        _ => Err("Byte order must be a boolean.".to_string()),
+    }
+}
+
+fn number(value: &LiteralTypes) -> Result<f64, String> {
+    match value {
+        LiteralTypes::Number(n) => Ok(*n),
+        _ => Err("Value must be a number.".to_string()),
+    }
+}
+
+fn expect_arguments(name: &str, arguments: &[LiteralTypes], count: usize) -> Result<(), String> {
+    if arguments.len() == count {
+        Ok(())
+    } else {
+        Err(format!(
+            "Expected {} arguments but got {} for {}.",
+            count,
+            arguments.len(),
+            name
+        ))
+    }
+}
+
+impl HostObject for Bytes {
+    fn type_name(&self) -> &str {
+        "Bytes"
+    }
+
+    fn get(&self, name: &str) -> Option<LiteralTypes> {
+        match name {
+            "length" => Some(LiteralTypes::Number(self.data.len() as f64)),
+            _ => None,
+        }
+    }
+
+    fn set(&mut self, _name: &str, _value: LiteralTypes) -> Result<(), String> {
+        Err("Can't add properties to bytes.".to_string())
+    }
+
+    fn call_method(
+        &mut self,
+        name: &str,
+        arguments: &[LiteralTypes],
+    ) -> Result<LiteralTypes, String> {
+        match name {
+            "get" => {
+                expect_arguments(name, arguments, 1)?;
+                let i = self.element(&arguments[0])?;
+                Ok(LiteralTypes::Number(self.data[i] as f64))
+            }
+            "set" => {
+                expect_arguments(name, arguments, 2)?;
+                let i = self.element(&arguments[0])?;
+                self.data[i] = byte(&arguments[1])?;
+                Ok(LiteralTypes::Nil)
+            }
+            // From `start` up to but not including `end`, as a new buffer
+            "slice" => {
+                expect_arguments(name, arguments, 2)?;
+                let start = index(&arguments[0], self.data.len())?;
+                let end = index(&arguments[1], self.data.len())?;
+                if start > end {
+                    return Err("Slice start is after its end.".to_string());
+                }
+                Ok(Bytes::new(self.data[start..end].to_vec()).into_value())
+            }
+            "readU8" => {
+                expect_arguments(name, arguments, 1)?;
+                let [byte] = self.read(&arguments[0])?;
+                Ok(LiteralTypes::Number(byte as f64))
+            }
+            "readU32" => {
+                let little = little_endian(name, arguments, 1)?;
+                let bytes = self.read(&arguments[0])?;
+                let n = match little {
+                    true => u32::from_le_bytes(bytes),
+                    false => u32::from_be_bytes(bytes),
+                };
+                Ok(LiteralTypes::Number(n as f64))
+            }
+            "readF64" => {
+                let little = little_endian(name, arguments, 1)?;
+                let bytes = self.read(&arguments[0])?;
+                let n = match little {
+                    true => f64::from_le_bytes(bytes),
+                    false => f64::from_be_bytes(bytes),
+                };
+                Ok(LiteralTypes::Number(n))
+            }
+            "writeU8" => {
+                expect_arguments(name, arguments, 2)?;
+                self.write(&arguments[0], [byte(&arguments[1])?])
+            }
+            "writeU32" => {
+                let little = little_endian(name, arguments, 2)?;
+                let n = match number(&arguments[1])? {
+                    n if n.fract() == 0.0 && (0.0..=u32::MAX as f64).contains(&n) => n as u32,
+                    _ => {
+                        return Err("Value must be a whole number that fits in 32 bits.".to_string())
+                    }
+                };
+                let bytes = match little {
+                    true => n.to_le_bytes(),
+                    false => n.to_be_bytes(),
+                };
+                self.write(&arguments[0], bytes)
+            }
+            "writeF64" => {
+                let little = little_endian(name, arguments, 2)?;
+                let n = number(&arguments[1])?;
+                let bytes = match little {
+                    true => n.to_le_bytes(),
+                    false => n.to_be_bytes(),
+                };
+                self.write(&arguments[0], bytes)
+            }
+            "toString" => {
+                expect_arguments(name, arguments, 0)?;
+                match String::from_utf8(self.data.clone()) {
+                    Ok(string) => Ok(LiteralTypes::String(string)),
+                    Err(_) => Err("Bytes aren't valid UTF-8.".to_string()),
+                }
+            }
+            _ => Err(format!("Undefined method {}.", name)),
+        }
+    }
+
+    fn as_any(&self) -> Option<&dyn Any> {
+        Some(self)
+    }
+}
+
+const BASE64: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+// What the encoding natives encode: a string as UTF-8, or a buffer
+fn encoded(value: &LiteralTypes) -> Result<Vec<u8>, Exit> {
+    match value {
+        LiteralTypes::String(string) => Ok(string.as_bytes().to_vec()),
+        value => bytes(value).ok_or_else(|| Exit::message("Can only encode strings and bytes.")),
+    }
+}
+
+fn decoded(value: &LiteralTypes) -> Result<&str, Exit> {
+    match value {
+        LiteralTypes::String(string) => Ok(string),
+        _ => Err(Exit::message("Can only decode strings.")),
+    }
+}
+
+// Standard base64, padded with `=`
+fn base64_encode(data: &[u8]) -> String {
+    let mut text = String::with_capacity(data.len().div_ceil(3) * 4);
+    for chunk in data.chunks(3) {
+        let bits = chunk.iter().enumerate().fold(0u32, |bits, (i, &byte)| {
+            bits | (byte as u32) << (16 - 8 * i)
+        });
+        for i in 0..4 {
+            match i <= chunk.len() {
+                true => text.push(BASE64[(bits >> (18 - 6 * i) & 63) as usize] as char),
+                false => text.push('='),
+            }
+        }
+    }
+    text
+}
+
+// Takes text with or without its padding
+fn base64_decode(text: &str) -> Result<Vec<u8>, String> {
+    let invalid = || "Invalid base64.".to_string();
+    let text = text.trim_end_matches('=');
+    if text.len() % 4 == 1 {
+        return Err(invalid());
+    }
+    let mut data = Vec::with_capacity(text.len() * 3 / 4);
+    for chunk in text.as_bytes().chunks(4) {
+        let mut bits = 0u32;
+        for (i, &c) in chunk.iter().enumerate() {
+            let value = BASE64.iter().position(|&b| b == c).ok_or_else(invalid)?;
+            bits |= (value as u32) << (18 - 6 * i);
+        }
+        for i in 0..chunk.len() - 1 {
+            data.push((bits >> (16 - 8 * i)) as u8);
+        }
+    }
+    Ok(data)
+}
+
+pub(crate) fn hex_encode(data: &[u8]) -> String {
+    data.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+// Takes upper or lower case digits
+pub(crate) fn hex_decode(text: &str) -> Result<Vec<u8>, String> {
+    let invalid = || "Invalid hex.".to_string();
+    if text.len() % 2 == 1 || !text.bytes().all(|c| c.is_ascii_hexdigit()) {
+        return Err(invalid());
+    }
+    (0..text.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&text[i..i + 2], 16).map_err(|_| invalid()))
+        .collect()
+}
+
+// Defines `bytes` and the encoding natives
+pub fn define_natives(interpreter: &mut Interpreter) {
+    interpreter.define_native("bytes", 1, |_, arguments| {
+        let data = match &arguments[0] {
+            LiteralTypes::String(string) => string.as_bytes().to_vec(),
+            LiteralTypes::List(list) => list
+                .borrow()
+                .iter()
+                .map(byte)
+                .collect::<Result<_, _>>()
+                .map_err(Exit::message)?,
+            LiteralTypes::Number(_) => zeroed(&arguments[0]).map_err(Exit::message)?,
+            // A copy of another buffer
+            value => bytes(value).ok_or_else(|| {
+                Exit::message("Can only make bytes from a string, a list or a length.")
+            })?,
+        };
+        Ok(Bytes::new(data).into_value())
+    });
+
+    interpreter.define_native("base64Encode", 1, |_, arguments| {
+        Ok(base64_encode(&encoded(&arguments[0])?).into())
+    });
+    interpreter.define_native("base64Decode", 1, |_, arguments| {
+        let data = base64_decode(decoded(&arguments[0])?).map_err(Exit::message)?;
+        Ok(Bytes::new(data).into_value())
+    });
+    interpreter.define_native("hexEncode", 1, |_, arguments| {
+        Ok(hex_encode(&encoded(&arguments[0])?).into())
+    });
+    interpreter.define_native("hexDecode", 1, |_, arguments| {
+        let data = hex_decode(decoded(&arguments[0])?).map_err(Exit::message)?;
+        Ok(Bytes::new(data).into_value())
+    });
+}
@@ -0,0 +1,102 @@
+/// Config-format natives: `tomlParse(text)` and `yamlParse(text)` turn a document into Lox
+/// values, tables and mappings becoming maps and arrays and sequences lists. TOML dates and
+/// times become strings, and a YAML stream gives its first document.
+use std::collections::BTreeMap;
+
+use yaml_rust2::{Yaml, YamlLoader};
+
+use crate::interpreter::{Exit, Interpreter};
+use crate::token::LiteralTypes;
+
+fn text(value: &LiteralTypes) -> Result<&str, Exit> {
+    match value {
+        LiteralTypes::String(text) => Ok(text),
+        _ => Err(Exit::message("Can only parse strings.")),
+    }
+}
+
+fn from_toml(value: toml::Value) -> LiteralTypes {
+    match value {
+        toml::Value::String(string) => string.into(),
+        toml::Value::Integer(n) => (n as f64).into(),
+        toml::Value::Float(n) => n.into(),
+        toml::Value::Boolean(b) => b.into(),
+        toml::Value::Datetime(datetime) => datetime.to_string().into(),
+        toml::Value::Array(items) => items.into_iter().map(from_toml).collect::<Vec<_>>().into(),
+        toml::Value::Table(table) => table
+            .into_iter()
+            .map(|(key, value)| (key, from_toml(value)))
+            .collect::<BTreeMap<_, _>>()
+            .into(),
+    }
+}
+
+fn from_yaml(value: Yaml) -> Result<LiteralTypes, String> {
+    Ok(match value {
+        Yaml::Null => LiteralTypes::Nil,
+        Yaml::Boolean(b) => b.into(),
+        Yaml::Integer(n) => (n as f64).into(),
+        Yaml::Real(ref real) => match value.as_f64() {
+            Some(n) => n.into(),
+            None => return Err(format!("Invalid YAML number '{}'.", real)),
+        },
+        Yaml::String(string) => string.into(),
+        Yaml::Array(items) => items
+            .into_iter()
+            .map(from_yaml)
+            .collect::<Result<Vec<_>, _>>()?
+            .into(),
+        Yaml::Hash(hash) => hash
+            .into_iter()
+            .map(|(key, value)| Ok((yaml_key(key)?, from_yaml(value)?)))
+            .collect::<Result<BTreeMap<_, _>, String>>()?
+            .into(),
+        Yaml::Alias(_) | Yaml::BadValue => return Err("Invalid YAML value.".to_string()),
+    })
+}
+
+// Map keys are strings, so scalar keys like `1` or `true` are written out as one
+fn yaml_key(key: Yaml) -> Result<String, String> {
+    match key {
+        Yaml::String(key) | Yaml::Real(key) => Ok(key),
+        Yaml::Integer(n) => Ok(n.to_string()),
+        Yaml::Boolean(b) => Ok(b.to_string()),
+        Yaml::Null => Ok("nil".to_string()),
+        _ => Err("YAML keys must be scalars.".to_string()),
+    }
+}
+
+// Where in the text parsing failed, followed by why when the parser says
+fn toml_error(text: &str, error: &toml::de::Error) -> String {
+    let offset = error.span().map_or(0, |span| span.start);
+    let before = &text[..offset.min(text.len())];
+    let line = before.matches('\n').count() + 1;
+    let column = before.chars().rev().take_while(|&c| c != '\n').count() + 1;
+    match error.message().trim() {
+        "" => format!("Invalid TOML at line {}, column {}.", line, column),
+        message => format!(
+            "Invalid TOML at line {}, column {}: {}.",
+            line, column, message
+        ),
+    }
+}
+
+// Defines `tomlParse` and `yamlParse`
+pub fn define_natives(interpreter: &mut Interpreter) {
+    interpreter.define_native("tomlParse", 1, |_, arguments| {
+        let text = text(&arguments[0])?;
+        match text.parse::<toml::Table>() {
+            Ok(table) => Ok(from_toml(toml::Value::Table(table))),
+            Err(error) => Err(Exit::message(toml_error(text, &error))),
+        }
+    });
+
+    interpreter.define_native("yamlParse", 1, |_, arguments| {
+        let documents = YamlLoader::load_from_str(text(&arguments[0])?)
+            .map_err(|error| Exit::message(format!("Invalid YAML: {}.", error)))?;
+        match documents.into_iter().next() {
+            Some(document) => from_yaml(document).map_err(Exit::message),
+            None => Ok(LiteralTypes::Nil),
+        }
+    });
+}
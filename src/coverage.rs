@@ -0,0 +1,113 @@
+/// Line coverage: which lines holding statements ran, and how often, recorded through the
+/// interpreter's statement hook. Reported as a summary or in the lcov format.
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::rc::Rc;
+
+use crate::interpreter::{Exit, Hook, Interpreter};
+use crate::stmt::Stmt;
+
+// Number of statements run on every line that has one
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Coverage {
+    pub lines: BTreeMap<usize, usize>,
+}
+
+impl Coverage {
+    // Every line a statement starts on, none of them run yet
+    pub(crate) fn new(statements: &[Stmt]) -> Self {
+        let mut coverage = Coverage::default();
+        coverage.add_lines(statements);
+        coverage
+    }
+
+    fn add_lines(&mut self, statements: &[Stmt]) {
+        for statement in statements.iter() {
+            // Blocks aren't run themselves, only the statements in them
+            if !matches!(statement, Stmt::Block(_)) {
+                self.lines.insert(statement.line(), 0);
+            }
+            match statement {
+                Stmt::Block(block) => self.add_lines(&block.statements),
+                Stmt::If(stmt) => {
+                    self.add_lines(std::slice::from_ref(&stmt.then_branch));
+                    if let Some(else_branch) = &stmt.else_branch {
+                        self.add_lines(std::slice::from_ref(else_branch));
+                    }
+                }
+                Stmt::While(stmt) => {
+                    self.add_lines(std::slice::from_ref(&stmt.body));
+                    if let Some(increment) = &stmt.increment {
+                        self.add_lines(std::slice::from_ref(increment));
+                    }
+                }
+                Stmt::Function(function) => self.add_lines(&function.body),
+                Stmt::Class(class) => self.add_lines(&class.methods),
+                _ => {}
+            }
+        }
+    }
+
+    // Lines that ran at least once
+    pub fn covered(&self) -> usize {
+        self.lines.values().filter(|&&hits| hits > 0).count()
+    }
+
+    pub fn total(&self) -> usize {
+        self.lines.len()
+    }
+
+    pub fn percent(&self) -> f64 {
+        match self.total() {
+            0 => 100.0,
+            total => self.covered() as f64 * 100.0 / total as f64,
+        }
+    }
+
+    // e.g. "script.lox: 8/10 lines (80.0%), not run: 4, 7"
+    pub fn summary(&self, path: &str) -> String {
+        let mut summary = format!(
+            "{}: {}/{} lines ({:.1}%)",
+            path,
+            self.covered(),
+            self.total(),
+            self.percent()
+        );
+        let missed: Vec<String> = self
+            .lines
+            .iter()
+            .filter(|(_, &hits)| hits == 0)
+            .map(|(line, _)| line.to_string())
+            .collect();
+        if !missed.is_empty() {
+            summary.push_str(&format!(", not run: {}", missed.join(", ")));
+        }
+        summary
+    }
+
+    // One lcov record for the script, as read by genhtml and coverage services
+    pub fn lcov(&self, path: &str) -> String {
+        let mut lcov = format!("TN:\nSF:{}\n", path);
+        for (line, hits) in self.lines.iter() {
+            lcov.push_str(&format!("DA:{},{}\n", line, hits));
+        }
+        lcov.push_str(&format!(
+            "LF:{}\nLH:{}\nend_of_record\n",
+            self.total(),
+            self.covered()
+        ));
+        lcov
+    }
+}
+
+// Counts every statement run into coverage shared with whoever reads it afterwards
+pub(crate) struct CoverageHook {
+    pub coverage: Rc<RefCell<Coverage>>,
+}
+
+impl Hook for CoverageHook {
+    fn statement(&mut self, _interpreter: &mut Interpreter, line: usize) -> Result<(), Exit> {
+        *self.coverage.borrow_mut().lines.entry(line).or_insert(0) += 1;
+        Ok(())
+    }
+}
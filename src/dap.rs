@@ -0,0 +1,433 @@
+/// Debug Adapter Protocol server for `rlox debug --dap`, so editors can debug scripts.
+/// Messages are JSON framed by `Content-Length` headers, read from and written to the
+/// streams the server is given, and the script's output is sent as `output` events.
+use std::cell::RefCell;
+use std::collections::BTreeSet;
+use std::fs;
+use std::io::{self, BufRead, Write};
+use std::path::Path;
+use std::rc::Rc;
+
+use serde_json::{json, Value};
+
+use crate::debugger::{describe, frames, Debugger, Frontend, Reason, Resume, Stop};
+use crate::interpreter::Interpreter;
+use crate::lox_callable::{Callable, LoxInstance};
+use crate::token::LiteralTypes;
+use crate::{capture_diagnostics, LoxError};
+
+// Only one thread ever runs
+const THREAD_ID: u64 = 1;
+
+// Writes messages to the client, numbering them as the protocol requires
+struct Sender {
+    output: Box<dyn Write>,
+    seq: u64,
+}
+
+impl Sender {
+    fn send(&mut self, mut message: Value) -> io::Result<()> {
+        message["seq"] = json!(self.seq);
+        self.seq += 1;
+        let body = message.to_string();
+        write!(
+            self.output,
+            "Content-Length: {}\r\n\r\n{}",
+            body.len(),
+            body
+        )?;
+        self.output.flush()
+    }
+
+    fn respond(&mut self, request: &Value, body: Value) -> io::Result<()> {
+        self.send(json!({
+            "type": "response",
+            "request_seq": request["seq"],
+            "command": request["command"],
+            "success": true,
+            "body": body,
+        }))
+    }
+
+    fn fail(&mut self, request: &Value, message: &str) -> io::Result<()> {
+        self.send(json!({
+            "type": "response",
+            "request_seq": request["seq"],
+            "command": request["command"],
+            "success": false,
+            "message": message,
+        }))
+    }
+
+    fn event(&mut self, event: &str, body: Value) -> io::Result<()> {
+        self.send(json!({ "type": "event", "event": event, "body": body }))
+    }
+}
+
+// Sends what the script prints to the client instead of writing it to stdout, a line at a time
+struct OutputEvents {
+    sender: Rc<RefCell<Sender>>,
+    line: Vec<u8>,
+}
+
+impl Write for OutputEvents {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.line.extend_from_slice(buf);
+        if buf.contains(&b'\n') {
+            self.flush()?;
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        if self.line.is_empty() {
+            return Ok(());
+        }
+        let output = String::from_utf8_lossy(&self.line).to_string();
+        self.line.clear();
+        self.sender
+            .borrow_mut()
+            .event("output", json!({ "category": "stdout", "output": output }))
+    }
+}
+
+// What a `variablesReference` handed to the client stands for, valid until execution resumes
+enum Reference {
+    // Index into `Interpreter::scopes`
+    Scope(usize),
+    Instance(Rc<RefCell<LoxInstance>>),
+}
+
+struct Session {
+    input: Box<dyn BufRead>,
+    sender: Rc<RefCell<Sender>>,
+    program: Option<String>,
+    breakpoints: BTreeSet<usize>,
+    stop_on_entry: bool,
+    references: Vec<Reference>,
+}
+
+impl Session {
+    // Next request, `None` once the client has closed the stream
+    fn read(&mut self) -> io::Result<Option<Value>> {
+        let mut length = None;
+        loop {
+            let mut header = String::new();
+            if self.input.read_line(&mut header)? == 0 {
+                return Ok(None);
+            }
+            let header = header.trim();
+            if header.is_empty() {
+                break;
+            }
+            if let Some(value) = header.strip_prefix("Content-Length:") {
+                length = value.trim().parse::<usize>().ok();
+            }
+        }
+        let Some(length) = length else {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "Missing Content-Length header.",
+            ));
+        };
+        let mut body = vec![0; length];
+        self.input.read_exact(&mut body)?;
+        serde_json::from_slice(&body)
+            .map(Some)
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))
+    }
+
+    fn respond(&self, request: &Value, body: Value) -> io::Result<()> {
+        self.sender.borrow_mut().respond(request, body)
+    }
+
+    // Requests that are answered the same way whether or not the script is running
+    fn handle_common(
+        &mut self,
+        request: &Value,
+        breakpoints: &mut BTreeSet<usize>,
+    ) -> io::Result<()> {
+        match request["command"].as_str().unwrap_or("") {
+            "initialize" => {
+                let capabilities = json!({
+                    "supportsConfigurationDoneRequest": true,
+                    "supportsStepBack": true,
+                });
+                self.respond(request, capabilities)?;
+                self.sender.borrow_mut().event("initialized", json!({}))
+            }
+            "setBreakpoints" => {
+                let arguments = &request["arguments"];
+                let lines: Vec<usize> = arguments["breakpoints"]
+                    .as_array()
+                    .map(|breakpoints| {
+                        breakpoints
+                            .iter()
+                            .filter_map(|b| b["line"].as_u64())
+                            .map(|line| line as usize)
+                            .collect()
+                    })
+                    .unwrap_or_default();
+                // Only the program being debugged has breakpoints that can be hit
+                let path = arguments["source"]["path"].as_str().map(Path::new);
+                let verified = match &self.program {
+                    Some(program) => {
+                        path.and_then(Path::file_name) == Path::new(program).file_name()
+                    }
+                    None => true,
+                };
+                if verified {
+                    *breakpoints = lines.iter().copied().collect();
+                }
+                let breakpoints: Vec<Value> = lines
+                    .iter()
+                    .map(|line| json!({ "verified": verified, "line": line }))
+                    .collect();
+                self.respond(request, json!({ "breakpoints": breakpoints }))
+            }
+            "threads" => self.respond(
+                request,
+                json!({ "threads": [{ "id": THREAD_ID, "name": "main" }] }),
+            ),
+            _ => self
+                .sender
+                .borrow_mut()
+                .fail(request, "Request not supported here."),
+        }
+    }
+
+    fn reference(&mut self, reference: Reference) -> usize {
+        self.references.push(reference);
+        self.references.len()
+    }
+
+    fn stack_trace(&self, interpreter: &Interpreter, stop: Stop) -> Value {
+        let program = self.program.clone().unwrap_or_default();
+        let frames: Vec<Value> = frames(interpreter, stop.line)
+            .into_iter()
+            .enumerate()
+            .map(|(id, (name, line))| {
+                json!({
+                    "id": id,
+                    "name": name,
+                    "line": line,
+                    "column": 1,
+                    "source": { "path": program },
+                })
+            })
+            .collect();
+        json!({ "stackFrames": frames, "totalFrames": frames.len() })
+    }
+
+    // The frame's own variables, then the globals
+    fn scopes(&mut self, interpreter: &Interpreter, frame: usize) -> Value {
+        let globals = interpreter.scopes().len() - 1;
+        let locals = self.reference(Reference::Scope(frame.min(globals)));
+        let globals = self.reference(Reference::Scope(globals));
+        json!({ "scopes": [
+            { "name": "Locals", "variablesReference": locals, "expensive": false },
+            { "name": "Globals", "variablesReference": globals, "expensive": false },
+        ]})
+    }
+
+    fn variables(&mut self, interpreter: &Interpreter, reference: usize) -> Value {
+        let variables = match self.references.get(reference.wrapping_sub(1)) {
+            Some(Reference::Scope(index)) => interpreter
+                .scopes()
+                .into_iter()
+                .nth(*index)
+                .map(|scope| scope.variables)
+                .unwrap_or_default(),
+            Some(Reference::Instance(instance)) => instance.borrow().field_values(),
+            None => Vec::new(),
+        };
+        let variables: Vec<Value> = variables
+            .into_iter()
+            .map(|(name, value)| {
+                // Instances can be expanded to show their fields
+                let fields = match &value {
+                    LiteralTypes::Callable(Callable::Instance(instance)) => {
+                        self.reference(Reference::Instance(Rc::clone(instance)))
+                    }
+                    _ => 0,
+                };
+                json!({
+                    "name": name.as_str(),
+                    "value": describe(interpreter, &value),
+                    "variablesReference": fields,
+                })
+            })
+            .collect();
+        json!({ "variables": variables })
+    }
+
+    fn paused(
+        &mut self,
+        interpreter: &mut Interpreter,
+        stop: Stop,
+        breakpoints: &mut BTreeSet<usize>,
+    ) -> io::Result<Resume> {
+        let reason = match stop.reason {
+            Reason::Entry => "entry",
+            Reason::Breakpoint => "breakpoint",
+            Reason::Step => "step",
+        };
+        self.sender.borrow_mut().event(
+            "stopped",
+            json!({ "reason": reason, "threadId": THREAD_ID, "allThreadsStopped": true }),
+        )?;
+        self.references.clear();
+
+        loop {
+            let Some(request) = self.read()? else {
+                return Ok(Resume::Quit);
+            };
+            let arguments = &request["arguments"];
+            let resume = match request["command"].as_str().unwrap_or("") {
+                "stackTrace" => {
+                    let body = self.stack_trace(interpreter, stop);
+                    self.respond(&request, body)?;
+                    continue;
+                }
+                "scopes" => {
+                    let frame = arguments["frameId"].as_u64().unwrap_or(0) as usize;
+                    let body = self.scopes(interpreter, frame);
+                    self.respond(&request, body)?;
+                    continue;
+                }
+                "variables" => {
+                    let reference = arguments["variablesReference"].as_u64().unwrap_or(0);
+                    let body = self.variables(interpreter, reference as usize);
+                    self.respond(&request, body)?;
+                    continue;
+                }
+                "continue" => Resume::Continue,
+                "next" => Resume::StepOver,
+                "stepIn" => Resume::StepInto,
+                "stepOut" => Resume::StepOut,
+                "stepBack" => Resume::StepBack,
+                "reverseContinue" => Resume::ReverseContinue,
+                "disconnect" | "terminate" => Resume::Quit,
+                _ => {
+                    self.handle_common(&request, breakpoints)?;
+                    continue;
+                }
+            };
+            let body = match resume {
+                Resume::Continue => json!({ "allThreadsContinued": true }),
+                _ => json!({}),
+            };
+            self.respond(&request, body)?;
+            return Ok(resume);
+        }
+    }
+}
+
+// Handed to the debugger while the script runs, sharing the session with `serve`
+struct Client(Rc<RefCell<Session>>);
+
+impl Frontend for Client {
+    fn paused(
+        &mut self,
+        interpreter: &mut Interpreter,
+        stop: Stop,
+        breakpoints: &mut BTreeSet<usize>,
+    ) -> Resume {
+        // A client that can't be reached any more can't resume the script either
+        self.0
+            .borrow_mut()
+            .paused(interpreter, stop, breakpoints)
+            .unwrap_or(Resume::Quit)
+    }
+}
+
+// Serves one debugging session: configuration requests, then the launched program running
+// under the debugger, then whatever the client asks until it disconnects
+pub fn serve(input: Box<dyn BufRead>, output: Box<dyn Write>) -> io::Result<()> {
+    let sender = Rc::new(RefCell::new(Sender { output, seq: 1 }));
+    let session = Rc::new(RefCell::new(Session {
+        input,
+        sender: Rc::clone(&sender),
+        program: None,
+        breakpoints: BTreeSet::new(),
+        stop_on_entry: false,
+        references: Vec::new(),
+    }));
+
+    loop {
+        let mut session = session.borrow_mut();
+        let Some(request) = session.read()? else {
+            return Ok(());
+        };
+        match request["command"].as_str().unwrap_or("") {
+            "launch" => {
+                let arguments = &request["arguments"];
+                session.program = arguments["program"].as_str().map(str::to_string);
+                session.stop_on_entry = arguments["stopOnEntry"].as_bool().unwrap_or(false);
+                session.respond(&request, json!({}))?;
+            }
+            "configurationDone" => {
+                session.respond(&request, json!({}))?;
+                break;
+            }
+            "disconnect" => return session.respond(&request, json!({})),
+            _ => {
+                let mut breakpoints = std::mem::take(&mut session.breakpoints);
+                session.handle_common(&request, &mut breakpoints)?;
+                session.breakpoints = breakpoints;
+            }
+        }
+    }
+
+    let (program, breakpoints, stop_on_entry) = {
+        let session = session.borrow();
+        let program = session.program.clone().unwrap_or_default();
+        (program, session.breakpoints.clone(), session.stop_on_entry)
+    };
+    let exit_code = match fs::read_to_string(&program) {
+        Ok(source) => {
+            let output = Box::new(OutputEvents {
+                sender: Rc::clone(&sender),
+                line: Vec::new(),
+            });
+            let client = Client(Rc::clone(&session));
+            let debugger = Debugger::new(Box::new(client), breakpoints, stop_on_entry);
+            let (result, diagnostics) = capture_diagnostics(|| debugger.run(&source, output));
+            for diagnostic in diagnostics {
+                let output = format!("{}\n", diagnostic);
+                sender
+                    .borrow_mut()
+                    .event("output", json!({ "category": "stderr", "output": output }))?;
+            }
+            // The code `rlox` exits with for a script ending that way
+            match result {
+                Ok(()) => 0,
+                Err(LoxError::Runtime(..) | LoxError::Resolve(_)) => 70,
+                Err(_) => 65,
+            }
+        }
+        Err(_) => {
+            let output = format!("Error reading file '{}'\n", program);
+            sender
+                .borrow_mut()
+                .event("output", json!({ "category": "stderr", "output": output }))?;
+            1
+        }
+    };
+    sender
+        .borrow_mut()
+        .event("exited", json!({ "exitCode": exit_code }))?;
+    sender.borrow_mut().event("terminated", json!({}))?;
+
+    loop {
+        let mut session = session.borrow_mut();
+        let Some(request) = session.read()? else {
+            return Ok(());
+        };
+        if request["command"] == "disconnect" {
+            return session.respond(&request, json!({}));
+        }
+        let mut breakpoints = BTreeSet::new();
+        session.handle_common(&request, &mut breakpoints)?;
+    }
+}
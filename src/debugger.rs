@@ -0,0 +1,409 @@
+/// Debugger run through the interpreter's statement hook. It stops at breakpoints and after
+/// steps, then hands control to a frontend: the `rlox debug` console or the DAP server.
+/// Stepping backwards runs the script again from the start, deterministically and with its
+/// output held back, until it reaches the statement stepped back to.
+use std::cell::{Cell, RefCell};
+use std::collections::BTreeSet;
+use std::io::{self, BufRead, Write};
+use std::path::Path;
+use std::rc::Rc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::interpreter::{Exit, Hook, Interpreter};
+use crate::lox_callable::Callable;
+use crate::token::LiteralTypes;
+
+// What to do once a frontend lets the script carry on
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Resume {
+    // Run until the next breakpoint
+    Continue,
+    // Stop at the next statement, inside any function it calls
+    StepInto,
+    // Stop at the next statement that isn't inside a call made from this one
+    StepOver,
+    // Stop once the running function has returned
+    StepOut,
+    // Go back to the statement that ran before this one
+    StepBack,
+    // Go back to the last breakpoint hit before this statement, or the start
+    ReverseContinue,
+    // Stop the script
+    Quit,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Reason {
+    Entry,
+    Breakpoint,
+    Step,
+}
+
+// Where execution has stopped and why
+#[derive(Debug, Clone, Copy)]
+pub struct Stop {
+    pub line: usize,
+    pub reason: Reason,
+}
+
+// Shows the user where execution stopped and waits to be told how to carry on.
+// Breakpoints can be changed while stopped.
+pub trait Frontend {
+    fn paused(
+        &mut self,
+        interpreter: &mut Interpreter,
+        stop: Stop,
+        breakpoints: &mut BTreeSet<usize>,
+    ) -> Resume;
+}
+
+enum Mode {
+    Entry,
+    Run,
+    Step,
+    // Call depth the step was made at
+    StepOver(usize),
+    StepOut(usize),
+    // Running again from the start, to stop at this statement
+    Rewind(usize),
+}
+
+pub struct Debugger {
+    frontend: Box<dyn Frontend>,
+    // Lines of the script to stop at
+    breakpoints: BTreeSet<usize>,
+    mode: Mode,
+    // Line of every statement run so far, the checkpoints stepping backwards can go to
+    history: Vec<usize>,
+    // Set while rewinding, so the script's output isn't written twice
+    muted: Rc<Cell<bool>>,
+}
+
+impl Debugger {
+    pub fn new(
+        frontend: Box<dyn Frontend>,
+        breakpoints: BTreeSet<usize>,
+        stop_on_entry: bool,
+    ) -> Self {
+        Debugger {
+            frontend,
+            breakpoints,
+            mode: if stop_on_entry {
+                Mode::Entry
+            } else {
+                Mode::Run
+            },
+            history: Vec::new(),
+            muted: Rc::new(Cell::new(false)),
+        }
+    }
+
+    // Runs the source under the debugger, with its output going to `output`. Every run sets
+    // `clock()` and `random()` going the same way, so running again reaches the same state.
+    pub fn run(self, source: &str, output: Box<dyn Write>) -> Result<(), crate::LoxError> {
+        let muted = Rc::clone(&self.muted);
+        let debugger = Rc::new(RefCell::new(self));
+        let output = Rc::new(RefCell::new(output));
+        let seed = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |elapsed| elapsed.as_nanos() as u64);
+        loop {
+            let mut interpreter = Interpreter::with_output(Box::new(Muted {
+                output: Rc::clone(&output),
+                muted: Rc::clone(&muted),
+            }));
+            interpreter.set_deterministic(Some(seed));
+            interpreter.set_hook(Some(Box::new(Shared(Rc::clone(&debugger)))));
+            let result = crate::run_with(&mut interpreter, source);
+            let mut debugger = debugger.borrow_mut();
+            // A run that ended before the statement rewound to didn't go the same way
+            match debugger.mode {
+                Mode::Rewind(target) if debugger.history.len() > target => {}
+                _ => {
+                    debugger.mute(false);
+                    return result;
+                }
+            }
+            debugger.history.clear();
+        }
+    }
+
+    // Stops the script to run it again up to the statement at `position`
+    fn rewind(&mut self, position: usize) -> Result<Mode, Exit> {
+        self.mute(true);
+        self.mode = Mode::Rewind(position);
+        Err(Exit::Halt)
+    }
+
+    // Holds back output and errors, which were written the first time the script ran
+    fn mute(&self, muted: bool) {
+        self.muted.set(muted);
+        crate::mute_diagnostics(muted);
+    }
+}
+
+impl Hook for Debugger {
+    fn statement(&mut self, interpreter: &mut Interpreter, line: usize) -> Result<(), Exit> {
+        let depth = interpreter.call_stack().len();
+        let position = self.history.len();
+        self.history.push(line);
+        let reason = match self.mode {
+            Mode::Rewind(target) if position < target => return Ok(()),
+            Mode::Rewind(_) => {
+                self.mute(false);
+                Reason::Step
+            }
+            Mode::Entry => Reason::Entry,
+            Mode::Step => Reason::Step,
+            Mode::StepOver(from) if depth <= from => Reason::Step,
+            Mode::StepOut(from) if depth < from => Reason::Step,
+            _ if self.breakpoints.contains(&line) => Reason::Breakpoint,
+            _ => return Ok(()),
+        };
+
+        let stop = Stop { line, reason };
+        self.mode = match self
+            .frontend
+            .paused(interpreter, stop, &mut self.breakpoints)
+        {
+            Resume::Continue => Mode::Run,
+            Resume::StepInto => Mode::Step,
+            Resume::StepOver => Mode::StepOver(depth),
+            Resume::StepOut => Mode::StepOut(depth),
+            Resume::StepBack => self.rewind(position.saturating_sub(1))?,
+            Resume::ReverseContinue => {
+                let breakpoint = self.history[..position]
+                    .iter()
+                    .rposition(|line| self.breakpoints.contains(line));
+                self.rewind(breakpoint.unwrap_or(0))?
+            }
+            Resume::Quit => return Err(Exit::Halt),
+        };
+        Ok(())
+    }
+}
+
+// Lets the debugger be the hook of every run of the script
+struct Shared(Rc<RefCell<Debugger>>);
+
+impl Hook for Shared {
+    fn statement(&mut self, interpreter: &mut Interpreter, line: usize) -> Result<(), Exit> {
+        self.0.borrow_mut().statement(interpreter, line)
+    }
+}
+
+// Writes the script's output unless the debugger is rewinding
+struct Muted {
+    output: Rc<RefCell<Box<dyn Write>>>,
+    muted: Rc<Cell<bool>>,
+}
+
+impl Write for Muted {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self.muted.get() {
+            true => Ok(buf.len()),
+            false => self.output.borrow_mut().write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.output.borrow_mut().flush()
+    }
+}
+
+// Name and current line of every active call, innermost first, ending with the script
+pub fn frames(interpreter: &Interpreter, line: usize) -> Vec<(String, usize)> {
+    let mut frames = Vec::new();
+    let mut line = line;
+    for call in interpreter.call_stack().iter().rev() {
+        frames.push((call.function.to_string(), line));
+        line = call.line;
+    }
+    frames.push(("script".to_string(), line));
+    frames
+}
+
+// A value as the debugger shows it, with strings quoted to tell them apart from other values
+pub fn describe(interpreter: &Interpreter, value: &LiteralTypes) -> String {
+    match value {
+        LiteralTypes::String(s) => format!("{:?}", s),
+        LiteralTypes::Unassigned => "<unassigned>".to_string(),
+        _ => interpreter.stringify(value),
+    }
+}
+
+// Line-based frontend for `rlox debug`, reading commands from stdin
+pub struct Console {
+    script: String,
+    source: Rc<str>,
+    // Run again when an empty line is entered
+    last_command: String,
+}
+
+const HELP: &str = "\
+break [file:]line   stop at a line, or list breakpoints without one
+delete line         remove a breakpoint
+continue            run to the next breakpoint
+step                run the next statement, stepping into calls
+next                run the next statement, stepping over calls
+out                 run until the current function returns
+back                go back to the statement that ran before this one
+reverse             go back to the last breakpoint hit, or the start
+locals              show the variables of every scope, innermost first
+print name[.field]  show a variable, or a field of an instance
+backtrace           show the active calls
+quit                stop the script";
+
+impl Console {
+    pub fn new(script: &str, source: &str) -> Self {
+        Console {
+            script: script.to_string(),
+            source: source.into(),
+            last_command: String::new(),
+        }
+    }
+
+    fn show_line(&self, line: usize) {
+        let text = self
+            .source
+            .lines()
+            .nth(line.saturating_sub(1))
+            .unwrap_or("");
+        println!("{:>4} | {}", line, text.trim_end());
+    }
+
+    // Parses `12` or `script.lox:12`, only lines of the script being debugged can be used
+    fn breakpoint_line(&self, location: &str) -> Result<usize, String> {
+        let line = match location.rsplit_once(':') {
+            Some((file, line)) => {
+                if Path::new(file).file_name() != Path::new(&self.script).file_name() {
+                    return Err(format!("Only '{}' is being debugged.", self.script));
+                }
+                line
+            }
+            None => location,
+        };
+        match line.parse() {
+            Ok(line) if line > 0 => Ok(line),
+            _ => Err(format!("Expected a line number, found '{}'.", line)),
+        }
+    }
+
+    fn print(&self, interpreter: &Interpreter, path: &str) -> Result<String, String> {
+        let mut names = path.split('.');
+        let name = names.next().unwrap_or("");
+        let mut value = interpreter
+            .scopes()
+            .into_iter()
+            .find_map(|scope| {
+                scope
+                    .variables
+                    .into_iter()
+                    .find(|(variable, _)| variable.as_str() == name)
+            })
+            .map(|(_, value)| value)
+            .ok_or_else(|| format!("No variable named '{}'.", name))?;
+        for field in names {
+            value = match &value {
+                LiteralTypes::Callable(Callable::Instance(instance)) => instance
+                    .borrow()
+                    .field_values()
+                    .into_iter()
+                    .find(|(name, _)| name.as_str() == field)
+                    .map(|(_, value)| value)
+                    .ok_or_else(|| format!("No field named '{}'.", field))?,
+                _ => return Err(format!("'{}' isn't an instance.", field)),
+            };
+        }
+
+        let mut text = describe(interpreter, &value);
+        if let LiteralTypes::Callable(Callable::Instance(instance)) = &value {
+            let fields: Vec<String> = instance
+                .borrow()
+                .field_values()
+                .iter()
+                .map(|(name, value)| format!("{} = {}", name, describe(interpreter, value)))
+                .collect();
+            text.push_str(&format!(" {{ {} }}", fields.join(", ")));
+        }
+        Ok(text)
+    }
+}
+
+impl Frontend for Console {
+    fn paused(
+        &mut self,
+        interpreter: &mut Interpreter,
+        stop: Stop,
+        breakpoints: &mut BTreeSet<usize>,
+    ) -> Resume {
+        if stop.reason == Reason::Breakpoint {
+            println!("Breakpoint at line {}", stop.line);
+        }
+        self.show_line(stop.line);
+
+        loop {
+            print!("(debug) ");
+            let _ = io::stdout().flush();
+            let mut line = String::new();
+            if io::stdin().lock().read_line(&mut line).unwrap_or(0) == 0 {
+                return Resume::Quit;
+            }
+            let command = match line.trim() {
+                "" => self.last_command.clone(),
+                command => command.to_string(),
+            };
+            self.last_command = command.clone();
+
+            let (name, argument) = command.split_once(' ').unwrap_or((&command, ""));
+            let argument = argument.trim();
+            match name {
+                "b" | "break" if argument.is_empty() => {
+                    for line in breakpoints.iter() {
+                        println!("{}:{}", self.script, line);
+                    }
+                }
+                "b" | "break" => match self.breakpoint_line(argument) {
+                    Ok(line) => {
+                        breakpoints.insert(line);
+                        println!("Breakpoint set at line {}", line);
+                    }
+                    Err(message) => println!("{}", message),
+                },
+                "d" | "delete" => match self.breakpoint_line(argument) {
+                    Ok(line) if breakpoints.remove(&line) => {
+                        println!("Breakpoint at line {} deleted", line)
+                    }
+                    Ok(line) => println!("No breakpoint at line {}.", line),
+                    Err(message) => println!("{}", message),
+                },
+                "c" | "continue" => return Resume::Continue,
+                "s" | "step" => return Resume::StepInto,
+                "n" | "next" => return Resume::StepOver,
+                "o" | "out" => return Resume::StepOut,
+                "k" | "back" => return Resume::StepBack,
+                "r" | "reverse" => return Resume::ReverseContinue,
+                "q" | "quit" => return Resume::Quit,
+                "l" | "locals" => {
+                    for scope in interpreter.scopes() {
+                        println!("{}:", scope.name);
+                        for (name, value) in scope.variables.iter() {
+                            println!("  {} = {}", name, describe(interpreter, value));
+                        }
+                    }
+                }
+                "p" | "print" => match self.print(interpreter, argument) {
+                    Ok(text) => println!("{}", text),
+                    Err(message) => println!("{}", message),
+                },
+                "bt" | "backtrace" => {
+                    for (i, (function, line)) in frames(interpreter, stop.line).iter().enumerate() {
+                        println!("#{} {} at line {}", i, function, line);
+                    }
+                }
+                "h" | "help" => println!("{}", HELP),
+                _ => println!("Unknown command '{}', 'help' lists them.", name),
+            }
+        }
+    }
+}
@@ -0,0 +1,138 @@
+/// Documentation generator for `rlox doc`. Lists the top-level functions and classes of
+/// scripts with their `///` comments, as Markdown or as a standalone HTML page.
+use crate::expr::Expr;
+use crate::stmt::{Function, Stmt};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DocFormat {
+    Markdown,
+    Html,
+}
+
+// A documented function, method or class
+#[derive(Debug, Clone, PartialEq)]
+pub struct DocItem {
+    // How it's declared, e.g. `fun area(width, height)` or `class Square < Shape`
+    pub signature: String,
+    pub doc: Option<String>,
+    // Methods, for classes
+    pub methods: Vec<DocItem>,
+}
+
+// Everything documented in one script
+#[derive(Debug, Clone, PartialEq)]
+pub struct ScriptDoc {
+    pub path: String,
+    pub items: Vec<DocItem>,
+}
+
+impl ScriptDoc {
+    pub(crate) fn new(path: &str, statements: &[Stmt]) -> Self {
+        let items = statements
+            .iter()
+            .filter_map(|statement| match statement {
+                Stmt::Function(function) => Some(function_item(function, "fun ")),
+                Stmt::Class(class) => {
+                    let super_class = match class.super_class.as_deref() {
+                        Some(Expr::Variable(variable)) => format!(" < {}", variable.name.lexeme),
+                        _ => String::new(),
+                    };
+                    let methods = class
+                        .methods
+                        .iter()
+                        .filter_map(|method| match method {
+                            Stmt::Function(method) => Some(function_item(method, "")),
+                            _ => None,
+                        })
+                        .collect();
+                    Some(DocItem {
+                        signature: format!("class {}{}", class.name.lexeme, super_class),
+                        doc: class.doc.clone(),
+                        methods,
+                    })
+                }
+                _ => None,
+            })
+            .collect();
+        ScriptDoc {
+            path: path.to_string(),
+            items,
+        }
+    }
+}
+
+fn function_item(function: &Function, keyword: &str) -> DocItem {
+    let params: Vec<&str> = function.params.iter().map(|p| p.lexeme.as_str()).collect();
+    DocItem {
+        signature: format!("{}{}({})", keyword, function.name.lexeme, params.join(", ")),
+        doc: function.doc.clone(),
+        methods: Vec::new(),
+    }
+}
+
+pub fn render(scripts: &[ScriptDoc], format: DocFormat) -> String {
+    match format {
+        DocFormat::Markdown => markdown(scripts),
+        DocFormat::Html => html(scripts),
+    }
+}
+
+fn markdown(scripts: &[ScriptDoc]) -> String {
+    let mut text = String::new();
+    for script in scripts.iter() {
+        text.push_str(&format!("# {}\n", script.path));
+        for item in script.items.iter() {
+            markdown_item(&mut text, item, "##");
+            for method in item.methods.iter() {
+                markdown_item(&mut text, method, "###");
+            }
+        }
+        text.push('\n');
+    }
+    text
+}
+
+fn markdown_item(text: &mut String, item: &DocItem, heading: &str) {
+    text.push_str(&format!("\n{} `{}`\n", heading, item.signature));
+    if let Some(doc) = &item.doc {
+        text.push_str(&format!("\n{}\n", doc));
+    }
+}
+
+fn html(scripts: &[ScriptDoc]) -> String {
+    let mut text =
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>Documentation</title>\n</head>\n<body>\n"
+            .to_string();
+    for script in scripts.iter() {
+        text.push_str(&format!("<h1>{}</h1>\n", escape(&script.path)));
+        for item in script.items.iter() {
+            html_item(&mut text, item, "h2");
+            for method in item.methods.iter() {
+                html_item(&mut text, method, "h3");
+            }
+        }
+    }
+    text.push_str("</body>\n</html>\n");
+    text
+}
+
+// Blank lines in a comment separate paragraphs
+fn html_item(text: &mut String, item: &DocItem, heading: &str) {
+    text.push_str(&format!(
+        "<{0}><code>{1}</code></{0}>\n",
+        heading,
+        escape(&item.signature)
+    ));
+    if let Some(doc) = &item.doc {
+        for paragraph in doc.split("\n\n").filter(|p| !p.trim().is_empty()) {
+            text.push_str(&format!("<p>{}</p>\n", escape(paragraph.trim())));
+        }
+    }
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
@@ -2,16 +2,48 @@ use std::cell::RefCell;
 use std::collections::HashMap;
 use std::rc::Rc;
 
-use crate::report;
 use crate::{
-    interpreter::Exit,
+    interpreter::{ErrorKind, Exit},
+    symbol::Symbol,
     token::{LiteralTypes, Token},
 };
 
+// Global variables. Every name gets a fixed index the first time it's declared or
+// referenced, so resolved accesses index `values` instead of hashing the name.
 #[derive(Debug, Clone, Default)]
 pub struct Environment {
-    pub values: HashMap<String, LiteralTypes>,
-    pub enclosing: Option<Rc<RefCell<Environment>>>,
+    pub names: HashMap<Symbol, usize>,
+    // `None` for names that have been referenced but not defined yet
+    pub values: Vec<Option<LiteralTypes>>,
+}
+
+// A local captured by a closure, shared between the frame that declared it and the closure
+pub type Upvalue = Rc<RefCell<LiteralTypes>>;
+
+// Where the resolver found a local variable, relative to the function accessing it
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Binding {
+    // Slot in the current call frame
+    Local(usize),
+    // Index into the upvalues captured by the current function
+    Upvalue(usize),
+    // Index into the globals, for names that aren't local anywhere
+    Global(usize),
+}
+
+// Locals live directly on the interpreter's stack until a closure captures them,
+// at which point the slot is moved into an upvalue shared with the closure.
+#[derive(Debug, Clone)]
+pub enum Slot {
+    Value(LiteralTypes),
+    Captured(Upvalue),
+}
+
+// The part of the stack and the upvalues belonging to the running function
+#[derive(Clone, Default)]
+pub struct Frame {
+    pub base: usize,
+    pub upvalues: Rc<[Upvalue]>,
 }
 
 impl Environment {
@@ -19,62 +51,79 @@ impl Environment {
         Default::default()
     }
 
-    pub fn new_with_enclosing(enclosing: Rc<RefCell<Environment>>) -> Self {
-        Environment {
-            values: HashMap::new(),
-            enclosing: Some(enclosing),
+    // Index of the name, reserving one if the name hasn't been seen before
+    pub fn index(&mut self, name: &Symbol) -> usize {
+        if let Some(&index) = self.names.get(name) {
+            return index;
         }
+        self.values.push(None);
+        self.names.insert(name.clone(), self.values.len() - 1);
+        self.values.len() - 1
     }
 
-    pub fn define(&mut self, name: String, value: LiteralTypes) {
-        self.values.insert(name, value);
+    pub fn define(&mut self, name: Symbol, value: LiteralTypes) {
+        let index = self.index(&name);
+        self.values[index] = Some(value);
     }
 
+    // Looks the name up, for accesses the resolver didn't give an index
     pub fn get(&self, name: &Token) -> Result<LiteralTypes, Exit> {
-        if self.values.contains_key(&name.lexeme) {
-            Ok(self.values.get(&name.lexeme).unwrap().clone())
-        } else if self.enclosing.is_some() {
-            Ok(self.enclosing.as_ref().unwrap().borrow().get(name)?)
-        } else {
-            report(name.line, &format!("Undefined variable '{}'.", name.lexeme));
-            Err(Exit::RuntimeError {})
+        match self.names.get(&name.lexeme) {
+            Some(&index) => self.get_at(index, name),
+            None => Err(undefined(name)),
+        }
+    }
+
+    pub fn get_at(&self, index: usize, name: &Token) -> Result<LiteralTypes, Exit> {
+        match &self.values[index] {
+            Some(value) => Ok(value.clone()),
+            None => Err(undefined(name)),
         }
     }
 
     pub fn assign(&mut self, name: &Token, value: LiteralTypes) -> Result<(), Exit> {
-        if self.values.contains_key(&name.lexeme) {
-            self.values.insert(name.lexeme.clone(), value);
-            Ok(())
-        } else if let Some(enclosing) = &self.enclosing {
-            enclosing.borrow_mut().assign(name, value)?;
-            Ok(())
-        } else {
-            report(name.line, &format!("Undefined variable '{}'.", name.lexeme));
-            Err(Exit::RuntimeError {})
+        match self.names.get(&name.lexeme) {
+            Some(&index) => self.assign_at(index, name, value),
+            None => Err(undefined(name)),
+        }
+    }
+
+    pub fn assign_at(
+        &mut self,
+        index: usize,
+        name: &Token,
+        value: LiteralTypes,
+    ) -> Result<(), Exit> {
+        match &mut self.values[index] {
+            Some(slot) => {
+                *slot = value;
+                Ok(())
+            }
+            None => Err(undefined(name)),
         }
     }
+}
+
+fn undefined(name: &Token) -> Exit {
+    Exit::error_of(
+        ErrorKind::UndefinedVariable,
+        name,
+        format!("Undefined variable '{}'.", name.lexeme),
+    )
+}
 
-    pub fn get_at(&self, distance: usize, name: Token) -> Result<LiteralTypes, Exit> {
-        if distance == 0 {
-            self.get(&name)
-        } else {
-            self.enclosing
-                .as_ref()
-                .unwrap()
-                .borrow()
-                .get_at(distance - 1, name)
+impl Slot {
+    pub fn get(&self) -> LiteralTypes {
+        match self {
+            Slot::Value(value) => value.clone(),
+            Slot::Captured(upvalue) => upvalue.borrow().clone(),
         }
     }
 
-    pub fn assign_at(&mut self, distance: usize, name: Token, value: LiteralTypes) {
-        if distance == 0 {
-            self.define(name.lexeme, value);
-        } else {
-            self.enclosing
-                .as_ref()
-                .unwrap()
-                .borrow_mut()
-                .assign_at(distance - 1, name, value);
+    pub fn set(&mut self, value: LiteralTypes) {
+        match self {
+            Slot::Value(v) => *v = value,
+            Slot::Captured(upvalue) => *upvalue.borrow_mut() = value,
         }
     }
 }
@@ -0,0 +1,184 @@
+/// The event loop behind `async fun`, `await` and timers. Calling an async function schedules
+/// its body as a task and returns a promise of what it returns, and `setTimeout` and
+/// `setInterval` schedule calls for later. Tasks run when a script awaits a promise that
+/// hasn't settled yet, and once the script itself has run, timers once no task is left. An
+/// `await` doesn't suspend the function it's in: it runs other tasks until its promise
+/// settles, so tasks started while waiting finish first.
+use std::any::Any;
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::rc::Rc;
+
+use crate::interpreter::RuntimeError;
+use crate::lox_callable::{Callable, HostObject, LoxFunction};
+use crate::token::LiteralTypes;
+
+pub(crate) enum State {
+    Pending,
+    Fulfilled(LiteralTypes),
+    Rejected(Box<RuntimeError>),
+}
+
+pub(crate) type Settlement = Rc<RefCell<State>>;
+
+// What calling an async function returns
+pub struct Promise {
+    state: Settlement,
+}
+
+impl HostObject for Promise {
+    fn type_name(&self) -> &str {
+        "Promise"
+    }
+
+    fn get(&self, _name: &str) -> Option<LiteralTypes> {
+        None
+    }
+
+    fn set(&mut self, _name: &str, _value: LiteralTypes) -> Result<(), String> {
+        Err("Can't add properties to a promise.".to_string())
+    }
+
+    fn call_method(
+        &mut self,
+        name: &str,
+        _arguments: &[LiteralTypes],
+    ) -> Result<LiteralTypes, String> {
+        Err(format!("Undefined method {}.", name))
+    }
+
+    fn as_any(&self) -> Option<&dyn Any> {
+        Some(self)
+    }
+}
+
+// The settlement of the promise a value is, if it's one
+pub(crate) fn promise(value: &LiteralTypes) -> Option<Settlement> {
+    let LiteralTypes::Callable(Callable::Host(object)) = value else {
+        return None;
+    };
+    let object = object.borrow();
+    let promise = object.as_any()?.downcast_ref::<Promise>()?;
+    Some(Rc::clone(&promise.state))
+}
+
+// An async function call whose body hasn't run yet
+pub(crate) struct Task {
+    pub function: LoxFunction,
+    pub arguments: Vec<LiteralTypes>,
+    // Line of the call, for backtraces
+    pub line: usize,
+    pub promise: Settlement,
+}
+
+// A call scheduled by `setTimeout` or `setInterval`
+struct Timer {
+    id: usize,
+    // Time from `clock()` at which it's due
+    due: f64,
+    // Seconds between calls, for intervals
+    interval: Option<f64>,
+    callback: LiteralTypes,
+}
+
+#[derive(Default)]
+pub(crate) struct EventLoop {
+    tasks: VecDeque<Task>,
+    // Promises that were rejected without anything awaiting them yet
+    unhandled: Vec<Settlement>,
+    timers: Vec<Timer>,
+    next_timer_id: usize,
+    // First error of a timer's callback, which is reported as it happens
+    failed: Option<Box<RuntimeError>>,
+}
+
+impl EventLoop {
+    pub fn schedule(
+        &mut self,
+        function: LoxFunction,
+        arguments: Vec<LiteralTypes>,
+        line: usize,
+    ) -> LiteralTypes {
+        let state = Rc::new(RefCell::new(State::Pending));
+        self.tasks.push_back(Task {
+            function,
+            arguments,
+            line,
+            promise: Rc::clone(&state),
+        });
+        let promise = Promise { state };
+        LiteralTypes::Callable(Callable::Host(Rc::new(RefCell::new(promise))))
+    }
+
+    pub fn next_task(&mut self) -> Option<Task> {
+        self.tasks.pop_front()
+    }
+
+    // Returns the id `clearTimeout` and `clearInterval` take, starting from 1
+    pub fn add_timer(&mut self, due: f64, interval: Option<f64>, callback: LiteralTypes) -> usize {
+        self.next_timer_id += 1;
+        self.timers.push(Timer {
+            id: self.next_timer_id,
+            due,
+            interval,
+            callback,
+        });
+        self.next_timer_id
+    }
+
+    pub fn clear_timer(&mut self, id: usize) {
+        self.timers.retain(|timer| timer.id != id);
+    }
+
+    // When the timer due first is due and what to call then. A timeout is taken off the
+    // timers, an interval stays on them due again a period later.
+    pub fn next_timer(&mut self) -> Option<(f64, LiteralTypes)> {
+        // Timers due at the same time run in the order they were set
+        let (index, _) = self
+            .timers
+            .iter()
+            .enumerate()
+            .min_by(|(_, a), (_, b)| a.due.total_cmp(&b.due).then(a.id.cmp(&b.id)))?;
+        let timer = &mut self.timers[index];
+        let due = timer.due;
+        match timer.interval {
+            Some(interval) => {
+                timer.due += interval;
+                Some((due, timer.callback.clone()))
+            }
+            None => Some((due, self.timers.remove(index).callback)),
+        }
+    }
+
+    pub fn fail(&mut self, error: Box<RuntimeError>) {
+        self.failed.get_or_insert(error);
+    }
+
+    pub fn take_failed(&mut self) -> Option<Box<RuntimeError>> {
+        self.failed.take()
+    }
+
+    pub fn settle(&mut self, promise: &Settlement, state: State) {
+        if matches!(state, State::Rejected(_)) {
+            self.unhandled.push(Rc::clone(promise));
+        }
+        *promise.borrow_mut() = state;
+    }
+
+    // The promise's rejection has been seen by whatever awaited it
+    pub fn handled(&mut self, promise: &Settlement) {
+        self.unhandled
+            .retain(|unhandled| !Rc::ptr_eq(unhandled, promise));
+    }
+
+    // Errors of the rejected promises nothing has awaited, in the order they happened
+    pub fn take_unhandled(&mut self) -> Vec<RuntimeError> {
+        self.unhandled
+            .drain(..)
+            .filter_map(|promise| match &*promise.borrow() {
+                State::Rejected(error) => Some(error.as_ref().clone()),
+                _ => None,
+            })
+            .collect()
+    }
+}
@@ -0,0 +1,178 @@
+/// Extensions: crates and libraries that add natives to the interpreter. A host registers the
+/// ones compiled into it with `Interpreter::add_extension`, and scripts load them by name with
+/// `loadExtension(name)`. With the `plugins` feature, `loadExtension(path)` also loads a shared
+/// library exporting `rlox_extension_api_version` and `rlox_extension_init`, see
+/// `include/rlox.h`, once the host has called `Interpreter::set_allow_extensions`.
+use std::collections::HashSet;
+use std::rc::Rc;
+
+use crate::interpreter::{Exit, Interpreter};
+use crate::token::LiteralTypes;
+
+// Version of the interface between rlox and its extensions, raised whenever it changes in a
+// way extensions built for an earlier one would break on
+pub const API_VERSION: u32 = 1;
+
+pub trait Extension {
+    // What scripts give `loadExtension` to load it
+    fn name(&self) -> &str;
+
+    // `API_VERSION` of the rlox the extension was written for
+    fn api_version(&self) -> u32;
+
+    // Defines the extension's natives
+    fn register(&self, interpreter: &mut Interpreter);
+}
+
+#[derive(Default)]
+pub(crate) struct Registry {
+    available: Vec<Rc<dyn Extension>>,
+    // Names of the registered extensions and paths of the libraries loaded so far
+    loaded: HashSet<String>,
+    // Kept open for as long as the natives they defined can be called
+    #[cfg(feature = "plugins")]
+    libraries: Vec<libloading::Library>,
+}
+
+impl Registry {
+    pub fn add(&mut self, extension: Rc<dyn Extension>) {
+        self.available
+            .retain(|available| available.name() != extension.name());
+        self.available.push(extension);
+    }
+}
+
+// Defines `loadExtension`
+pub(crate) fn define_natives(interpreter: &mut Interpreter) {
+    interpreter.define_native("loadExtension", 1, |interpreter, arguments| {
+        let LiteralTypes::String(name) = &arguments[0] else {
+            return Err(Exit::message("Extension name must be a string."));
+        };
+        if interpreter.extensions().loaded.contains(name) {
+            return Ok(LiteralTypes::Nil);
+        }
+        let extension = interpreter
+            .extensions()
+            .available
+            .iter()
+            .find(|extension| extension.name() == name)
+            .cloned();
+        match extension {
+            Some(extension) => {
+                check_version(name, extension.api_version())?;
+                extension.register(interpreter);
+                interpreter.extensions().loaded.insert(name.clone());
+                Ok(LiteralTypes::Nil)
+            }
+            #[cfg(feature = "plugins")]
+            None => plugins::load(interpreter, name),
+            #[cfg(not(feature = "plugins"))]
+            None => Err(Exit::message(format!("Undefined extension '{}'.", name))),
+        }
+    });
+}
+
+fn check_version(name: &str, version: u32) -> Result<(), Exit> {
+    if version == API_VERSION {
+        return Ok(());
+    }
+    Err(Exit::message(format!(
+        "Extension '{}' was built for extension API {} but rlox has {}.",
+        name, version, API_VERSION
+    )))
+}
+
+#[cfg(feature = "plugins")]
+mod plugins {
+    use std::ffi::{c_char, c_int, c_void};
+    use std::path::Path;
+
+    use libloading::{Library, Symbol};
+
+    use super::check_version;
+    use crate::ffi::{self, RloxNativeFn};
+    use crate::interpreter::{Exit, Interpreter};
+    use crate::token::LiteralTypes;
+
+    // Handed to a library's `rlox_extension_init`, which defines its natives through it
+    #[repr(C)]
+    pub struct RloxExtensionApi {
+        pub context: *mut c_void,
+        pub register_native: unsafe extern "C" fn(
+            context: *mut c_void,
+            name: *const c_char,
+            arity: usize,
+            function: RloxNativeFn,
+            user_data: *mut c_void,
+        ) -> c_int,
+    }
+
+    unsafe extern "C" fn register_native(
+        context: *mut c_void,
+        name: *const c_char,
+        arity: usize,
+        function: RloxNativeFn,
+        user_data: *mut c_void,
+    ) -> c_int {
+        if context.is_null() || name.is_null() {
+            return -1;
+        }
+        ffi::register_native(
+            &mut *(context as *mut Interpreter),
+            name,
+            arity,
+            function,
+            user_data,
+        )
+    }
+
+    pub fn load(interpreter: &mut Interpreter, path: &str) -> Result<LiteralTypes, Exit> {
+        if !interpreter.allow_extensions() {
+            return Err(Exit::message("Loading extensions isn't allowed."));
+        }
+        // The same library given by another path isn't loaded twice
+        let key = match Path::new(path).canonicalize() {
+            Ok(path) => path.to_string_lossy().into_owned(),
+            Err(error) => {
+                return Err(Exit::message(format!(
+                    "Error loading extension '{}': {}.",
+                    path, error
+                )))
+            }
+        };
+        if interpreter.extensions().loaded.contains(&key) {
+            return Ok(LiteralTypes::Nil);
+        }
+        let failed = |error: libloading::Error| {
+            Exit::message(format!("Error loading extension '{}': {}.", path, error))
+        };
+
+        // Loading a library runs its initializers, which is why it takes the capability
+        let library = unsafe { Library::new(&key) }.map_err(failed)?;
+        let version = unsafe {
+            let version: Symbol<unsafe extern "C" fn() -> u32> =
+                library.get(b"rlox_extension_api_version").map_err(failed)?;
+            version()
+        };
+        check_version(path, version)?;
+        let code = unsafe {
+            let init: Symbol<unsafe extern "C" fn(*const RloxExtensionApi) -> c_int> =
+                library.get(b"rlox_extension_init").map_err(failed)?;
+            let api = RloxExtensionApi {
+                context: interpreter as *mut Interpreter as *mut c_void,
+                register_native,
+            };
+            init(&api)
+        };
+        // Natives it defined before failing can still be called, so the library stays open
+        interpreter.extensions().libraries.push(library);
+        if code != 0 {
+            return Err(Exit::message(format!(
+                "Extension '{}' failed to initialize with code {}.",
+                path, code
+            )));
+        }
+        interpreter.extensions().loaded.insert(key);
+        Ok(LiteralTypes::Nil)
+    }
+}
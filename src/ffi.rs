@@ -0,0 +1,182 @@
+/// C ABI for embedding the interpreter in non-Rust applications, see `include/rlox.h`
+use std::ffi::{c_char, c_int, c_void, CStr, CString};
+use std::{io, ptr};
+
+use crate::{
+    interpreter::{Exit, Interpreter},
+    token::LiteralTypes,
+    LoxError,
+};
+
+#[repr(C)]
+#[derive(Clone, Copy, PartialEq)]
+pub enum RloxValueType {
+    Nil,
+    Bool,
+    Number,
+    String,
+    // Functions, classes, instances, lists and maps, which can't cross the boundary
+    Object,
+    // Returned by a native to raise a runtime error, with `string` as its message
+    Error,
+}
+
+// Only the field matching `kind` is meaningful.
+// `string` is a NUL-terminated UTF-8 string. Arguments' strings are valid until the native
+// returns, and the strings it returns only need to be valid until then, as they're copied.
+#[repr(C)]
+pub struct RloxValue {
+    pub kind: RloxValueType,
+    pub boolean: bool,
+    pub number: f64,
+    pub string: *const c_char,
+}
+
+pub type RloxNativeFn =
+    extern "C" fn(user_data: *mut c_void, args: *const RloxValue, arg_count: usize) -> RloxValue;
+
+pub struct Rlox {
+    interpreter: Interpreter,
+}
+
+impl RloxValue {
+    fn new(kind: RloxValueType) -> Self {
+        RloxValue {
+            kind,
+            boolean: false,
+            number: 0.0,
+            string: ptr::null(),
+        }
+    }
+
+    // Strings are copied, so the host keeps ownership of the memory it returned
+    unsafe fn to_literal(&self) -> Result<LiteralTypes, Exit> {
+        match self.kind {
+            RloxValueType::Bool => Ok(LiteralTypes::Bool(self.boolean)),
+            RloxValueType::Number => Ok(LiteralTypes::Number(self.number)),
+            RloxValueType::String if !self.string.is_null() => {
+                Ok(LiteralTypes::String(self.copy_string()))
+            }
+            RloxValueType::Error if !self.string.is_null() => {
+                Err(Exit::message(self.copy_string()))
+            }
+            RloxValueType::Error => Err(Exit::message("Native function failed.")),
+            _ => Ok(LiteralTypes::Nil),
+        }
+    }
+
+    unsafe fn copy_string(&self) -> String {
+        CStr::from_ptr(self.string).to_string_lossy().into_owned()
+    }
+}
+
+// Converts arguments for a native call, `strings` keeps the backing memory alive
+fn to_values(arguments: &[LiteralTypes], strings: &mut Vec<CString>) -> Vec<RloxValue> {
+    arguments
+        .iter()
+        .map(|argument| match argument {
+            LiteralTypes::Nil | LiteralTypes::Unassigned => RloxValue::new(RloxValueType::Nil),
+            LiteralTypes::Bool(b) => RloxValue {
+                boolean: *b,
+                ..RloxValue::new(RloxValueType::Bool)
+            },
+            LiteralTypes::Number(n) => RloxValue {
+                number: *n,
+                ..RloxValue::new(RloxValueType::Number)
+            },
+            LiteralTypes::String(s) => {
+                let string = CString::new(s.replace('\0', "")).unwrap_or_default();
+                let value = RloxValue {
+                    string: string.as_ptr(),
+                    ..RloxValue::new(RloxValueType::String)
+                };
+                strings.push(string);
+                value
+            }
+            LiteralTypes::Callable(_) | LiteralTypes::List(_) | LiteralTypes::Map(_) => {
+                RloxValue::new(RloxValueType::Object)
+            }
+        })
+        .collect()
+}
+
+#[no_mangle]
+pub extern "C" fn rlox_new() -> *mut Rlox {
+    let interpreter = Interpreter::with_output(Box::new(io::stdout()));
+    Box::into_raw(Box::new(Rlox { interpreter }))
+}
+
+/// Runs a script, keeping globals from earlier runs.
+/// Returns 0 on success, 65 for static errors and 70 for runtime errors.
+///
+/// # Safety
+/// `vm` must come from `rlox_new` and `source` must be a NUL-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn rlox_run(vm: *mut Rlox, source: *const c_char) -> c_int {
+    if vm.is_null() || source.is_null() {
+        return 64;
+    }
+    let source = match CStr::from_ptr(source).to_str() {
+        Ok(s) => s,
+        Err(_) => return 65,
+    };
+
+    // Resolution errors are reported as the CLI reports them, with runtime errors
+    match crate::run_with(&mut (*vm).interpreter, source) {
+        Ok(_) => 0,
+        Err(LoxError::Runtime(..) | LoxError::Resolve(_)) => 70,
+        Err(_) => 65,
+    }
+}
+
+/// Defines a global function backed by `function`, which receives `user_data` on every call.
+/// Returning a value of kind `Error` raises a runtime error with its string as the message.
+/// Returns 0 on success and -1 on invalid arguments.
+///
+/// # Safety
+/// `vm` must come from `rlox_new` and `name` must be a NUL-terminated string.
+/// `user_data` must stay valid for as long as the interpreter is alive.
+#[no_mangle]
+pub unsafe extern "C" fn rlox_register_native(
+    vm: *mut Rlox,
+    name: *const c_char,
+    arity: usize,
+    function: RloxNativeFn,
+    user_data: *mut c_void,
+) -> c_int {
+    if vm.is_null() || name.is_null() {
+        return -1;
+    }
+    register_native(&mut (*vm).interpreter, name, arity, function, user_data)
+}
+
+// Defines a native for `rlox_register_native` and for extensions loaded with `loadExtension`
+pub(crate) unsafe fn register_native(
+    interpreter: &mut Interpreter,
+    name: *const c_char,
+    arity: usize,
+    function: RloxNativeFn,
+    user_data: *mut c_void,
+) -> c_int {
+    let name = match CStr::from_ptr(name).to_str() {
+        Ok(s) => s,
+        Err(_) => return -1,
+    };
+
+    interpreter.define_native(name, arity, move |_, arguments| {
+        let mut strings = Vec::new();
+        let values = to_values(arguments, &mut strings);
+        let result = function(user_data, values.as_ptr(), values.len());
+        result.to_literal()
+    });
+    0
+}
+
+/// # Safety
+/// `vm` must come from `rlox_new` and must not be used afterwards.
+#[no_mangle]
+pub unsafe extern "C" fn rlox_free(vm: *mut Rlox) {
+    if !vm.is_null() {
+        drop(Box::from_raw(vm));
+    }
+}
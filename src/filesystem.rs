@@ -0,0 +1,162 @@
+/// Filesystem natives: `listDir(path)`, `exists(path)`, `mkdir(path)`, `remove(path)`,
+/// `stat(path)`, `canonicalize(path)` and `readBytes(path)`. They only run when the host has allowed filesystem
+/// access with `Interpreter::set_allow_fs`, and relative paths are taken from the working
+/// directory. `pathJoin`, `dirname`, `basename` and `extension` only work on the path itself,
+/// with the separators of the platform, so they're always allowed.
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::time::UNIX_EPOCH;
+
+use crate::bytes::Bytes;
+use crate::interpreter::{Exit, Interpreter};
+use crate::token::LiteralTypes;
+
+fn string_path(value: &LiteralTypes) -> Result<&str, Exit> {
+    match value {
+        LiteralTypes::String(path) => Ok(path),
+        _ => Err(Exit::message("Path must be a string.")),
+    }
+}
+
+// The path a native was given, once filesystem access is known to be allowed
+fn path<'a>(interpreter: &Interpreter, value: &'a LiteralTypes) -> Result<&'a str, Exit> {
+    if !interpreter.allow_fs() {
+        return Err(Exit::message("Filesystem access isn't allowed."));
+    }
+    string_path(value)
+}
+
+// A part of a path as a string, `nil` when the path doesn't have it
+fn part(part: Option<impl AsRef<Path>>) -> LiteralTypes {
+    match part {
+        Some(part) => part.as_ref().to_string_lossy().into_owned().into(),
+        None => LiteralTypes::Nil,
+    }
+}
+
+fn failed(action: &str, path: &str, error: io::Error) -> Exit {
+    Exit::message(format!("Error {} '{}': {}.", action, path, error))
+}
+
+// What `stat` gives for a path: its `type` ("file", "dir", "symlink" or "other"), `size` in
+// bytes, `modified` in seconds since the Unix epoch like `clock()`, and `readonly`
+fn stat(path: &str) -> io::Result<LiteralTypes> {
+    // Links are described themselves rather than what they point to
+    let metadata = fs::symlink_metadata(path)?;
+    let file_type = metadata.file_type();
+    let kind = if file_type.is_file() {
+        "file"
+    } else if file_type.is_dir() {
+        "dir"
+    } else if file_type.is_symlink() {
+        "symlink"
+    } else {
+        "other"
+    };
+    let modified = match metadata.modified()?.duration_since(UNIX_EPOCH) {
+        Ok(since) => LiteralTypes::Number(since.as_secs_f64()),
+        Err(_) => LiteralTypes::Nil,
+    };
+    let map = BTreeMap::from([
+        ("type".to_string(), kind.into()),
+        ("size".to_string(), (metadata.len() as f64).into()),
+        ("modified".to_string(), modified),
+        (
+            "readonly".to_string(),
+            metadata.permissions().readonly().into(),
+        ),
+    ]);
+    Ok(map.into())
+}
+
+// Defines `listDir`, `exists`, `mkdir`, `remove`, `stat`, `canonicalize` and `readBytes`, and
+// the natives working on paths
+pub fn define_natives(interpreter: &mut Interpreter) {
+    // Names of the entries in a directory, sorted
+    interpreter.define_native("listDir", 1, |interpreter, arguments| {
+        let path = path(interpreter, &arguments[0])?;
+        let mut names = fs::read_dir(path)
+            .and_then(|entries| {
+                entries
+                    .map(|entry| Ok(entry?.file_name().to_string_lossy().into_owned()))
+                    .collect::<io::Result<Vec<_>>>()
+            })
+            .map_err(|error| failed("listing", path, error))?;
+        names.sort();
+        Ok(names
+            .into_iter()
+            .map(LiteralTypes::String)
+            .collect::<Vec<_>>()
+            .into())
+    });
+
+    interpreter.define_native("exists", 1, |interpreter, arguments| {
+        let path = path(interpreter, &arguments[0])?;
+        Ok(fs::symlink_metadata(path).is_ok().into())
+    });
+
+    // Makes the parents it needs too, and does nothing if the directory is already there
+    interpreter.define_native("mkdir", 1, |interpreter, arguments| {
+        let path = path(interpreter, &arguments[0])?;
+        fs::create_dir_all(path).map_err(|error| failed("creating", path, error))?;
+        Ok(LiteralTypes::Nil)
+    });
+
+    // Removes a file, or a directory with everything in it
+    interpreter.define_native("remove", 1, |interpreter, arguments| {
+        let path = path(interpreter, &arguments[0])?;
+        let result = match fs::symlink_metadata(path) {
+            Ok(metadata) if metadata.is_dir() => fs::remove_dir_all(path),
+            _ => fs::remove_file(path),
+        };
+        result.map_err(|error| failed("removing", path, error))?;
+        Ok(LiteralTypes::Nil)
+    });
+
+    interpreter.define_native("stat", 1, |interpreter, arguments| {
+        let path = path(interpreter, &arguments[0])?;
+        stat(path).map_err(|error| failed("reading", path, error))
+    });
+
+    // The contents of a file as a byte buffer
+    interpreter.define_native("readBytes", 1, |interpreter, arguments| {
+        let path = path(interpreter, &arguments[0])?;
+        let data = fs::read(path).map_err(|error| failed("reading", path, error))?;
+        Ok(Bytes::new(data).into_value())
+    });
+
+    // Absolute form of a path that exists, with links and `..` resolved
+    interpreter.define_native("canonicalize", 1, |interpreter, arguments| {
+        let path = path(interpreter, &arguments[0])?;
+        let canonical = fs::canonicalize(path).map_err(|error| failed("resolving", path, error))?;
+        let canonical = canonical.to_string_lossy();
+        // Windows gives drive paths as `\\?\C:\...`, which most programs don't take
+        Ok(match canonical.strip_prefix(r"\\?\") {
+            Some(drive) if drive.as_bytes().get(1) == Some(&b':') => drive.into(),
+            _ => canonical.into_owned().into(),
+        })
+    });
+
+    // An absolute second path replaces the first, as `Path::join` does
+    interpreter.define_native("pathJoin", 2, |_, arguments| {
+        let base = string_path(&arguments[0])?;
+        let path = string_path(&arguments[1])?;
+        Ok(part(Some(Path::new(base).join(path))))
+    });
+
+    // The path without its last component, `""` for a relative path of just one
+    interpreter.define_native("dirname", 1, |_, arguments| {
+        Ok(part(Path::new(string_path(&arguments[0])?).parent()))
+    });
+
+    interpreter.define_native("basename", 1, |_, arguments| {
+        Ok(part(Path::new(string_path(&arguments[0])?).file_name()))
+    });
+
+    // Without the dot, so `"txt"` for `"notes.txt"`
+    interpreter.define_native("extension", 1, |_, arguments| {
+        Ok(part(Path::new(string_path(&arguments[0])?).extension()))
+    });
+}
@@ -0,0 +1,180 @@
+/// Panic-free entry points for fuzzing the front end and interpreter, see `fuzz/`
+use std::io;
+
+use arbitrary::{Arbitrary, Unstructured};
+
+use crate::{
+    interpreter::Interpreter,
+    parser::Parser,
+    resolver::Resolver,
+    scanner::Scanner,
+    symbol::Symbol,
+    token::{LiteralTypes, Token, TokenType},
+};
+
+// Bounds for running untrusted programs
+#[derive(Debug, Clone, Copy)]
+pub struct FuzzLimits {
+    pub max_source_len: usize,
+    pub max_steps: usize,
+}
+
+impl Default for FuzzLimits {
+    fn default() -> Self {
+        FuzzLimits {
+            max_source_len: 4096,
+            max_steps: 10_000,
+        }
+    }
+}
+
+// Token vector that always ends with `Eof` and has lexemes matching each token type
+#[derive(Debug, Clone)]
+pub struct TokenStream(pub Vec<Token>);
+
+pub fn fuzz_scan(data: &[u8]) {
+    let source = String::from_utf8_lossy(data).into_owned();
+    let _ = crate::capture_diagnostics(|| Scanner::new(&source).scan_tokens());
+}
+
+pub fn fuzz_parse(data: &[u8]) {
+    let source = String::from_utf8_lossy(data).into_owned();
+    let _ = crate::capture_diagnostics(|| {
+        let tokens = Scanner::new(&source).scan_tokens();
+        parse_and_resolve(tokens);
+    });
+}
+
+// Parses a generated token stream directly, skipping the scanner
+pub fn fuzz_parse_tokens(tokens: TokenStream) {
+    let _ = crate::capture_diagnostics(|| parse_and_resolve(tokens.0));
+}
+
+pub fn fuzz_interpret_with_limits(data: &[u8], limits: FuzzLimits) {
+    if data.len() > limits.max_source_len {
+        return;
+    }
+    let source = String::from_utf8_lossy(data);
+    let _ = crate::capture_diagnostics(|| {
+        let mut interpreter = Interpreter::with_output(Box::new(io::sink()));
+        interpreter.set_step_limit(Some(limits.max_steps));
+        crate::run_with(&mut interpreter, &source)
+    });
+}
+
+fn parse_and_resolve(tokens: Vec<Token>) {
+    let mut parser = Parser::new(tokens, 0);
+    if let Ok(statements) = parser.parse() {
+        let mut interpreter = Interpreter::with_output(Box::new(io::sink()));
+        let _ = Resolver::new(&mut interpreter).resolve_each(&statements);
+    }
+}
+
+impl<'a> Arbitrary<'a> for TokenStream {
+    fn arbitrary(u: &mut Unstructured<'a>) -> arbitrary::Result<Self> {
+        let mut tokens = Vec::new();
+        let mut line = 1;
+        for _ in 0..u.arbitrary_len::<TokenType>()? {
+            let ttype = TokenType::arbitrary(u)?;
+            if ttype == TokenType::Eof {
+                break;
+            }
+            line += u.int_in_range(0..=1)?;
+            tokens.push(arbitrary_token(u, ttype, line)?);
+        }
+        tokens.push(Token::new(
+            TokenType::Eof,
+            Symbol::intern(""),
+            LiteralTypes::Nil,
+            line,
+            1,
+            0,
+            0,
+        ));
+        Ok(TokenStream(tokens))
+    }
+}
+
+fn arbitrary_token(
+    u: &mut Unstructured<'_>,
+    ttype: TokenType,
+    line: usize,
+) -> arbitrary::Result<Token> {
+    let (lexeme, literal) = match ttype {
+        TokenType::Identifier => {
+            let names = ["a", "b", "f", "A", "B", "init", "this", "x"];
+            let name = u.choose(&names)?.to_string();
+            (name.clone(), LiteralTypes::String(name))
+        }
+        TokenType::String => {
+            let value = String::arbitrary(u)?;
+            (format!("\"{}\"", value), LiteralTypes::String(value))
+        }
+        TokenType::Number => {
+            let value = f64::from(u32::arbitrary(u)?);
+            (value.to_string(), LiteralTypes::Number(value))
+        }
+        TokenType::True => ("true".to_string(), LiteralTypes::Bool(true)),
+        TokenType::False => ("false".to_string(), LiteralTypes::Bool(false)),
+        _ => (fixed_lexeme(&ttype).to_string(), LiteralTypes::Nil),
+    };
+    // There's no source for the tokens to have offsets in
+    Ok(Token::new(
+        ttype,
+        Symbol::intern(&lexeme),
+        literal,
+        line,
+        1,
+        0,
+        0,
+    ))
+}
+
+fn fixed_lexeme(ttype: &TokenType) -> &'static str {
+    match ttype {
+        TokenType::LeftParen => "(",
+        TokenType::RightParen => ")",
+        TokenType::LeftBrace => "{",
+        TokenType::RightBrace => "}",
+        TokenType::Comma => ",",
+        TokenType::Dot => ".",
+        TokenType::Minus => "-",
+        TokenType::Plus => "+",
+        TokenType::Semicolon => ";",
+        TokenType::Slash => "/",
+        TokenType::Star => "*",
+        TokenType::Bang => "!",
+        TokenType::BangEqual => "!=",
+        TokenType::Equal => "=",
+        TokenType::EqualEqual => "==",
+        TokenType::Greater => ">",
+        TokenType::GreaterEqual => ">=",
+        TokenType::Less => "<",
+        TokenType::LessEqual => "<=",
+        TokenType::And => "and",
+        TokenType::Async => "async",
+        TokenType::Await => "await",
+        TokenType::Break => "break",
+        TokenType::Class => "class",
+        TokenType::Continue => "continue",
+        TokenType::Else => "else",
+        TokenType::Fun => "fun",
+        TokenType::For => "for",
+        TokenType::If => "if",
+        TokenType::Nil => "nil",
+        TokenType::Or => "or",
+        TokenType::Print => "print",
+        TokenType::Return => "return",
+        TokenType::Super => "super",
+        TokenType::This => "this",
+        TokenType::Var => "var",
+        TokenType::While => "while",
+        TokenType::Identifier
+        | TokenType::String
+        | TokenType::Number
+        | TokenType::True
+        | TokenType::False
+        | TokenType::DocComment
+        | TokenType::Eof => "",
+    }
+}
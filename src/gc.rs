@@ -0,0 +1,398 @@
+/// Cycle collector for values kept alive only by references among themselves
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::rc::{Rc, Weak};
+
+use crate::{
+    environment::Upvalue,
+    interpreter::HeapReport,
+    lox_callable::{Callable, LoxClass, LoxInstance},
+    token::LiteralTypes,
+};
+
+// Collections run once this many objects are tracked, and after that whenever the
+// number of tracked objects doubles
+const FIRST_COLLECTION: usize = 1024;
+
+// Objects that can take part in a reference cycle. Only upvalues and instances are
+// registered when created, everything else is found by following references from them.
+pub struct Heap {
+    objects: Vec<Tracked>,
+    next_collection: usize,
+    // Instances whose class has a `deinit` method, which these handles don't keep alive
+    finalizable: Vec<Weak<RefCell<LoxInstance>>>,
+    // Garbage a collection found, whose `deinit` is yet to run
+    finalizing: Vec<Rc<RefCell<LoxInstance>>>,
+    // Where finalizable instances go when their last reference is dropped
+    dropped: Finalizer,
+}
+
+// Queue of instances whose `deinit` is yet to run. A finalizable instance holds the one of
+// its heap, and dropping it moves its class and fields to a new instance on the queue.
+pub(crate) type Finalizer = Rc<RefCell<Vec<Rc<RefCell<LoxInstance>>>>>;
+
+enum Tracked {
+    Upvalue(Weak<RefCell<LiteralTypes>>),
+    Instance(Weak<RefCell<LoxInstance>>),
+}
+
+type List = Rc<RefCell<Vec<LiteralTypes>>>;
+type Map = Rc<RefCell<BTreeMap<String, LiteralTypes>>>;
+
+// A shared allocation found while tracing
+#[derive(Clone)]
+enum Object {
+    Upvalue(Upvalue),
+    Instance(Rc<RefCell<LoxInstance>>),
+    List(List),
+    Map(Map),
+    Upvalues(Rc<[Upvalue]>),
+    Class(Rc<LoxClass>),
+}
+
+struct Node {
+    object: Object,
+    // References to the object coming from other traced objects
+    internal: usize,
+    live: bool,
+}
+
+impl Heap {
+    pub fn new() -> Self {
+        Heap {
+            objects: Vec::new(),
+            next_collection: FIRST_COLLECTION,
+            finalizable: Vec::new(),
+            finalizing: Vec::new(),
+            dropped: Rc::default(),
+        }
+    }
+
+    pub fn track_upvalue(&mut self, upvalue: &Upvalue) {
+        self.objects.push(Tracked::Upvalue(Rc::downgrade(upvalue)));
+    }
+
+    pub fn track_instance(&mut self, instance: &Rc<RefCell<LoxInstance>>) {
+        self.objects
+            .push(Tracked::Instance(Rc::downgrade(instance)));
+    }
+
+    // Has `deinit` called on the instance once it's dropped or found to be garbage
+    pub fn track_finalizable(&mut self, instance: &Rc<RefCell<LoxInstance>>) {
+        instance.borrow_mut().finalizer = Some(Rc::clone(&self.dropped));
+        self.finalizable.push(Rc::downgrade(instance));
+    }
+
+    pub fn has_finalizing(&self) -> bool {
+        !self.finalizing.is_empty() || !self.dropped.borrow().is_empty()
+    }
+
+    // Instances to call `deinit` on, which are no longer held by the heap
+    pub fn take_finalizing(&mut self) -> Vec<Rc<RefCell<LoxInstance>>> {
+        let mut instances = std::mem::take(&mut self.finalizing);
+        instances.append(&mut self.dropped.borrow_mut());
+        instances
+    }
+
+    // Every instance still waiting for its `deinit`, for when the program ends
+    pub fn take_finalizable(&mut self) -> Vec<Rc<RefCell<LoxInstance>>> {
+        let mut instances = self.take_finalizing();
+        for instance in self.finalizable.drain(..) {
+            if let Some(instance) = instance.upgrade() {
+                instance.borrow_mut().finalizer = None;
+                instances.push(instance);
+            }
+        }
+        instances
+    }
+
+    // Every tracked instance that's still alive
+    pub fn instances(&self) -> impl Iterator<Item = Rc<RefCell<LoxInstance>>> + '_ {
+        self.objects.iter().filter_map(|tracked| match tracked {
+            Tracked::Instance(weak) => weak.upgrade(),
+            Tracked::Upvalue(_) => None,
+        })
+    }
+
+    // Counts every object reachable from the roots or tracked, which is everything alive
+    pub fn census(&self, roots: Vec<LiteralTypes>, upvalues: Vec<Upvalue>) -> HeapReport {
+        let mut pending: Vec<Object> = upvalues.into_iter().map(Object::Upvalue).collect();
+        for value in roots.iter() {
+            value_children(value, &mut |child| pending.push(child));
+        }
+        pending.extend(self.objects.iter().filter_map(|tracked| match tracked {
+            Tracked::Upvalue(weak) => weak.upgrade().map(Object::Upvalue),
+            Tracked::Instance(weak) => weak.upgrade().map(Object::Instance),
+        }));
+
+        let mut report = HeapReport::default();
+        let mut seen = HashSet::new();
+        // Instances of each class, keyed by its layout, which every copy of a class shares
+        let mut classes: HashMap<usize, (String, usize)> = HashMap::new();
+        while let Some(object) = pending.pop() {
+            if !seen.insert(object.id()) {
+                continue;
+            }
+            match &object {
+                Object::Upvalue(_) => report.captured += 1,
+                Object::Instance(instance) => {
+                    report.instances += 1;
+                    if let Ok(instance) = instance.try_borrow() {
+                        let class = &instance.class;
+                        let layout = Rc::as_ptr(&class.layout) as *const () as usize;
+                        classes.entry(layout).or_insert((class.name.clone(), 0)).1 += 1;
+                    }
+                }
+                Object::List(_) => report.lists += 1,
+                Object::Map(_) => report.maps += 1,
+                Object::Upvalues(_) => report.closures += 1,
+                Object::Class(class) => {
+                    let layout = Rc::as_ptr(&class.layout) as *const () as usize;
+                    classes.entry(layout).or_insert((class.name.clone(), 0));
+                }
+            }
+            object.children(&mut |child| pending.push(child));
+        }
+        report.classes = classes.into_values().collect();
+        // Most instances first
+        report
+            .classes
+            .sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+        report
+    }
+
+    pub fn should_collect(&self) -> bool {
+        self.objects.len() >= self.next_collection
+    }
+
+    // Frees every traced object that is only referenced by other garbage, returning how many.
+    // An object referenced from anywhere that isn't traced, like the stack, globals or a
+    // value held by the host, counts as a root, so it's safe to collect at any point.
+    pub fn collect(&mut self) -> usize {
+        let mut nodes: HashMap<usize, Node> = HashMap::new();
+        let mut pending: Vec<Object> = Vec::new();
+
+        self.objects.retain(|tracked| match tracked {
+            Tracked::Upvalue(weak) => weak.upgrade().map(Object::Upvalue).is_some_and(|o| {
+                pending.push(o);
+                true
+            }),
+            Tracked::Instance(weak) => weak.upgrade().map(Object::Instance).is_some_and(|o| {
+                pending.push(o);
+                true
+            }),
+        });
+
+        // Find everything reachable from the tracked objects, counting references among them
+        let mut traced = Vec::new();
+        while let Some(object) = pending.pop() {
+            let id = object.id();
+            if nodes.contains_key(&id) {
+                continue;
+            }
+            nodes.insert(
+                id,
+                Node {
+                    object: object.clone(),
+                    internal: 0,
+                    live: false,
+                },
+            );
+            traced.push(id);
+            object.children(&mut |child| pending.push(child));
+        }
+        for id in traced.iter() {
+            let object = nodes[id].object.clone();
+            object.children(&mut |child| {
+                if let Some(node) = nodes.get_mut(&child.id()) {
+                    node.internal += 1;
+                }
+            });
+        }
+
+        // Objects with references the trace didn't see are roots, mark what they reach.
+        // The node's own handle accounts for one of the strong references.
+        let mut marking: Vec<usize> = traced
+            .iter()
+            .filter(|id| {
+                let node = &nodes[*id];
+                node.object.strong_count() - 1 > node.internal
+            })
+            .copied()
+            .collect();
+        while let Some(id) = marking.pop() {
+            let node = nodes.get_mut(&id).unwrap();
+            if node.live {
+                continue;
+            }
+            node.live = true;
+            node.object.clone().children(&mut |child| {
+                let id = child.id();
+                if nodes.get(&id).is_some_and(|n| !n.live) {
+                    marking.push(id);
+                }
+            });
+        }
+
+        // Garbage with a `deinit` to run is handed back instead, and nothing is freed until
+        // it has run, since it can make the garbage reachable again
+        let mut finalizing = Vec::new();
+        self.finalizable.retain(|instance| {
+            let id = instance.as_ptr() as *const () as usize;
+            match instance.upgrade() {
+                Some(instance) if nodes.get(&id).is_some_and(|node| !node.live) => {
+                    // Its `deinit` runs from here, not again when it's dropped
+                    if let Ok(mut garbage) = instance.try_borrow_mut() {
+                        garbage.finalizer = None;
+                    }
+                    finalizing.push(instance);
+                    false
+                }
+                Some(_) => true,
+                None => false,
+            }
+        });
+        if !finalizing.is_empty() {
+            self.finalizing.extend(finalizing);
+            self.next_collection = FIRST_COLLECTION.max(self.objects.len() * 2);
+            return 0;
+        }
+
+        let garbage: Vec<Object> = nodes
+            .into_values()
+            .filter(|node| !node.live)
+            .map(|node| node.object)
+            .collect();
+        let freed = garbage.len();
+        for object in garbage.iter() {
+            object.clear();
+        }
+        drop(garbage);
+
+        self.objects.shrink_to_fit();
+        self.next_collection = FIRST_COLLECTION.max(self.objects.len() * 2);
+        freed
+    }
+}
+
+impl Default for Heap {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Object {
+    fn id(&self) -> usize {
+        match self {
+            Object::Upvalue(o) => Rc::as_ptr(o) as *const () as usize,
+            Object::Instance(o) => Rc::as_ptr(o) as *const () as usize,
+            Object::List(o) => Rc::as_ptr(o) as *const () as usize,
+            Object::Map(o) => Rc::as_ptr(o) as *const () as usize,
+            Object::Upvalues(o) => Rc::as_ptr(o) as *const () as usize,
+            Object::Class(o) => Rc::as_ptr(o) as *const () as usize,
+        }
+    }
+
+    fn strong_count(&self) -> usize {
+        match self {
+            Object::Upvalue(o) => Rc::strong_count(o),
+            Object::Instance(o) => Rc::strong_count(o),
+            Object::List(o) => Rc::strong_count(o),
+            Object::Map(o) => Rc::strong_count(o),
+            Object::Upvalues(o) => Rc::strong_count(o),
+            Object::Class(o) => Rc::strong_count(o),
+        }
+    }
+
+    // Objects borrowed elsewhere while collecting are skipped, which keeps whatever
+    // they reference alive
+    fn children(&self, visit: &mut dyn FnMut(Object)) {
+        match self {
+            Object::Upvalue(o) => {
+                if let Ok(value) = o.try_borrow() {
+                    value_children(&value, visit);
+                }
+            }
+            Object::Instance(o) => {
+                if let Ok(instance) = o.try_borrow() {
+                    visit(Object::Class(Rc::clone(&instance.class)));
+                    instance
+                        .fields
+                        .iter()
+                        .flatten()
+                        .for_each(|value| value_children(value, visit));
+                }
+            }
+            Object::List(o) => {
+                if let Ok(list) = o.try_borrow() {
+                    list.iter().for_each(|value| value_children(value, visit));
+                }
+            }
+            Object::Map(o) => {
+                if let Ok(map) = o.try_borrow() {
+                    map.values().for_each(|value| value_children(value, visit));
+                }
+            }
+            Object::Upvalues(o) => o
+                .iter()
+                .for_each(|upvalue| visit(Object::Upvalue(Rc::clone(upvalue)))),
+            Object::Class(o) => class_children(o, visit),
+        }
+    }
+
+    // Breaks the cycles running through a garbage object
+    fn clear(&self) {
+        match self {
+            Object::Upvalue(o) => {
+                if let Ok(mut value) = o.try_borrow_mut() {
+                    *value = LiteralTypes::Nil;
+                }
+            }
+            Object::Instance(o) => {
+                if let Ok(mut instance) = o.try_borrow_mut() {
+                    instance.fields.clear();
+                }
+            }
+            Object::List(o) => {
+                if let Ok(mut list) = o.try_borrow_mut() {
+                    list.clear();
+                }
+            }
+            Object::Map(o) => {
+                if let Ok(mut map) = o.try_borrow_mut() {
+                    map.clear();
+                }
+            }
+            // Immutable, so any cycle through them also runs through one of the above
+            Object::Upvalues(_) | Object::Class(_) => (),
+        }
+    }
+}
+
+fn value_children(value: &LiteralTypes, visit: &mut dyn FnMut(Object)) {
+    match value {
+        LiteralTypes::List(list) => visit(Object::List(Rc::clone(list))),
+        LiteralTypes::Map(map) => visit(Object::Map(Rc::clone(map))),
+        LiteralTypes::Callable(Callable::Function(function)) => {
+            visit(Object::Upvalues(Rc::clone(&function.upvalues)));
+            if let Some(receiver) = &function.receiver {
+                visit(Object::Instance(Rc::clone(receiver)));
+            }
+        }
+        LiteralTypes::Callable(Callable::Class(class)) => visit(Object::Class(Rc::clone(class))),
+        LiteralTypes::Callable(Callable::Instance(instance)) => {
+            visit(Object::Instance(Rc::clone(instance)))
+        }
+        // Whatever natives and host objects hold isn't visible, so it's never collected
+        _ => (),
+    }
+}
+
+fn class_children(class: &LoxClass, visit: &mut dyn FnMut(Object)) {
+    for method in class.methods.values() {
+        visit(Object::Upvalues(Rc::clone(&method.upvalues)));
+    }
+    if let Some(super_class) = &class.super_class {
+        visit(Object::Class(Rc::clone(super_class)));
+    }
+}
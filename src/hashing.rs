@@ -0,0 +1,38 @@
+/// Hashing natives: `sha256(value)` and `md5(value)` give a digest as lowercase hex, and
+/// `crc32(value)` a checksum as a number. They hash a string's UTF-8 or a byte buffer's bytes.
+use md5::Md5;
+use sha2::{Digest, Sha256};
+
+use crate::bytes;
+use crate::interpreter::{Exit, Interpreter};
+use crate::token::LiteralTypes;
+
+// The bytes a value stands for when hashing it
+fn data(value: &LiteralTypes) -> Result<Vec<u8>, Exit> {
+    match value {
+        LiteralTypes::String(string) => Ok(string.as_bytes().to_vec()),
+        value => {
+            bytes::bytes(value).ok_or_else(|| Exit::message("Can only hash strings and bytes."))
+        }
+    }
+}
+
+fn hex(digest: &[u8]) -> LiteralTypes {
+    LiteralTypes::String(bytes::hex_encode(digest))
+}
+
+// Defines `sha256`, `md5` and `crc32`
+pub fn define_natives(interpreter: &mut Interpreter) {
+    interpreter.define_native("sha256", 1, |_, arguments| {
+        Ok(hex(&Sha256::digest(data(&arguments[0])?)))
+    });
+
+    interpreter.define_native("md5", 1, |_, arguments| {
+        Ok(hex(&Md5::digest(data(&arguments[0])?)))
+    });
+
+    interpreter.define_native("crc32", 1, |_, arguments| {
+        let checksum = crc32fast::hash(&data(&arguments[0])?);
+        Ok(LiteralTypes::Number(checksum as f64))
+    });
+}
@@ -0,0 +1,87 @@
+/// HTTP client natives: `httpGet(url)` and `httpPost(url, body, headers)` make a request and
+/// return a map of its `status`, `headers` and `body`. Statuses like 404 are responses like
+/// any other, only failing to reach the server is an error. They only run when the host has
+/// allowed network access with `Interpreter::set_allow_net`.
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+use crate::interpreter::{Exit, Interpreter};
+use crate::token::LiteralTypes;
+
+// How long connecting and each read or write can take before the request fails
+const TIMEOUT: Duration = Duration::from_secs(30);
+
+fn agent() -> ureq::Agent {
+    ureq::AgentBuilder::new().timeout(TIMEOUT).build()
+}
+
+fn url(value: &LiteralTypes) -> Result<&str, Exit> {
+    match value {
+        LiteralTypes::String(url) => Ok(url),
+        _ => Err(Exit::message("URL must be a string.")),
+    }
+}
+
+fn check_allowed(interpreter: &Interpreter) -> Result<(), Exit> {
+    if interpreter.allow_net() {
+        Ok(())
+    } else {
+        Err(Exit::message("Network access isn't allowed."))
+    }
+}
+
+// The response as a map, with a server's error statuses turned back into responses
+fn response(result: Result<ureq::Response, ureq::Error>) -> Result<LiteralTypes, Exit> {
+    let response = match result {
+        Ok(response) | Err(ureq::Error::Status(_, response)) => response,
+        Err(error) => return Err(Exit::message(format!("Request failed: {}.", error))),
+    };
+    let status = LiteralTypes::Number(response.status() as f64);
+    let headers: BTreeMap<_, _> = response
+        .headers_names()
+        .into_iter()
+        .filter_map(|name| {
+            let value = response.header(&name)?.to_string();
+            Some((name, LiteralTypes::String(value)))
+        })
+        .collect();
+    let body = response
+        .into_string()
+        .map_err(|error| Exit::message(format!("Error reading response: {}.", error)))?;
+    let map = BTreeMap::from([
+        ("status".to_string(), status),
+        ("headers".to_string(), headers.into()),
+        ("body".to_string(), LiteralTypes::String(body)),
+    ]);
+    Ok(map.into())
+}
+
+// Defines `httpGet` and `httpPost`
+pub fn define_natives(interpreter: &mut Interpreter) {
+    interpreter.define_native("httpGet", 1, |interpreter, arguments| {
+        check_allowed(interpreter)?;
+        response(agent().get(url(&arguments[0])?).call())
+    });
+
+    interpreter.define_native("httpPost", 3, |interpreter, arguments| {
+        check_allowed(interpreter)?;
+        let mut request = agent().post(url(&arguments[0])?);
+        match &arguments[2] {
+            LiteralTypes::Nil => {}
+            LiteralTypes::Map(headers) => {
+                for (name, value) in headers.borrow().iter() {
+                    let LiteralTypes::String(value) = value else {
+                        return Err(Exit::message("Header values must be strings."));
+                    };
+                    request = request.set(name, value);
+                }
+            }
+            _ => return Err(Exit::message("Headers must be a map or nil.")),
+        }
+        let body = match &arguments[1] {
+            LiteralTypes::String(body) => body,
+            _ => return Err(Exit::message("Body must be a string.")),
+        };
+        response(request.send_string(body))
+    });
+}
@@ -0,0 +1,368 @@
+/// Parsed script kept up to date as it's edited, for editors. An edit re-scans and re-parses
+/// only the top-level statements it touches, the others keep their nodes and ids and are
+/// moved to their new lines.
+use std::cell::Cell;
+use std::collections::HashMap;
+use std::ops::Range;
+use std::rc::Rc;
+
+use crate::expr::Expr;
+use crate::parser::Parser;
+use crate::query::{Ast, NodeSpans, Span};
+use crate::scanner::{ScanErrorKind, Scanner};
+use crate::stmt::Stmt;
+use crate::token::Token;
+use crate::{LoxError, SourceGuard};
+
+// Replaces the text between two byte offsets of the source, `end` exclusive
+#[derive(Debug, Clone, PartialEq)]
+pub struct TextEdit {
+    pub start: usize,
+    pub end: usize,
+    pub text: String,
+}
+
+// A top-level statement, or text that didn't parse
+struct Item {
+    // From the start of its first token to the end of its last, or the whole lines for text
+    // that didn't parse
+    span: Span,
+    statement: Option<Stmt>,
+    // Ids of its nodes and the span each was parsed from
+    ids: Range<usize>,
+    spans: HashMap<usize, Span>,
+}
+
+pub struct Document {
+    source: String,
+    // In source order
+    items: Vec<Item>,
+    // Id given to the first node of the next parse
+    next_id: usize,
+}
+
+impl Document {
+    // Syntax errors are reported as usual, the rest of the script is still parsed
+    pub fn new(source: &str) -> Self {
+        let mut document = Document {
+            source: source.to_string(),
+            items: Vec::new(),
+            next_id: 0,
+        };
+        let (items, _) = document.parse(0, source.len());
+        document.items = items;
+        document
+    }
+
+    pub fn source(&self) -> &str {
+        &self.source
+    }
+
+    // Whether any of the script failed to parse
+    pub fn has_errors(&self) -> bool {
+        self.items.iter().any(|item| item.statement.is_none())
+    }
+
+    pub fn ast(&self) -> Ast {
+        let statements = self.items.iter().filter_map(|item| item.statement.as_ref());
+        Ast::new(&self.source, self, statements)
+    }
+
+    // Applies the edit and parses the statements it touches again. When they don't parse,
+    // the error is reported and `LoxError::Parse` returned, as `run` does; they're parsed
+    // again with the next edit near them.
+    // Panics if the offsets aren't on character boundaries of the source.
+    pub fn edit(&mut self, edit: &TextEdit) -> Result<(), LoxError> {
+        let (start, end, affected) = self.affected(edit);
+
+        let removed = &self.source[edit.start..edit.end];
+        let bytes = edit.text.len() as isize - removed.len() as isize;
+        let lines =
+            edit.text.matches('\n').count() as isize - removed.matches('\n').count() as isize;
+        self.source.replace_range(edit.start..edit.end, &edit.text);
+
+        for item in self.items[affected.end..].iter_mut() {
+            item.shift(bytes, lines);
+        }
+        let (items, parsed) = self.parse(start, end.saturating_add_signed(bytes));
+        // A string left open runs on to the end of the script
+        let replaced = match items.last() {
+            Some(item) if item.span.end == self.source.len() => affected.start..self.items.len(),
+            _ => affected,
+        };
+        self.items.splice(replaced, items);
+        if parsed {
+            Ok(())
+        } else {
+            Err(LoxError::Parse(Vec::new()))
+        }
+    }
+
+    // Whole lines of the source to parse again for the edit and the items they hold
+    fn affected(&self, edit: &TextEdit) -> (usize, usize, Range<usize>) {
+        let items = &self.items;
+        let first = items
+            .iter()
+            .position(|item| item.span.start <= edit.end && edit.start <= item.span.end);
+        let (mut first, mut last) = match first {
+            Some(first) => {
+                let last = items
+                    .iter()
+                    .rposition(|item| item.span.start <= edit.end)
+                    .map_or(first + 1, |last| last + 1);
+                (first, last)
+            }
+            // Text typed before a statement can end it or become its doc comment
+            None => {
+                let next = items
+                    .iter()
+                    .position(|item| item.span.start >= edit.end)
+                    .unwrap_or(items.len());
+                (next, (next + 1).min(items.len()))
+            }
+        };
+
+        let (mut start, mut end) = (edit.start, edit.end);
+        loop {
+            let before = (first, last, start, end);
+            if first < last {
+                start = start.min(items[first].span.start);
+                end = end.max(items[last - 1].span.end);
+            }
+            start = line_start(&self.source, start);
+            end = line_end(&self.source, end);
+            // Doc comments of the first statement
+            while start > 0 {
+                let above = line_start(&self.source, start - 1);
+                if !is_doc_line(&self.source[above..start]) {
+                    break;
+                }
+                start = above;
+            }
+            // Statements sharing a line with the others
+            first = items
+                .iter()
+                .position(|item| item.span.end > start)
+                .map_or(first, |i| i.min(first));
+            last = items
+                .iter()
+                .rposition(|item| item.span.start < end)
+                .map_or(last, |i| last.max(i + 1));
+            // The next statement, when its doc comment may have changed
+            if let Some(next) = items.get(last) {
+                let between: Vec<&str> = self.source[end..next.span.start].split('\n').collect();
+                if between.len() > 1 && between[1..between.len() - 1].iter().all(|l| is_doc_line(l))
+                {
+                    last += 1;
+                }
+            }
+            if (first, last, start, end) == before {
+                return (start, end, first..last);
+            }
+        }
+    }
+
+    // Parses the whole lines between the offsets into items. Text left in an open string is
+    // a single item that didn't parse, up to the end of the script.
+    fn parse(&mut self, start: usize, end: usize) -> (Vec<Item>, bool) {
+        let _source = SourceGuard::set(&self.source);
+        let line = 1 + self.source[..start].matches('\n').count();
+        // Set as the parser pulls tokens from the scanner
+        let unterminated = Cell::new(false);
+        let tokens =
+            Scanner::from_line(&self.source[start..end], line, start).filter_map(|token| {
+                token
+                    .map_err(|error| {
+                        if error.kind == ScanErrorKind::UnterminatedString {
+                            unterminated.set(true);
+                        }
+                        error.report();
+                    })
+                    .ok()
+            });
+        let mut parser = Parser::new(tokens, self.next_id);
+        let parsed = parser.parse();
+        self.next_id = parser.end_id();
+
+        let unterminated = unterminated.get();
+        let (Ok(statements), false) = (parsed, unterminated) else {
+            let end = if unterminated { self.source.len() } else { end };
+            let failed = Item {
+                span: Span { start, end },
+                statement: None,
+                ids: 0..0,
+                spans: HashMap::new(),
+            };
+            return (vec![failed], false);
+        };
+        let items = statements
+            .into_iter()
+            .zip(parser.top_level())
+            .map(|(statement, (span, ids))| Item {
+                span,
+                statement: Some(statement),
+                spans: parser.node_spans(ids.clone()),
+                ids,
+            })
+            .collect();
+        (items, true)
+    }
+}
+
+impl NodeSpans for Document {
+    fn span_of(&self, uuid: usize) -> Option<Span> {
+        let item = self.items.iter().find(|item| item.ids.contains(&uuid))?;
+        item.spans.get(&uuid).copied()
+    }
+}
+
+fn line_start(source: &str, offset: usize) -> usize {
+    source[..offset].rfind('\n').map_or(0, |i| i + 1)
+}
+
+fn line_end(source: &str, offset: usize) -> usize {
+    source[offset..]
+        .find('\n')
+        .map_or(source.len(), |i| offset + i)
+}
+
+fn is_doc_line(line: &str) -> bool {
+    line.trim_start().starts_with("///")
+}
+
+impl Item {
+    // Moves the item after an edit above it
+    fn shift(&mut self, bytes: isize, lines: isize) {
+        shift_span(&mut self.span, bytes);
+        for span in self.spans.values_mut() {
+            shift_span(span, bytes);
+        }
+        if let Some(statement) = &mut self.statement {
+            shift_stmt(statement, bytes, lines);
+        }
+    }
+}
+
+fn shift_span(span: &mut Span, bytes: isize) {
+    span.start = span.start.saturating_add_signed(bytes);
+    span.end = span.end.saturating_add_signed(bytes);
+}
+
+fn shift_token(token: &mut Token, bytes: isize, lines: isize) {
+    token.line = token.line.saturating_add_signed(lines);
+    token.start = token.start.saturating_add_signed(bytes);
+    token.end = token.end.saturating_add_signed(bytes);
+}
+
+fn shift_stmt(stmt: &mut Stmt, bytes: isize, lines: isize) {
+    match stmt {
+        Stmt::Expression(s) => {
+            s.line = s.line.saturating_add_signed(lines);
+            shift_expr(&mut s.expression, bytes, lines);
+        }
+        Stmt::Print(s) => {
+            shift_token(&mut s.keyword, bytes, lines);
+            shift_expr(&mut s.expression, bytes, lines);
+        }
+        Stmt::Var(s) => {
+            shift_token(&mut s.name, bytes, lines);
+            if let Some(initializer) = &mut s.initializer {
+                shift_expr(initializer, bytes, lines);
+            }
+        }
+        Stmt::Block(s) => {
+            shift_token(&mut s.brace, bytes, lines);
+            for statement in s.statements.iter_mut() {
+                shift_stmt(statement, bytes, lines);
+            }
+        }
+        Stmt::If(s) => {
+            shift_token(&mut s.keyword, bytes, lines);
+            shift_expr(&mut s.condition, bytes, lines);
+            shift_stmt(&mut s.then_branch, bytes, lines);
+            if let Some(else_branch) = &mut s.else_branch {
+                shift_stmt(else_branch, bytes, lines);
+            }
+        }
+        Stmt::While(s) => {
+            shift_token(&mut s.keyword, bytes, lines);
+            shift_expr(&mut s.condition, bytes, lines);
+            shift_stmt(&mut s.body, bytes, lines);
+            if let Some(increment) = &mut s.increment {
+                shift_stmt(increment, bytes, lines);
+            }
+        }
+        Stmt::Break(s) => shift_token(&mut s.keyword, bytes, lines),
+        Stmt::Continue(s) => shift_token(&mut s.keyword, bytes, lines),
+        Stmt::Function(s) => {
+            let s = Rc::make_mut(s);
+            shift_token(&mut s.name, bytes, lines);
+            for param in s.params.iter_mut() {
+                shift_token(param, bytes, lines);
+            }
+            for statement in s.body.iter_mut() {
+                shift_stmt(statement, bytes, lines);
+            }
+        }
+        Stmt::Return(s) => {
+            shift_token(&mut s.keyword, bytes, lines);
+            shift_expr(&mut s.value, bytes, lines);
+        }
+        Stmt::Class(s) => {
+            shift_token(&mut s.name, bytes, lines);
+            if let Some(super_class) = &mut s.super_class {
+                shift_expr(super_class, bytes, lines);
+            }
+            for method in s.methods.iter_mut() {
+                shift_stmt(method, bytes, lines);
+            }
+        }
+    }
+}
+
+fn shift_expr(expr: &mut Expr, bytes: isize, lines: isize) {
+    match expr {
+        Expr::Assignment(e) => {
+            shift_token(&mut e.name, bytes, lines);
+            shift_expr(&mut e.value, bytes, lines);
+        }
+        Expr::Binary(e) => {
+            shift_expr(&mut e.left, bytes, lines);
+            shift_token(&mut e.operator, bytes, lines);
+            shift_expr(&mut e.right, bytes, lines);
+        }
+        Expr::Call(e) => {
+            shift_expr(&mut e.callee, bytes, lines);
+            shift_token(&mut e.paren, bytes, lines);
+            for argument in e.arguments.iter_mut() {
+                shift_expr(argument, bytes, lines);
+            }
+        }
+        Expr::Get(e) => {
+            shift_expr(&mut e.object, bytes, lines);
+            shift_token(&mut e.name, bytes, lines);
+        }
+        Expr::Grouping(e) => shift_expr(&mut e.expr, bytes, lines),
+        Expr::Literal(_) => {}
+        Expr::Logical(e) => {
+            shift_expr(&mut e.left, bytes, lines);
+            shift_token(&mut e.operator, bytes, lines);
+            shift_expr(&mut e.right, bytes, lines);
+        }
+        Expr::Set(e) => {
+            shift_expr(&mut e.object, bytes, lines);
+            shift_token(&mut e.name, bytes, lines);
+            shift_expr(&mut e.value, bytes, lines);
+        }
+        Expr::Super(e) => {
+            shift_token(&mut e.keyword, bytes, lines);
+            shift_token(&mut e.method, bytes, lines);
+        }
+        Expr::This(e) => shift_token(&mut e.keyword, bytes, lines),
+        Expr::Unary(e) => {
+            shift_token(&mut e.operator, bytes, lines);
+            shift_expr(&mut e.right, bytes, lines);
+        }
+        Expr::Variable(e) => shift_token(&mut e.name, bytes, lines),
+    }
+}
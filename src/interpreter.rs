@@ -1,70 +1,1726 @@
 use std::cell::RefCell;
 use std::collections::HashMap;
+use std::fmt;
+use std::io::{self, Write};
+use std::ops::Range;
 use std::rc::Rc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
 
-use crate::environment::Environment;
+use crate::ast_printer::{AstPrinter, Form};
+use crate::environment::{Binding, Environment, Frame, Slot, Upvalue};
+use crate::event_loop::{self, EventLoop, Settlement, State};
 use crate::expr::{self, *};
-use crate::lox_callable::{Callable, LoxCallable, LoxClass, LoxFunction};
-use crate::report;
+use crate::extensions::{Extension, Registry};
+use crate::gc::Heap;
+use crate::lox_callable::{
+    Callable, HostObject, LoxCallable, LoxClass, LoxFunction, LoxInstance, NativeFunction,
+};
+use crate::loxc::{Place, Resolution};
+use crate::parser::DEFAULT_MAX_DEPTH;
 use crate::stmt::{self, *};
+use crate::symbol::Symbol;
 use crate::token::{LiteralTypes, Token, TokenType};
 
+// Leaves room on the 2 MB stack threads get by default, even in a debug build
+pub const DEFAULT_MAX_CALL_DEPTH: usize = 150;
+
+// Seconds of virtual time each statement takes in deterministic mode
+const VIRTUAL_STEP: f64 = 1e-6;
+
+// Shortest period of `setInterval` in seconds, shorter ones are run this often
+const MIN_INTERVAL: f64 = 0.001;
+
+// How `print` and string conversions write numbers
+#[derive(Debug, Clone)]
+pub struct NumberFormat {
+    // Rounds to this many significant digits, otherwise the shortest exact form is used
+    pub significant_digits: Option<usize>,
+    // Whole numbers print as `3` rather than `3.0`
+    pub integer_display: bool,
+    // Numbers whose decimal exponent reaches this, either way, print like `1.5e21`
+    pub scientific_threshold: i32,
+}
+
+impl Default for NumberFormat {
+    fn default() -> Self {
+        NumberFormat {
+            significant_digits: None,
+            integer_display: true,
+            scientific_threshold: 21,
+        }
+    }
+}
+
+impl NumberFormat {
+    pub fn format(&self, num: f64) -> String {
+        if !num.is_finite() {
+            return num.to_string();
+        }
+        let num = match self.significant_digits {
+            // Formatting in scientific notation rounds the mantissa, which then parses back
+            Some(digits) => format!("{:.*e}", digits.max(1) - 1, num)
+                .parse()
+                .unwrap_or(num),
+            None => num,
+        };
+
+        let exponent = if num == 0.0 {
+            0
+        } else {
+            num.abs().log10().floor() as i32
+        };
+        if exponent.abs() >= self.scientific_threshold {
+            return format!("{:e}", num);
+        }
+
+        let text = num.to_string();
+        if !self.integer_display && num.fract() == 0.0 {
+            return format!("{}.0", text);
+        }
+        text
+    }
+}
+
+// What `Interpreter::reload` did, by name
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ReloadReport {
+    // Functions, classes and global variables that weren't defined before
+    pub added: Vec<String>,
+    // Functions and classes declared differently than before
+    pub changed: Vec<String>,
+    // Functions and classes the source no longer declares. They stay defined, as values
+    // referring to them may still be around.
+    pub removed: Vec<String>,
+    // Live instances moved onto the new definition of their class
+    pub migrated: usize,
+    // Classes whose superclass changed, so their live instances keep the old definition
+    pub incompatible: Vec<String>,
+}
+
+// What's alive on the heap, from `Interpreter::heap_report`
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct HeapReport {
+    // Every live class with its number of live instances, by name
+    pub classes: Vec<(String, usize)>,
+    pub instances: usize,
+    // Function values, bound methods sharing their method's
+    pub closures: usize,
+    // Variables closures captured, which outlive the call that declared them
+    pub captured: usize,
+    pub lists: usize,
+    pub maps: usize,
+    // Globals and locals of the calls active now
+    pub globals: usize,
+    pub locals: usize,
+}
+
+impl fmt::Display for HeapReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "instances: {}", self.instances)?;
+        for (class, instances) in self.classes.iter() {
+            writeln!(f, "  {:<24} {}", class, instances)?;
+        }
+        writeln!(f, "closures:  {}", self.closures)?;
+        writeln!(f, "captured:  {}", self.captured)?;
+        writeln!(f, "lists:     {}", self.lists)?;
+        writeln!(f, "maps:      {}", self.maps)?;
+        write!(f, "globals:   {}\nlocals:    {}", self.globals, self.locals)
+    }
+}
+
+// Counters of what a script did, for performance investigations and tuning limits
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Stats {
+    pub statements: usize,
+    // Calls of functions, methods, classes and natives
+    pub calls: usize,
+    // Instances, closures and captured variables created
+    pub allocations: usize,
+    // Most calls active at once
+    pub max_call_depth: usize,
+    pub collections: usize,
+    // Values the collections freed
+    pub freed: usize,
+}
+
+impl fmt::Display for Stats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "statements:          {}", self.statements)?;
+        writeln!(f, "calls:               {}", self.calls)?;
+        writeln!(f, "allocations:         {}", self.allocations)?;
+        writeln!(f, "max call depth:      {}", self.max_call_depth)?;
+        write!(
+            f,
+            "garbage collections: {} ({} freed)",
+            self.collections, self.freed
+        )
+    }
+}
+
 pub struct Interpreter {
     pub globals: Rc<RefCell<Environment>>,
-    pub environment: Rc<RefCell<Environment>>,
-    locals: HashMap<Expr, usize>,
+    // Locals of every active call, the running one's starting at `frame.base`
+    stack: Vec<Slot>,
+    frame: Frame,
+    // Where every resolved local variable access finds its variable
+    locals: Vec<Option<Binding>>,
+    // Where every `super` expression finds `this`
+    receivers: HashMap<usize, Binding>,
+    // Frame slot of every local declaration, keyed by the declaring node's uuid
+    declarations: HashMap<usize, usize>,
+    // Variables every function captures when it's declared, keyed by the function's uuid
+    captures: HashMap<usize, Rc<[Binding]>>,
+    // What the last instance seen by each `Get` and `Set` resolved to, indexed by the uuid
+    property_caches: Vec<Option<PropertyCache>>,
+    heap: Heap,
+    output: crate::Sink,
+    // Where errors and `eprint` go, stderr unless the host sets another
+    errors: crate::Sink,
+    number_format: NumberFormat,
+    // Maximum number of statements to execute before failing, used to bound untrusted scripts
+    step_limit: Option<usize>,
+    // Calls that can be active at once before failing with a stack overflow
+    max_call_depth: usize,
+    steps: usize,
+    // Whether `run_with` folds constant expressions before resolving
+    optimize: bool,
+    // How deeply `run_with` lets expressions and statements nest
+    max_nesting: usize,
+    // Conditions must be booleans and nil can't be an operand of arithmetic or comparisons
+    strict: bool,
+    // Whether natives that reach the network, like `httpGet`, may run
+    allow_net: bool,
+    // Whether natives that touch the filesystem, like `listDir`, may run
+    allow_fs: bool,
+    // Whether scripts may handle signals with `onSignal`
+    allow_signals: bool,
+    // Set by `return` when its value is a call, which `visit_call` then leaves to the caller
+    tail_call: bool,
+    // First node id not used by any tree run so far
+    next_id: usize,
+    // Functions that haven't returned yet, the innermost last
+    calls: Vec<CallSite>,
+    // Line of the call being made, recorded by the function once it starts
+    call_line: usize,
+    // Set while starting a tail call, which replaces the frames of the calls that led to it
+    tail_called: bool,
+    // Called before each statement runs, e.g. by a debugger
+    hook: Option<Box<dyn Hook>>,
+    // Names of the locals on the stack, only kept while a hook is set
+    slot_names: Vec<Option<Symbol>>,
+    // Whether `clock()` reads virtual time, counted in statements run, instead of the system's
+    deterministic: bool,
+    // Seconds since the Unix epoch, from the system unless the host gave another source
+    time_source: Box<dyn Fn() -> f64>,
+    // State of the generator behind `random()`, seeded from the time source when it's first
+    // needed unless a seed was given
+    random_state: Option<u64>,
+    // Virtual time spent waiting for timers in deterministic mode, or where there's no way
+    // to sleep, like WebAssembly in a browser
+    waited: f64,
+    // Everything but the statements, which are counted as `steps`
+    stats: Stats,
+    // Async calls whose bodies haven't run yet
+    event_loop: EventLoop,
+    // Functions `onExit` registered, run last first once the program ends
+    exit_hooks: Vec<LiteralTypes>,
+    // Code the script gave `exit`, which halted it
+    exit_code: Option<i32>,
+    // Flags set from outside, e.g. by a signal, and the function to call once one is, which
+    // happens before the next statement runs
+    interrupts: Vec<(Arc<AtomicBool>, LiteralTypes)>,
+    // Whether scripts may load extensions from shared libraries with `loadExtension`
+    allow_extensions: bool,
+    // Extensions scripts can load, and the libraries loaded, which go last so the natives
+    // they defined are dropped before them
+    extensions: Registry,
+}
+
+// Called with the line of each statement about to run, apart from blocks, which only
+// group the statements they hold. Errors it returns stop the script the same way a
+// runtime error on that statement would.
+pub trait Hook {
+    fn statement(&mut self, interpreter: &mut Interpreter, line: usize) -> Result<(), Exit>;
+
+    // Called as a Lox function starts, with the line it was called from
+    fn call(
+        &mut self,
+        _interpreter: &mut Interpreter,
+        _function: &Token,
+        _line: usize,
+    ) -> Result<(), Exit> {
+        Ok(())
+    }
+
+    // Called as the function that started last returns, or leaves with an error
+    fn returned(&mut self, _interpreter: &mut Interpreter) {}
+
+    // Called with the value a variable's declaration or an assignment gave it
+    fn write(
+        &mut self,
+        _interpreter: &mut Interpreter,
+        _name: &Token,
+        _value: &LiteralTypes,
+    ) -> Result<(), Exit> {
+        Ok(())
+    }
+}
+
+// Variables of one active call, the script's top-level blocks or the globals
+#[derive(Debug, Clone)]
+pub struct Scope {
+    pub name: String,
+    pub variables: Vec<(Symbol, LiteralTypes)>,
 }
 
 pub enum Exit {
-    RuntimeError,
+    RuntimeError(Box<RuntimeError>),
     Return(ReturnExit),
+    TailCall(Box<TailCall>),
+    // Stops the whole script without an error, e.g. when a debugger quits
+    Halt,
+    // Leave the innermost loop, or skip to its next iteration
+    Break,
+    Continue,
+}
+
+// What went wrong, for hosts that handle some runtime errors differently
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorKind {
+    // A value of the wrong type, like adding a number to a string or calling a string
+    Type,
+    // Reading a variable that isn't defined or hasn't been assigned yet
+    UndefinedVariable,
+    // Reading a property, method or key an object doesn't have
+    UndefinedProperty,
+    // Calling a function with the wrong number of arguments
+    Arity,
+    StackOverflow,
+    StepLimit,
+    // Raised by a native function, e.g. one that couldn't read a file
+    Native,
+    Other,
+}
+
+// Error raised while running a script, reported once it reaches the top level
+#[derive(Debug, Clone)]
+pub struct RuntimeError {
+    // Where the error happened, `None` for errors no token caused, like running out of steps
+    pub token: Option<Token>,
+    pub message: String,
+    pub kind: ErrorKind,
+    // Calls active when the error happened, filled in by the innermost function it leaves
+    pub backtrace: Vec<CallSite>,
+}
+
+// A call to a function that hasn't returned yet
+#[derive(Debug, Clone)]
+pub struct CallSite {
+    pub function: Symbol,
+    // Line the function was called from
+    pub line: usize,
+    // Whether it was reached through tail calls, whose frames it took over
+    pub tail_called: bool,
+    // Where the function's locals start on the stack
+    pub base: usize,
+}
+
+impl RuntimeError {
+    pub fn new(token: &Token, message: impl Into<String>) -> Self {
+        RuntimeError {
+            token: Some(token.clone()),
+            message: message.into(),
+            kind: ErrorKind::Other,
+            backtrace: Vec::new(),
+        }
+    }
+
+    pub fn line(&self) -> usize {
+        self.token.as_ref().map_or(0, |token| token.line)
+    }
+
+    // Describes the calls the error passed through, e.g.
+    // "in fib at line 4, called from main at line 10, called from script at line 12"
+    pub fn describe_backtrace(&self) -> Option<String> {
+        if self.backtrace.is_empty() {
+            return None;
+        }
+        let mut line = self.line();
+        let mut elided = "";
+        let mut calls = Vec::new();
+        for call in self.backtrace.iter().rev() {
+            let from = if calls.is_empty() {
+                "in"
+            } else {
+                "called from"
+            };
+            calls.push(format!(
+                "{} {} at line {}{}",
+                from, call.function, line, elided
+            ));
+            line = call.line;
+            elided = if call.tail_called {
+                " (tail calls elided)"
+            } else {
+                ""
+            };
+        }
+        calls.push(format!("called from script at line {}{}", line, elided));
+
+        // Runs of the same call, as deep recursion leaves, are shown once with a count
+        let mut collapsed: Vec<(String, usize)> = Vec::new();
+        for call in calls {
+            match collapsed.last_mut() {
+                Some((last, count)) if *last == call => *count += 1,
+                _ => collapsed.push((call, 1)),
+            }
+        }
+        let calls: Vec<String> = collapsed
+            .into_iter()
+            .map(|(call, count)| match count {
+                1 => call,
+                _ => format!("{} ({} times)", call, count),
+            })
+            .collect();
+        Some(calls.join(", "))
+    }
+}
+
+impl fmt::Display for RuntimeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.token {
+            Some(token) => write!(
+                f,
+                "[Line {}, Column {}] Error: {}",
+                token.line, token.column, self.message
+            ),
+            None => write!(f, "[Line 0] Error: {}", self.message),
+        }
+    }
+}
+
+impl Exit {
+    pub fn error(token: &Token, message: impl Into<String>) -> Self {
+        Exit::RuntimeError(Box::new(RuntimeError::new(token, message)))
+    }
+
+    pub fn error_of(kind: ErrorKind, token: &Token, message: impl Into<String>) -> Self {
+        let mut error = RuntimeError::new(token, message);
+        error.kind = kind;
+        Exit::RuntimeError(Box::new(error))
+    }
+
+    // Error raised by a native, which is given the location of the call it failed in
+    pub fn message(message: impl Into<String>) -> Self {
+        Exit::RuntimeError(Box::new(RuntimeError {
+            token: None,
+            message: message.into(),
+            kind: ErrorKind::Native,
+            backtrace: Vec::new(),
+        }))
+    }
+
+    // Tells what kind of error a runtime error is, e.g. one raised with `Exit::message`
+    pub fn with_kind(mut self, kind: ErrorKind) -> Self {
+        if let Exit::RuntimeError(error) = &mut self {
+            error.kind = kind;
+        }
+        self
+    }
 }
 
 pub struct ReturnExit {
     pub value: LiteralTypes,
 }
 
+// Call made by a `return` statement, left for the returning function's caller to run
+pub struct TailCall {
+    pub function: LoxFunction,
+    pub arguments: Vec<LiteralTypes>,
+}
+
+// Result of a property lookup, valid for instances of the class owning `layout` for as
+// long as no new field has been added to it
+struct PropertyCache {
+    layout: Rc<RefCell<Vec<Symbol>>>,
+    layout_len: usize,
+    slot: Option<usize>,
+    method: Option<LoxFunction>,
+}
+
+impl PropertyCache {
+    fn new(class: &LoxClass, name: &Symbol) -> Self {
+        PropertyCache {
+            layout: Rc::clone(&class.layout),
+            layout_len: class.layout.borrow().len(),
+            slot: class.field_slot(name),
+            method: class.find_method(name).cloned(),
+        }
+    }
+
+    fn matches(&self, class: &LoxClass) -> bool {
+        Rc::ptr_eq(&self.layout, &class.layout) && self.layout_len == class.layout.borrow().len()
+    }
+}
+
 impl Interpreter {
     pub fn new() -> Self {
-        let globals = Rc::new(RefCell::new(Environment::new()));
-        Interpreter {
-            globals: Rc::clone(&globals),
-            environment: Rc::clone(&globals),
-            locals: HashMap::new(),
+        Interpreter::with_output(Box::new(io::stdout()))
+    }
+
+    // Output of `print` statements goes to the given writer instead of stdout
+    pub fn with_output(output: Box<dyn Write>) -> Self {
+        let mut interpreter = Interpreter {
+            globals: Rc::new(RefCell::new(Environment::new())),
+            stack: Vec::new(),
+            frame: Frame::default(),
+            locals: Vec::new(),
+            receivers: HashMap::new(),
+            declarations: HashMap::new(),
+            captures: HashMap::new(),
+            property_caches: Vec::new(),
+            heap: Heap::new(),
+            output: Rc::new(RefCell::new(output)),
+            errors: Rc::new(RefCell::new(Box::new(io::stderr()))),
+            number_format: NumberFormat::default(),
+            step_limit: None,
+            max_call_depth: DEFAULT_MAX_CALL_DEPTH,
+            steps: 0,
+            optimize: false,
+            max_nesting: DEFAULT_MAX_DEPTH,
+            strict: false,
+            allow_net: false,
+            allow_fs: false,
+            allow_signals: false,
+            tail_call: false,
+            next_id: 0,
+            calls: Vec::new(),
+            call_line: 0,
+            tail_called: false,
+            hook: None,
+            slot_names: Vec::new(),
+            deterministic: false,
+            time_source: Box::new(system_time),
+            random_state: None,
+            waited: 0.0,
+            stats: Stats::default(),
+            event_loop: EventLoop::default(),
+            exit_hooks: Vec::new(),
+            exit_code: None,
+            interrupts: Vec::new(),
+            allow_extensions: false,
+            extensions: Registry::default(),
+        };
+        interpreter.define_native("clock", 0, |interpreter, _| {
+            Ok(LiteralTypes::Number(interpreter.now()))
+        });
+        interpreter.define_native("random", 0, |interpreter, _| {
+            Ok(LiteralTypes::Number(interpreter.random()))
+        });
+        interpreter.define_native("eprint", 1, |interpreter, arguments| {
+            let text = interpreter.stringify(&arguments[0]);
+            interpreter.write_error(&format!("{}\n", text))?;
+            Ok(LiteralTypes::Nil)
+        });
+        interpreter.define_native("setTimeout", 2, |interpreter, arguments| {
+            interpreter.set_timer(arguments, false)
+        });
+        interpreter.define_native("setInterval", 2, |interpreter, arguments| {
+            interpreter.set_timer(arguments, true)
+        });
+        for name in ["clearTimeout", "clearInterval"] {
+            interpreter.define_native(name, 1, |interpreter, arguments| {
+                if let LiteralTypes::Number(id) = arguments[0] {
+                    interpreter.event_loop.clear_timer(id as usize);
+                }
+                Ok(LiteralTypes::Nil)
+            });
         }
+        interpreter.define_native("onExit", 1, |interpreter, arguments| match &arguments[0] {
+            LiteralTypes::Callable(
+                Callable::Function(_) | Callable::Class(_) | Callable::Native(_),
+            ) => {
+                interpreter.exit_hooks.push(arguments[0].clone());
+                Ok(LiteralTypes::Nil)
+            }
+            _ => Err(Exit::message("Can only run functions on exit.")),
+        });
+        // Stops the script, skipping its pending async calls and timers
+        interpreter.define_native("exit", 1, |interpreter, arguments| match arguments[0] {
+            LiteralTypes::Number(code) if code.fract() == 0.0 => {
+                interpreter.exit_code = Some(code as i32);
+                Err(Exit::Halt)
+            }
+            _ => Err(Exit::message("Exit code must be a whole number.")),
+        });
+        crate::bytes::define_natives(&mut interpreter);
+        crate::numbers::define_natives(&mut interpreter);
+        crate::string_builder::define_natives(&mut interpreter);
+        crate::weak::define_natives(&mut interpreter);
+        crate::reflection::define_natives(&mut interpreter);
+        crate::extensions::define_natives(&mut interpreter);
+        interpreter
+    }
+
+    // Errors reported while running scripts, and what they `eprint`, go to the given writer
+    // instead of stderr, apart from errors a caller is collecting
+    pub fn set_error_output(&mut self, errors: Box<dyn Write>) {
+        self.errors = Rc::new(RefCell::new(errors));
+    }
+
+    pub(crate) fn error_output(&self) -> &crate::Sink {
+        &self.errors
+    }
+
+    pub(crate) fn output(&self) -> &crate::Sink {
+        &self.output
+    }
+
+    // Makes execution fail once more than `limit` statements have run
+    pub fn set_step_limit(&mut self, limit: Option<usize>) {
+        self.step_limit = limit;
+    }
+
+    // Statements run so far
+    pub fn steps(&self) -> usize {
+        self.steps
+    }
+
+    // Given a seed, `clock()` and `random()` no longer depend on when the script runs:
+    // time starts at zero and advances with every statement, and random numbers follow
+    // from the seed. `None` goes back to the system's time and an unpredictable sequence.
+    pub fn set_deterministic(&mut self, seed: Option<u64>) {
+        self.deterministic = seed.is_some();
+        self.random_state = seed;
+        self.waited = 0.0;
+    }
+
+    pub fn deterministic(&self) -> bool {
+        self.deterministic
+    }
+
+    // Where `clock()` reads the time and `random()` takes its seed from, in seconds since
+    // the Unix epoch, for hosts with a clock of their own or tests that fix the time
+    pub fn set_time_source(&mut self, source: impl Fn() -> f64 + 'static) {
+        self.time_source = Box::new(source);
+    }
+
+    // Seconds since the Unix epoch, or of virtual time in deterministic mode
+    pub fn now(&self) -> f64 {
+        match self.deterministic {
+            true => self.steps as f64 * VIRTUAL_STEP + self.waited,
+            false => (self.time_source)() + self.waited,
+        }
+    }
+
+    // Waits until `now()` reaches the time. Virtual time is moved on instead.
+    fn wait_until(&mut self, time: f64) {
+        let wait = time - self.now();
+        if wait <= 0.0 {
+            return;
+        }
+        match self.deterministic {
+            true => self.waited += wait,
+            #[cfg(not(target_arch = "wasm32"))]
+            false => std::thread::sleep(
+                std::time::Duration::try_from_secs_f64(wait).unwrap_or(std::time::Duration::MAX),
+            ),
+            #[cfg(target_arch = "wasm32")]
+            false => self.waited += wait,
+        }
+    }
+
+    // Next number of the sequence, in [0, 1)
+    pub fn random(&mut self) -> f64 {
+        // SplitMix64
+        let state = self
+            .random_state
+            .unwrap_or_else(|| (self.time_source)().to_bits())
+            .wrapping_add(0x9e37_79b9_7f4a_7c15);
+        self.random_state = Some(state);
+        let mut z = state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^= z >> 31;
+        // The top 53 bits fill a double's mantissa exactly
+        (z >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    // What the scripts run so far did
+    pub fn stats(&self) -> Stats {
+        Stats {
+            statements: self.steps,
+            ..self.stats.clone()
+        }
+    }
+
+    // Every Lox call also nests on the host's stack, so only a thread with a bigger stack
+    // than the default leaves room for a higher limit
+    pub fn set_max_call_depth(&mut self, depth: usize) {
+        self.max_call_depth = depth;
+    }
+
+    pub fn max_call_depth(&self) -> usize {
+        self.max_call_depth
+    }
+
+    pub fn set_optimize(&mut self, enabled: bool) {
+        self.optimize = enabled;
+    }
+
+    pub fn optimizes(&self) -> bool {
+        self.optimize
+    }
+
+    // Parsing, resolving and running all recurse once per level, so hosts parsing
+    // untrusted input on a small stack may need less than the default
+    pub fn set_max_nesting(&mut self, depth: usize) {
+        self.max_nesting = depth;
+    }
+
+    pub fn max_nesting(&self) -> usize {
+        self.max_nesting
+    }
+
+    pub fn set_number_format(&mut self, format: NumberFormat) {
+        self.number_format = format;
+    }
+
+    pub fn set_strict(&mut self, enabled: bool) {
+        self.strict = enabled;
+    }
+
+    pub fn strict(&self) -> bool {
+        self.strict
+    }
+
+    // Off by default, so scripts can only reach the network when the host lets them
+    pub fn set_allow_net(&mut self, allowed: bool) {
+        self.allow_net = allowed;
+    }
+
+    pub fn allow_net(&self) -> bool {
+        self.allow_net
+    }
+
+    // Off by default as well, for files and directories
+    pub fn set_allow_fs(&mut self, allowed: bool) {
+        self.allow_fs = allowed;
+    }
+
+    pub fn allow_fs(&self) -> bool {
+        self.allow_fs
+    }
+
+    // Off by default too, since a handler keeps a signal from ending the process
+    pub fn set_allow_signals(&mut self, allowed: bool) {
+        self.allow_signals = allowed;
+    }
+
+    pub fn allow_signals(&self) -> bool {
+        self.allow_signals
+    }
+
+    // Off by default, since a library loaded with `loadExtension` runs native code. Doesn't
+    // apply to the extensions the host added itself.
+    pub fn set_allow_extensions(&mut self, allowed: bool) {
+        self.allow_extensions = allowed;
+    }
+
+    pub fn allow_extensions(&self) -> bool {
+        self.allow_extensions
+    }
+
+    // Makes an extension compiled into the host available to scripts, which load it with
+    // `loadExtension(name)`. Replaces an earlier one with the same name.
+    pub fn add_extension(&mut self, extension: Rc<dyn Extension>) {
+        self.extensions.add(extension);
+    }
+
+    pub(crate) fn extensions(&mut self) -> &mut Registry {
+        &mut self.extensions
+    }
+
+    // Calls `handler` before the next statement once `flag` is set, e.g. from a signal
+    // handler or another thread, which lets long runs be stopped gracefully. Replaces the
+    // handler if the flag already has one.
+    pub fn on_interrupt(&mut self, flag: Arc<AtomicBool>, handler: LiteralTypes) {
+        match self
+            .interrupts
+            .iter_mut()
+            .find(|(f, _)| Arc::ptr_eq(f, &flag))
+        {
+            Some((_, previous)) => *previous = handler,
+            None => self.interrupts.push((flag, handler)),
+        }
+    }
+
+    // Calls the handlers of the interrupts that have happened since the last check
+    fn check_interrupts(&mut self) -> Result<(), Exit> {
+        let handlers: Vec<LiteralTypes> = self
+            .interrupts
+            .iter()
+            .filter(|(flag, _)| flag.swap(false, Ordering::SeqCst))
+            .map(|(_, handler)| handler.clone())
+            .collect();
+        for handler in handlers {
+            self.call_value(&handler, &[])?;
+        }
+        Ok(())
+    }
+
+    // Local variables are only named for `scopes` while a hook is set, so set it
+    // before running the script it inspects
+    pub fn set_hook(&mut self, hook: Option<Box<dyn Hook>>) {
+        self.hook = hook;
+    }
+
+    // Trees parsed for this interpreter number their nodes from here, since everything
+    // the resolver records is keyed by node id
+    pub fn next_id(&self) -> usize {
+        self.next_id
+    }
+
+    pub fn set_next_id(&mut self, id: usize) {
+        self.next_id = id;
+    }
+
+    // Records a Lox function starting, called from the line of the call being made.
+    // Fails instead once the calls would nest deeper than the limit.
+    pub fn enter_call(&mut self, function: &Token) -> Result<(), Exit> {
+        if self.calls.len() >= self.max_call_depth {
+            return Err(Exit::error_of(
+                ErrorKind::StackOverflow,
+                function,
+                format!("Stack overflow (max call depth {}).", self.max_call_depth),
+            ));
+        }
+        self.calls.push(CallSite {
+            function: function.lexeme.clone(),
+            line: self.call_line,
+            tail_called: std::mem::take(&mut self.tail_called),
+            base: self.stack.len(),
+        });
+        self.stats.max_call_depth = self.stats.max_call_depth.max(self.calls.len());
+        let line = self.call_line;
+        self.with_hook(|hook, interpreter| hook.call(interpreter, function, line))
+    }
+
+    pub fn exit_call(&mut self) {
+        self.calls.pop();
+        let _ = self.with_hook(|hook, interpreter| {
+            hook.returned(interpreter);
+            Ok(())
+        });
+    }
+
+    pub fn call_stack(&self) -> &[CallSite] {
+        &self.calls
+    }
+
+    // Line the call being made comes from
+    pub(crate) fn call_line(&self) -> usize {
+        self.call_line
+    }
+
+    // A tail call starts as if called from the line of the call it replaces, `line`
+    pub(crate) fn start_tail_call(&mut self, line: usize) {
+        self.call_line = line;
+        self.tail_called = true;
+    }
+
+    // Makes a value, e.g. a host object, visible to scripts as a global
+    pub fn define(&mut self, name: &str, value: LiteralTypes) {
+        self.globals
+            .borrow_mut()
+            .define(Symbol::intern(name), value);
+    }
+
+    // Value of a global, if the script or host has defined it
+    pub fn global(&self, name: &str) -> Option<LiteralTypes> {
+        let globals = self.globals.borrow();
+        let &index = globals.names.get(&Symbol::intern(name))?;
+        globals.values[index].clone()
+    }
+
+    // Exposes a Rust function to scripts as a global
+    pub fn define_native(
+        &mut self,
+        name: &str,
+        arity: usize,
+        function: impl Fn(&mut Interpreter, &[LiteralTypes]) -> Result<LiteralTypes, Exit> + 'static,
+    ) {
+        let native = NativeFunction::new(name.to_string(), arity, function);
+        self.define(name, LiteralTypes::Callable(Callable::Native(native)));
+    }
+
+    // Like `define_native`, for a function that can also be given up to `optional` more
+    // arguments, which it gets only when the script passes them
+    pub fn define_native_with_optional(
+        &mut self,
+        name: &str,
+        arity: usize,
+        optional: usize,
+        function: impl Fn(&mut Interpreter, &[LiteralTypes]) -> Result<LiteralTypes, Exit> + 'static,
+    ) {
+        let mut native = NativeFunction::new(name.to_string(), arity, function);
+        native.optional = optional;
+        self.define(name, LiteralTypes::Callable(Callable::Native(native)));
+    }
+
+    // Replaces a global, such as a native like `clock`, with a mock while `f` runs, then puts
+    // back what was there before. Lets tests of embedded scripts fake time or failing IO.
+    pub fn with_mock<R>(
+        &mut self,
+        name: &str,
+        arity: usize,
+        mock: impl Fn(&mut Interpreter, &[LiteralTypes]) -> Result<LiteralTypes, Exit> + 'static,
+        f: impl FnOnce(&mut Interpreter) -> R,
+    ) -> R {
+        let previous = self.global(name);
+        self.define_native(name, arity, mock);
+        let result = f(self);
+        let mut globals = self.globals.borrow_mut();
+        let index = globals.names[&Symbol::intern(name)];
+        globals.values[index] = previous;
+        result
+    }
+
+    // Calls a function, class or native with the arguments, as a script calling it would,
+    // e.g. from a native that takes a callback
+    pub fn call_value(
+        &mut self,
+        callee: &LiteralTypes,
+        arguments: &[LiteralTypes],
+    ) -> Result<LiteralTypes, Exit> {
+        let function: &dyn LoxCallable = match callee {
+            LiteralTypes::Callable(Callable::Function(function)) => function,
+            LiteralTypes::Callable(Callable::Class(class)) => class,
+            LiteralTypes::Callable(Callable::Native(native)) => native,
+            _ => {
+                return Err(Exit::message("Can only call functions and classes.")
+                    .with_kind(ErrorKind::Type))
+            }
+        };
+        function
+            .check_arity(arguments.len())
+            .map_err(|message| Exit::message(message).with_kind(ErrorKind::Arity))?;
+        function.call(self, arguments)
     }
 
     pub fn interpret(&mut self, statements: &[Stmt]) -> Result<(), Exit> {
-        let mut has_error = false;
+        let _errors = crate::ErrorsGuard::set(&self.errors);
+        let result = self.run_top_level(statements, None);
+        self.finish(result)
+    }
+
+    // Runs the statements like `interpret`, adding the value of each expression statement
+    // to `values` as it runs. Async calls are left until every statement has run.
+    pub fn interpret_values(
+        &mut self,
+        statements: &[Stmt],
+        values: &mut Vec<LiteralTypes>,
+    ) -> Result<(), Exit> {
+        let _errors = crate::ErrorsGuard::set(&self.errors);
+        let result = self.run_top_level(statements, Some(values));
+        self.finish(result)
+    }
+
+    // Runs a script's statements, returning `Exit::Halt` if one halted it. Given `values`,
+    // expression statements add their value to it.
+    fn run_top_level(
+        &mut self,
+        statements: &[Stmt],
+        mut values: Option<&mut Vec<LiteralTypes>>,
+    ) -> Result<(), Exit> {
+        // Locals declared in top-level blocks live in a frame of their own
+        let caller = self.push_frame(Rc::new([]));
+        // Execution carries on after an error, the first one is returned
+        let mut result = Ok(());
         for statement in statements.iter() {
-            let s = self.execute(statement);
-            match &s {
-                Ok(_) => (),
-                Err(e) => {
-                    if let Exit::RuntimeError = e {
-                        has_error = true;
+            let executed = match (statement, values.as_deref_mut()) {
+                (Stmt::Expression(expression), Some(values)) => self
+                    .expression_value(expression)
+                    .map(|value| values.push(value)),
+                _ => self.execute(statement),
+            };
+            let error = match executed {
+                Err(Exit::RuntimeError(error)) => error,
+                Err(Exit::Halt) => {
+                    result = Err(Exit::Halt);
+                    break;
+                }
+                _ => continue,
+            };
+            report_error(&error);
+            if result.is_ok() {
+                result = Err(Exit::RuntimeError(error));
+            }
+        }
+        self.pop_frame(caller);
+        result
+    }
+
+    // Once a script has run, runs the async calls it left unless it was halted. Returns the
+    // script's first error, or else the first error of its async calls.
+    fn finish(&mut self, result: Result<(), Exit>) -> Result<(), Exit> {
+        if let Err(Exit::Halt) = result {
+            return Ok(());
+        }
+        match self.run_until_idle() {
+            Err(Exit::RuntimeError(error)) => result.and(Err(Exit::RuntimeError(error))),
+            _ => result,
+        }
+    }
+
+    // Runs the statements like `interpret`, returning the value of the last one when it's an
+    // expression statement
+    pub fn interpret_value(&mut self, statements: &[Stmt]) -> Result<Option<LiteralTypes>, Exit> {
+        let _errors = crate::ErrorsGuard::set(&self.errors);
+        let Some((Stmt::Expression(last), rest)) = statements.split_last() else {
+            return self.interpret(statements).map(|_| None);
+        };
+        let result = self.run_top_level(rest, None);
+        if result.is_err() {
+            return self.finish(result).map(|_| None);
+        }
+        match self.expression_value(last) {
+            Ok(value) => self.finish(Ok(())).map(|_| Some(value)),
+            Err(Exit::RuntimeError(error)) => {
+                report_error(&error);
+                self.finish(Err(Exit::RuntimeError(error))).map(|_| None)
+            }
+            Err(Exit::Halt) => Ok(None),
+            Err(exit) => Err(exit),
+        }
+    }
+
+    // Runs an expression statement, returning the value it discarded
+    fn expression_value(&mut self, statement: &Expression) -> Result<LiteralTypes, Exit> {
+        self.count_step()?;
+        self.run_hook(statement.line)?;
+        self.evaluate(&statement.expression)
+    }
+
+    // The code the script gave `exit`, if it called it
+    pub fn exit_code(&self) -> Option<i32> {
+        self.exit_code
+    }
+
+    // Calls the functions `onExit` registered, the last registered first, as hosts should
+    // once the program ends, whether normally or through `exit`. Every one runs even if
+    // another fails or exits, and the first error is returned.
+    pub fn run_exit_hooks(&mut self) -> Result<(), Exit> {
+        let _errors = crate::ErrorsGuard::set(&self.errors);
+        let mut result = Ok(());
+        while let Some(hook) = self.exit_hooks.pop() {
+            if let Err(Exit::RuntimeError(error)) = self.call_value(&hook, &[]) {
+                report_error(&error);
+                if result.is_ok() {
+                    result = Err(Exit::RuntimeError(error));
+                }
+            }
+        }
+        result
+    }
+
+    // Schedules the body of an async function, returning the promise of what it returns
+    pub(crate) fn schedule(
+        &mut self,
+        function: LoxFunction,
+        arguments: Vec<LiteralTypes>,
+    ) -> LiteralTypes {
+        self.event_loop
+            .schedule(function, arguments, self.call_line)
+    }
+
+    // Runs the bodies of async calls and waits for timers until none are left, as scripts
+    // do once they've run. Errors of calls whose promise nothing awaited are reported then,
+    // and the first error of a timer's callback or else of those is returned.
+    pub fn run_until_idle(&mut self) -> Result<(), Exit> {
+        let _errors = crate::ErrorsGuard::set(&self.errors);
+        while self.run_task()? {}
+        let unhandled = self.event_loop.take_unhandled();
+        for error in unhandled.iter() {
+            report_error(error);
+        }
+        let unhandled = unhandled.into_iter().next().map(Box::new);
+        match self.event_loop.take_failed().or(unhandled) {
+            Some(error) => Err(Exit::RuntimeError(error)),
+            None => Ok(()),
+        }
+    }
+
+    // Schedules a call `setTimeout` or `setInterval` asked for, returning its id
+    fn set_timer(
+        &mut self,
+        arguments: &[LiteralTypes],
+        repeat: bool,
+    ) -> Result<LiteralTypes, Exit> {
+        let callback = match &arguments[0] {
+            LiteralTypes::Callable(Callable::Function(_) | Callable::Native(_)) => {
+                arguments[0].clone()
+            }
+            _ => return Err(Exit::message("Expected a function to call.")),
+        };
+        let seconds = match arguments[1] {
+            LiteralTypes::Number(ms) if ms.is_finite() && ms >= 0.0 => ms / 1000.0,
+            _ => return Err(Exit::message("Expected a delay in milliseconds.")),
+        };
+        // An interval due again right away would keep every other timer from running
+        let interval = repeat.then_some(seconds.max(MIN_INTERVAL));
+        let id = self
+            .event_loop
+            .add_timer(self.now() + seconds, interval, callback);
+        Ok(LiteralTypes::Number(id as f64))
+    }
+
+    // Runs the next scheduled async call, or else waits for the next timer and calls it.
+    // Returns false when there's nothing left to run.
+    fn run_task(&mut self) -> Result<bool, Exit> {
+        let Some(task) = self.event_loop.next_task() else {
+            let Some((due, callback)) = self.event_loop.next_timer() else {
+                return Ok(false);
+            };
+            self.wait_until(due);
+            match self.call_value(&callback, &[]) {
+                Ok(_) => {}
+                Err(Exit::RuntimeError(error)) => {
+                    report_error(&error);
+                    self.event_loop.fail(error);
+                }
+                Err(exit) => return Err(exit),
+            }
+            return Ok(true);
+        };
+        self.call_line = task.line;
+        let state = match task.function.resume(self, &task.arguments) {
+            Ok(value) => State::Fulfilled(value),
+            Err(Exit::RuntimeError(error)) => State::Rejected(error),
+            Err(exit) => return Err(exit),
+        };
+        self.event_loop.settle(&task.promise, state);
+        Ok(true)
+    }
+
+    // Runs async calls until the promise settles, with what it settled with
+    fn await_promise(
+        &mut self,
+        promise: &Settlement,
+        keyword: &Token,
+    ) -> Result<LiteralTypes, Exit> {
+        loop {
+            match &*promise.borrow() {
+                State::Fulfilled(value) => return Ok(value.clone()),
+                State::Rejected(error) => {
+                    self.event_loop.handled(promise);
+                    return Err(Exit::RuntimeError(error.clone()));
+                }
+                State::Pending => {}
+            }
+            if !self.run_task()? {
+                return Err(Exit::error(
+                    keyword,
+                    "Awaited a promise that can never settle.",
+                ));
+            }
+        }
+    }
+
+    fn execute(&mut self, stmt: &Stmt) -> Result<(), Exit> {
+        self.count_step()?;
+        if !matches!(stmt, Stmt::Block(_)) {
+            self.run_hook(stmt.line())?;
+        }
+        stmt.accept(self)
+    }
+
+    fn count_step(&mut self) -> Result<(), Exit> {
+        self.steps += 1;
+        if !self.interrupts.is_empty() {
+            self.check_interrupts()?;
+        }
+        if self.heap.has_finalizing() {
+            let instances = self.heap.take_finalizing();
+            self.finalize(instances)?;
+        }
+        match self.step_limit {
+            Some(limit) if self.steps > limit => Err(Exit::RuntimeError(Box::new(RuntimeError {
+                token: None,
+                message: format!("Step limit of {} exceeded.", limit),
+                kind: ErrorKind::StepLimit,
+                backtrace: Vec::new(),
+            }))),
+            _ => Ok(()),
+        }
+    }
+
+    // Tells the hook a variable was given a value other than by a declaration or assignment,
+    // like a parameter by a call
+    pub(crate) fn written(&mut self, name: &Token, value: &LiteralTypes) -> Result<(), Exit> {
+        self.with_hook(|hook, interpreter| hook.write(interpreter, name, value))
+    }
+
+    fn run_hook(&mut self, line: usize) -> Result<(), Exit> {
+        self.with_hook(|hook, interpreter| hook.statement(interpreter, line))
+    }
+
+    fn with_hook(
+        &mut self,
+        f: impl FnOnce(&mut dyn Hook, &mut Interpreter) -> Result<(), Exit>,
+    ) -> Result<(), Exit> {
+        let Some(mut hook) = self.hook.take() else {
+            return Ok(());
+        };
+        let result = f(hook.as_mut(), self);
+        // The hook may have replaced itself
+        if self.hook.is_none() {
+            self.hook = Some(hook);
+        }
+        result
+    }
+
+    // Variables in scope where execution is, innermost first: the running call's, then
+    // every caller's, then the script's top-level blocks' and lastly the globals.
+    // Locals of blocks that have ended are included until their slot is reused.
+    pub fn scopes(&self) -> Vec<Scope> {
+        let mut scopes = Vec::new();
+        let mut end = self.stack.len();
+        let frames = self
+            .calls
+            .iter()
+            .rev()
+            .map(|call| (call.function.to_string(), call.base))
+            .chain([("script".to_string(), 0)]);
+        for (name, base) in frames {
+            let variables = (base..end.max(base))
+                .filter_map(|index| {
+                    let name = self.slot_names.get(index)?.clone()?;
+                    Some((name, self.stack[index].get()))
+                })
+                .collect();
+            scopes.push(Scope { name, variables });
+            end = base;
+        }
+
+        let globals = self.globals.borrow();
+        let mut variables: Vec<(Symbol, LiteralTypes)> = globals
+            .names
+            .iter()
+            .filter_map(|(name, &index)| Some((name.clone(), globals.values[index].clone()?)))
+            .collect();
+        variables.sort_by(|(l, _), (r, _)| l.as_str().cmp(r.as_str()));
+        scopes.push(Scope {
+            name: "globals".to_string(),
+            variables,
+        });
+        scopes
+    }
+
+    // Bindings are indexed by the id of the expression accessing the variable
+    pub fn resolve(&mut self, uuid: usize, binding: Binding) {
+        if uuid >= self.locals.len() {
+            self.locals.resize(uuid + 1, None);
+        }
+        self.locals[uuid] = Some(binding);
+    }
+
+    // Gives a name that isn't local anywhere its index among the globals. The index is
+    // reserved even if the global isn't defined yet, so it can be defined later.
+    pub fn resolve_global(&mut self, uuid: usize, name: &Symbol) {
+        let index = self.globals.borrow_mut().index(name);
+        self.resolve(uuid, Binding::Global(index));
+    }
+
+    pub fn resolve_receiver(&mut self, uuid: usize, binding: Binding) {
+        self.receivers.insert(uuid, binding);
+    }
+
+    pub fn resolve_declaration(&mut self, uuid: usize, slot: usize) {
+        self.declarations.insert(uuid, slot);
+    }
+
+    pub fn resolve_captures(&mut self, uuid: usize, captures: Vec<Binding>) {
+        self.captures.insert(uuid, captures.into());
+    }
+
+    // What the resolver recorded for the nodes numbered in `ids`, for storing a resolved script
+    pub(crate) fn resolution(&self, ids: Range<usize>) -> Resolution {
+        let globals = self.globals.borrow();
+        let mut names = vec![None; globals.values.len()];
+        for (name, &index) in globals.names.iter() {
+            names[index] = Some(name.clone());
+        }
+        let place = |binding: &Binding| match *binding {
+            Binding::Local(slot) => Place::Local(slot),
+            Binding::Upvalue(index) => Place::Upvalue(index),
+            Binding::Global(index) => {
+                Place::Global(names[index].clone().expect("every global index has a name"))
+            }
+        };
+
+        let locals = ids
+            .clone()
+            .filter_map(|uuid| Some((uuid, place(self.locals.get(uuid)?.as_ref()?))))
+            .collect();
+        let mut receivers: Vec<(usize, Place)> = self
+            .receivers
+            .iter()
+            .filter(|(uuid, _)| ids.contains(uuid))
+            .map(|(&uuid, binding)| (uuid, place(binding)))
+            .collect();
+        let mut declarations: Vec<(usize, usize)> = self
+            .declarations
+            .iter()
+            .filter(|(uuid, _)| ids.contains(uuid))
+            .map(|(&uuid, &slot)| (uuid, slot))
+            .collect();
+        let mut captures: Vec<(usize, Vec<Place>)> = self
+            .captures
+            .iter()
+            .filter(|(uuid, _)| ids.contains(uuid))
+            .map(|(&uuid, bindings)| (uuid, bindings.iter().map(place).collect()))
+            .collect();
+        // In id order, so compiling the same script always gives the same file
+        receivers.sort_by_key(|(uuid, _)| *uuid);
+        declarations.sort_by_key(|(uuid, _)| *uuid);
+        captures.sort_by_key(|(uuid, _)| *uuid);
+        Resolution {
+            locals,
+            receivers,
+            declarations,
+            captures,
+        }
+    }
+
+    // Takes back what `resolution` recorded, instead of resolving the script again
+    pub(crate) fn restore(&mut self, resolution: Resolution) {
+        for (uuid, place) in resolution.locals {
+            let binding = self.bind(place);
+            self.resolve(uuid, binding);
+        }
+        for (uuid, place) in resolution.receivers {
+            let binding = self.bind(place);
+            self.resolve_receiver(uuid, binding);
+        }
+        for (uuid, slot) in resolution.declarations {
+            self.resolve_declaration(uuid, slot);
+        }
+        for (uuid, places) in resolution.captures {
+            let bindings = places.into_iter().map(|place| self.bind(place)).collect();
+            self.resolve_captures(uuid, bindings);
+        }
+    }
+
+    fn bind(&mut self, place: Place) -> Binding {
+        match place {
+            Place::Local(slot) => Binding::Local(slot),
+            Place::Upvalue(index) => Binding::Upvalue(index),
+            Place::Global(name) => Binding::Global(self.globals.borrow_mut().index(&name)),
+        }
+    }
+
+    // Starts a call frame above the caller's locals, returning the caller's frame
+    pub fn push_frame(&mut self, upvalues: Rc<[Upvalue]>) -> Frame {
+        let frame = Frame {
+            base: self.stack.len(),
+            upvalues,
+        };
+        std::mem::replace(&mut self.frame, frame)
+    }
+
+    pub fn pop_frame(&mut self, caller: Frame) {
+        self.stack.truncate(self.frame.base);
+        self.slot_names.truncate(self.frame.base);
+        self.frame = caller;
+    }
+
+    // Gives a slot of the current frame a new variable, leaving closures over the old one intact
+    pub fn define_local(&mut self, slot: usize, value: LiteralTypes) {
+        let index = self.frame.base + slot;
+        if index >= self.stack.len() {
+            self.stack.resize(index + 1, Slot::Value(LiteralTypes::Nil));
+        }
+        self.stack[index] = Slot::Value(value);
+    }
+
+    // Records which variable a slot of the current frame holds, for `scopes` to show
+    pub fn name_local(&mut self, slot: usize, name: &Symbol) {
+        if self.hook.is_none() {
+            return;
+        }
+        let index = self.frame.base + slot;
+        if index >= self.slot_names.len() {
+            self.slot_names.resize(index + 1, None);
+        }
+        self.slot_names[index] = Some(name.clone());
+    }
+
+    // Globals can be undefined, so they're read and written through `globals` instead
+    fn read(&self, binding: Binding) -> LiteralTypes {
+        match binding {
+            Binding::Local(slot) => self.stack[self.frame.base + slot].get(),
+            Binding::Upvalue(index) => self.frame.upvalues[index].borrow().clone(),
+            Binding::Global(_) => unreachable!("globals aren't read through bindings"),
+        }
+    }
+
+    fn write(&mut self, binding: Binding, value: LiteralTypes) {
+        match binding {
+            Binding::Local(slot) => self.stack[self.frame.base + slot].set(value),
+            Binding::Upvalue(index) => *self.frame.upvalues[index].borrow_mut() = value,
+            Binding::Global(_) => unreachable!("globals aren't written through bindings"),
+        }
+    }
+
+    // Moves a local into an upvalue the first time a closure captures it
+    fn capture(&mut self, binding: Binding) -> Upvalue {
+        match binding {
+            Binding::Local(slot) => {
+                let slot = &mut self.stack[self.frame.base + slot];
+                if let Slot::Value(value) = slot {
+                    let upvalue =
+                        Rc::new(RefCell::new(std::mem::replace(value, LiteralTypes::Nil)));
+                    self.heap.track_upvalue(&upvalue);
+                    self.stats.allocations += 1;
+                    *slot = Slot::Captured(upvalue);
+                }
+                match slot {
+                    Slot::Captured(upvalue) => Rc::clone(upvalue),
+                    Slot::Value(_) => unreachable!(),
+                }
+            }
+            Binding::Upvalue(index) => Rc::clone(&self.frame.upvalues[index]),
+            Binding::Global(_) => unreachable!("globals are never captured"),
+        }
+    }
+
+    fn closure(&mut self, declaration: &Rc<Function>, is_initializer: bool) -> LoxFunction {
+        let captures = self
+            .captures
+            .get(&declaration.uuid)
+            .cloned()
+            .unwrap_or_else(|| Rc::new([]));
+        let upvalues: Vec<Upvalue> = captures.iter().map(|b| self.capture(*b)).collect();
+        if self.heap.should_collect() {
+            self.collect_garbage();
+        }
+        self.stats.allocations += 1;
+        LoxFunction::new(Rc::clone(declaration), upvalues.into(), is_initializer)
+    }
+
+    // Registers a new instance with the cycle collector
+    pub fn track_instance(&mut self, instance: &Rc<RefCell<LoxInstance>>) {
+        self.heap.track_instance(instance);
+        let class = Rc::clone(&instance.borrow().class);
+        if class.find_method(&Symbol::intern("deinit")).is_some() {
+            self.heap.track_finalizable(instance);
+        }
+        self.stats.allocations += 1;
+        if self.heap.should_collect() {
+            self.collect_garbage();
+        }
+    }
+
+    // Everything alive now, whether or not the script can still reach it, so values kept
+    // alive only by cycles or by the host show up too
+    pub fn heap_report(&self) -> HeapReport {
+        let globals = self.globals.borrow();
+        let mut roots: Vec<LiteralTypes> = globals.values.iter().flatten().cloned().collect();
+        let mut upvalues: Vec<Upvalue> = self.frame.upvalues.to_vec();
+        for slot in self.stack.iter() {
+            match slot {
+                Slot::Value(value) => roots.push(value.clone()),
+                Slot::Captured(upvalue) => upvalues.push(Rc::clone(upvalue)),
+            }
+        }
+        let mut report = self.heap.census(roots, upvalues);
+        report.globals = globals.values.iter().flatten().count();
+        report.locals = self.stack.len();
+        report
+    }
+
+    // Calls `deinit` on each instance. Errors are reported but don't stop the script, since
+    // it didn't call the method itself; only `exit` does.
+    fn finalize(&mut self, instances: Vec<Rc<RefCell<LoxInstance>>>) -> Result<(), Exit> {
+        let deinit = Symbol::intern("deinit");
+        for instance in instances {
+            let class = Rc::clone(&instance.borrow().class);
+            let Some(method) = class.find_method(&deinit) else {
+                continue;
+            };
+            let method = LiteralTypes::Callable(Callable::Function(method.bind(instance)));
+            match self.call_value(&method, &[]) {
+                Err(Exit::RuntimeError(error)) => report_error(&error),
+                Err(Exit::Halt) => return Err(Exit::Halt),
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    // Calls `deinit` on every instance that has one and hasn't been collected, as hosts should
+    // once the program ends, after `run_exit_hooks`
+    pub fn run_finalizers(&mut self) {
+        let _errors = crate::ErrorsGuard::set(&self.errors);
+        let instances = self.heap.take_finalizable();
+        let _ = self.finalize(instances);
+    }
+
+    // Frees values that are only kept alive by reference cycles, returning how many were freed
+    pub fn collect_garbage(&mut self) -> usize {
+        let freed = self.heap.collect();
+        trace_event!(freed, "collect garbage");
+        self.stats.collections += 1;
+        self.stats.freed += freed;
+        freed
+    }
+
+    // Runs the edited source of a running script again, for hosts that reload scripts as
+    // they change. Functions and classes are defined again, but global variables that are
+    // already defined keep their values and other top-level statements don't run. Live
+    // instances of a class defined again are moved onto the new class with their fields,
+    // unless its superclass changed.
+    // Fails as `run_with` does. Nothing has changed when the source doesn't parse.
+    pub fn reload(&mut self, source: &str) -> Result<ReloadReport, crate::LoxError> {
+        let _source = crate::SourceGuard::set(source);
+        let statements = crate::parse_for(self, source)?;
+
+        let mut report = ReloadReport::default();
+        let mut previous = HashMap::new();
+        let statements: Vec<Stmt> = statements
+            .into_iter()
+            .filter(|statement| match statement {
+                Stmt::Var(var) => {
+                    let kept = self.global(&var.name.lexeme).is_some();
+                    if !kept {
+                        report.added.push(var.name.lexeme.to_string());
                     }
+                    !kept
+                }
+                Stmt::Function(function) => {
+                    previous.insert(
+                        function.name.lexeme.clone(),
+                        self.global(&function.name.lexeme),
+                    );
+                    true
+                }
+                Stmt::Class(class) => {
+                    previous.insert(class.name.lexeme.clone(), self.global(&class.name.lexeme));
+                    true
+                }
+                _ => false,
+            })
+            .collect();
+        let globals = self.globals.borrow();
+        report.removed = globals
+            .names
+            .iter()
+            .filter(|(name, &index)| {
+                let declaration = matches!(
+                    globals.values[index],
+                    Some(LiteralTypes::Callable(
+                        Callable::Function(_) | Callable::Class(_)
+                    ))
+                );
+                declaration && !previous.contains_key(*name)
+            })
+            .map(|(name, _)| name.to_string())
+            .collect();
+        report.removed.sort();
+        drop(globals);
+
+        crate::execute(self, &statements)?;
+
+        for statement in statements.iter() {
+            let name = match statement {
+                Stmt::Function(function) => &function.name,
+                Stmt::Class(class) => &class.name,
+                _ => continue,
+            };
+            let old = previous.remove(&name.lexeme).flatten();
+            let new = self.global(&name.lexeme);
+            let (Some(old), Some(new)) = (old, new) else {
+                report.added.push(name.lexeme.to_string());
+                continue;
+            };
+            if declaration_source(&old) != declaration_source(&new) {
+                report.changed.push(name.lexeme.to_string());
+            }
+            if let (
+                LiteralTypes::Callable(Callable::Class(old)),
+                LiteralTypes::Callable(Callable::Class(new)),
+            ) = (old, new)
+            {
+                let super_name =
+                    |class: &LoxClass| class.super_class.as_ref().map(|c| c.name.clone());
+                if super_name(&old) == super_name(&new) {
+                    report.migrated += self.migrate(&old, &new);
+                } else {
+                    report.incompatible.push(name.lexeme.to_string());
+                }
+            }
+        }
+        Ok(report)
+    }
+
+    // Moves the live instances of a class onto its new definition, returning how many
+    fn migrate(&mut self, old: &Rc<LoxClass>, new: &Rc<LoxClass>) -> usize {
+        let mut migrated = 0;
+        for instance in self.heap.instances() {
+            let mut instance = instance.borrow_mut();
+            if !Rc::ptr_eq(&instance.class.layout, &old.layout) {
+                continue;
+            }
+            let fields = instance.field_values();
+            instance.class = Rc::clone(new);
+            instance.fields = Vec::new();
+            for (name, value) in fields {
+                let slot = new.add_field(&name);
+                instance.set_field(slot, value);
+            }
+            migrated += 1;
+        }
+        migrated
+    }
+
+    // Defines a declared name in its frame slot, or as a global at the top level
+    fn declare(&mut self, uuid: usize, name: &Token, value: LiteralTypes) {
+        match self.declarations.get(&uuid) {
+            Some(&slot) => {
+                self.define_local(slot, value);
+                self.name_local(slot, &name.lexeme);
+            }
+            None => self.globals.borrow_mut().define(name.lexeme.clone(), value),
+        }
+    }
+
+    fn evaluate(&mut self, expr: &Expr) -> Result<LiteralTypes, Exit> {
+        expr.accept(self)
+    }
+
+    // Applies a binary operator to its evaluated operands, apart from `visit_binary` so that
+    // recursing through nested operands takes little of the stack
+    fn binary(
+        &mut self,
+        expr: &Binary,
+        left: LiteralTypes,
+        right: LiteralTypes,
+    ) -> Result<LiteralTypes, Exit> {
+        // Arithmetic and comparisons on two numbers skip the checks below
+        if let (LiteralTypes::Number(l), LiteralTypes::Number(r)) = (&left, &right) {
+            if let Some(op) = number_op(&expr.operator.ttype) {
+                return Ok(op(*l, *r));
+            }
+        }
+
+        // Usually the result of a function that ended without returning a value
+        let equality = matches!(
+            expr.operator.ttype,
+            TokenType::EqualEqual | TokenType::BangEqual
+        );
+        if self.strict && !equality && (left == LiteralTypes::Nil || right == LiteralTypes::Nil) {
+            return Err(Exit::error_of(
+                ErrorKind::Type,
+                &expr.operator,
+                "Operands can't be nil in strict mode.",
+            ));
+        }
+
+        match &expr.operator.ttype {
+            TokenType::Minus => {
+                if let (LiteralTypes::Number(left_num), LiteralTypes::Number(right_num)) =
+                    (left, right)
+                {
+                    Ok(LiteralTypes::Number(left_num - right_num))
+                } else {
+                    Err(Exit::error_of(
+                        ErrorKind::Type,
+                        &expr.operator,
+                        "Operands must be numbers.",
+                    ))
+                }
+            }
+            TokenType::Slash => {
+                if let (LiteralTypes::Number(left_num), LiteralTypes::Number(right_num)) =
+                    (left, right)
+                {
+                    Ok(LiteralTypes::Number(left_num / right_num))
+                } else {
+                    Err(Exit::error_of(
+                        ErrorKind::Type,
+                        &expr.operator,
+                        "Operands must be numbers.",
+                    ))
                 }
             }
+            TokenType::Star => {
+                if let (LiteralTypes::Number(left_num), LiteralTypes::Number(right_num)) =
+                    (left, right)
+                {
+                    Ok(LiteralTypes::Number(left_num * right_num))
+                } else {
+                    Err(Exit::error_of(
+                        ErrorKind::Type,
+                        &expr.operator,
+                        "Operands must be numbers.",
+                    ))
+                }
+            }
+            TokenType::Plus => match (left, right) {
+                (LiteralTypes::Number(left_num), LiteralTypes::Number(right_num)) => {
+                    Ok(LiteralTypes::Number(left_num + right_num))
+                }
+                (LiteralTypes::String(left_str), LiteralTypes::String(right_str)) => {
+                    Ok(LiteralTypes::String(format!("{}{}", left_str, right_str)))
+                }
+                _ => Err(Exit::error_of(
+                    ErrorKind::Type,
+                    &expr.operator,
+                    "Operands must be two numbers or two strings.",
+                )),
+            },
+            TokenType::Greater => Ok(LiteralTypes::Bool(match (left, right) {
+                (LiteralTypes::Number(left_num), LiteralTypes::Number(right_num)) => {
+                    left_num > right_num
+                }
+                (LiteralTypes::String(left_str), LiteralTypes::String(right_str)) => {
+                    left_str > right_str
+                }
+                _ => false,
+            })),
+            TokenType::GreaterEqual => Ok(LiteralTypes::Bool(match (left, right) {
+                (LiteralTypes::Number(left_num), LiteralTypes::Number(right_num)) => {
+                    left_num >= right_num
+                }
+                (LiteralTypes::String(left_str), LiteralTypes::String(right_str)) => {
+                    left_str >= right_str
+                }
+                _ => false,
+            })),
+            TokenType::Less => Ok(LiteralTypes::Bool(match (left, right) {
+                (LiteralTypes::Number(left_num), LiteralTypes::Number(right_num)) => {
+                    left_num < right_num
+                }
+                (LiteralTypes::String(left_str), LiteralTypes::String(right_str)) => {
+                    left_str < right_str
+                }
+                _ => false,
+            })),
+            TokenType::LessEqual => Ok(LiteralTypes::Bool(match (left, right) {
+                (LiteralTypes::Number(left_num), LiteralTypes::Number(right_num)) => {
+                    left_num <= right_num
+                }
+                (LiteralTypes::String(left_str), LiteralTypes::String(right_str)) => {
+                    left_str <= right_str
+                }
+                _ => false,
+            })),
+            TokenType::BangEqual => Ok(LiteralTypes::Bool(!self.is_equal(&left, &right))),
+            TokenType::EqualEqual => Ok(LiteralTypes::Bool(self.is_equal(&left, &right))),
+            _ => unreachable!(),
         }
-
-        if has_error {
-            Err(Exit::RuntimeError {})
-        } else {
-            Ok(())
-        }
-    }
-
-    fn execute(&mut self, stmt: &Stmt) -> Result<(), Exit> {
-        stmt.accept(self)
-    }
-
-    pub fn resolve(&mut self, expr: &Expr, depth: usize) {
-        self.locals.insert(expr.clone(), depth);
-    }
-
-    fn evaluate(&mut self, expr: &Expr) -> Result<LiteralTypes, Exit> {
-        expr.accept(self)
     }
 
     fn is_truthy(&self, ltype: &LiteralTypes) -> bool {
@@ -75,7 +1731,21 @@ impl Interpreter {
         }
     }
 
-    fn is_equal(&self, left: &LiteralTypes, right: &LiteralTypes) -> bool {
+    // Evaluates the condition of `if` or a loop, which strict mode requires to be a boolean
+    fn condition(&mut self, condition: &Expr, keyword: &Token) -> Result<bool, Exit> {
+        let value = self.evaluate(condition)?;
+        match value {
+            LiteralTypes::Bool(b) => Ok(b),
+            _ if self.strict => Err(Exit::error_of(
+                ErrorKind::Type,
+                keyword,
+                "Condition must be a boolean in strict mode.",
+            )),
+            _ => Ok(self.is_truthy(&value)),
+        }
+    }
+
+    pub(crate) fn is_equal(&self, left: &LiteralTypes, right: &LiteralTypes) -> bool {
         if *left == LiteralTypes::Nil && *right == LiteralTypes::Nil {
             return true;
         } else if *left == LiteralTypes::Nil {
@@ -92,52 +1762,209 @@ impl Interpreter {
             (left, right)
         {
             left_bool == right_bool
+        } else if let (
+            LiteralTypes::Callable(left_callable),
+            LiteralTypes::Callable(right_callable),
+        ) = (left, right)
+        {
+            left_callable == right_callable
         } else {
             false
         }
     }
 
+    // Writes to where `print` does, without adding a newline
+    pub fn write_output(&mut self, text: &str) -> Result<(), Exit> {
+        if self.output.borrow_mut().write_all(text.as_bytes()).is_err() {
+            return Err(Exit::RuntimeError(Box::new(RuntimeError {
+                token: None,
+                message: "Failed to write output.".to_string(),
+                kind: ErrorKind::Other,
+                backtrace: Vec::new(),
+            })));
+        }
+        Ok(())
+    }
+
+    pub fn write_error(&mut self, text: &str) -> Result<(), Exit> {
+        if self.errors.borrow_mut().write_all(text.as_bytes()).is_err() {
+            return Err(Exit::message("Failed to write error output."));
+        }
+        Ok(())
+    }
+
     pub fn stringify(&self, ltype: &LiteralTypes) -> String {
         match ltype {
-            LiteralTypes::Nil => "nil".to_string(),
-            LiteralTypes::Number(num) => {
-                let mut text = num.to_string();
-                if text.ends_with(".0") {
-                    text = text[0..text.len() - 2].to_string();
-                }
-                text
-            }
+            LiteralTypes::Nil | LiteralTypes::Unassigned => "nil".to_string(),
+            LiteralTypes::Number(num) => self.number_format.format(*num),
             LiteralTypes::String(s) => s.to_string(),
             LiteralTypes::Bool(b) => b.to_string(),
             LiteralTypes::Callable(c) => match c {
                 Callable::Instance(ins) => ins.borrow().to_string(),
                 Callable::Function(func) => func.to_string(),
+                Callable::Native(native) => native.to_string(),
+                Callable::Host(host) => format!("{} instance", host.borrow().type_name()),
                 _ => "callable".to_string(),
             },
+            LiteralTypes::List(list) => {
+                let items: Vec<String> = list.borrow().iter().map(|v| self.stringify(v)).collect();
+                format!("[{}]", items.join(", "))
+            }
+            LiteralTypes::Map(map) => {
+                let entries: Vec<String> = map
+                    .borrow()
+                    .iter()
+                    .map(|(k, v)| format!("{}: {}", k, self.stringify(v)))
+                    .collect();
+                format!("{{{}}}", entries.join(", "))
+            }
+        }
+    }
+
+    pub fn execute_block(&mut self, statements: &[Stmt]) -> Result<(), Exit> {
+        statements.iter().try_for_each(|stat| self.execute(stat))
+    }
+
+    fn get_property(&mut self, object: LiteralTypes, expr: &Get) -> Result<LiteralTypes, Exit> {
+        trace_event!(property = %expr.name.lexeme, line = expr.name.line, "get");
+        match object {
+            LiteralTypes::Callable(Callable::Instance(ins)) => {
+                self.get_instance_property(&ins, expr.uuid, &expr.name)
+            }
+            LiteralTypes::Map(map) => match map.borrow().get(expr.name.lexeme.as_str()) {
+                Some(value) => Ok(value.clone()),
+                None => Err(Exit::error_of(
+                    ErrorKind::UndefinedProperty,
+                    &expr.name,
+                    format!("Undefined key {}.", expr.name.lexeme),
+                )),
+            },
+            LiteralTypes::Callable(Callable::Host(host)) => {
+                match host.borrow().get(&expr.name.lexeme) {
+                    Some(value) => Ok(value),
+                    None => Err(Exit::error_of(
+                        ErrorKind::UndefinedProperty,
+                        &expr.name,
+                        format!("Undefined property {}.", expr.name.lexeme),
+                    )),
+                }
+            }
+            _ => Err(Exit::error_of(
+                ErrorKind::Type,
+                &expr.name,
+                "Only instances have properties.",
+            )),
+        }
+    }
+
+    fn property_cache(&mut self, uuid: usize, class: &LoxClass, name: &Symbol) -> &PropertyCache {
+        if uuid >= self.property_caches.len() {
+            self.property_caches.resize_with(uuid + 1, || None);
+        }
+        let cache = &mut self.property_caches[uuid];
+        if !cache.as_ref().is_some_and(|cache| cache.matches(class)) {
+            *cache = Some(PropertyCache::new(class, name));
         }
+        cache.as_ref().unwrap()
     }
 
-    pub fn execute_block(
+    // Fields shadow methods, which are bound to the instance
+    fn get_instance_property(
         &mut self,
-        statements: &[Stmt],
-        environment: Environment,
-    ) -> Result<(), Exit> {
-        let previous = Rc::clone(&self.environment);
-        self.environment = Rc::new(RefCell::new(environment));
+        instance: &Rc<RefCell<LoxInstance>>,
+        uuid: usize,
+        name: &Token,
+    ) -> Result<LiteralTypes, Exit> {
+        let this = instance.borrow();
+        let cache = self.property_cache(uuid, &this.class, &name.lexeme);
+        if let Some(value) = cache.slot.and_then(|slot| this.field(slot)) {
+            return Ok(value.clone());
+        }
+        match &cache.method {
+            Some(method) => Ok(LiteralTypes::Callable(Callable::Function(
+                method.bind(Rc::clone(instance)),
+            ))),
+            None => Err(Exit::error_of(
+                ErrorKind::UndefinedProperty,
+                name,
+                format!("Undefined property {}.", name.lexeme),
+            )),
+        }
+    }
 
-        let result = statements.iter().try_for_each(|stat| self.execute(stat));
+    fn call_host_method(
+        &mut self,
+        host: &Rc<RefCell<dyn HostObject>>,
+        expr: &Get,
+        arguments: &[Expr],
+    ) -> Result<LiteralTypes, Exit> {
+        let mut values = Vec::new();
+        for argument in arguments.iter() {
+            values.push(self.evaluate(argument)?);
+        }
 
-        self.environment = previous;
-        result
+        trace_span!("call", method = %expr.name.lexeme, line = expr.name.line);
+        let result = host.borrow_mut().call_method(&expr.name.lexeme, &values);
+        result.map_err(|message| Exit::error(&expr.name, message))
     }
 
-    fn look_up_variable(&self, name: Token, expr: Expr) -> Result<LiteralTypes, Exit> {
-        let distance = self.locals.get(&expr);
-        if let Some(d) = distance {
-            self.environment.borrow_mut().get_at(*d, name)
-        } else {
-            self.globals.borrow().get(&name)
+    fn binding(&self, uuid: usize) -> Option<Binding> {
+        self.locals.get(uuid).copied().flatten()
+    }
+
+    fn look_up_variable(&self, name: &Token, uuid: usize) -> Result<LiteralTypes, Exit> {
+        let value = match self.binding(uuid) {
+            Some(Binding::Global(index)) => self.globals.borrow().get_at(index, name)?,
+            Some(binding) => self.read(binding),
+            None => self.globals.borrow().get(name)?,
+        };
+        if let LiteralTypes::Unassigned = value {
+            return Err(Exit::error_of(
+                ErrorKind::UndefinedVariable,
+                name,
+                format!("Variable '{}' is used before being assigned.", name.lexeme),
+            ));
         }
+        Ok(value)
+    }
+}
+
+type NumberOp = fn(f64, f64) -> LiteralTypes;
+
+// `SystemTime::now` panics on wasm32-unknown-unknown, where the browser's clock is used
+#[cfg(not(target_arch = "wasm32"))]
+fn system_time() -> f64 {
+    use std::time::{SystemTime, UNIX_EPOCH};
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0.0, |elapsed| elapsed.as_secs_f64())
+}
+
+#[cfg(target_arch = "wasm32")]
+fn system_time() -> f64 {
+    js_sys::Date::now() / 1000.0
+}
+
+fn number_op(operator: &TokenType) -> Option<NumberOp> {
+    let op: NumberOp = match operator {
+        TokenType::Minus => |l, r| LiteralTypes::Number(l - r),
+        TokenType::Slash => |l, r| LiteralTypes::Number(l / r),
+        TokenType::Star => |l, r| LiteralTypes::Number(l * r),
+        TokenType::Plus => |l, r| LiteralTypes::Number(l + r),
+        TokenType::Greater => |l, r| LiteralTypes::Bool(l > r),
+        TokenType::GreaterEqual => |l, r| LiteralTypes::Bool(l >= r),
+        TokenType::Less => |l, r| LiteralTypes::Bool(l < r),
+        TokenType::LessEqual => |l, r| LiteralTypes::Bool(l <= r),
+        TokenType::EqualEqual => |l, r| LiteralTypes::Bool(l == r),
+        TokenType::BangEqual => |l, r| LiteralTypes::Bool(l != r),
+        _ => return None,
+    };
+    Some(op)
+}
+
+impl Default for Interpreter {
+    fn default() -> Self {
+        Self::new()
     }
 }
 
@@ -149,37 +1976,26 @@ impl stmt::Visitor<Result<(), Exit>> for Interpreter {
 
     fn visit_print(&mut self, stmt: &Print) -> Result<(), Exit> {
         let value = self.evaluate(&stmt.expression)?;
-        println!("{}", self.stringify(&value));
-        Ok(())
+        let text = self.stringify(&value);
+        self.write_output(&format!("{}\n", text))
     }
 
     fn visit_var(&mut self, stmt: &Var) -> Result<(), Exit> {
-        let value = if let Expr::Literal(Literal {
-            uuid: _usize,
-            value: LiteralTypes::Nil,
-        }) = *stmt.initializer
-        {
-            LiteralTypes::Nil
-        } else {
-            self.evaluate(&stmt.initializer)?
+        let value = match &stmt.initializer {
+            Some(initializer) => self.evaluate(initializer)?,
+            None => LiteralTypes::Unassigned,
         };
-        self.environment
-            .borrow_mut()
-            .define(stmt.name.lexeme.clone(), value);
+        self.written(&stmt.name, &value)?;
+        self.declare(stmt.uuid, &stmt.name, value);
         Ok(())
     }
 
     fn visit_block(&mut self, stmt: &Block) -> Result<(), Exit> {
-        self.execute_block(
-            &stmt.statements,
-            Environment::new_with_enclosing(self.environment.clone()),
-        )?;
-        Ok(())
+        self.execute_block(&stmt.statements)
     }
 
     fn visit_if(&mut self, stmt: &If) -> Result<(), Exit> {
-        let ltype = self.evaluate(&stmt.condition)?;
-        if self.is_truthy(&ltype) {
+        if self.condition(&stmt.condition, &stmt.keyword)? {
             self.execute(&stmt.then_branch)?;
         } else if let Some(else_branch) = stmt.else_branch.as_ref() {
             self.execute(else_branch)?;
@@ -190,26 +2006,46 @@ impl stmt::Visitor<Result<(), Exit>> for Interpreter {
 
     fn visit_while(&mut self, stmt: &While) -> Result<(), Exit> {
         loop {
-            let ltype = self.evaluate(&stmt.condition)?;
-            if !self.is_truthy(&ltype) {
+            if !self.condition(&stmt.condition, &stmt.keyword)? {
                 break;
             }
-            self.execute(&stmt.body)?;
+            match self.execute(&stmt.body) {
+                Ok(()) | Err(Exit::Continue) => {}
+                Err(Exit::Break) => break,
+                Err(exit) => return Err(exit),
+            }
+            if let Some(increment) = &stmt.increment {
+                self.execute(increment)?;
+            }
         }
 
         Ok(())
     }
 
-    fn visit_function(&mut self, stmt: &Function) -> Result<(), Exit> {
-        let function = LoxFunction::new(stmt.clone(), Rc::clone(&self.environment), false);
-        self.environment.borrow_mut().define(
-            stmt.name.lexeme.clone(),
-            LiteralTypes::Callable(Callable::Function(function)),
-        );
+    fn visit_break(&mut self, _stmt: &Break) -> Result<(), Exit> {
+        Err(Exit::Break)
+    }
+
+    fn visit_continue(&mut self, _stmt: &Continue) -> Result<(), Exit> {
+        Err(Exit::Continue)
+    }
+
+    fn visit_function(&mut self, stmt: &Rc<Function>) -> Result<(), Exit> {
+        // Declared before the closure is created, so a recursive function can capture itself
+        self.declare(stmt.uuid, &stmt.name, LiteralTypes::Nil);
+        let function = LiteralTypes::Callable(Callable::Function(self.closure(stmt, false)));
+        match self.declarations.get(&stmt.uuid) {
+            Some(&slot) => self.write(Binding::Local(slot), function),
+            None => self
+                .globals
+                .borrow_mut()
+                .define(stmt.name.lexeme.clone(), function),
+        }
         Ok(())
     }
 
     fn visit_return(&mut self, stmt: &Return) -> Result<(), Exit> {
+        self.tail_call = matches!(stmt.value.as_ref(), Expr::Call(_));
         let value = self.evaluate(&stmt.value)?;
         Err(Exit::Return(ReturnExit { value }))
     }
@@ -220,48 +2056,39 @@ impl stmt::Visitor<Result<(), Exit>> for Interpreter {
         if let Some(sc) = &stmt.super_class {
             super_class = self.evaluate(sc)?;
             if let LiteralTypes::Callable(Callable::Class(c)) = &super_class {
-                s_c = Some(c.clone());
+                s_c = Some(Rc::clone(c));
             } else {
-                report(stmt.name.line, "Superclass must be a class.");
-                return Err(Exit::RuntimeError);
+                return Err(Exit::error_of(
+                    ErrorKind::Type,
+                    &stmt.name,
+                    "Superclass must be a class.",
+                ));
             }
         }
 
-        self.environment
-            .borrow_mut()
-            .define(stmt.name.lexeme.clone(), LiteralTypes::Nil);
+        self.declare(stmt.uuid, &stmt.name, LiteralTypes::Nil);
 
-        if let Some(Expr::Variable(_)) = &stmt.super_class {
-            self.environment = Rc::new(RefCell::new(Environment::new_with_enclosing(Rc::clone(
-                &self.environment,
-            ))));
-            self.environment
-                .borrow_mut()
-                .define("super".to_string(), super_class);
+        // The resolver gives `super` a slot of its own, keyed by the superclass expression
+        if let Some(Expr::Variable(sc)) = stmt.super_class.as_deref() {
+            if let Some(&slot) = self.declarations.get(&sc.uuid) {
+                self.define_local(slot, super_class);
+            }
         }
 
         let mut methods = HashMap::new();
         for method in stmt.methods.iter() {
             if let Stmt::Function(m) = method {
-                let function = LoxFunction::new(
-                    m.clone(),
-                    Rc::clone(&self.environment),
-                    m.name.lexeme.eq("init"),
-                );
+                let function = self.closure(m, m.name.lexeme == "init");
                 methods.insert(m.name.lexeme.clone(), function);
             }
         }
 
-        let class = LoxClass::new(stmt.name.lexeme.clone(), s_c, methods);
-
-        if let Some(Expr::Variable(_)) = &stmt.super_class {
-            let enclosing = Rc::clone(self.environment.borrow_mut().enclosing.as_ref().unwrap());
-            self.environment = enclosing;
+        let class = LoxClass::new(stmt.name.lexeme.to_string(), s_c, methods);
+        let class = LiteralTypes::Callable(Callable::Class(Rc::new(class)));
+        match self.declarations.get(&stmt.uuid) {
+            Some(&slot) => self.write(Binding::Local(slot), class),
+            None => self.globals.borrow_mut().assign(&stmt.name, class)?,
         }
-
-        self.environment
-            .borrow_mut()
-            .assign(&stmt.name, LiteralTypes::Callable(Callable::Class(class)))?;
         Ok(())
     }
 }
@@ -277,16 +2104,19 @@ impl expr::Visitor<Result<LiteralTypes, Exit>> for Interpreter {
 
     fn visit_assignment(&mut self, expr: &Assignment) -> Result<LiteralTypes, Exit> {
         let value = self.evaluate(&expr.value)?;
-        let distance = self.locals.get(&Expr::Assignment(expr.clone()));
-        if let Some(d) = distance {
-            self.environment
-                .borrow_mut()
-                .assign_at(*d, expr.name.clone(), value.clone());
-        } else {
-            self.globals
+        match self.binding(expr.uuid) {
+            Some(Binding::Global(index)) => {
+                self.globals
+                    .borrow_mut()
+                    .assign_at(index, &expr.name, value.clone())?
+            }
+            Some(binding) => self.write(binding, value.clone()),
+            None => self
+                .globals
                 .borrow_mut()
-                .assign(&expr.name, value.clone())?;
+                .assign(&expr.name, value.clone())?,
         }
+        self.written(&expr.name, &value)?;
         Ok(value)
     }
 
@@ -310,113 +2140,142 @@ impl expr::Visitor<Result<LiteralTypes, Exit>> for Interpreter {
         match &expr.operator.ttype {
             TokenType::Minus => match right {
                 LiteralTypes::Number(num) => Ok(LiteralTypes::Number(-num)),
-                _ => {
-                    report(expr.operator.line, "Operand must be a number.");
-                    Err(Exit::RuntimeError {})
-                }
+                _ => Err(Exit::error_of(
+                    ErrorKind::Type,
+                    &expr.operator,
+                    "Operand must be a number.",
+                )),
+            },
+            TokenType::Bang => match right {
+                LiteralTypes::Bool(b) => Ok(LiteralTypes::Bool(!b)),
+                _ if self.strict => Err(Exit::error_of(
+                    ErrorKind::Type,
+                    &expr.operator,
+                    "Operand must be a boolean in strict mode.",
+                )),
+                _ => Ok(LiteralTypes::Bool(!self.is_truthy(&right))),
+            },
+            // Anything but a promise is already settled
+            TokenType::Await => match event_loop::promise(&right) {
+                Some(promise) => self.await_promise(&promise, &expr.operator),
+                None => Ok(right),
             },
-            TokenType::Bang => Ok(LiteralTypes::Bool(!self.is_truthy(&right))),
             _ => unreachable!(),
         }
     }
 
     fn visit_variable(&mut self, expr: &Variable) -> Result<LiteralTypes, Exit> {
         // self.environment.borrow().get(&expr.name)
-        self.look_up_variable(expr.name.clone(), Expr::Variable(expr.clone()))
+        self.look_up_variable(&expr.name, expr.uuid)
     }
 
     fn visit_call(&mut self, expr: &Call) -> Result<LiteralTypes, Exit> {
-        let callee = self.evaluate(&expr.callee)?;
+        let tail_call = std::mem::take(&mut self.tail_call);
+        // Methods of host objects are dispatched by name instead of being looked up as values
+        let callee = if let Expr::Get(get) = expr.callee.as_ref() {
+            let object = self.evaluate(&get.object)?;
+            if let LiteralTypes::Callable(Callable::Host(host)) = &object {
+                self.stats.calls += 1;
+                return self.call_host_method(host, get, &expr.arguments);
+            }
+            self.get_property(object, get)?
+        } else {
+            self.evaluate(&expr.callee)?
+        };
         let mut arguments = Vec::new();
         for argument in expr.arguments.iter() {
             arguments.push(self.evaluate(argument)?);
         }
 
-        if let LiteralTypes::Callable(Callable::Function(function)) = callee {
-            if arguments.len() != function.arity() {
-                report(
-                    expr.paren.line,
-                    &format!(
-                        "Expected {} arguments but got {}.",
-                        function.arity(),
-                        arguments.len()
-                    ),
-                );
-
-                return Err(Exit::RuntimeError {});
+        let function: &dyn LoxCallable = match &callee {
+            LiteralTypes::Callable(Callable::Function(function)) => function,
+            LiteralTypes::Callable(Callable::Class(class)) => class,
+            LiteralTypes::Callable(Callable::Native(native)) => native,
+            _ => {
+                return Err(Exit::error_of(
+                    ErrorKind::Type,
+                    &expr.paren,
+                    "Can only call functions and classes.",
+                ));
             }
+        };
 
-            function.call(self, &arguments)
-        } else if let LiteralTypes::Callable(Callable::Class(class)) = callee {
-            if arguments.len() != class.arity() {
-                report(
-                    expr.paren.line,
-                    &format!(
-                        "Expected {} arguments but got {}.",
-                        class.arity(),
-                        arguments.len()
-                    ),
-                );
+        if let Err(message) = function.check_arity(arguments.len()) {
+            return Err(Exit::error_of(ErrorKind::Arity, &expr.paren, message));
+        }
 
-                return Err(Exit::RuntimeError {});
+        self.stats.calls += 1;
+        if let (true, LiteralTypes::Callable(Callable::Function(function))) = (tail_call, &callee) {
+            return Err(Exit::TailCall(Box::new(TailCall {
+                function: function.clone(),
+                arguments,
+            })));
+        }
+        self.call_line = expr.paren.line;
+        let result = function.call(self, &arguments);
+        match (result, &callee) {
+            (Err(Exit::RuntimeError(mut error)), LiteralTypes::Callable(Callable::Native(_)))
+                if error.token.is_none() =>
+            {
+                error.token = Some(expr.paren.clone());
+                Err(Exit::RuntimeError(error))
             }
-
-            class.call(self, &arguments)
-        } else {
-            report(expr.paren.line, "Can only call functions and classes.");
-            Err(Exit::RuntimeError {})
+            (result, _) => result,
         }
     }
 
     fn visit_get(&mut self, expr: &Get) -> Result<LiteralTypes, Exit> {
         let object = self.evaluate(&expr.object)?;
-        if let LiteralTypes::Callable(Callable::Instance(ins)) = object {
-            ins.borrow_mut().get(&expr.name)
-        } else {
-            report(expr.name.line, "Only instances have properties.");
-            Err(Exit::RuntimeError)
-        }
+        self.get_property(object, expr)
     }
 
     fn visit_set(&mut self, expr: &Set) -> Result<LiteralTypes, Exit> {
         let object = self.evaluate(&expr.object)?;
-        if let LiteralTypes::Callable(Callable::Instance(ins)) = object {
-            let value = self.evaluate(&expr.value)?;
-            ins.borrow_mut().set(&expr.name, &value);
-            Ok(value)
-        } else {
-            report(expr.name.line, "Only instances have fields.");
-            Err(Exit::RuntimeError)
+        trace_event!(property = %expr.name.lexeme, line = expr.name.line, "set");
+        match object {
+            LiteralTypes::Callable(Callable::Instance(ins)) => {
+                let value = self.evaluate(&expr.value)?;
+                let class = Rc::clone(&ins.borrow().class);
+                match self
+                    .property_cache(expr.uuid, &class, &expr.name.lexeme)
+                    .slot
+                {
+                    Some(slot) => ins.borrow_mut().set_field(slot, value.clone()),
+                    None => ins.borrow_mut().set(&expr.name, &value),
+                }
+                Ok(value)
+            }
+            LiteralTypes::Map(map) => {
+                let value = self.evaluate(&expr.value)?;
+                map.borrow_mut()
+                    .insert(expr.name.lexeme.to_string(), value.clone());
+                Ok(value)
+            }
+            LiteralTypes::Callable(Callable::Host(host)) => {
+                let value = self.evaluate(&expr.value)?;
+                if let Err(message) = host.borrow_mut().set(&expr.name.lexeme, value.clone()) {
+                    return Err(Exit::error(&expr.name, message));
+                }
+                Ok(value)
+            }
+            _ => Err(Exit::error_of(
+                ErrorKind::Type,
+                &expr.name,
+                "Only instances have fields.",
+            )),
         }
     }
 
     fn visit_this(&mut self, expr: &This) -> Result<LiteralTypes, Exit> {
-        self.look_up_variable(expr.keyword.clone(), Expr::This(expr.clone()))
+        self.look_up_variable(&expr.keyword, expr.uuid)
     }
 
     fn visit_super(&mut self, expr: &Super) -> Result<LiteralTypes, Exit> {
-        let distance = self.locals.get(&Expr::Super(expr.clone()));
-        if distance.is_none() {
-            return Err(Exit::RuntimeError);
-        }
-        let super_class = self.environment.borrow().get_at(
-            *distance.unwrap(),
-            Token {
-                ttype: TokenType::Super,
-                lexeme: "super".to_string(),
-                literal: LiteralTypes::Nil,
-                line: expr.method.line,
-            },
-        )?;
-        let object = self.environment.borrow().get_at(
-            distance.unwrap() - 1,
-            Token {
-                ttype: TokenType::This,
-                lexeme: "this".to_string(),
-                literal: LiteralTypes::Nil,
-                line: expr.method.line,
-            },
-        )?;
+        let (super_class, object) = match (self.binding(expr.uuid), self.receivers.get(&expr.uuid))
+        {
+            (Some(class), Some(&receiver)) => (self.read(class), self.read(receiver)),
+            _ => return Err(Exit::error(&expr.keyword, "Can't use 'super' here.")),
+        };
 
         if let LiteralTypes::Callable(Callable::Class(c)) = &super_class {
             let method = c.find_method(&expr.method.lexeme);
@@ -424,107 +2283,65 @@ impl expr::Visitor<Result<LiteralTypes, Exit>> for Interpreter {
                 match method {
                     Some(m) => return Ok(LiteralTypes::Callable(Callable::Function(m.bind(ins)))),
                     None => {
-                        report(
-                            expr.method.line,
-                            &format!("Undefined property {}.", expr.method.lexeme),
-                        );
-                        return Err(Exit::RuntimeError);
+                        return Err(Exit::error_of(
+                            ErrorKind::UndefinedProperty,
+                            &expr.method,
+                            format!("Undefined property {}.", expr.method.lexeme),
+                        ));
                     }
                 }
             }
         }
-        Err(Exit::RuntimeError)
+        Err(Exit::error(&expr.keyword, "Can't use 'super' here."))
     }
 
     fn visit_binary(&mut self, expr: &Binary) -> Result<LiteralTypes, Exit> {
         let left = self.evaluate(&expr.left)?;
         let right = self.evaluate(&expr.right)?;
+        self.binary(expr, left, right)
+    }
+}
 
-        match &expr.operator.ttype {
-            TokenType::Minus => {
-                if let (LiteralTypes::Number(left_num), LiteralTypes::Number(right_num)) =
-                    (left, right)
-                {
-                    Ok(LiteralTypes::Number(left_num - right_num))
-                } else {
-                    report(expr.operator.line, "Operands must be numbers.");
-                    Err(Exit::RuntimeError {})
-                }
-            }
-            TokenType::Slash => {
-                if let (LiteralTypes::Number(left_num), LiteralTypes::Number(right_num)) =
-                    (left, right)
-                {
-                    Ok(LiteralTypes::Number(left_num / right_num))
-                } else {
-                    report(expr.operator.line, "Operands must be numbers.");
-                    Err(Exit::RuntimeError {})
-                }
-            }
-            TokenType::Star => {
-                if let (LiteralTypes::Number(left_num), LiteralTypes::Number(right_num)) =
-                    (left, right)
-                {
-                    Ok(LiteralTypes::Number(left_num * right_num))
-                } else {
-                    report(expr.operator.line, "Operands must be numbers.");
-                    Err(Exit::RuntimeError {})
-                }
-            }
-            TokenType::Plus => match (left, right) {
-                (LiteralTypes::Number(left_num), LiteralTypes::Number(right_num)) => {
-                    Ok(LiteralTypes::Number(left_num + right_num))
-                }
-                (LiteralTypes::String(left_str), LiteralTypes::String(right_str)) => {
-                    Ok(LiteralTypes::String(format!("{}{}", left_str, right_str)))
-                }
-                _ => {
-                    report(
-                        expr.operator.line,
-                        "Operands must be two numbers or two strings.",
-                    );
-                    Err(Exit::RuntimeError {})
-                }
-            },
-            TokenType::Greater => Ok(LiteralTypes::Bool(match (left, right) {
-                (LiteralTypes::Number(left_num), LiteralTypes::Number(right_num)) => {
-                    left_num > right_num
-                }
-                (LiteralTypes::String(left_str), LiteralTypes::String(right_str)) => {
-                    left_str > right_str
-                }
-                _ => false,
-            })),
-            TokenType::GreaterEqual => Ok(LiteralTypes::Bool(match (left, right) {
-                (LiteralTypes::Number(left_num), LiteralTypes::Number(right_num)) => {
-                    left_num >= right_num
-                }
-                (LiteralTypes::String(left_str), LiteralTypes::String(right_str)) => {
-                    left_str >= right_str
-                }
-                _ => false,
-            })),
-            TokenType::Less => Ok(LiteralTypes::Bool(match (left, right) {
-                (LiteralTypes::Number(left_num), LiteralTypes::Number(right_num)) => {
-                    left_num < right_num
-                }
-                (LiteralTypes::String(left_str), LiteralTypes::String(right_str)) => {
-                    left_str < right_str
-                }
-                _ => false,
-            })),
-            TokenType::LessEqual => Ok(LiteralTypes::Bool(match (left, right) {
-                (LiteralTypes::Number(left_num), LiteralTypes::Number(right_num)) => {
-                    left_num <= right_num
-                }
-                (LiteralTypes::String(left_str), LiteralTypes::String(right_str)) => {
-                    left_str <= right_str
-                }
-                _ => false,
-            })),
-            TokenType::BangEqual => Ok(LiteralTypes::Bool(!self.is_equal(&left, &right))),
-            TokenType::EqualEqual => Ok(LiteralTypes::Bool(self.is_equal(&left, &right))),
-            _ => unreachable!(),
+// Source of a function or class's declaration, to tell whether a reload changed it
+fn declaration_source(value: &LiteralTypes) -> Option<String> {
+    let mut printer = AstPrinter::new(Form::Source);
+    match value {
+        LiteralTypes::Callable(Callable::Function(function)) => {
+            Some(printer.print(&[Stmt::Function(Rc::clone(&function.declaration))]))
         }
+        LiteralTypes::Callable(Callable::Class(class)) => {
+            let mut methods: Vec<&LoxFunction> = class.methods.values().collect();
+            methods.sort_by(|l, r| {
+                l.declaration
+                    .name
+                    .lexeme
+                    .as_str()
+                    .cmp(r.declaration.name.lexeme.as_str())
+            });
+            let methods: Vec<Stmt> = methods
+                .into_iter()
+                .map(|method| Stmt::Function(Rc::clone(&method.declaration)))
+                .collect();
+            let super_class = class.super_class.as_ref().map(|c| c.name.as_str());
+            Some(format!("{:?} {}", super_class, printer.print(&methods)))
+        }
+        _ => None,
+    }
+}
+
+pub(crate) fn report_error(error: &RuntimeError) {
+    let mut diagnostic = match &error.token {
+        Some(token) => crate::diagnostic(
+            token.line,
+            token.column,
+            token.lexeme.chars().count(),
+            &error.message,
+        ),
+        None => error.to_string(),
+    };
+    if let Some(backtrace) = error.describe_backtrace() {
+        let gutter = " ".repeat(error.line().to_string().len());
+        diagnostic.push_str(&format!("\n{} = note: {}", gutter, backtrace));
     }
+    crate::emit(diagnostic);
 }
@@ -0,0 +1,502 @@
+/// Jupyter kernel for `rlox kernel connection.json`, so Lox can be run in notebooks. Cells
+/// run one after another in a `Session`, so later cells see what earlier ones defined.
+/// Speaks just enough of ZeroMQ's wire protocol, ZMTP 3.0 over TCP, for the sockets Jupyter
+/// connects to.
+use std::error::Error;
+use std::fs;
+use std::io::{self, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::mpsc;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use hmac::{Hmac, Mac};
+use serde_json::{json, Value};
+use sha2::Sha256;
+
+use crate::bytes;
+use crate::scanner::{ScanErrorKind, Scanner};
+use crate::session::Session;
+use crate::token::{LiteralTypes, TokenType};
+use crate::LoxError;
+
+const PROTOCOL_VERSION: &str = "5.3";
+
+// Separates the routing ids of a message from its signed parts
+const DELIMITER: &[u8] = b"<IDS|MSG>";
+
+// Flags of a ZMTP frame
+const MORE: u8 = 0x01;
+const LONG: u8 = 0x02;
+const COMMAND: u8 = 0x04;
+
+// Largest frame read from a peer, far above any message Jupyter sends
+const MAX_FRAME_SIZE: u64 = 64 << 20;
+
+type Subscribers = Arc<Mutex<Vec<TcpStream>>>;
+
+// Runs the kernel with the ports and key of a connection file written by Jupyter, until the
+// client asks it to shut down
+pub fn serve(connection_file: &str) -> Result<(), Box<dyn Error>> {
+    let text = fs::read_to_string(connection_file)
+        .map_err(|_| format!("Error reading file '{}'", connection_file))?;
+    let connection: Value = serde_json::from_str(&text)?;
+    let key = connection["key"]
+        .as_str()
+        .unwrap_or_default()
+        .as_bytes()
+        .to_vec();
+    if !key.is_empty() && connection["signature_scheme"] != "hmac-sha256" {
+        return Err(format!(
+            "Unsupported signature scheme {}",
+            connection["signature_scheme"]
+        )
+        .into());
+    }
+    let ip = connection["ip"].as_str().unwrap_or("127.0.0.1");
+    let address = |port: &str| format!("{}:{}", ip, connection[port]);
+
+    let (requests, received) = mpsc::channel();
+    for port in ["shell_port", "control_port"] {
+        let requests = requests.clone();
+        listen(&address(port), "ROUTER", move |mut stream| {
+            let Ok(replies) = stream.try_clone() else {
+                return;
+            };
+            while let Ok(frames) = read_message(&mut stream) {
+                let Ok(replies) = replies.try_clone() else {
+                    return;
+                };
+                if requests.send((replies, frames)).is_err() {
+                    return;
+                }
+            }
+        })?;
+    }
+    // Cells can't read input, so nothing is ever sent on stdin
+    listen(&address("stdin_port"), "ROUTER", |mut stream| {
+        while read_message(&mut stream).is_ok() {}
+    })?;
+    // Replies to heartbeats with the same message
+    listen(&address("hb_port"), "REP", |mut stream| {
+        while let Ok(frames) = read_message(&mut stream) {
+            if write_message(&mut stream, &frames).is_err() {
+                return;
+            }
+        }
+    })?;
+    // Every message is published to every subscriber, which all subscribe to everything
+    let subscribers = Subscribers::default();
+    let publish_to = Arc::clone(&subscribers);
+    listen(&address("iopub_port"), "PUB", move |stream| {
+        if let Ok(mut subscribers) = publish_to.lock() {
+            subscribers.push(stream);
+        }
+    })?;
+
+    let mut kernel = Kernel::new(Signer::new(key), subscribers);
+    for (mut stream, frames) in received {
+        let Some(request) = kernel.signer.parse(&frames) else {
+            continue;
+        };
+        if !kernel.handle(&mut stream, &request) {
+            break;
+        }
+    }
+    Ok(())
+}
+
+// Accepts connections on a thread of its own, handing each to `connected` on another thread
+// once the ZMTP handshake is done
+fn listen(
+    address: &str,
+    socket_type: &'static str,
+    connected: impl Fn(TcpStream) + Send + Sync + 'static,
+) -> io::Result<()> {
+    let listener = TcpListener::bind(address)?;
+    let connected = Arc::new(connected);
+    thread::spawn(move || {
+        for mut stream in listener.incoming().flatten() {
+            let connected = Arc::clone(&connected);
+            thread::spawn(move || {
+                if handshake(&mut stream, socket_type).is_ok() {
+                    connected(stream);
+                }
+            });
+        }
+    });
+    Ok(())
+}
+
+// Exchanges greetings and `READY` commands with a peer, without security
+fn handshake(stream: &mut TcpStream, socket_type: &str) -> io::Result<()> {
+    let mut greeting = [0u8; 64];
+    greeting[0] = 0xFF;
+    greeting[9] = 0x7F;
+    greeting[10] = 3;
+    greeting[12..16].copy_from_slice(b"NULL");
+    stream.write_all(&greeting)?;
+
+    let mut peer = [0u8; 64];
+    stream.read_exact(&mut peer)?;
+    if peer[0] != 0xFF || peer[9] != 0x7F || peer[10] < 3 || &peer[12..16] != b"NULL" {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "unsupported peer",
+        ));
+    }
+
+    // The command's name, then its one property
+    let mut ready = b"\x05READY\x0bSocket-Type".to_vec();
+    ready.extend_from_slice(&(socket_type.len() as u32).to_be_bytes());
+    ready.extend_from_slice(socket_type.as_bytes());
+    let mut frame = Vec::new();
+    push_frame(&mut frame, COMMAND, &ready);
+    stream.write_all(&frame)?;
+    // The peer's own `READY`, whose properties don't matter here
+    while read_frame(stream)?.0 & COMMAND == 0 {}
+    Ok(())
+}
+
+fn read_frame(stream: &mut TcpStream) -> io::Result<(u8, Vec<u8>)> {
+    let mut flags = [0u8; 1];
+    stream.read_exact(&mut flags)?;
+    let size = if flags[0] & LONG != 0 {
+        let mut size = [0u8; 8];
+        stream.read_exact(&mut size)?;
+        u64::from_be_bytes(size)
+    } else {
+        let mut size = [0u8; 1];
+        stream.read_exact(&mut size)?;
+        size[0] as u64
+    };
+    if size > MAX_FRAME_SIZE {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "frame too large",
+        ));
+    }
+    // The body grows as it arrives, rather than being allocated from what the peer claims
+    let mut body = Vec::new();
+    stream.take(size).read_to_end(&mut body)?;
+    if body.len() as u64 != size {
+        return Err(io::ErrorKind::UnexpectedEof.into());
+    }
+    Ok((flags[0], body))
+}
+
+// Reads the frames of the next message, skipping commands
+fn read_message(stream: &mut TcpStream) -> io::Result<Vec<Vec<u8>>> {
+    let mut frames = Vec::new();
+    loop {
+        let (flags, body) = read_frame(stream)?;
+        if flags & COMMAND != 0 {
+            continue;
+        }
+        frames.push(body);
+        if flags & MORE == 0 {
+            return Ok(frames);
+        }
+    }
+}
+
+fn push_frame(buffer: &mut Vec<u8>, flags: u8, body: &[u8]) {
+    if body.len() > u8::MAX as usize {
+        buffer.push(flags | LONG);
+        buffer.extend_from_slice(&(body.len() as u64).to_be_bytes());
+    } else {
+        buffer.push(flags);
+        buffer.push(body.len() as u8);
+    }
+    buffer.extend_from_slice(body);
+}
+
+fn write_message(stream: &mut TcpStream, frames: &[Vec<u8>]) -> io::Result<()> {
+    let mut buffer = Vec::new();
+    for (i, frame) in frames.iter().enumerate() {
+        let flags = if i + 1 < frames.len() { MORE } else { 0 };
+        push_frame(&mut buffer, flags, frame);
+    }
+    stream.write_all(&buffer)
+}
+
+// A message from the client
+struct Message {
+    header: Value,
+    content: Value,
+}
+
+// Signs and numbers the messages sent, and checks the signatures of those received
+struct Signer {
+    key: Vec<u8>,
+    id: String,
+    sent: u64,
+}
+
+impl Signer {
+    fn new(key: Vec<u8>) -> Self {
+        let started = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        Signer {
+            key,
+            id: format!("{:032x}", started.as_nanos()),
+            sent: 0,
+        }
+    }
+
+    // HMAC of a message's header, parent header, metadata and content
+    fn sign(&self, parts: &[Vec<u8>]) -> Option<Hmac<Sha256>> {
+        let mut mac = Hmac::<Sha256>::new_from_slice(&self.key).ok()?;
+        for part in parts.iter() {
+            mac.update(part);
+        }
+        Some(mac)
+    }
+
+    // Frames of a message, ignoring any buffers after the content. `None` if the message
+    // isn't one or its signature is wrong.
+    fn parse(&self, frames: &[Vec<u8>]) -> Option<Message> {
+        let delimiter = frames.iter().position(|frame| frame == DELIMITER)?;
+        let signature = frames.get(delimiter + 1)?;
+        let parts = frames.get(delimiter + 2..delimiter + 6)?;
+        if !self.key.is_empty() {
+            let signature = bytes::hex_decode(std::str::from_utf8(signature).ok()?).ok()?;
+            self.sign(parts)?.verify_slice(&signature).ok()?;
+        }
+        Some(Message {
+            header: serde_json::from_slice(&parts[0]).ok()?,
+            content: serde_json::from_slice(&parts[3]).ok()?,
+        })
+    }
+
+    fn frames(&mut self, msg_type: &str, parent: &Value, content: Value) -> Vec<Vec<u8>> {
+        self.sent += 1;
+        let header = json!({
+            "msg_id": format!("{}-{}", self.id, self.sent),
+            "session": self.id,
+            "username": "kernel",
+            "date": now(),
+            "msg_type": msg_type,
+            "version": PROTOCOL_VERSION,
+        });
+        let parts: Vec<Vec<u8>> = [header, parent.clone(), json!({}), content]
+            .iter()
+            .map(|part| part.to_string().into_bytes())
+            .collect();
+        let signature = match self.key.is_empty() {
+            true => String::new(),
+            false => self.sign(&parts).map_or(String::new(), |mac| {
+                bytes::hex_encode(&mac.finalize().into_bytes())
+            }),
+        };
+        let mut frames = vec![DELIMITER.to_vec(), signature.into_bytes()];
+        frames.extend(parts);
+        frames
+    }
+}
+
+// The current time in ISO 8601, as message headers carry it
+fn now() -> String {
+    let elapsed = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+    let seconds = elapsed.as_secs();
+    // Civil date from days since the epoch, with years starting in March
+    let days = (seconds / 86400) as i64 + 719468;
+    let era = days.div_euclid(146097);
+    let day_of_era = days - era * 146097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month + 2) / 5 + 1;
+    let month = if month < 10 { month + 3 } else { month - 9 };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:06}Z",
+        year,
+        month,
+        day,
+        seconds % 86400 / 3600,
+        seconds % 3600 / 60,
+        seconds % 60,
+        elapsed.subsec_micros()
+    )
+}
+
+struct Kernel {
+    signer: Signer,
+    cells: Session,
+    subscribers: Subscribers,
+}
+
+impl Kernel {
+    fn new(signer: Signer, subscribers: Subscribers) -> Self {
+        Kernel {
+            signer,
+            cells: Session::new(),
+            subscribers,
+        }
+    }
+
+    // Sends a message on iopub, dropping subscribers that have gone away
+    fn publish(&mut self, msg_type: &str, parent: &Value, content: Value) {
+        let mut frames = vec![msg_type.as_bytes().to_vec()];
+        frames.extend(self.signer.frames(msg_type, parent, content));
+        if let Ok(mut subscribers) = self.subscribers.lock() {
+            subscribers.retain_mut(|stream| write_message(stream, &frames).is_ok());
+        }
+    }
+
+    // Answers a request from the shell or control socket, returning false once asked to
+    // shut down. Requests it doesn't know are ignored.
+    fn handle(&mut self, stream: &mut TcpStream, request: &Message) -> bool {
+        let parent = &request.header;
+        let msg_type = parent["msg_type"].as_str().unwrap_or_default().to_string();
+        self.publish("status", parent, json!({ "execution_state": "busy" }));
+        let reply = match msg_type.as_str() {
+            "kernel_info_request" => Some(kernel_info()),
+            "execute_request" => Some(self.execute(request)),
+            "is_complete_request" => Some(is_complete(
+                request.content["code"].as_str().unwrap_or_default(),
+            )),
+            "comm_info_request" => Some(json!({ "status": "ok", "comms": {} })),
+            "history_request" => Some(json!({ "status": "ok", "history": [] })),
+            // Cells run on the thread reading requests, so there's never one to interrupt
+            "interrupt_request" => Some(json!({ "status": "ok" })),
+            "shutdown_request" => Some(json!({
+                "status": "ok",
+                "restart": request.content["restart"],
+            })),
+            _ => None,
+        };
+        if let Some(reply) = reply {
+            let reply_type = msg_type.replace("_request", "_reply");
+            let frames = self.signer.frames(&reply_type, parent, reply);
+            // A client that's gone can't be answered, but others may still be there
+            let _ = write_message(stream, &frames);
+        }
+        self.publish("status", parent, json!({ "execution_state": "idle" }));
+        msg_type != "shutdown_request"
+    }
+
+    // Runs a cell, its number being the execution count Jupyter shows
+    fn execute(&mut self, request: &Message) -> Value {
+        let parent = &request.header;
+        let code = request.content["code"].as_str().unwrap_or_default();
+        let silent = request.content["silent"].as_bool().unwrap_or(false);
+        let cell = self.cells.execute_cell(code);
+        let count = cell.id;
+        if !silent {
+            self.publish(
+                "execute_input",
+                parent,
+                json!({ "code": code, "execution_count": count }),
+            );
+            if !cell.output.is_empty() {
+                self.publish(
+                    "stream",
+                    parent,
+                    json!({ "name": "stdout", "text": cell.output }),
+                );
+            }
+        }
+
+        let error = match cell.result {
+            Ok(value) => {
+                // The value of a cell ending in an expression, unless it's nil
+                if let Some(value) = value.filter(|value| !matches!(value, LiteralTypes::Nil)) {
+                    let text = self.cells.interpreter().stringify(&value);
+                    if !silent {
+                        self.publish(
+                            "execute_result",
+                            parent,
+                            json!({
+                                "execution_count": count,
+                                "data": { "text/plain": text },
+                                "metadata": {},
+                            }),
+                        );
+                    }
+                }
+                return json!({
+                    "status": "ok",
+                    "execution_count": count,
+                    "user_expressions": {},
+                    "payload": [],
+                });
+            }
+            Err(error) => error,
+        };
+        let diagnostics = cell.diagnostics;
+
+        let error = json!({
+            "ename": match error {
+                LoxError::Runtime(..) => "RuntimeError",
+                _ => "SyntaxError",
+            },
+            // Message of the first error, without its position
+            "evalue": diagnostics.first().map_or("", |diagnostic| {
+                let line = diagnostic.lines().next().unwrap_or_default();
+                line.split_once("Error: ").map_or(line, |(_, message)| message)
+            }),
+            // Every error as reported, its first line in red
+            "traceback": diagnostics
+                .iter()
+                .flat_map(|diagnostic| {
+                    diagnostic.lines().enumerate().map(|(i, line)| match i {
+                        0 => format!("\x1b[0;31m{}\x1b[0m", line),
+                        _ => line.to_string(),
+                    })
+                })
+                .collect::<Vec<String>>(),
+        });
+        if !silent {
+            self.publish("error", parent, error.clone());
+        }
+        let mut reply = error;
+        reply["status"] = json!("error");
+        reply["execution_count"] = json!(count);
+        reply
+    }
+}
+
+fn kernel_info() -> Value {
+    json!({
+        "status": "ok",
+        "protocol_version": PROTOCOL_VERSION,
+        "implementation": "rlox",
+        "implementation_version": env!("CARGO_PKG_VERSION"),
+        "language_info": {
+            "name": "lox",
+            "version": env!("CARGO_PKG_VERSION"),
+            "mimetype": "text/x-lox",
+            "file_extension": ".lox",
+        },
+        "banner": "rlox, a Lox interpreter",
+        "help_links": [],
+    })
+}
+
+// Code is incomplete while a string, brace or parenthesis is left open, so the frontend
+// lets the user keep typing
+fn is_complete(code: &str) -> Value {
+    let mut depth = 0;
+    for token in Scanner::new(code) {
+        match token {
+            Ok(token) => match token.ttype {
+                TokenType::LeftBrace | TokenType::LeftParen => depth += 1,
+                TokenType::RightBrace | TokenType::RightParen => depth -= 1,
+                _ => {}
+            },
+            Err(error) if error.kind == ScanErrorKind::UnterminatedString => depth += 1,
+            Err(_) => {}
+        }
+    }
+    match depth > 0 {
+        true => json!({ "status": "incomplete", "indent": "  " }),
+        false => json!({ "status": "complete" }),
+    }
+}
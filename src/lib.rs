@@ -1,59 +1,511 @@
-use ::std::{error::Error, fs, io, process};
-use std::io::Write;
-use std::path::Path;
+use ::std::{cell::Cell, cell::RefCell, collections::BTreeSet, env, error::Error, fs, io, rc::Rc};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
 
-use interpreter::Interpreter;
+use ast_printer::{AstPrinter, Form};
+use coverage::{Coverage, CoverageHook};
+use debugger::{Console, Debugger};
+use doc::{DocFormat, ScriptDoc};
+use interpreter::{Exit, Interpreter, RuntimeError};
+use linter::{LintConfig, LintDiagnostic, Linter};
+use lox_callable::Callable;
+use loxc::LoadError;
+use optimizer::Optimizer;
 use parser::Parser;
+use profile::{Profile, ProfileHook};
+use query::Ast;
+use recording::{Recorder, Replay, Trace};
 use resolver::Resolver;
 use scanner::Scanner;
-use token::{Token, TokenType};
+use semantic::{SemanticKind, SemanticToken};
+use stmt::Stmt;
+use template::Template;
+use testing::TestReport;
+use token::{LiteralTypes, Token, TokenType};
 
+#[cfg(feature = "derive")]
+pub use rlox_derive::{lox_methods, HostObject};
+#[cfg(feature = "serde")]
+pub use serde_bridge::{eval_config_to, lox_value_to, to_lox_value};
+
+#[macro_use]
+mod trace;
+
+pub mod ast_printer;
+pub mod bytes;
+#[cfg(feature = "config")]
+pub mod config;
+pub mod coverage;
+#[cfg(feature = "dap")]
+pub mod dap;
+pub mod debugger;
+pub mod doc;
 mod environment;
+mod event_loop;
 mod expr;
-mod interpreter;
-mod lox_callable;
+pub mod extensions;
+#[cfg(feature = "ffi")]
+pub mod ffi;
+pub mod filesystem;
+#[cfg(feature = "fuzz")]
+pub mod fuzz;
+mod gc;
+#[cfg(feature = "hash")]
+pub mod hashing;
+#[cfg(feature = "http")]
+pub mod http;
+pub mod incremental;
+pub mod interpreter;
+#[cfg(feature = "kernel")]
+pub mod kernel;
+pub mod linter;
+pub mod lox_callable;
+pub mod loxc;
+mod numbers;
+mod optimizer;
 mod parser;
+pub mod profile;
+pub mod query;
+pub mod recording;
+mod reflection;
 mod resolver;
-mod scanner;
+pub mod scanner;
+pub mod semantic;
+#[cfg(feature = "serde")]
+pub mod serde_bridge;
+pub mod session;
+mod shared_buffer;
+#[cfg(feature = "signals")]
+pub mod signals;
 mod stmt;
-mod token;
+mod string_builder;
+pub mod symbol;
+mod template;
+pub mod testing;
+pub mod threads;
+pub mod token;
+#[cfg(feature = "wasm")]
+pub mod wasm;
+mod weak;
+
+thread_local! {
+    // When set, reported errors are collected here instead of being printed to stderr
+    static DIAGNOSTICS: RefCell<Option<Vec<String>>> = const { RefCell::new(None) };
+    // Script being run, so errors can show the line they point at
+    static SOURCE: RefCell<Option<Rc<str>>> = const { RefCell::new(None) };
+    // Set while errors already reported are reported again, e.g. by a rewinding debugger
+    static MUTED: Cell<bool> = const { Cell::new(false) };
+    // Error output of the interpreter running, where errors go when they aren't collected
+    static ERRORS: RefCell<Option<Sink>> = const { RefCell::new(None) };
+}
+
+// Writer an interpreter shares with whatever else writes where it does
+pub(crate) type Sink = Rc<RefCell<Box<dyn Write>>>;
+
+// Sends reported errors to an interpreter's error output until dropped
+pub(crate) struct ErrorsGuard {
+    previous: Option<Sink>,
+}
+
+impl ErrorsGuard {
+    pub(crate) fn set(output: &Sink) -> Self {
+        let previous = ERRORS.with(|current| current.replace(Some(Rc::clone(output))));
+        ErrorsGuard { previous }
+    }
+}
+
+impl Drop for ErrorsGuard {
+    fn drop(&mut self) {
+        ERRORS.with(|current| current.replace(self.previous.take()));
+    }
+}
 
-// Error display with exit
-pub fn handle_error(err: String) {
-    eprintln!("{}", err);
-    process::exit(1);
+// Makes the source available to diagnostics until dropped
+struct SourceGuard {
+    previous: Option<Rc<str>>,
+}
+
+impl SourceGuard {
+    fn set(source: &str) -> Self {
+        let previous = SOURCE.with(|current| current.replace(Some(source.into())));
+        SourceGuard { previous }
+    }
+}
+
+impl Drop for SourceGuard {
+    fn drop(&mut self) {
+        SOURCE.with(|current| current.replace(self.previous.take()));
+    }
 }
 
 // For handling language errors
 pub fn report(line: usize, message: &str) {
-    let err = format!("[Line {}] Error: {}", line, message);
-    eprintln!("{}", err);
+    emit(format!("[Line {}] Error: {}", line, message));
+}
+
+// Reports an error spanning `width` characters, underlined in the source when it's known
+pub fn report_at(line: usize, column: usize, width: usize, message: &str) {
+    emit(diagnostic(line, column, width, message));
+}
+
+fn diagnostic(line: usize, column: usize, width: usize, message: &str) -> String {
+    let mut diagnostic = format!("[Line {}, Column {}] Error: {}", line, column, message);
+    if let Some(snippet) = snippet(line, column, width) {
+        diagnostic.push('\n');
+        diagnostic.push_str(&snippet);
+    }
+    diagnostic
+}
+
+//   2 |     print x +;
+//     |              ^
+fn snippet(line: usize, column: usize, width: usize) -> Option<String> {
+    let text = SOURCE.with(|source| {
+        let source = source.borrow();
+        Some(
+            source
+                .as_ref()?
+                .lines()
+                .nth(line.checked_sub(1)?)?
+                .to_string(),
+        )
+    })?;
+    // Tabs are kept so the underline lines up however they're displayed
+    let indent: String = text
+        .chars()
+        .take(column.saturating_sub(1))
+        .map(|c| if c == '\t' { '\t' } else { ' ' })
+        .collect();
+    let gutter = " ".repeat(line.to_string().len());
+    Some(format!(
+        "{} |\n{} | {}\n{} | {}{}",
+        gutter,
+        line,
+        text,
+        gutter,
+        indent,
+        "^".repeat(width.max(1))
+    ))
+}
+
+pub fn warn(line: usize, message: &str) {
+    emit(format!("[Line {}] Warning: {}", line, message));
+}
+
+fn emit(diagnostic: String) {
+    if MUTED.get() {
+        return;
+    }
+    DIAGNOSTICS.with(|diagnostics| match diagnostics.borrow_mut().as_mut() {
+        Some(collected) => collected.push(diagnostic),
+        None => match ERRORS.with(|errors| errors.borrow().clone()) {
+            Some(errors) => {
+                let _ = writeln!(errors.borrow_mut(), "{}", diagnostic);
+            }
+            None => eprintln!("{}", diagnostic),
+        },
+    });
 }
 
 pub fn error(token: Token, message: &str) {
     if token.ttype == TokenType::Eof {
-        report(token.line, &("at end ".to_owned() + message));
+        report_at(
+            token.line,
+            token.column,
+            1,
+            &("at end ".to_owned() + message),
+        );
     } else {
-        report(
+        report_at(
             token.line,
+            token.column,
+            token.lexeme.chars().count(),
             &("at '".to_owned() + &token.lexeme + "'. " + message),
         );
     }
 }
 
-// Called when no argument is provided
-pub fn run_prompt() {
-    loop {
-        print!(">> ");
+// Drops reported errors while `muted` is set
+pub(crate) fn mute_diagnostics(muted: bool) {
+    MUTED.set(muted);
+}
+
+// Runs `f` while collecting every reported error instead of printing it
+pub fn capture_diagnostics<T>(f: impl FnOnce() -> T) -> (T, Vec<String>) {
+    let previous = DIAGNOSTICS.with(|diagnostics| diagnostics.replace(Some(Vec::new())));
+    let result = f();
+    let collected = DIAGNOSTICS.with(|diagnostics| diagnostics.replace(previous));
+    (result, collected.unwrap_or_default())
+}
+
+// Called when no argument is provided. Lines run one after another in the same session,
+// after the startup script if there is one, until one calls `exit` or the input ends.
+pub fn run_prompt(strict: bool) -> Result<Outcome, Box<dyn Error>> {
+    let mut interpreter = cli_interpreter(strict, None)?;
+    if let Some(path) = startup_script() {
+        match fs::read_to_string(&path) {
+            Ok(content) => {
+                let _ = run_with(&mut interpreter, &content);
+            }
+            Err(_) => eprintln!("Error reading file '{}'", path.display()),
+        }
+    }
+
+    // Lines typed so far of a statement that continues on the next one
+    let mut source = String::new();
+    while interpreter.exit_code().is_none() {
+        print!("{}", if source.is_empty() { ">> " } else { ".. " });
         let mut line = String::new();
         let _ = io::stdout().flush();
-        io::stdin().read_line(&mut line).unwrap();
-        run(&line);
+        if io::stdin().read_line(&mut line).unwrap_or(0) == 0 {
+            break;
+        }
+        if source.is_empty() && line.trim().to_lowercase() == "exit" {
+            break;
+        }
+        // A blank line runs what's been typed even if it's still open
+        if source.is_empty() || !line.trim().is_empty() {
+            source.push_str(&line);
+            if is_open(&source) {
+                continue;
+            }
+        }
+        let line = with_semicolon(std::mem::take(&mut source));
+        match run_for_value(&mut interpreter, &line) {
+            Ok(Some(value)) if value != LiteralTypes::Nil => {
+                let text = interpreter.stringify(&value);
+                let _ = interpreter.write_output(&format!("{}\n", text));
+            }
+            _ => {}
+        }
+    }
+    Ok(outcome(&mut interpreter, Ok(())))
+}
+
+// A line that's a bare expression prints its value, unless it's nil, and can leave out its
+// `;`, which goes after its last token so a comment ending the line doesn't swallow it
+fn with_semicolon(mut line: String) -> String {
+    if is_open(&line) {
+        return line;
     }
+    let last = Scanner::new(&line)
+        .filter_map(Result::ok)
+        .filter(|token| !matches!(token.ttype, TokenType::Eof | TokenType::DocComment))
+        .last();
+    match last {
+        Some(token) if !matches!(token.ttype, TokenType::Semicolon | TokenType::RightBrace) => {
+            line.insert(token.end, ';');
+        }
+        _ => {}
+    }
+    line
+}
+
+// Whether the source opens more braces or parentheses than it closes, so what's typed at the
+// prompt continues on the next line
+fn is_open(source: &str) -> bool {
+    let depth =
+        Scanner::new(source)
+            .filter_map(Result::ok)
+            .fold(0isize, |depth, token| match token.ttype {
+                TokenType::LeftBrace | TokenType::LeftParen => depth + 1,
+                TokenType::RightBrace | TokenType::RightParen => depth - 1,
+                _ => depth,
+            });
+    depth > 0
+}
+
+// How a command of the CLI went, which `main` turns into the exit code
+#[derive(Debug)]
+pub enum Outcome {
+    Done,
+    // The script ended itself with `exit(code)`
+    Exited(i32),
+    Failed(LoxError),
+    // Tests failed, or the linter found something
+    Flagged,
+}
+
+// Runs the script's `onExit` functions and the `deinit` methods of the instances still alive
+// once it has ended, then tells how it went: with the code the script gave `exit`, or else
+// how it ran, where a failing `onExit` function counts as a runtime error
+fn outcome(interpreter: &mut Interpreter, result: Result<(), LoxError>) -> Outcome {
+    let hooks = ran(interpreter.run_exit_hooks());
+    interpreter.run_finalizers();
+    match (interpreter.exit_code(), result.and(hooks)) {
+        (Some(code), _) => Outcome::Exited(code),
+        (None, Err(error)) => Outcome::Failed(error),
+        (None, Ok(())) => Outcome::Done,
+    }
+}
+
+// The CLI runs scripts on a 64 MB stack, see `main`, with room for much deeper recursion
+// than `DEFAULT_MAX_CALL_DEPTH`, which suits the stack of any thread
+const CLI_MAX_CALL_DEPTH: usize = 1000;
+
+// Interpreter for the CLI writing to stdout, set up by the flags given and then by the
+// environment: `RLOX_STRICT`, `RLOX_SEED`, `RLOX_MAX_DEPTH` (calls), `RLOX_MAX_NESTING`,
+// `RLOX_STEP_LIMIT`, `RLOX_OPTIMIZE`, `RLOX_ALLOW_NET`, `RLOX_ALLOW_FS`,
+// `RLOX_ALLOW_SIGNALS` and `RLOX_ALLOW_EXTENSIONS`. Flags win over the environment.
+fn cli_interpreter(strict: bool, seed: Option<u64>) -> Result<Interpreter, Box<dyn Error>> {
+    let mut interpreter = Interpreter::with_output(Box::new(io::stdout()));
+    interpreter.set_strict(strict || env_flag("RLOX_STRICT")?);
+    interpreter.set_deterministic(seed.or(env_option("RLOX_SEED")?));
+    interpreter.set_max_call_depth(env_option("RLOX_MAX_DEPTH")?.unwrap_or(CLI_MAX_CALL_DEPTH));
+    if let Some(depth) = env_option("RLOX_MAX_NESTING")? {
+        interpreter.set_max_nesting(depth);
+    }
+    interpreter.set_step_limit(env_option("RLOX_STEP_LIMIT")?);
+    interpreter.set_optimize(env_flag("RLOX_OPTIMIZE")?);
+    interpreter.set_allow_net(env_flag("RLOX_ALLOW_NET")?);
+    interpreter.set_allow_fs(env_flag("RLOX_ALLOW_FS")?);
+    interpreter.set_allow_signals(env_flag("RLOX_ALLOW_SIGNALS")?);
+    interpreter.set_allow_extensions(env_flag("RLOX_ALLOW_EXTENSIONS")?);
+    threads::define_natives(&mut interpreter);
+    filesystem::define_natives(&mut interpreter);
+    #[cfg(feature = "config")]
+    config::define_natives(&mut interpreter);
+    #[cfg(feature = "hash")]
+    hashing::define_natives(&mut interpreter);
+    #[cfg(feature = "http")]
+    http::define_natives(&mut interpreter);
+    #[cfg(feature = "signals")]
+    signals::define_natives(&mut interpreter);
+    Ok(interpreter)
+}
+
+// Value of an environment variable, `None` when it's unset or empty
+fn env_option<T: std::str::FromStr>(name: &str) -> Result<Option<T>, Box<dyn Error>> {
+    let value = env::var(name).unwrap_or_default();
+    match value.trim() {
+        "" => Ok(None),
+        value => match value.parse() {
+            Ok(parsed) => Ok(Some(parsed)),
+            Err(_) => Err(format!("Invalid value '{}' for {}", value, name).into()),
+        },
+    }
+}
+
+// Whether an environment variable turns an option on, as `1`, `true`, `0` or `false`
+fn env_flag(name: &str) -> Result<bool, Box<dyn Error>> {
+    let value: Option<String> = env_option(name)?;
+    match value.as_deref() {
+        None | Some("0") | Some("false") => Ok(false),
+        Some("1") | Some("true") => Ok(true),
+        Some(value) => Err(format!("Invalid value '{}' for {}", value, name).into()),
+    }
+}
+
+// `RLOX_INIT` if it's set, otherwise `~/.loxrc` if it exists
+fn startup_script() -> Option<PathBuf> {
+    if let Some(path) = env::var_os("RLOX_INIT") {
+        return Some(PathBuf::from(path));
+    }
+    let home = env::var_os("HOME").or_else(|| env::var_os("USERPROFILE"))?;
+    let path = Path::new(&home).join(".loxrc");
+    path.is_file().then_some(path)
+}
+
+// Called when an argument is provided. Given a seed, the script runs in deterministic mode.
+// With `stats`, what it did is printed to stderr after.
+pub fn run_file(
+    arg: &str,
+    strict: bool,
+    seed: Option<u64>,
+    stats: bool,
+) -> Result<Outcome, Box<dyn Error>> {
+    let mut interpreter = cli_interpreter(strict, seed)?;
+    let result = if Path::new(arg).extension().is_some_and(|ext| ext == "loxc") {
+        let compiled = fs::read(arg).map_err(|_| format!("Error reading file '{}'", arg))?;
+        run_compiled(&mut interpreter, &compiled)
+    } else {
+        run_with(&mut interpreter, &read_script(arg)?)
+    };
+    let outcome = outcome(&mut interpreter, result);
+    if stats {
+        eprintln!("{}", interpreter.stats());
+    }
+    Ok(outcome)
 }
 
-// Called when an argument is provided
-pub fn run_file(arg: &str) -> Result<(), Box<dyn Error>> {
+// Compiles a script for `rlox compile`, writing it next to the script unless given a path
+pub fn compile_file(arg: &str, output: Option<&str>) -> Result<Outcome, Box<dyn Error>> {
+    let content = read_script(arg)?;
+    let compiled = match compile(&content) {
+        Ok(compiled) => compiled,
+        Err(error) => return Ok(Outcome::Failed(error)),
+    };
+    let output = output.map_or_else(|| Path::new(arg).with_extension("loxc"), PathBuf::from);
+    fs::write(&output, compiled)
+        .map_err(|_| format!("Error writing file '{}'", output.display()))?;
+    Ok(Outcome::Done)
+}
+
+// Prints the tokens of a script, one a line, for `rlox --tokens`. Scan errors are reported
+// among them and fail the command.
+pub fn tokens_file(arg: &str) -> Result<Outcome, Box<dyn Error>> {
+    let content = read_script(arg)?;
+    let _source = SourceGuard::set(&content);
+    let mut outcome = Outcome::Done;
+    write_stdout(|stdout| {
+        for token in Scanner::new(content.trim_end()) {
+            match token {
+                Ok(token) => writeln!(stdout, "{}", token.show())?,
+                Err(error) => {
+                    error.report();
+                    outcome = Outcome::Failed(LoxError::Scan(Vec::new()));
+                }
+            }
+        }
+        Ok(())
+    })?;
+    Ok(outcome)
+}
+
+// Prints the syntax tree of a script without running it, for `rlox --ast`
+pub fn ast_file(arg: &str) -> Result<Outcome, Box<dyn Error>> {
+    match print_ast(&read_script(arg)?, Form::Lisp) {
+        Ok(ast) => {
+            write_stdout(|stdout| stdout.write_all(ast.as_bytes()))?;
+            Ok(Outcome::Done)
+        }
+        Err(error) => Ok(Outcome::Failed(error)),
+    }
+}
+
+// Writes what a command prints to stdout, locked once for all of it. A reader that stops
+// reading early, like `head`, ends the output without failing the command.
+fn write_stdout(
+    write: impl FnOnce(&mut io::StdoutLock) -> io::Result<()>,
+) -> Result<(), Box<dyn Error>> {
+    let mut stdout = io::stdout().lock();
+    match write(&mut stdout).and_then(|_| stdout.flush()) {
+        Err(error) if error.kind() != io::ErrorKind::BrokenPipe => Err(error.into()),
+        _ => Ok(()),
+    }
+}
+
+// Runs a script like `run_file`, then prints a coverage summary to stderr and, given
+// a path, writes an lcov report there
+pub fn cover_file(
+    arg: &str,
+    strict: bool,
+    seed: Option<u64>,
+    lcov: Option<&str>,
+) -> Result<Outcome, Box<dyn Error>> {
+    let content = read_script(arg)?;
+    let mut interpreter = cli_interpreter(strict, seed)?;
+    let (result, coverage) = run_with_coverage(&mut interpreter, &content);
+    let outcome = outcome(&mut interpreter, result);
+    eprintln!("{}", coverage.summary(arg));
+    if let Some(lcov) = lcov {
+        fs::write(lcov, coverage.lcov(arg))
+            .map_err(|_| format!("Error writing coverage to '{}'", lcov))?;
+    }
+    Ok(outcome)
+}
+
+fn read_script(arg: &str) -> Result<String, Box<dyn Error>> {
     let ext = Path::new(arg).extension();
     match ext {
         Some(e) => {
@@ -64,48 +516,601 @@ pub fn run_file(arg: &str) -> Result<(), Box<dyn Error>> {
         None => return Err("Cannot identify file extension.".into()),
     }
 
-    let content = fs::read_to_string(arg);
-    match &content {
-        Ok(c) => {
-            run(c);
-            Ok(())
+    fs::read_to_string(arg).map_err(|_| format!("Error reading file '{}'", arg).into())
+}
+
+// Runs the source with `print` output going to `output`. Errors are written to stderr as
+// they're reported, and the returned `LoxError` tells the stage that failed.
+pub fn run(content: &str, output: Box<dyn Write>) -> Result<(), LoxError> {
+    let mut interpreter = Interpreter::with_output(output);
+    run_with(&mut interpreter, content)
+}
+
+// Runs the source on an existing interpreter, keeping its globals between runs
+pub fn run_with(interpreter: &mut Interpreter, content: &str) -> Result<(), LoxError> {
+    let _source = SourceGuard::set(content);
+    let statements = parse_for(interpreter, content)?;
+    execute(interpreter, &statements)
+}
+
+// An interpreter for hosts embedding rlox as a library. Unlike `run_with`, errors are
+// returned with what went wrong rather than written to the error output.
+pub struct Lox {
+    interpreter: Interpreter,
+}
+
+// Failure of running source by the stage that failed, with the errors it reported when
+// they were collected, as `Lox` does, rather than written out. A runtime error also has the
+// first error raised, to tell its kind and where it happened.
+#[derive(Debug, Clone)]
+pub enum LoxError {
+    Scan(Vec<String>),
+    Parse(Vec<String>),
+    Resolve(Vec<String>),
+    Runtime(Box<RuntimeError>, Vec<String>),
+    // A compiled script that can't be loaded
+    Load(LoadError),
+}
+
+impl LoxError {
+    pub fn diagnostics(&self) -> &[String] {
+        match self {
+            LoxError::Scan(diagnostics)
+            | LoxError::Parse(diagnostics)
+            | LoxError::Resolve(diagnostics)
+            | LoxError::Runtime(_, diagnostics) => diagnostics,
+            LoxError::Load(_) => &[],
         }
-        Err(_) => Err(format!("Error reading file '{}'", arg).into()),
     }
 }
 
-fn run(content: &str) {
-    if content.trim().to_lowercase() == "exit" {
-        process::exit(0);
+impl std::fmt::Display for LoxError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LoxError::Load(error) => write!(f, "Error: {}", error),
+            _ if !self.diagnostics().is_empty() => write!(f, "{}", self.diagnostics().join("\n")),
+            LoxError::Scan(_) => write!(f, "Scan errors were reported."),
+            LoxError::Parse(_) => write!(f, "Parse errors were reported."),
+            LoxError::Resolve(_) => write!(f, "Resolve errors were reported."),
+            LoxError::Runtime(error, _) => write!(f, "{}", error),
+        }
+    }
+}
+
+impl Error for LoxError {}
+
+impl Default for Lox {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Lox {
+    // Prints to stdout
+    pub fn new() -> Self {
+        Self::with_output(Box::new(io::stdout()))
+    }
+
+    pub fn with_output(output: Box<dyn Write>) -> Self {
+        Lox {
+            interpreter: Interpreter::with_output(output),
+        }
+    }
+
+    // The interpreter source runs on, e.g. to define natives or set limits
+    pub fn interpreter(&mut self) -> &mut Interpreter {
+        &mut self.interpreter
     }
+
+    // Runs the source, keeping globals between runs. Source with scan errors isn't run even
+    // if it parses.
+    pub fn run_source(&mut self, src: &str) -> Result<(), LoxError> {
+        let _source = SourceGuard::set(src);
+        let statements = self.prepare(src)?;
+        let (result, diagnostics) = capture_diagnostics(|| self.interpreter.interpret(&statements));
+        match result {
+            Err(Exit::RuntimeError(error)) => Err(LoxError::Runtime(error, diagnostics)),
+            _ => Ok(()),
+        }
+    }
+
+    // Runs the source as a configuration file, returning the value of its last statement if
+    // that's an expression, otherwise the value of its `config` global, or nil if it has
+    // neither. Values the host defined on the interpreter beforehand can be read by it.
+    pub fn eval_config(&mut self, src: &str) -> Result<LiteralTypes, LoxError> {
+        let _source = SourceGuard::set(src);
+        let statements = self.prepare(src)?;
+        let (result, diagnostics) =
+            capture_diagnostics(|| self.interpreter.interpret_value(&statements));
+        match result {
+            Err(Exit::RuntimeError(error)) => Err(LoxError::Runtime(error, diagnostics)),
+            Ok(Some(value)) => Ok(value),
+            _ => Ok(self
+                .interpreter
+                .global("config")
+                .unwrap_or(LiteralTypes::Nil)),
+        }
+    }
+
+    // Scans, parses and resolves the source, failing with the first stage's errors
+    fn prepare(&mut self, src: &str) -> Result<Vec<Stmt>, LoxError> {
+        let mut scanned = true;
+        let (statements, diagnostics) = capture_diagnostics(|| {
+            let tokens = Scanner::new(src.trim_end()).filter_map(|token| {
+                token
+                    .map_err(|error| {
+                        error.report();
+                        scanned = false;
+                    })
+                    .ok()
+            });
+            parse_tokens(&mut self.interpreter, tokens)
+        });
+        let statements = match statements {
+            _ if !scanned => return Err(LoxError::Scan(diagnostics)),
+            Ok(statements) => statements,
+            Err(_) => return Err(LoxError::Parse(diagnostics)),
+        };
+        let (resolved, diagnostics) =
+            capture_diagnostics(|| resolve(&mut self.interpreter, &statements));
+        match resolved {
+            Ok(_) => Ok(statements),
+            Err(_) => Err(LoxError::Resolve(diagnostics)),
+        }
+    }
+}
+
+// Runs the source like `run_with`, returning the value of its last statement if that's an
+// expression
+fn run_for_value(
+    interpreter: &mut Interpreter,
+    content: &str,
+) -> Result<Option<LiteralTypes>, LoxError> {
+    let _source = SourceGuard::set(content);
+    let statements = parse_for(interpreter, content)?;
+    resolve(interpreter, &statements)?;
+    ran(interpreter.interpret_value(&statements))
+}
+
+// Renders a template, text with embedded Lox, to the interpreter's output: `{{ expression }}`
+// writes the value of the expression and `{% statements %}` runs the statements, which can
+// wrap text in loops and conditionals. Keeps the interpreter's globals between runs, like
+// `run_with`. Fails as `run` does, a template with a tag left open failing to scan.
+pub fn render_template(interpreter: &mut Interpreter, content: &str) -> Result<(), LoxError> {
+    let _source = SourceGuard::set(content);
+    let _errors = ErrorsGuard::set(interpreter.error_output());
+    let template = Template::scan(content).ok_or(LoxError::Scan(Vec::new()))?;
+    let texts = template.texts;
+    interpreter.define_native(template::WRITE_TEXT, 1, move |interpreter, arguments| {
+        if let LiteralTypes::Number(index) = arguments[0] {
+            interpreter.write_output(&texts[index as usize])?;
+        }
+        Ok(LiteralTypes::Nil)
+    });
+    interpreter.define_native(template::WRITE_VALUE, 1, |interpreter, arguments| {
+        let text = interpreter.stringify(&arguments[0]);
+        interpreter.write_output(&text)?;
+        Ok(LiteralTypes::Nil)
+    });
+    let statements = parse_tokens(interpreter, template.tokens)?;
+    execute(interpreter, &statements)
+}
+
+// Renders a template file to stdout, for `rlox render`
+pub fn render_file(arg: &str, strict: bool) -> Result<Outcome, Box<dyn Error>> {
+    let content = fs::read_to_string(arg).map_err(|_| format!("Error reading file '{}'", arg))?;
+    let mut interpreter = cli_interpreter(strict, None)?;
+    match render_template(&mut interpreter, &content) {
+        Ok(_) => Ok(Outcome::Done),
+        Err(error) => Ok(Outcome::Failed(error)),
+    }
+}
+
+// Runs the source like `run_with`, also counting how often each line with a statement ran
+pub fn run_with_coverage(
+    interpreter: &mut Interpreter,
+    content: &str,
+) -> (Result<(), LoxError>, Coverage) {
+    let _source = SourceGuard::set(content);
+    let statements = match parse_for(interpreter, content) {
+        Ok(statements) => statements,
+        Err(error) => return (Err(error), Coverage::default()),
+    };
+    let coverage = Rc::new(RefCell::new(Coverage::new(&statements)));
+    interpreter.set_hook(Some(Box::new(CoverageHook {
+        coverage: Rc::clone(&coverage),
+    })));
+    let result = execute(interpreter, &statements);
+    interpreter.set_hook(None);
+    let coverage = coverage.borrow().clone();
+    (result, coverage)
+}
+
+// Runs a script like `run_file`, writing a profile of it in the folded-stack format to
+// `output` and the number of samples taken to stderr
+pub fn profile_file(
+    arg: &str,
+    strict: bool,
+    seed: Option<u64>,
+    output: &str,
+) -> Result<Outcome, Box<dyn Error>> {
+    let content = read_script(arg)?;
+    let mut interpreter = cli_interpreter(strict, seed)?;
+    let (result, profile) = run_with_profile(&mut interpreter, &content);
+    let outcome = outcome(&mut interpreter, result);
+    fs::write(output, profile.folded())
+        .map_err(|_| format!("Error writing profile to '{}'", output))?;
+    eprintln!(
+        "{}: {} samples written to '{}'",
+        arg,
+        profile.samples(),
+        output
+    );
+    Ok(outcome)
+}
+
+// Runs the source like `run_with`, sampling the calls active at every statement
+pub fn run_with_profile(
+    interpreter: &mut Interpreter,
+    content: &str,
+) -> (Result<(), LoxError>, Profile) {
+    let _source = SourceGuard::set(content);
+    let statements = match parse_for(interpreter, content) {
+        Ok(statements) => statements,
+        Err(error) => return (Err(error), Profile::default()),
+    };
+    let profile = Rc::new(RefCell::new(Profile::default()));
+    interpreter.set_hook(Some(Box::new(ProfileHook::new(Rc::clone(&profile)))));
+    let result = execute(interpreter, &statements);
+    interpreter.set_hook(None);
+    let profile = profile.borrow().clone();
+    (result, profile)
+}
+
+// Writes a copy of the running rlox carrying the compiled script, for `rlox bundle`. The
+// copy runs the script when started.
+pub fn bundle_file(arg: &str, output: &str) -> Result<Outcome, Box<dyn Error>> {
+    let content = read_script(arg)?;
+    let compiled = match compile(&content) {
+        Ok(compiled) => compiled,
+        Err(error) => return Ok(Outcome::Failed(error)),
+    };
+    let executable = fs::read(env::current_exe()?)?;
+    fs::write(output, loxc::bundle(&executable, &compiled))
+        .map_err(|_| format!("Error writing file '{}'", output))?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        fs::set_permissions(output, fs::Permissions::from_mode(0o755))?;
+    }
+    Ok(Outcome::Done)
+}
+
+// Runs the script the running executable carries, if `rlox bundle` made it
+pub fn run_bundled() -> Option<Result<Outcome, Box<dyn Error>>> {
+    let mut file = fs::File::open(env::current_exe().ok()?).ok()?;
+    let end = file.seek(SeekFrom::End(0)).ok()?;
+    let trailer_len = loxc::BUNDLE_TRAILER_LEN as u64;
+    let mut trailer = [0; loxc::BUNDLE_TRAILER_LEN];
+    file.seek(SeekFrom::Start(end.checked_sub(trailer_len)?))
+        .ok()?;
+    file.read_exact(&mut trailer).ok()?;
+    let len = loxc::bundled_len(&trailer)?;
+    let start = end.checked_sub(trailer_len + len as u64)?;
+    let mut compiled = vec![0; len];
+    file.seek(SeekFrom::Start(start)).ok()?;
+    file.read_exact(&mut compiled).ok()?;
+
+    // Set up like `rlox script.lox`, so the environment, `exit` and `onExit` behave the same
+    let mut interpreter = match cli_interpreter(false, None) {
+        Ok(interpreter) => interpreter,
+        Err(err) => return Some(Err(err)),
+    };
+    let result = run_compiled(&mut interpreter, &compiled);
+    Some(Ok(outcome(&mut interpreter, result)))
+}
+
+// Parses and resolves the source into the contents of a `.loxc` file, failing as `run`
+// would on syntax and resolution errors
+pub fn compile(content: &str) -> Result<Vec<u8>, LoxError> {
+    let _source = SourceGuard::set(content);
+    let mut interpreter = Interpreter::with_output(Box::new(io::sink()));
+    let first_id = interpreter.next_id();
+    let statements = parse_for(&mut interpreter, content)?;
+    resolve(&mut interpreter, &statements)?;
+    let end_id = interpreter.next_id();
+    let resolution = interpreter.resolution(first_id..end_id);
+    Ok(loxc::encode(&statements, &resolution, first_id, end_id))
+}
+
+// Runs a compiled script on an existing interpreter without scanning, parsing or resolving
+// it. A file that can't be loaded is reported and fails with `LoxError::Load`.
+pub fn run_compiled(interpreter: &mut Interpreter, compiled: &[u8]) -> Result<(), LoxError> {
+    // Trees nest deeper than their source, a `for` loop becoming a block around a `while`
+    let max_depth = interpreter.max_nesting().saturating_mul(4);
+    let program = match loxc::decode(compiled, interpreter.next_id(), max_depth) {
+        Ok(program) => program,
+        Err(error) => {
+            let _errors = ErrorsGuard::set(interpreter.error_output());
+            emit(format!("Error: {}", error));
+            return Err(LoxError::Load(error));
+        }
+    };
+    interpreter.set_next_id(program.end_id);
+    interpreter.restore(program.resolution);
+    interpret(interpreter, &program.statements)
+}
+
+// Scans, parses and optionally optimizes the source, numbering nodes after the ones
+// the interpreter has already seen
+fn parse_for(interpreter: &mut Interpreter, content: &str) -> Result<Vec<Stmt>, LoxError> {
+    trace_span!("parse", bytes = content.len());
+    let _errors = ErrorsGuard::set(interpreter.error_output());
+
     //scanning
-    let mut scanner = Scanner::new(content.trim().to_string());
-    let tokens = scanner.scan_tokens();
+    let tokens = Scanner::new(content.trim_end()).reported();
+    parse_tokens(interpreter, tokens)
+}
 
+// Parses and optionally optimizes scanned tokens, as `parse_for` does
+fn parse_tokens(
+    interpreter: &mut Interpreter,
+    tokens: impl IntoIterator<Item = Token>,
+) -> Result<Vec<Stmt>, LoxError> {
+    let _errors = ErrorsGuard::set(interpreter.error_output());
     //parsing
-    let mut parser = Parser::new(tokens);
+    let mut parser = Parser::new(tokens, interpreter.next_id());
+    parser.set_max_depth(interpreter.max_nesting());
     let statements = parser.parse();
+    interpreter.set_next_id(parser.end_id());
 
-    match &statements {
-        Ok(e) => {
-            let mut interpreter = Interpreter::new();
-
-            //resolving
-            let mut resolver = Resolver::new(&mut interpreter);
-            let r = resolver.resolve_each(e);
-            match &r {
-                Ok(_) => {
-                    //interpreting
-                    let interpreted = interpreter.interpret(e);
-
-                    match &interpreted {
-                        Ok(_) => (),
-                        Err(_) => process::exit(70),
-                    }
-                }
-                Err(_) => process::exit(70),
+    //optimizing
+    match statements {
+        Ok(s) if interpreter.optimizes() => {
+            let mut optimizer = Optimizer::new();
+            optimizer.set_strict(interpreter.strict());
+            Ok(optimizer.optimize(&s))
+        }
+        Ok(s) => Ok(s),
+        Err(_) => Err(LoxError::Parse(Vec::new())),
+    }
+}
+
+// Resolves and runs parsed statements
+fn execute(interpreter: &mut Interpreter, statements: &[Stmt]) -> Result<(), LoxError> {
+    resolve(interpreter, statements)?;
+    interpret(interpreter, statements)
+}
+
+fn resolve(interpreter: &mut Interpreter, statements: &[Stmt]) -> Result<(), LoxError> {
+    trace_span!("resolve", statements = statements.len());
+    let _errors = ErrorsGuard::set(interpreter.error_output());
+    let mut resolver = Resolver::new(interpreter);
+    resolver
+        .resolve_each(statements)
+        .map_err(|_| LoxError::Resolve(Vec::new()))
+}
+
+fn interpret(interpreter: &mut Interpreter, statements: &[Stmt]) -> Result<(), LoxError> {
+    trace_span!("interpret");
+    ran(interpreter.interpret(statements))
+}
+
+// The runtime error a run stopped with, if it did. Other ways of stopping are the script's
+// own, like `exit` or a debugger quitting.
+fn ran<T: Default>(result: Result<T, Exit>) -> Result<T, LoxError> {
+    match result {
+        Ok(value) => Ok(value),
+        Err(Exit::RuntimeError(error)) => Err(LoxError::Runtime(error, Vec::new())),
+        Err(_) => Ok(T::default()),
+    }
+}
+
+// Parses the source and prints its syntax tree without running it, failing as `run` does on
+// a syntax error
+pub fn print_ast(content: &str, form: Form) -> Result<String, LoxError> {
+    let _source = SourceGuard::set(content);
+    let tokens = Scanner::new(content.trim_end()).reported();
+    match Parser::new(tokens, 0).parse() {
+        Ok(statements) => Ok(AstPrinter::new(form).print(&statements)),
+        Err(_) => Err(LoxError::Parse(Vec::new())),
+    }
+}
+
+// Parses the source into a tree that can be searched by position and kind, without running
+// it. Fails as `run` does on a syntax error.
+pub fn parse_ast(content: &str) -> Result<Ast, LoxError> {
+    let _source = SourceGuard::set(content);
+    let tokens = Scanner::new(content.trim_end()).reported();
+    let mut parser = Parser::new(tokens, 0);
+    match parser.parse() {
+        Ok(statements) => Ok(Ast::new(content, &parser, &statements)),
+        Err(_) => Err(LoxError::Parse(Vec::new())),
+    }
+}
+
+// Classifies every keyword, name, literal, operator and comment of the source for syntax
+// highlighting, without running it. Never fails: code with errors is still highlighted as
+// far as it scans and parses, and nothing is reported.
+pub fn semantic_tokens(content: &str) -> Vec<SemanticToken> {
+    let _source = SourceGuard::set(content);
+    let tokens: Vec<Token> = Scanner::new(content).filter_map(Result::ok).collect();
+    let (statements, _) = capture_diagnostics(|| Parser::new(tokens.clone(), 0).parse_recovering());
+    // Globals the interpreter starts with, which scripts can shadow like any other
+    let interpreter = Interpreter::with_output(Box::new(io::sink()));
+    let globals = interpreter.globals.borrow();
+    let globals = globals
+        .names
+        .iter()
+        .filter_map(|(name, &index)| {
+            let kind = match globals.values[index].as_ref()? {
+                LiteralTypes::Callable(Callable::Class(_)) => SemanticKind::Class,
+                LiteralTypes::Callable(_) => SemanticKind::Function,
+                _ => SemanticKind::Variable,
+            };
+            Some((name.clone(), kind))
+        })
+        .collect();
+    semantic::classify(content, &tokens, &statements, globals)
+}
+
+// Parses the source and collects the documentation of its functions and classes, failing
+// as `run` does on a syntax error
+pub fn document(content: &str, path: &str) -> Result<ScriptDoc, LoxError> {
+    let _source = SourceGuard::set(content);
+    let tokens = Scanner::new(content.trim_end()).reported();
+    match Parser::new(tokens, 0).parse() {
+        Ok(statements) => Ok(ScriptDoc::new(path, &statements)),
+        Err(_) => Err(LoxError::Parse(Vec::new())),
+    }
+}
+
+// Documents a script, or every `.lox` script under a directory, for `rlox doc`
+pub fn doc_path(path: &str, format: DocFormat) -> Result<String, Box<dyn Error>> {
+    let mut scripts = Vec::new();
+    for path in lox_files(path)? {
+        let name = path.display().to_string();
+        let content =
+            fs::read_to_string(&path).map_err(|_| format!("Error reading file '{}'", name))?;
+        let script =
+            document(&content, &name).map_err(|_| format!("Syntax errors in '{}'", name))?;
+        scripts.push(script);
+    }
+    Ok(doc::render(&scripts, format))
+}
+
+// Runs the tests of a script or of every script under a directory for `rlox test --native`,
+// each script in an interpreter of its own, flagged if any test failed. A script that fails
+// outside a test counts as a failed test named after it.
+pub fn test_path(path: &str) -> Result<Outcome, Box<dyn Error>> {
+    let mut report = TestReport::default();
+    for path in lox_files(path)? {
+        let name = path.display().to_string();
+        let content =
+            fs::read_to_string(&path).map_err(|_| format!("Error reading file '{}'", name))?;
+        println!("{}:", name);
+        let mut interpreter = cli_interpreter(false, None)?;
+        let results = testing::define_natives(&mut interpreter);
+        let result = run_with(&mut interpreter, &content);
+        report.add(&results.borrow());
+        if let Err(error) = result {
+            let message = match error {
+                LoxError::Runtime(..) => "Script failed with a runtime error.",
+                _ => "Script has errors and didn't run.",
+            };
+            report.failed.push((name, message.to_string()));
+        }
+    }
+    println!("\n{}", report);
+    Ok(match report.failed.is_empty() {
+        true => Outcome::Done,
+        false => Outcome::Flagged,
+    })
+}
+
+// The path if it's a file, otherwise every `.lox` file under it, in order
+fn lox_files(path: &str) -> Result<Vec<PathBuf>, Box<dyn Error>> {
+    let mut files = Vec::new();
+    let mut paths = vec![PathBuf::from(path)];
+    while let Some(path) = paths.pop() {
+        if path.is_dir() {
+            let mut entries: Vec<PathBuf> = fs::read_dir(&path)?
+                .filter_map(|entry| Some(entry.ok()?.path()))
+                .filter(|entry| entry.is_dir() || entry.extension().is_some_and(|e| e == "lox"))
+                .collect();
+            // Popped from the end, so they're listed in order
+            entries.sort_by(|l, r| r.cmp(l));
+            paths.extend(entries);
+            continue;
+        }
+        files.push(path);
+    }
+    Ok(files)
+}
+
+// Parses the source and checks it against the enabled lint rules without running it,
+// failing as `run` does on a syntax error
+pub fn lint(content: &str, config: &LintConfig) -> Result<Vec<LintDiagnostic>, LoxError> {
+    let _source = SourceGuard::set(content);
+    let tokens = Scanner::new(content.trim_end()).reported();
+    match Parser::new(tokens, 0).parse() {
+        Ok(statements) => Ok(Linter::new(config).lint(&statements)),
+        Err(_) => Err(LoxError::Parse(Vec::new())),
+    }
+}
+
+// Lints a script for `rlox lint`, printing what it finds, flagged if anything was found.
+// Uses the config file given, or `.loxlint` in the working directory if there is one.
+pub fn lint_file(path: &str, config_path: Option<&str>) -> Result<Outcome, Box<dyn Error>> {
+    let config = match config_path {
+        Some(config_path) => Some(
+            fs::read_to_string(config_path)
+                .map_err(|_| format!("Error reading config '{}'", config_path))?,
+        ),
+        None => fs::read_to_string(".loxlint").ok(),
+    };
+    let config = match config {
+        Some(text) => LintConfig::parse(&text)?,
+        None => LintConfig::default(),
+    };
+
+    let content = fs::read_to_string(path).map_err(|_| format!("Error reading file '{}'", path))?;
+    match lint(&content, &config) {
+        Ok(diagnostics) => {
+            for diagnostic in diagnostics.iter() {
+                println!("{}", diagnostic);
             }
+            Ok(match diagnostics.is_empty() {
+                true => Outcome::Done,
+                false => Outcome::Flagged,
+            })
         }
-        Err(_) => process::exit(65),
+        Err(error) => Ok(Outcome::Failed(error)),
+    }
+}
+
+// Runs a script for `rlox record`, writing a trace of it to `output`, or next to the script
+// unless given a path
+pub fn record_file(
+    arg: &str,
+    output: Option<&str>,
+    strict: bool,
+    seed: Option<u64>,
+) -> Result<Outcome, Box<dyn Error>> {
+    let content = read_script(arg)?;
+    let output = output.map_or_else(|| Path::new(arg).with_extension("trace"), PathBuf::from);
+    let file = fs::File::create(&output)
+        .map_err(|_| format!("Error writing file '{}'", output.display()))?;
+    let recorder = Recorder::new(Box::new(io::BufWriter::new(file)), arg)?;
+    let mut interpreter = cli_interpreter(strict, seed)?;
+    interpreter.set_hook(Some(Box::new(recorder)));
+    let result = run_with(&mut interpreter, &content);
+    // Flushes the trace
+    interpreter.set_hook(None);
+    match result {
+        Ok(_) => Ok(Outcome::Done),
+        Err(error) => Ok(Outcome::Failed(error)),
+    }
+}
+
+// Steps through a trace written by `rlox record`, for `rlox replay`
+pub fn replay_file(path: &str) -> Result<(), Box<dyn Error>> {
+    let text = fs::read_to_string(path).map_err(|_| format!("Error reading file '{}'", path))?;
+    let trace = Trace::parse(&text)?;
+    // The script may have changed or gone since, the trace is replayed either way
+    let source = fs::read_to_string(&trace.script).ok();
+    Replay::new(trace, source.as_deref()).run();
+    Ok(())
+}
+
+// Runs a script under the console debugger for `rlox debug`, stopped before its first
+// statement so breakpoints can be set
+pub fn debug_file(path: &str) -> Result<Outcome, Box<dyn Error>> {
+    let content = fs::read_to_string(path).map_err(|_| format!("Error reading file '{}'", path))?;
+    let console = Console::new(path, &content);
+    let debugger = Debugger::new(Box::new(console), BTreeSet::new(), true);
+    match debugger.run(&content, Box::new(io::stdout())) {
+        Ok(_) => Ok(Outcome::Done),
+        Err(error) => Ok(Outcome::Failed(error)),
     }
 }
@@ -0,0 +1,374 @@
+/// Lint pass that looks for code that's valid but probably not what was meant.
+/// Each rule can be turned off in a config file of `rule = on|off` lines.
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::rc::Rc;
+
+use crate::expr::{self, *};
+use crate::optimizer::{constant_value, is_truthy};
+use crate::stmt::{self, *};
+use crate::symbol::Symbol;
+use crate::token::{LiteralTypes, Token};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Rule {
+    UnusedVariable,
+    Shadowing,
+    EmptyBlock,
+    AssignmentInCondition,
+    ConstantCondition,
+}
+
+impl Rule {
+    pub const ALL: [Rule; 5] = [
+        Rule::UnusedVariable,
+        Rule::Shadowing,
+        Rule::EmptyBlock,
+        Rule::AssignmentInCondition,
+        Rule::ConstantCondition,
+    ];
+
+    // Name used for the rule in config files and diagnostics
+    pub fn name(&self) -> &'static str {
+        match self {
+            Rule::UnusedVariable => "unused-variable",
+            Rule::Shadowing => "shadowing",
+            Rule::EmptyBlock => "empty-block",
+            Rule::AssignmentInCondition => "assignment-in-condition",
+            Rule::ConstantCondition => "constant-condition",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Rule> {
+        Rule::ALL.into_iter().find(|rule| rule.name() == name)
+    }
+}
+
+// Which rules are checked, every one unless the config turns it off
+#[derive(Debug, Clone, Default)]
+pub struct LintConfig {
+    disabled: HashSet<Rule>,
+}
+
+impl LintConfig {
+    // Reads lines like `shadowing = off`, skipping blank lines and `#` comments
+    pub fn parse(text: &str) -> Result<LintConfig, String> {
+        let mut config = LintConfig::default();
+        for (i, line) in text.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or("").trim();
+            if line.is_empty() {
+                continue;
+            }
+            let (name, setting) = line
+                .split_once('=')
+                .ok_or_else(|| format!("Line {}: expected 'rule = on|off'.", i + 1))?;
+            let rule = Rule::from_name(name.trim())
+                .ok_or_else(|| format!("Line {}: unknown rule '{}'.", i + 1, name.trim()))?;
+            let enabled = match setting.trim() {
+                "on" => true,
+                "off" => false,
+                other => {
+                    return Err(format!(
+                        "Line {}: expected 'on' or 'off', found '{}'.",
+                        i + 1,
+                        other
+                    ))
+                }
+            };
+            config.set(rule, enabled);
+        }
+        Ok(config)
+    }
+
+    pub fn set(&mut self, rule: Rule, enabled: bool) {
+        if enabled {
+            self.disabled.remove(&rule);
+        } else {
+            self.disabled.insert(rule);
+        }
+    }
+
+    pub fn is_enabled(&self, rule: Rule) -> bool {
+        !self.disabled.contains(&rule)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct LintDiagnostic {
+    pub rule: Rule,
+    pub line: usize,
+    pub column: usize,
+    pub message: String,
+}
+
+impl fmt::Display for LintDiagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "[Line {}, Column {}] Warning ({}): {}",
+            self.line,
+            self.column,
+            self.rule.name(),
+            self.message
+        )
+    }
+}
+
+// A name declared in a scope, and whether anything has read it yet
+struct Declared {
+    token: Token,
+    used: bool,
+    // Only variables are reported when unused, not parameters, functions or classes
+    is_variable: bool,
+}
+
+pub(crate) struct Linter<'a> {
+    config: &'a LintConfig,
+    // Globals first, then every block and function being linted
+    scopes: Vec<HashMap<Symbol, Declared>>,
+    diagnostics: Vec<LintDiagnostic>,
+}
+
+impl<'a> Linter<'a> {
+    pub fn new(config: &'a LintConfig) -> Self {
+        Linter {
+            config,
+            scopes: vec![HashMap::new()],
+            diagnostics: Vec::new(),
+        }
+    }
+
+    // Diagnostics for the whole script, in source order
+    pub fn lint(mut self, statements: &[Stmt]) -> Vec<LintDiagnostic> {
+        self.lint_each(statements);
+        self.diagnostics.sort_by_key(|d| (d.line, d.column));
+        self.diagnostics
+    }
+
+    fn lint_each(&mut self, statements: &[Stmt]) {
+        for statement in statements.iter() {
+            statement.accept(self);
+        }
+    }
+
+    fn lint_expr(&mut self, expr: &Expr) {
+        expr.accept(self);
+    }
+
+    fn report(&mut self, rule: Rule, token: &Token, message: String) {
+        if self.config.is_enabled(rule) {
+            self.diagnostics.push(LintDiagnostic {
+                rule,
+                line: token.line,
+                column: token.column,
+                message,
+            });
+        }
+    }
+
+    fn begin_scope(&mut self) {
+        self.scopes.push(HashMap::new());
+    }
+
+    fn end_scope(&mut self) {
+        let Some(scope) = self.scopes.pop() else {
+            return;
+        };
+        for declared in scope.values() {
+            if declared.is_variable && !declared.used {
+                self.report(
+                    Rule::UnusedVariable,
+                    &declared.token,
+                    format!("Variable '{}' is never used.", declared.token.lexeme),
+                );
+            }
+        }
+    }
+
+    fn declare(&mut self, name: &Token, is_variable: bool) {
+        // Names are only shadowed from inside a block or function, never at the top level
+        if self.scopes.len() > 1 {
+            let enclosing = self.scopes[..self.scopes.len() - 1]
+                .iter()
+                .rev()
+                .find_map(|scope| scope.get(&name.lexeme));
+            if let Some(shadowed) = enclosing {
+                let message = format!(
+                    "'{}' shadows the declaration on line {}.",
+                    name.lexeme, shadowed.token.line
+                );
+                self.report(Rule::Shadowing, name, message);
+            }
+        }
+        if let Some(scope) = self.scopes.last_mut() {
+            scope.insert(
+                name.lexeme.clone(),
+                Declared {
+                    token: name.clone(),
+                    used: false,
+                    is_variable,
+                },
+            );
+        }
+    }
+
+    fn mark_used(&mut self, name: &Symbol) {
+        if let Some(declared) = self
+            .scopes
+            .iter_mut()
+            .rev()
+            .find_map(|scope| scope.get_mut(name))
+        {
+            declared.used = true;
+        }
+    }
+
+    fn function(&mut self, function: &Function) {
+        self.begin_scope();
+        for param in function.params.iter() {
+            self.declare(param, false);
+        }
+        self.lint_each(&function.body);
+        self.end_scope();
+    }
+
+    fn condition(&mut self, condition: &Expr, keyword: &Token) {
+        if let Expr::Assignment(_) | Expr::Set(_) = condition {
+            self.report(
+                Rule::AssignmentInCondition,
+                keyword,
+                "Assignment used as a condition, did you mean '=='?".to_string(),
+            );
+        }
+        // `while (true)` is how loops that exit another way are written
+        let infinite_loop = matches!(
+            condition,
+            Expr::Literal(Literal {
+                value: LiteralTypes::Bool(true),
+                ..
+            })
+        );
+        if let (Some(value), false) = (constant_value(condition), infinite_loop) {
+            let message = format!("Condition is always {}.", is_truthy(&value));
+            self.report(Rule::ConstantCondition, keyword, message);
+        }
+        self.lint_expr(condition);
+    }
+}
+
+impl<'a> stmt::Visitor<()> for Linter<'a> {
+    fn visit_expression(&mut self, stmt: &Expression) {
+        self.lint_expr(&stmt.expression);
+    }
+
+    fn visit_print(&mut self, stmt: &Print) {
+        self.lint_expr(&stmt.expression);
+    }
+
+    fn visit_var(&mut self, stmt: &Var) {
+        if let Some(initializer) = &stmt.initializer {
+            self.lint_expr(initializer);
+        }
+        self.declare(&stmt.name, true);
+    }
+
+    fn visit_block(&mut self, stmt: &Block) {
+        if stmt.statements.is_empty() {
+            self.report(Rule::EmptyBlock, &stmt.brace, "Empty block.".to_string());
+        }
+        self.begin_scope();
+        self.lint_each(&stmt.statements);
+        self.end_scope();
+    }
+
+    fn visit_if(&mut self, stmt: &If) {
+        self.condition(&stmt.condition, &stmt.keyword);
+        stmt.then_branch.accept(self);
+        if let Some(else_branch) = &stmt.else_branch {
+            else_branch.accept(self);
+        }
+    }
+
+    fn visit_while(&mut self, stmt: &While) {
+        self.condition(&stmt.condition, &stmt.keyword);
+        stmt.body.accept(self);
+        if let Some(increment) = &stmt.increment {
+            increment.accept(self);
+        }
+    }
+
+    fn visit_break(&mut self, _stmt: &Break) {}
+
+    fn visit_continue(&mut self, _stmt: &Continue) {}
+
+    fn visit_function(&mut self, stmt: &Rc<Function>) {
+        self.declare(&stmt.name, false);
+        self.function(stmt);
+    }
+
+    fn visit_return(&mut self, stmt: &Return) {
+        self.lint_expr(&stmt.value);
+    }
+
+    fn visit_class(&mut self, stmt: &Class) {
+        self.declare(&stmt.name, false);
+        if let Some(super_class) = &stmt.super_class {
+            self.lint_expr(super_class);
+        }
+        for method in stmt.methods.iter() {
+            if let Stmt::Function(method) = method {
+                self.function(method);
+            }
+        }
+    }
+}
+
+impl<'a> expr::Visitor<()> for Linter<'a> {
+    fn visit_assignment(&mut self, expr: &Assignment) {
+        self.lint_expr(&expr.value);
+    }
+
+    fn visit_binary(&mut self, expr: &Binary) {
+        self.lint_expr(&expr.left);
+        self.lint_expr(&expr.right);
+    }
+
+    fn visit_grouping(&mut self, expr: &Grouping) {
+        self.lint_expr(&expr.expr);
+    }
+
+    fn visit_literal(&self, _expr: &Literal) {}
+
+    fn visit_logical(&mut self, expr: &Logical) {
+        self.lint_expr(&expr.left);
+        self.lint_expr(&expr.right);
+    }
+
+    fn visit_unary(&mut self, expr: &Unary) {
+        self.lint_expr(&expr.right);
+    }
+
+    fn visit_variable(&mut self, expr: &Variable) {
+        self.mark_used(&expr.name.lexeme);
+    }
+
+    fn visit_call(&mut self, expr: &Call) {
+        self.lint_expr(&expr.callee);
+        for argument in expr.arguments.iter() {
+            self.lint_expr(argument);
+        }
+    }
+
+    fn visit_get(&mut self, expr: &Get) {
+        self.lint_expr(&expr.object);
+    }
+
+    fn visit_set(&mut self, expr: &Set) {
+        self.lint_expr(&expr.object);
+        self.lint_expr(&expr.value);
+    }
+
+    fn visit_this(&mut self, _expr: &This) {}
+
+    fn visit_super(&mut self, _expr: &Super) {}
+}
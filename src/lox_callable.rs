@@ -1,18 +1,20 @@
-use crate::token::TokenType;
 use crate::{
-    environment::Environment,
-    interpreter::{Exit, Interpreter},
-    report,
+    environment::Upvalue,
+    gc::Finalizer,
+    interpreter::{ErrorKind, Exit, Interpreter},
     stmt::Function,
+    symbol::Symbol,
     token::{LiteralTypes, Token},
 };
+use std::{any::Any, collections::HashMap, fmt};
 use std::{cell::RefCell, rc::Rc};
-use std::{collections::HashMap, fmt};
 
 pub enum Callable {
     Function(LoxFunction),
-    Class(LoxClass),
+    Class(Rc<LoxClass>),
     Instance(Rc<RefCell<LoxInstance>>),
+    Native(NativeFunction),
+    Host(Rc<RefCell<dyn HostObject>>),
 }
 
 impl fmt::Debug for Callable {
@@ -25,36 +27,97 @@ impl Clone for Callable {
     fn clone(&self) -> Self {
         match self {
             Callable::Function(lox_function) => Callable::Function(lox_function.clone()),
-            Callable::Class(class) => Callable::Class(class.clone()),
+            Callable::Class(class) => Callable::Class(Rc::clone(class)),
             Callable::Instance(ins) => Callable::Instance(ins.clone()),
+            Callable::Native(native) => Callable::Native(native.clone()),
+            Callable::Host(host) => Callable::Host(Rc::clone(host)),
         }
     }
 }
 
+// Callables are equal only when they are the same object
 impl PartialEq for Callable {
     fn eq(&self, other: &Self) -> bool {
-        std::mem::discriminant(self) == std::mem::discriminant(other)
+        match (self, other) {
+            (Callable::Function(l), Callable::Function(r)) => l == r,
+            (Callable::Class(l), Callable::Class(r)) => Rc::ptr_eq(l, r),
+            (Callable::Instance(l), Callable::Instance(r)) => Rc::ptr_eq(l, r),
+            (Callable::Native(l), Callable::Native(r)) => Rc::ptr_eq(&l.function, &r.function),
+            (Callable::Host(l), Callable::Host(r)) => Rc::ptr_eq(l, r),
+            _ => false,
+        }
     }
 }
 
 #[derive(Clone)]
 pub struct LoxFunction {
-    pub declaration: Box<Function>,
-    pub closure: Rc<RefCell<Environment>>,
+    pub declaration: Rc<Function>,
+    pub upvalues: Rc<[Upvalue]>,
+    // Instance a method was accessed on, passed to it as `this`
+    pub receiver: Option<Rc<RefCell<LoxInstance>>>,
     pub is_initializer: bool,
 }
 
-#[derive(Clone)]
 pub struct LoxClass {
     pub name: String,
-    pub super_class: Option<Box<LoxClass>>,
-    pub methods: HashMap<String, LoxFunction>,
+    pub super_class: Option<Rc<LoxClass>>,
+    // Includes the methods inherited from superclasses, so lookups never walk the chain
+    pub methods: HashMap<Symbol, LoxFunction>,
+    pub initializer: Option<LoxFunction>,
+    // Names of the fields set on instances, in the order they were given slots.
+    // Shared by every copy of the class, so it also identifies the class.
+    pub layout: Rc<RefCell<Vec<Symbol>>>,
+}
+
+type NativeFn = dyn Fn(&mut Interpreter, &[LiteralTypes]) -> Result<LiteralTypes, Exit>;
+
+// Function implemented by the host in Rust
+#[derive(Clone)]
+pub struct NativeFunction {
+    pub name: String,
+    pub arity: usize,
+    // Trailing arguments that can be left out, so the function gets fewer
+    pub optional: usize,
+    pub function: Rc<NativeFn>,
 }
 
 #[derive(Clone)]
 pub struct LoxInstance {
     pub class: Rc<LoxClass>,
-    pub fields: HashMap<String, LiteralTypes>,
+    // Indexed by slot in the class layout, `None` for fields this instance hasn't set
+    pub fields: Vec<Option<LiteralTypes>>,
+    // Set while the instance's `deinit` is yet to run
+    pub(crate) finalizer: Option<Finalizer>,
+}
+
+// Rust value handed to scripts, whose properties and methods are provided by the host.
+// Errors are returned as messages and reported as runtime errors at the access site.
+pub trait HostObject {
+    fn type_name(&self) -> &str;
+    fn get(&self, name: &str) -> Option<LiteralTypes>;
+    fn set(&mut self, name: &str, value: LiteralTypes) -> Result<(), String>;
+    fn call_method(
+        &mut self,
+        name: &str,
+        arguments: &[LiteralTypes],
+    ) -> Result<LiteralTypes, String>;
+
+    // Lets the crate recognise its own host objects, such as channels being sent to a thread
+    fn as_any(&self) -> Option<&dyn Any> {
+        None
+    }
+}
+
+// Method half of a derived `HostObject`, generated by `#[lox_methods]` on an impl block.
+// Types without script-visible methods can use the default with an empty impl.
+pub trait HostMethods {
+    fn call_method(
+        &mut self,
+        name: &str,
+        _arguments: &[LiteralTypes],
+    ) -> Result<LiteralTypes, String> {
+        Err(format!("Undefined method {}.", name))
+    }
 }
 
 pub trait LoxCallable {
@@ -64,73 +127,147 @@ pub trait LoxCallable {
         arguments: &[LiteralTypes],
     ) -> Result<LiteralTypes, Exit>;
     fn arity(&self) -> usize;
+
+    // Arguments after the first `arity` that callers can pass or leave out
+    fn optional(&self) -> usize {
+        0
+    }
+
+    // Why a call with `count` arguments can't be made, if it can't
+    fn check_arity(&self, count: usize) -> Result<(), String> {
+        let (arity, optional) = (self.arity(), self.optional());
+        match optional {
+            _ if (arity..=arity + optional).contains(&count) => Ok(()),
+            0 => Err(format!("Expected {} arguments but got {}.", arity, count)),
+            _ => Err(format!(
+                "Expected {} to {} arguments but got {}.",
+                arity,
+                arity + optional,
+                count
+            )),
+        }
+    }
+}
+
+// The same closure, or the same method bound to the same instance each time it's accessed
+impl PartialEq for LoxFunction {
+    fn eq(&self, other: &Self) -> bool {
+        let same_receiver = match (&self.receiver, &other.receiver) {
+            (Some(l), Some(r)) => Rc::ptr_eq(l, r),
+            (None, None) => true,
+            _ => false,
+        };
+        Rc::ptr_eq(&self.declaration, &other.declaration)
+            && Rc::ptr_eq(&self.upvalues, &other.upvalues)
+            && same_receiver
+    }
 }
 
 impl LoxFunction {
-    pub fn new(
-        declaration: Function,
-        closure: Rc<RefCell<Environment>>,
-        is_initializer: bool,
-    ) -> Self {
+    pub fn new(declaration: Rc<Function>, upvalues: Rc<[Upvalue]>, is_initializer: bool) -> Self {
         LoxFunction {
-            declaration: Box::new(declaration),
-            closure,
+            declaration,
+            upvalues,
+            receiver: None,
             is_initializer,
         }
     }
 
     pub fn bind(&self, instance: Rc<RefCell<LoxInstance>>) -> LoxFunction {
-        let environment = Rc::new(RefCell::new(Environment::new_with_enclosing(Rc::clone(
-            &self.closure,
-        ))));
-        environment.borrow_mut().define(
-            "this".to_string(),
-            LiteralTypes::Callable(Callable::Instance(instance)),
-        );
         LoxFunction {
-            declaration: self.declaration.clone(),
-            closure: environment,
-            is_initializer: self.is_initializer,
+            receiver: Some(instance),
+            ..self.clone()
         }
     }
 }
 
-impl LoxCallable for LoxFunction {
-    fn call(
+impl LoxFunction {
+    // Runs the body once, or for an async function schedules it to run. A call in tail
+    // position comes back as `Exit::TailCall` after this call's frame has been popped.
+    fn execute(
         &self,
         interpreter: &mut Interpreter,
         arguments: &[LiteralTypes],
     ) -> Result<LiteralTypes, Exit> {
-        let mut environment = Environment::new_with_enclosing(Rc::clone(&self.closure));
-        for (param, arg) in self.declaration.params.iter().zip(arguments.iter()) {
-            environment.define(param.lexeme.clone(), arg.clone())
+        if self.declaration.is_async {
+            return Ok(interpreter.schedule(self.clone(), arguments.to_vec()));
         }
+        self.run(interpreter, arguments)
+    }
 
-        let i = interpreter.execute_block(&self.declaration.body, environment);
+    // Runs the body of an async function, which calling it only scheduled
+    pub(crate) fn resume(
+        &self,
+        interpreter: &mut Interpreter,
+        arguments: &[LiteralTypes],
+    ) -> Result<LiteralTypes, Exit> {
+        let line = interpreter.call_line();
+        let result = self.run(interpreter, arguments);
+        finish_tail_calls(interpreter, line, result)
+    }
 
-        match &i {
-            Ok(_) => (),
-            Err(e) => {
-                if let Exit::Return(r) = e {
-                    return Ok(r.value.clone());
-                } else {
-                    return Err(Exit::RuntimeError);
-                }
+    fn run(
+        &self,
+        interpreter: &mut Interpreter,
+        arguments: &[LiteralTypes],
+    ) -> Result<LiteralTypes, Exit> {
+        trace_span!("call", function = %self.declaration.name.lexeme, line = self.declaration.name.line);
+        interpreter.enter_call(&self.declaration.name)?;
+        let caller = interpreter.push_frame(Rc::clone(&self.upvalues));
+        // Methods find `this` in the first slot, followed by the parameters
+        let mut slot = 0;
+        if let Some(instance) = &self.receiver {
+            interpreter.define_local(
+                slot,
+                LiteralTypes::Callable(Callable::Instance(Rc::clone(instance))),
+            );
+            interpreter.name_local(slot, &Symbol::intern("this"));
+            slot += 1;
+        }
+        for (arg, param) in arguments.iter().zip(self.declaration.params.iter()) {
+            interpreter.define_local(slot, arg.clone());
+            interpreter.name_local(slot, &param.lexeme);
+            slot += 1;
+        }
+
+        let mut i = arguments
+            .iter()
+            .zip(self.declaration.params.iter())
+            .try_for_each(|(arg, param)| interpreter.written(param, arg))
+            .and_then(|_| interpreter.execute_block(&self.declaration.body));
+        interpreter.pop_frame(caller);
+        if let Err(Exit::RuntimeError(error)) = &mut i {
+            if error.backtrace.is_empty() {
+                error.backtrace = interpreter.call_stack().to_vec();
             }
         }
-        if self.is_initializer {
-            return self.closure.borrow().get_at(
-                0,
-                Token {
-                    ttype: TokenType::This,
-                    lexeme: "this".to_string(),
-                    literal: LiteralTypes::Nil,
-                    line: self.declaration.name.line,
-                },
-            );
+        interpreter.exit_call();
+
+        match i {
+            Ok(_) => (),
+            Err(Exit::Return(r)) => return Ok(r.value),
+            Err(Exit::TailCall(call)) => return Err(Exit::TailCall(call)),
+            Err(error) => return Err(error),
+        }
+        if let (true, Some(instance)) = (self.is_initializer, &self.receiver) {
+            return Ok(LiteralTypes::Callable(Callable::Instance(Rc::clone(
+                instance,
+            ))));
         }
         Ok(LiteralTypes::Nil)
     }
+}
+
+impl LoxCallable for LoxFunction {
+    fn call(
+        &self,
+        interpreter: &mut Interpreter,
+        arguments: &[LiteralTypes],
+    ) -> Result<LiteralTypes, Exit> {
+        let line = interpreter.call_line();
+        let result = self.execute(interpreter, arguments);
+        finish_tail_calls(interpreter, line, result)
+    }
 
     fn arity(&self) -> usize {
         self.declaration.params.len()
@@ -146,38 +283,56 @@ impl fmt::Display for LoxFunction {
 impl LoxClass {
     pub fn new(
         name: String,
-        super_class: Option<LoxClass>,
-        methods: HashMap<String, LoxFunction>,
+        super_class: Option<Rc<LoxClass>>,
+        mut methods: HashMap<Symbol, LoxFunction>,
     ) -> Self {
+        if let Some(sc) = &super_class {
+            for (name, method) in sc.methods.iter() {
+                methods
+                    .entry(name.clone())
+                    .or_insert_with(|| method.clone());
+            }
+        }
         LoxClass {
             name,
-            super_class: super_class.map(Box::new),
+            initializer: methods.get(&Symbol::intern("init")).cloned(),
+            super_class,
             methods,
+            layout: Rc::new(RefCell::new(Vec::new())),
         }
     }
 
-    pub fn find_method(&self, name: &str) -> Option<&LoxFunction> {
-        let f = self.methods.get(name);
-        if f.is_none() {
-            if let Some(sc) = &self.super_class {
-                return sc.find_method(name);
-            }
+    pub fn field_slot(&self, name: &Symbol) -> Option<usize> {
+        self.layout.borrow().iter().position(|field| field == name)
+    }
+
+    // Slot for the named field, giving it the next one if no instance has set it yet
+    pub fn add_field(&self, name: &Symbol) -> usize {
+        if let Some(slot) = self.field_slot(name) {
+            return slot;
         }
+        let mut layout = self.layout.borrow_mut();
+        layout.push(name.clone());
+        layout.len() - 1
+    }
 
-        f
+    pub fn find_method(&self, name: &Symbol) -> Option<&LoxFunction> {
+        self.methods.get(name)
     }
 }
 
-impl LoxCallable for LoxClass {
+// Implemented on the shared handle so every instance refers to the same class
+impl LoxCallable for Rc<LoxClass> {
     fn call(
         &self,
         interpreter: &mut Interpreter,
         arguments: &[LiteralTypes],
     ) -> Result<LiteralTypes, Exit> {
-        let instance = Rc::new(RefCell::new(LoxInstance::new(Rc::new(self.clone()))));
+        trace_span!("instantiate", class = %self.name);
+        let instance = Rc::new(RefCell::new(LoxInstance::new(Rc::clone(self))));
+        interpreter.track_instance(&instance);
 
-        let initializer = self.find_method("init");
-        if let Some(init) = initializer {
+        if let Some(init) = &self.initializer {
             init.bind(Rc::clone(&instance))
                 .call(interpreter, arguments)?;
         }
@@ -188,8 +343,7 @@ impl LoxCallable for LoxClass {
     }
 
     fn arity(&self) -> usize {
-        let initializer = self.find_method("init");
-        if let Some(init) = initializer {
+        if let Some(init) = &self.initializer {
             init.arity()
         } else {
             0
@@ -197,29 +351,117 @@ impl LoxCallable for LoxClass {
     }
 }
 
+impl NativeFunction {
+    pub fn new(
+        name: String,
+        arity: usize,
+        function: impl Fn(&mut Interpreter, &[LiteralTypes]) -> Result<LiteralTypes, Exit> + 'static,
+    ) -> Self {
+        NativeFunction {
+            name,
+            arity,
+            optional: 0,
+            function: Rc::new(function),
+        }
+    }
+}
+
+impl LoxCallable for NativeFunction {
+    fn call(
+        &self,
+        interpreter: &mut Interpreter,
+        arguments: &[LiteralTypes],
+    ) -> Result<LiteralTypes, Exit> {
+        trace_span!("call", native = %self.name);
+        (self.function)(interpreter, arguments)
+    }
+
+    fn arity(&self) -> usize {
+        self.arity
+    }
+
+    fn optional(&self) -> usize {
+        self.optional
+    }
+}
+
+impl fmt::Display for NativeFunction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} native function", self.name)
+    }
+}
+
 impl LoxInstance {
     pub fn new(class: Rc<LoxClass>) -> Self {
         LoxInstance {
             class,
-            fields: HashMap::new(),
+            fields: Vec::new(),
+            finalizer: None,
         }
     }
 
-    pub fn get(&mut self, name: &Token) -> Result<LiteralTypes, Exit> {
-        if self.fields.contains_key(&name.lexeme) {
-            Ok(self.fields.get(&name.lexeme).unwrap().clone())
-        } else if let Some(method) = self.class.find_method(&name.lexeme) {
+    // Fields shadow methods, which are bound to the instance
+    pub fn get(instance: &Rc<RefCell<LoxInstance>>, name: &Token) -> Result<LiteralTypes, Exit> {
+        let this = instance.borrow();
+        if let Some(value) = this.get_field(&name.lexeme) {
+            Ok(value)
+        } else if let Some(method) = this.class.find_method(&name.lexeme) {
             Ok(LiteralTypes::Callable(Callable::Function(
-                method.bind(Rc::new(RefCell::new(self.to_owned()))),
+                method.bind(Rc::clone(instance)),
             )))
         } else {
-            report(name.line, &format!("Undefined property {}.", name.lexeme));
-            Err(Exit::RuntimeError)
+            Err(Exit::error_of(
+                ErrorKind::UndefinedProperty,
+                name,
+                format!("Undefined property {}.", name.lexeme),
+            ))
+        }
+    }
+
+    pub fn field(&self, slot: usize) -> Option<&LiteralTypes> {
+        self.fields.get(slot).and_then(|field| field.as_ref())
+    }
+
+    // Fields this instance has set, in the order its class gave them slots
+    pub fn field_values(&self) -> Vec<(Symbol, LiteralTypes)> {
+        let layout = self.class.layout.borrow();
+        layout
+            .iter()
+            .enumerate()
+            .filter_map(|(slot, name)| Some((name.clone(), self.field(slot)?.clone())))
+            .collect()
+    }
+
+    pub fn get_field(&self, name: &Symbol) -> Option<LiteralTypes> {
+        let slot = self.class.field_slot(name)?;
+        self.field(slot).cloned()
+    }
+
+    pub fn set_field(&mut self, slot: usize, value: LiteralTypes) {
+        if slot >= self.fields.len() {
+            self.fields.resize(slot + 1, None);
         }
+        self.fields[slot] = Some(value);
     }
 
     pub fn set(&mut self, name: &Token, value: &LiteralTypes) {
-        self.fields.insert(name.lexeme.clone(), value.clone());
+        let slot = self.class.add_field(&name.lexeme);
+        self.set_field(slot, value.clone());
+    }
+}
+
+// Dropping an instance whose `deinit` is yet to run queues a new one with its class and
+// fields for the interpreter to call it on, since the instance itself is gone
+impl Drop for LoxInstance {
+    fn drop(&mut self) {
+        if let Some(finalizer) = self.finalizer.take() {
+            let instance = LoxInstance {
+                class: Rc::clone(&self.class),
+                fields: std::mem::take(&mut self.fields),
+                finalizer: None,
+            };
+            finalizer.borrow_mut().push(Rc::new(RefCell::new(instance)));
+        }
     }
 }
 
@@ -228,3 +470,17 @@ impl fmt::Display for LoxInstance {
         write!(f, "{} instance", self.class.name)
     }
 }
+
+// Tail calls run here one after another instead of nesting on the host stack. Each takes
+// the place of the function called from `line`, so backtraces show it called from there.
+fn finish_tail_calls(
+    interpreter: &mut Interpreter,
+    line: usize,
+    mut result: Result<LiteralTypes, Exit>,
+) -> Result<LiteralTypes, Exit> {
+    while let Err(Exit::TailCall(call)) = result {
+        interpreter.start_tail_call(line);
+        result = call.function.execute(interpreter, &call.arguments);
+    }
+    result
+}
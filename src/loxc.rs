@@ -0,0 +1,843 @@
+/// Compiled scripts, the `.loxc` files written by `rlox compile`. A script is stored parsed
+/// and resolved, so running it goes straight to the interpreter.
+///
+/// A file is the magic `LOXC`, the format version as a little-endian u16, then the length
+/// and FNV-1a hash of the payload as little-endian u64s, then the payload: the statements
+/// followed by what the resolver recorded about them.
+use std::fmt;
+use std::ops::Range;
+use std::rc::Rc;
+
+use crate::expr::*;
+use crate::stmt::{self, Stmt};
+use crate::symbol::Symbol;
+use crate::token::{LiteralTypes, Token, TokenType};
+
+// Bumped whenever the payload changes shape, files of other versions are refused
+pub const FORMAT_VERSION: u16 = 4;
+
+const MAGIC: &[u8; 4] = b"LOXC";
+const HEADER_LEN: usize = 4 + 2 + 8 + 8;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum LoadError {
+    // Doesn't start with the magic
+    NotCompiled,
+    // Written for another version of the format
+    Version(u16),
+    // Cut short, or changed since it was written
+    Corrupt,
+}
+
+impl fmt::Display for LoadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LoadError::NotCompiled => write!(f, "Not a compiled Lox script."),
+            LoadError::Version(version) => write!(
+                f,
+                "Compiled for format version {}, this rlox reads version {}.",
+                version, FORMAT_VERSION
+            ),
+            LoadError::Corrupt => write!(f, "Compiled script is damaged."),
+        }
+    }
+}
+
+impl std::error::Error for LoadError {}
+
+// Where the resolver found a variable, with globals named rather than numbered since
+// their numbers depend on the interpreter
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum Place {
+    Local(usize),
+    Upvalue(usize),
+    Global(Symbol),
+}
+
+// What the resolver recorded for a script, keyed by node id
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Resolution {
+    pub locals: Vec<(usize, Place)>,
+    pub receivers: Vec<(usize, Place)>,
+    pub declarations: Vec<(usize, usize)>,
+    pub captures: Vec<(usize, Vec<Place>)>,
+}
+
+// A script read back from a compiled file
+pub(crate) struct Program {
+    pub statements: Vec<Stmt>,
+    pub resolution: Resolution,
+    // First id not given to any of its nodes
+    pub end_id: usize,
+}
+
+// Every token type, numbered by position
+const TOKEN_TYPES: [TokenType; 44] = [
+    TokenType::LeftParen,
+    TokenType::RightParen,
+    TokenType::LeftBrace,
+    TokenType::RightBrace,
+    TokenType::Comma,
+    TokenType::Dot,
+    TokenType::Minus,
+    TokenType::Plus,
+    TokenType::Semicolon,
+    TokenType::Slash,
+    TokenType::Star,
+    TokenType::Bang,
+    TokenType::BangEqual,
+    TokenType::Equal,
+    TokenType::EqualEqual,
+    TokenType::Greater,
+    TokenType::GreaterEqual,
+    TokenType::Less,
+    TokenType::LessEqual,
+    TokenType::Identifier,
+    TokenType::String,
+    TokenType::Number,
+    TokenType::And,
+    TokenType::Async,
+    TokenType::Await,
+    TokenType::Break,
+    TokenType::Class,
+    TokenType::Continue,
+    TokenType::Else,
+    TokenType::False,
+    TokenType::Fun,
+    TokenType::For,
+    TokenType::If,
+    TokenType::Nil,
+    TokenType::Or,
+    TokenType::Print,
+    TokenType::Return,
+    TokenType::Super,
+    TokenType::This,
+    TokenType::True,
+    TokenType::Var,
+    TokenType::While,
+    TokenType::DocComment,
+    TokenType::Eof,
+];
+
+// Ends an executable made by `rlox bundle`, after the compiled script and its length
+const BUNDLE_MAGIC: &[u8; 8] = b"LOXBUNDL";
+pub(crate) const BUNDLE_TRAILER_LEN: usize = 16;
+
+// Appends a compiled script to an interpreter executable, replacing any script it carries
+pub(crate) fn bundle(executable: &[u8], compiled: &[u8]) -> Vec<u8> {
+    let executable = match bundled_len(executable) {
+        Some(len) => &executable[..executable.len() - len - BUNDLE_TRAILER_LEN],
+        None => executable,
+    };
+    let mut bytes = Vec::with_capacity(executable.len() + compiled.len() + BUNDLE_TRAILER_LEN);
+    bytes.extend_from_slice(executable);
+    bytes.extend_from_slice(compiled);
+    bytes.extend_from_slice(&(compiled.len() as u64).to_le_bytes());
+    bytes.extend_from_slice(BUNDLE_MAGIC);
+    bytes
+}
+
+// Length of the compiled script ahead of the trailer ending the bytes, if they're an
+// executable made by `rlox bundle`. Only the trailer is needed, not the whole executable.
+pub(crate) fn bundled_len(bytes: &[u8]) -> Option<usize> {
+    let trailer = bytes.len().checked_sub(BUNDLE_TRAILER_LEN)?;
+    if &bytes[trailer + 8..] != BUNDLE_MAGIC {
+        return None;
+    }
+    let len = u64::from_le_bytes(bytes[trailer..trailer + 8].try_into().unwrap());
+    usize::try_from(len).ok()
+}
+
+fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf2_9ce4_8422_2325, |hash, &byte| {
+        (hash ^ byte as u64).wrapping_mul(0x0100_0000_01b3)
+    })
+}
+
+// Writes the statements, whose ids run from `first_id` to `end_id`, as a compiled file
+pub(crate) fn encode(
+    statements: &[Stmt],
+    resolution: &Resolution,
+    first_id: usize,
+    end_id: usize,
+) -> Vec<u8> {
+    let mut encoder = Encoder {
+        bytes: Vec::new(),
+        first_id,
+        ids: first_id..first_id,
+    };
+    encoder.usize(end_id - first_id);
+    encoder.stmts(statements);
+    encoder.resolution(resolution);
+
+    let payload = encoder.bytes;
+    let mut bytes = Vec::with_capacity(HEADER_LEN + payload.len());
+    bytes.extend_from_slice(MAGIC);
+    bytes.extend_from_slice(&FORMAT_VERSION.to_le_bytes());
+    bytes.extend_from_slice(&(payload.len() as u64).to_le_bytes());
+    bytes.extend_from_slice(&fnv1a(&payload).to_le_bytes());
+    bytes.extend_from_slice(&payload);
+    bytes
+}
+
+// Ids of the nodes in the statements, which the parser numbers consecutively, e.g. to
+// encode a single function
+pub(crate) fn node_ids(statements: &[Stmt]) -> Range<usize> {
+    let mut encoder = Encoder {
+        bytes: Vec::new(),
+        first_id: 0,
+        ids: Range {
+            start: usize::MAX,
+            end: 0,
+        },
+    };
+    encoder.stmts(statements);
+    encoder.ids
+}
+
+// Reads a compiled file, numbering its nodes from `first_id`. Statements and expressions
+// nested more than `max_depth` deep are refused rather than overflowing the stack.
+pub(crate) fn decode(
+    bytes: &[u8],
+    first_id: usize,
+    max_depth: usize,
+) -> Result<Program, LoadError> {
+    if bytes.len() < MAGIC.len() || &bytes[..MAGIC.len()] != MAGIC {
+        return Err(LoadError::NotCompiled);
+    }
+    if bytes.len() < HEADER_LEN {
+        return Err(LoadError::Corrupt);
+    }
+    let version = u16::from_le_bytes([bytes[4], bytes[5]]);
+    if version != FORMAT_VERSION {
+        return Err(LoadError::Version(version));
+    }
+    let len = u64::from_le_bytes(bytes[6..14].try_into().unwrap());
+    let hash = u64::from_le_bytes(bytes[14..22].try_into().unwrap());
+    let payload = &bytes[HEADER_LEN..];
+    if payload.len() as u64 != len || fnv1a(payload) != hash {
+        return Err(LoadError::Corrupt);
+    }
+
+    let mut decoder = Decoder {
+        bytes: payload,
+        position: 0,
+        first_id,
+        depth: 0,
+        max_depth,
+    };
+    let end_id = first_id
+        .checked_add(decoder.usize()?)
+        .ok_or(LoadError::Corrupt)?;
+    let statements = decoder.stmts()?;
+    let resolution = decoder.resolution()?;
+    if decoder.position != payload.len() {
+        return Err(LoadError::Corrupt);
+    }
+    Ok(Program {
+        statements,
+        resolution,
+        end_id,
+    })
+}
+
+struct Encoder {
+    bytes: Vec<u8>,
+    // Ids are written relative to the first, so the file can be loaded into any interpreter
+    first_id: usize,
+    // Lowest and one past the highest id written
+    ids: Range<usize>,
+}
+
+impl Encoder {
+    // LEB128
+    fn usize(&mut self, value: usize) {
+        let mut value = value;
+        while value >= 0x80 {
+            self.bytes.push(value as u8 | 0x80);
+            value >>= 7;
+        }
+        self.bytes.push(value as u8);
+    }
+
+    fn bool(&mut self, value: bool) {
+        self.bytes.push(value as u8);
+    }
+
+    fn str(&mut self, text: &str) {
+        self.usize(text.len());
+        self.bytes.extend_from_slice(text.as_bytes());
+    }
+
+    fn uuid(&mut self, uuid: usize) {
+        self.ids = self.ids.start.min(uuid)..self.ids.end.max(uuid + 1);
+        self.usize(uuid - self.first_id);
+    }
+
+    // Only constants appear in source or come out of the optimizer
+    fn literal(&mut self, literal: &LiteralTypes) {
+        match literal {
+            LiteralTypes::Bool(value) => {
+                self.bytes.push(1);
+                self.bool(*value);
+            }
+            LiteralTypes::Number(value) => {
+                self.bytes.push(2);
+                self.bytes.extend_from_slice(&value.to_le_bytes());
+            }
+            LiteralTypes::String(value) => {
+                self.bytes.push(3);
+                self.str(value);
+            }
+            _ => self.bytes.push(0),
+        }
+    }
+
+    fn token(&mut self, token: &Token) {
+        let ttype = TOKEN_TYPES
+            .iter()
+            .position(|ttype| *ttype == token.ttype)
+            .expect("every token type is listed");
+        self.usize(ttype);
+        self.str(&token.lexeme);
+        self.literal(&token.literal);
+        self.usize(token.line);
+        self.usize(token.column);
+        self.usize(token.start);
+        self.usize(token.end);
+    }
+
+    fn tokens(&mut self, tokens: &[Token]) {
+        self.usize(tokens.len());
+        for token in tokens.iter() {
+            self.token(token);
+        }
+    }
+
+    fn doc(&mut self, doc: &Option<String>) {
+        self.bool(doc.is_some());
+        if let Some(doc) = doc {
+            self.str(doc);
+        }
+    }
+
+    fn stmts(&mut self, statements: &[Stmt]) {
+        self.usize(statements.len());
+        for statement in statements.iter() {
+            self.stmt(statement);
+        }
+    }
+
+    fn stmt(&mut self, stmt: &Stmt) {
+        match stmt {
+            Stmt::Expression(s) => {
+                self.bytes.push(0);
+                self.usize(s.line);
+                self.expr(&s.expression);
+            }
+            Stmt::Print(s) => {
+                self.bytes.push(1);
+                self.token(&s.keyword);
+                self.expr(&s.expression);
+            }
+            Stmt::Var(s) => {
+                self.bytes.push(2);
+                self.uuid(s.uuid);
+                self.token(&s.name);
+                self.optional_expr(&s.initializer);
+            }
+            Stmt::Block(s) => {
+                self.bytes.push(3);
+                self.token(&s.brace);
+                self.stmts(&s.statements);
+            }
+            Stmt::If(s) => {
+                self.bytes.push(4);
+                self.token(&s.keyword);
+                self.expr(&s.condition);
+                self.stmt(&s.then_branch);
+                self.bool(s.else_branch.is_some());
+                if let Some(else_branch) = &s.else_branch {
+                    self.stmt(else_branch);
+                }
+            }
+            Stmt::While(s) => {
+                self.bytes.push(5);
+                self.token(&s.keyword);
+                self.expr(&s.condition);
+                self.stmt(&s.body);
+                self.bool(s.increment.is_some());
+                if let Some(increment) = &s.increment {
+                    self.stmt(increment);
+                }
+            }
+            Stmt::Function(s) => {
+                self.bytes.push(6);
+                self.uuid(s.uuid);
+                self.token(&s.name);
+                self.doc(&s.doc);
+                self.tokens(&s.params);
+                self.stmts(&s.body);
+                self.bool(s.is_async);
+            }
+            Stmt::Return(s) => {
+                self.bytes.push(7);
+                self.token(&s.keyword);
+                self.expr(&s.value);
+            }
+            Stmt::Break(s) => {
+                self.bytes.push(9);
+                self.token(&s.keyword);
+            }
+            Stmt::Continue(s) => {
+                self.bytes.push(10);
+                self.token(&s.keyword);
+            }
+            Stmt::Class(s) => {
+                self.bytes.push(8);
+                self.uuid(s.uuid);
+                self.token(&s.name);
+                self.doc(&s.doc);
+                self.optional_expr(&s.super_class);
+                self.stmts(&s.methods);
+            }
+        }
+    }
+
+    fn optional_expr(&mut self, expr: &Option<Box<Expr>>) {
+        self.bool(expr.is_some());
+        if let Some(expr) = expr {
+            self.expr(expr);
+        }
+    }
+
+    fn expr(&mut self, expr: &Expr) {
+        match expr {
+            Expr::Assignment(e) => {
+                self.bytes.push(0);
+                self.uuid(e.uuid);
+                self.token(&e.name);
+                self.expr(&e.value);
+            }
+            Expr::Binary(e) => {
+                self.bytes.push(1);
+                self.uuid(e.uuid);
+                self.expr(&e.left);
+                self.token(&e.operator);
+                self.expr(&e.right);
+            }
+            Expr::Grouping(e) => {
+                self.bytes.push(2);
+                self.uuid(e.uuid);
+                self.expr(&e.expr);
+            }
+            Expr::Literal(e) => {
+                self.bytes.push(3);
+                self.uuid(e.uuid);
+                self.literal(&e.value);
+            }
+            Expr::Logical(e) => {
+                self.bytes.push(4);
+                self.uuid(e.uuid);
+                self.expr(&e.left);
+                self.token(&e.operator);
+                self.expr(&e.right);
+            }
+            Expr::Unary(e) => {
+                self.bytes.push(5);
+                self.uuid(e.uuid);
+                self.token(&e.operator);
+                self.expr(&e.right);
+            }
+            Expr::Variable(e) => {
+                self.bytes.push(6);
+                self.uuid(e.uuid);
+                self.token(&e.name);
+            }
+            Expr::Call(e) => {
+                self.bytes.push(7);
+                self.uuid(e.uuid);
+                self.expr(&e.callee);
+                self.token(&e.paren);
+                self.usize(e.arguments.len());
+                for argument in e.arguments.iter() {
+                    self.expr(argument);
+                }
+            }
+            Expr::Get(e) => {
+                self.bytes.push(8);
+                self.uuid(e.uuid);
+                self.expr(&e.object);
+                self.token(&e.name);
+            }
+            Expr::Set(e) => {
+                self.bytes.push(9);
+                self.uuid(e.uuid);
+                self.expr(&e.object);
+                self.token(&e.name);
+                self.expr(&e.value);
+            }
+            Expr::This(e) => {
+                self.bytes.push(10);
+                self.uuid(e.uuid);
+                self.token(&e.keyword);
+            }
+            Expr::Super(e) => {
+                self.bytes.push(11);
+                self.uuid(e.uuid);
+                self.token(&e.keyword);
+                self.token(&e.method);
+            }
+        }
+    }
+
+    fn place(&mut self, place: &Place) {
+        match place {
+            Place::Local(slot) => {
+                self.bytes.push(0);
+                self.usize(*slot);
+            }
+            Place::Upvalue(index) => {
+                self.bytes.push(1);
+                self.usize(*index);
+            }
+            Place::Global(name) => {
+                self.bytes.push(2);
+                self.str(name);
+            }
+        }
+    }
+
+    fn places(&mut self, places: &[(usize, Place)]) {
+        self.usize(places.len());
+        for (uuid, place) in places.iter() {
+            self.uuid(*uuid);
+            self.place(place);
+        }
+    }
+
+    fn resolution(&mut self, resolution: &Resolution) {
+        self.places(&resolution.locals);
+        self.places(&resolution.receivers);
+        self.usize(resolution.declarations.len());
+        for (uuid, slot) in resolution.declarations.iter() {
+            self.uuid(*uuid);
+            self.usize(*slot);
+        }
+        self.usize(resolution.captures.len());
+        for (uuid, places) in resolution.captures.iter() {
+            self.uuid(*uuid);
+            self.usize(places.len());
+            for place in places.iter() {
+                self.place(place);
+            }
+        }
+    }
+}
+
+struct Decoder<'a> {
+    bytes: &'a [u8],
+    position: usize,
+    first_id: usize,
+    // How deeply the statement or expression being read is nested
+    depth: usize,
+    max_depth: usize,
+}
+
+impl Decoder<'_> {
+    fn byte(&mut self) -> Result<u8, LoadError> {
+        let byte = *self.bytes.get(self.position).ok_or(LoadError::Corrupt)?;
+        self.position += 1;
+        Ok(byte)
+    }
+
+    fn take(&mut self, len: usize) -> Result<&[u8], LoadError> {
+        let end = self.position.checked_add(len).ok_or(LoadError::Corrupt)?;
+        let bytes = self
+            .bytes
+            .get(self.position..end)
+            .ok_or(LoadError::Corrupt)?;
+        self.position = end;
+        Ok(bytes)
+    }
+
+    fn usize(&mut self) -> Result<usize, LoadError> {
+        let mut value: usize = 0;
+        let mut shift = 0;
+        loop {
+            let byte = self.byte()?;
+            if shift >= usize::BITS {
+                return Err(LoadError::Corrupt);
+            }
+            value |= ((byte & 0x7f) as usize) << shift;
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+            shift += 7;
+        }
+    }
+
+    fn bool(&mut self) -> Result<bool, LoadError> {
+        match self.byte()? {
+            0 => Ok(false),
+            1 => Ok(true),
+            _ => Err(LoadError::Corrupt),
+        }
+    }
+
+    fn string(&mut self) -> Result<String, LoadError> {
+        let len = self.usize()?;
+        let bytes = self.take(len)?;
+        String::from_utf8(bytes.to_vec()).map_err(|_| LoadError::Corrupt)
+    }
+
+    fn uuid(&mut self) -> Result<usize, LoadError> {
+        self.first_id
+            .checked_add(self.usize()?)
+            .ok_or(LoadError::Corrupt)
+    }
+
+    fn literal(&mut self) -> Result<LiteralTypes, LoadError> {
+        match self.byte()? {
+            0 => Ok(LiteralTypes::Nil),
+            1 => Ok(LiteralTypes::Bool(self.bool()?)),
+            2 => {
+                let bytes = self.take(8)?.try_into().unwrap();
+                Ok(LiteralTypes::Number(f64::from_le_bytes(bytes)))
+            }
+            3 => Ok(LiteralTypes::String(self.string()?)),
+            _ => Err(LoadError::Corrupt),
+        }
+    }
+
+    fn token(&mut self) -> Result<Token, LoadError> {
+        let ttype = TOKEN_TYPES
+            .get(self.usize()?)
+            .ok_or(LoadError::Corrupt)?
+            .clone();
+        let lexeme = Symbol::intern(&self.string()?);
+        let literal = self.literal()?;
+        let line = self.usize()?;
+        let column = self.usize()?;
+        let start = self.usize()?;
+        let end = self.usize()?;
+        Ok(Token::new(ttype, lexeme, literal, line, column, start, end))
+    }
+
+    fn tokens(&mut self) -> Result<Vec<Token>, LoadError> {
+        let len = self.usize()?;
+        (0..len).map(|_| self.token()).collect()
+    }
+
+    fn doc(&mut self) -> Result<Option<String>, LoadError> {
+        match self.bool()? {
+            true => Ok(Some(self.string()?)),
+            false => Ok(None),
+        }
+    }
+
+    fn stmts(&mut self) -> Result<Vec<Stmt>, LoadError> {
+        let len = self.usize()?;
+        (0..len).map(|_| self.stmt()).collect()
+    }
+
+    fn boxed_stmt(&mut self) -> Result<Box<Stmt>, LoadError> {
+        Ok(Box::new(self.stmt()?))
+    }
+
+    fn stmt(&mut self) -> Result<Stmt, LoadError> {
+        self.depth += 1;
+        if self.depth > self.max_depth {
+            return Err(LoadError::Corrupt);
+        }
+        let stmt = match self.byte()? {
+            0 => Stmt::Expression(stmt::Expression {
+                line: self.usize()?,
+                expression: self.expr()?,
+            }),
+            1 => Stmt::Print(stmt::Print {
+                keyword: self.token()?,
+                expression: self.expr()?,
+            }),
+            2 => Stmt::Var(stmt::Var {
+                uuid: self.uuid()?,
+                name: self.token()?,
+                initializer: self.optional_expr()?,
+            }),
+            3 => Stmt::Block(stmt::Block {
+                brace: self.token()?,
+                statements: self.stmts()?,
+            }),
+            4 => Stmt::If(stmt::If {
+                keyword: self.token()?,
+                condition: self.expr()?,
+                then_branch: self.boxed_stmt()?,
+                else_branch: match self.bool()? {
+                    true => Some(self.boxed_stmt()?),
+                    false => None,
+                },
+            }),
+            5 => Stmt::While(stmt::While {
+                keyword: self.token()?,
+                condition: self.expr()?,
+                body: self.boxed_stmt()?,
+                increment: match self.bool()? {
+                    true => Some(self.boxed_stmt()?),
+                    false => None,
+                },
+            }),
+            6 => Stmt::Function(Rc::new(stmt::Function {
+                uuid: self.uuid()?,
+                name: self.token()?,
+                doc: self.doc()?,
+                params: self.tokens()?,
+                body: self.stmts()?,
+                is_async: self.bool()?,
+            })),
+            7 => Stmt::Return(stmt::Return {
+                keyword: self.token()?,
+                value: self.expr()?,
+            }),
+            8 => Stmt::Class(stmt::Class {
+                uuid: self.uuid()?,
+                name: self.token()?,
+                doc: self.doc()?,
+                super_class: self.optional_expr()?,
+                methods: self.stmts()?,
+            }),
+            9 => Stmt::Break(stmt::Break {
+                keyword: self.token()?,
+            }),
+            10 => Stmt::Continue(stmt::Continue {
+                keyword: self.token()?,
+            }),
+            _ => return Err(LoadError::Corrupt),
+        };
+        self.depth -= 1;
+        Ok(stmt)
+    }
+
+    fn optional_expr(&mut self) -> Result<Option<Box<Expr>>, LoadError> {
+        match self.bool()? {
+            true => Ok(Some(self.expr()?)),
+            false => Ok(None),
+        }
+    }
+
+    fn expr(&mut self) -> Result<Box<Expr>, LoadError> {
+        self.depth += 1;
+        if self.depth > self.max_depth {
+            return Err(LoadError::Corrupt);
+        }
+        let expr = match self.byte()? {
+            0 => Expr::Assignment(Assignment {
+                uuid: self.uuid()?,
+                name: self.token()?,
+                value: self.expr()?,
+            }),
+            1 => Expr::Binary(Binary {
+                uuid: self.uuid()?,
+                left: self.expr()?,
+                operator: self.token()?,
+                right: self.expr()?,
+            }),
+            2 => Expr::Grouping(Grouping {
+                uuid: self.uuid()?,
+                expr: self.expr()?,
+            }),
+            3 => Expr::Literal(Literal {
+                uuid: self.uuid()?,
+                value: self.literal()?,
+            }),
+            4 => Expr::Logical(Logical {
+                uuid: self.uuid()?,
+                left: self.expr()?,
+                operator: self.token()?,
+                right: self.expr()?,
+            }),
+            5 => Expr::Unary(Unary {
+                uuid: self.uuid()?,
+                operator: self.token()?,
+                right: self.expr()?,
+            }),
+            6 => Expr::Variable(Variable {
+                uuid: self.uuid()?,
+                name: self.token()?,
+            }),
+            7 => Expr::Call(Call {
+                uuid: self.uuid()?,
+                callee: self.expr()?,
+                paren: self.token()?,
+                arguments: {
+                    let len = self.usize()?;
+                    (0..len)
+                        .map(|_| self.expr().map(|argument| *argument))
+                        .collect::<Result<_, _>>()?
+                },
+            }),
+            8 => Expr::Get(Get {
+                uuid: self.uuid()?,
+                object: self.expr()?,
+                name: self.token()?,
+            }),
+            9 => Expr::Set(Set {
+                uuid: self.uuid()?,
+                object: self.expr()?,
+                name: self.token()?,
+                value: self.expr()?,
+            }),
+            10 => Expr::This(This {
+                uuid: self.uuid()?,
+                keyword: self.token()?,
+            }),
+            11 => Expr::Super(Super {
+                uuid: self.uuid()?,
+                keyword: self.token()?,
+                method: self.token()?,
+            }),
+            _ => return Err(LoadError::Corrupt),
+        };
+        self.depth -= 1;
+        Ok(Box::new(expr))
+    }
+
+    fn place(&mut self) -> Result<Place, LoadError> {
+        match self.byte()? {
+            0 => Ok(Place::Local(self.usize()?)),
+            1 => Ok(Place::Upvalue(self.usize()?)),
+            2 => Ok(Place::Global(Symbol::intern(&self.string()?))),
+            _ => Err(LoadError::Corrupt),
+        }
+    }
+
+    fn places(&mut self) -> Result<Vec<(usize, Place)>, LoadError> {
+        let len = self.usize()?;
+        (0..len)
+            .map(|_| Ok((self.uuid()?, self.place()?)))
+            .collect()
+    }
+
+    fn resolution(&mut self) -> Result<Resolution, LoadError> {
+        let locals = self.places()?;
+        let receivers = self.places()?;
+        let len = self.usize()?;
+        let declarations = (0..len)
+            .map(|_| Ok((self.uuid()?, self.usize()?)))
+            .collect::<Result<_, LoadError>>()?;
+        let len = self.usize()?;
+        let captures = (0..len)
+            .map(|_| {
+                let uuid = self.uuid()?;
+                let len = self.usize()?;
+                let places = (0..len).map(|_| self.place()).collect::<Result<_, _>>()?;
+                Ok((uuid, places))
+            })
+            .collect::<Result<_, LoadError>>()?;
+        Ok(Resolution {
+            locals,
+            receivers,
+            declarations,
+            captures,
+        })
+    }
+}
@@ -1,18 +1,279 @@
-use std::env;
+use std::{env, error::Error, process, thread};
 
-use rlox::{handle_error, run_file, run_prompt};
+use rlox::doc::DocFormat;
+use rlox::{
+    ast_file, bundle_file, compile_file, cover_file, debug_file, doc_path, lint_file, profile_file,
+    record_file, render_file, replay_file, run_bundled, run_file, run_prompt, test_path,
+    tokens_file, LoxError, Outcome,
+};
+
+// Error display with exit
+fn handle_error(err: String) {
+    eprintln!("{}", err);
+    process::exit(1);
+}
+
+// Exits with the code for how the command went: the one a script gave `exit`, 65 for scripts
+// that don't scan, parse or load and 70 for ones failing to resolve or at runtime, as in
+// sysexits.h, and 1 when tests failed or the linter found something
+fn finish(result: Result<Outcome, Box<dyn Error>>) {
+    let code = match result {
+        Ok(Outcome::Done) => 0,
+        Ok(Outcome::Exited(code)) => code,
+        Ok(Outcome::Failed(LoxError::Runtime(..) | LoxError::Resolve(_))) => 70,
+        Ok(Outcome::Failed(_)) => 65,
+        Ok(Outcome::Flagged) => 1,
+        Err(err) => return handle_error(err.to_string()),
+    };
+    process::exit(code);
+}
+
+const USAGE: &str = "Usage: rlox [--strict] [--seed n] [--stats] [--coverage] [--lcov file]
+                 [--profile file] [[run] script]
+       rlox --tokens script | rlox --ast script
+       rlox compile script [-o file]
+       rlox bundle script -o file
+       rlox render template
+       rlox lint script [--config file]
+       rlox test --native path
+       rlox debug script | rlox debug --dap
+       rlox record script [-o file]
+       rlox replay trace
+       rlox kernel connection-file
+       rlox doc path [--html]";
+
+// Leaves room for the call depth the CLI allows even in debug builds
+const STACK_SIZE: usize = 64 * 1024 * 1024;
 
 fn main() {
-    let arg: Vec<String> = env::args().collect();
+    let cli = thread::Builder::new()
+        .stack_size(STACK_SIZE)
+        .spawn(run_cli)
+        .expect("failed to start the interpreter thread");
+    if cli.join().is_err() {
+        process::exit(101);
+    }
+}
+
+fn run_cli() {
+    // An executable made by `rlox bundle` runs the script it carries instead
+    if let Some(result) = run_bundled() {
+        finish(result);
+    }
+
+    let mut arg: Vec<String> = env::args().collect();
+    let strict = arg.iter().any(|a| a == "--strict");
+    arg.retain(|a| a != "--strict");
+    let stats = arg.iter().any(|a| a == "--stats");
+    arg.retain(|a| a != "--stats");
+    // Print what the scanner or the parser made of the script instead of running it
+    let tokens = arg.iter().any(|a| a == "--tokens");
+    arg.retain(|a| a != "--tokens");
+    let ast = arg.iter().any(|a| a == "--ast");
+    arg.retain(|a| a != "--ast");
+    let mut coverage = arg.iter().any(|a| a == "--coverage");
+    arg.retain(|a| a != "--coverage");
+    // Writing an lcov report turns coverage on as well
+    let lcov = match arg.iter().position(|a| a == "--lcov") {
+        Some(i) if i + 1 < arg.len() => {
+            coverage = true;
+            arg.remove(i);
+            Some(arg.remove(i))
+        }
+        Some(_) => return handle_error(USAGE.to_string()),
+        None => None,
+    };
+    // Writes the samples of a profile as folded stacks, for flamegraph tools
+    let profile = match arg.iter().position(|a| a == "--profile") {
+        Some(i) if i + 1 < arg.len() => {
+            arg.remove(i);
+            Some(arg.remove(i))
+        }
+        Some(_) => return handle_error(USAGE.to_string()),
+        None => None,
+    };
+    // Runs deterministically, `clock()` and `random()` following from the seed
+    let seed = match arg.iter().position(|a| a == "--seed") {
+        Some(i) if i + 1 < arg.len() => match arg[i + 1].parse() {
+            Ok(seed) => {
+                arg.drain(i..i + 2);
+                Some(seed)
+            }
+            Err(_) => return handle_error(USAGE.to_string()),
+        },
+        Some(_) => return handle_error(USAGE.to_string()),
+        None => None,
+    };
+
+    if arg.get(1).map(String::as_str) == Some("lint") {
+        return lint(&arg[2..]);
+    }
+    if arg.get(1).map(String::as_str) == Some("test") {
+        return test(&arg[2..]);
+    }
+    if arg.get(1).map(String::as_str) == Some("debug") {
+        return debug(&arg[2..]);
+    }
+    if arg.get(1).map(String::as_str) == Some("record") {
+        return record(&arg[2..], strict, seed);
+    }
+    if arg.get(1).map(String::as_str) == Some("replay") {
+        return replay(&arg[2..]);
+    }
+    if arg.get(1).map(String::as_str) == Some("kernel") {
+        return kernel(&arg[2..]);
+    }
+    if arg.get(1).map(String::as_str) == Some("doc") {
+        return doc(&arg[2..]);
+    }
+    if arg.get(1).map(String::as_str) == Some("compile") {
+        return compile(&arg[2..]);
+    }
+    if arg.get(1).map(String::as_str) == Some("bundle") {
+        return bundle(&arg[2..]);
+    }
+    if arg.get(1).map(String::as_str) == Some("render") {
+        return render(&arg[2..], strict);
+    }
+    // `rlox run script` is `rlox script`, for compiled scripts as well as source
+    if arg.get(1).map(String::as_str) == Some("run") && arg.len() == 3 {
+        arg.remove(1);
+    }
 
     // Does action according to number of arguments passed
     match arg.len() {
-        1 => run_prompt(),
-        2 => run_file(&arg[1]).unwrap_or_else(|err| {
-            handle_error(err.to_string());
-        }),
-        _ => {
-            handle_error("Usage: rlox [script]".to_string());
+        1 => finish(run_prompt(strict)),
+        2 => {
+            let result = match (coverage, profile) {
+                _ if tokens => tokens_file(&arg[1]),
+                _ if ast => ast_file(&arg[1]),
+                (true, _) => cover_file(&arg[1], strict, seed, lcov.as_deref()),
+                (false, Some(profile)) => profile_file(&arg[1], strict, seed, &profile),
+                (false, None) => run_file(&arg[1], strict, seed, stats),
+            };
+            finish(result)
         }
+        _ => handle_error(USAGE.to_string()),
+    }
+}
+
+// `rlox compile script [-o file]`
+fn compile(arg: &[String]) {
+    let (script, output) = match arg {
+        [script] => (script, None),
+        [script, flag, output] if flag == "-o" => (script, Some(output.as_str())),
+        _ => return handle_error("Usage: rlox compile script [-o file]".to_string()),
+    };
+    finish(compile_file(script, output))
+}
+
+// `rlox bundle script -o file`
+fn bundle(arg: &[String]) {
+    let (script, output) = match arg {
+        [script, flag, output] if flag == "-o" => (script, output),
+        _ => return handle_error("Usage: rlox bundle script -o file".to_string()),
+    };
+    finish(bundle_file(script, output))
+}
+
+// `rlox render template`, writing the rendered template to stdout
+fn render(arg: &[String], strict: bool) {
+    let [template] = arg else {
+        return handle_error("Usage: rlox render template".to_string());
+    };
+    finish(render_file(template, strict))
+}
+
+// `rlox lint script [--config file]`
+fn lint(arg: &[String]) {
+    let (script, config) = match arg {
+        [script] => (script, None),
+        [script, flag, config] if flag == "--config" => (script, Some(config.as_str())),
+        _ => return handle_error("Usage: rlox lint script [--config file]".to_string()),
+    };
+    finish(lint_file(script, config))
+}
+
+// `rlox test --native path`, running the tests scripts define with the testing natives
+fn test(arg: &[String]) {
+    let [flag, path] = arg else {
+        return handle_error("Usage: rlox test --native path".to_string());
+    };
+    if flag != "--native" {
+        return handle_error("Usage: rlox test --native path".to_string());
+    }
+    finish(test_path(path))
+}
+
+// `rlox debug script`, or `rlox debug --dap` to serve the Debug Adapter Protocol on stdio
+fn debug(arg: &[String]) {
+    match arg {
+        [flag] if flag == "--dap" => dap(),
+        [script] => finish(debug_file(script)),
+        _ => handle_error("Usage: rlox debug script | rlox debug --dap".to_string()),
+    }
+}
+
+// `rlox record script [-o file]`, running the script while writing a trace of it
+fn record(arg: &[String], strict: bool, seed: Option<u64>) {
+    let (script, output) = match arg {
+        [script] => (script, None),
+        [script, flag, output] if flag == "-o" => (script, Some(output.as_str())),
+        _ => return handle_error("Usage: rlox record script [-o file]".to_string()),
+    };
+    finish(record_file(script, output, strict, seed))
+}
+
+// `rlox replay trace`, stepping through a trace written by `rlox record`
+fn replay(arg: &[String]) {
+    let [trace] = arg else {
+        return handle_error("Usage: rlox replay trace".to_string());
+    };
+    if let Err(err) = replay_file(trace) {
+        handle_error(err.to_string());
+    }
+}
+
+#[cfg(feature = "dap")]
+fn dap() {
+    let input = Box::new(std::io::BufReader::new(std::io::stdin()));
+    if let Err(err) = rlox::dap::serve(input, Box::new(std::io::stdout())) {
+        handle_error(err.to_string());
+    }
+}
+
+#[cfg(not(feature = "dap"))]
+fn dap() {
+    handle_error("rlox was built without DAP support, rebuild with '--features dap'.".to_string());
+}
+
+// `rlox kernel connection-file`, started by Jupyter with the file it wrote
+#[cfg(feature = "kernel")]
+fn kernel(arg: &[String]) {
+    let [connection_file] = arg else {
+        return handle_error("Usage: rlox kernel connection-file".to_string());
+    };
+    if let Err(err) = rlox::kernel::serve(connection_file) {
+        handle_error(err.to_string());
+    }
+}
+
+#[cfg(not(feature = "kernel"))]
+fn kernel(_arg: &[String]) {
+    handle_error(
+        "rlox was built without Jupyter support, rebuild with '--features kernel'.".to_string(),
+    );
+}
+
+// `rlox doc path [--html]`, documenting a script or the scripts under a directory
+fn doc(arg: &[String]) {
+    let (path, format) = match arg {
+        [path] => (path, DocFormat::Markdown),
+        [path, flag] if flag == "--html" => (path, DocFormat::Html),
+        _ => return handle_error("Usage: rlox doc path [--html]".to_string()),
+    };
+    match doc_path(path, format) {
+        Ok(text) => print!("{}", text),
+        Err(err) => handle_error(err.to_string()),
     }
 }
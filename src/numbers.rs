@@ -0,0 +1,63 @@
+/// Parsing numbers out of strings: `parseInt(text, radix)`, where the radix from 2 to 36 can
+/// be left out, and `parseFloat(text)`. Whitespace around the number is ignored, and text that
+/// isn't a number gives `nil`, so scripts can check for it.
+use crate::interpreter::{Exit, Interpreter};
+use crate::token::LiteralTypes;
+
+fn text(value: &LiteralTypes) -> Result<&str, Exit> {
+    match value {
+        LiteralTypes::String(text) => Ok(text.trim()),
+        _ => Err(Exit::message("Can only parse strings.")),
+    }
+}
+
+fn radix(value: &LiteralTypes) -> Result<u32, Exit> {
+    match value {
+        LiteralTypes::Number(n) if n.fract() == 0.0 && (2.0..=36.0).contains(n) => Ok(*n as u32),
+        _ => Err(Exit::message("Radix must be a whole number from 2 to 36.")),
+    }
+}
+
+// An optionally signed integer. Without a radix, a `0x`, `0o` or `0b` prefix picks one.
+fn parse_int(text: &str, radix: Option<u32>) -> Option<f64> {
+    let (negative, text) = match text.strip_prefix('-') {
+        Some(rest) => (true, rest),
+        None => (false, text.strip_prefix('+').unwrap_or(text)),
+    };
+    let prefix = match text.get(..2).map(str::to_ascii_lowercase).as_deref() {
+        Some("0x") => Some(16),
+        Some("0o") => Some(8),
+        Some("0b") => Some(2),
+        _ => None,
+    };
+    // A prefix is also allowed when it's for the radix given
+    let (radix, digits) = match (radix, prefix) {
+        (None, Some(prefix)) => (prefix, &text[2..]),
+        (Some(radix), Some(prefix)) if radix == prefix => (radix, &text[2..]),
+        (radix, _) => (radix.unwrap_or(10), text),
+    };
+    if digits.is_empty() {
+        return None;
+    }
+    let value = digits.chars().try_fold(0.0, |value, c| {
+        Some(value * radix as f64 + c.to_digit(radix)? as f64)
+    })?;
+    Some(if negative { -value } else { value })
+}
+
+// Decimal numbers with an optional sign and exponent, as well as `inf` and `NaN`
+fn parse_float(text: &str) -> Option<f64> {
+    // Rust takes forms like `1.` and `.5`, which scripts can't write, but not `1_000`
+    text.parse().ok()
+}
+
+pub(crate) fn define_natives(interpreter: &mut Interpreter) {
+    interpreter.define_native_with_optional("parseInt", 1, 1, |_, arguments| {
+        let radix = arguments.get(1).map(radix).transpose()?;
+        Ok(parse_int(text(&arguments[0])?, radix).map_or(LiteralTypes::Nil, LiteralTypes::Number))
+    });
+
+    interpreter.define_native("parseFloat", 1, |_, arguments| {
+        Ok(parse_float(text(&arguments[0])?).map_or(LiteralTypes::Nil, LiteralTypes::Number))
+    });
+}
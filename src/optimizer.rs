@@ -0,0 +1,377 @@
+/// Optional pass run between parsing and resolving that simplifies the syntax tree.
+/// Constant expressions are folded and code that can never run is removed with a warning.
+use std::rc::Rc;
+
+use crate::expr::{self, *};
+use crate::stmt::{self, *};
+use crate::token::{LiteralTypes, TokenType};
+use crate::warn;
+
+pub struct Optimizer {
+    // Strict mode reports the errors below at runtime, so their expressions aren't folded
+    strict: bool,
+}
+
+impl Optimizer {
+    pub fn new() -> Self {
+        Optimizer { strict: false }
+    }
+
+    pub fn set_strict(&mut self, enabled: bool) {
+        self.strict = enabled;
+    }
+
+    // Statements following a `return` in the same block are dropped
+    pub fn optimize(&mut self, statements: &[Stmt]) -> Vec<Stmt> {
+        let mut optimized = Vec::new();
+        for (i, stmt) in statements.iter().enumerate() {
+            optimized.push(self.optimize_stmt(stmt));
+            if let Stmt::Return(r) = stmt {
+                if i + 1 < statements.len() {
+                    warn(r.keyword.line, "Unreachable code after return.");
+                }
+                break;
+            }
+        }
+        optimized
+    }
+
+    fn optimize_stmt(&mut self, stmt: &Stmt) -> Stmt {
+        stmt.accept(self)
+    }
+
+    fn optimize_expr(&mut self, expr: &Expr) -> Box<Expr> {
+        Box::new(expr.accept(self))
+    }
+
+    // Strict mode only accepts booleans for conditions and `!`
+    fn foldable_condition(&self, value: &LiteralTypes) -> bool {
+        !self.strict || matches!(value, LiteralTypes::Bool(_))
+    }
+}
+
+impl Default for Optimizer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn constant(expr: &Expr) -> Option<&LiteralTypes> {
+    match expr {
+        Expr::Literal(literal) => Some(&literal.value),
+        _ => None,
+    }
+}
+
+// Value the expression always evaluates to, when that's known without running it
+pub fn constant_value(expr: &Expr) -> Option<LiteralTypes> {
+    let folded = expr.accept(&mut Optimizer::new());
+    constant(&folded).cloned()
+}
+
+fn literal(uuid: usize, value: LiteralTypes) -> Expr {
+    Expr::Literal(Literal { uuid, value })
+}
+
+pub fn is_truthy(value: &LiteralTypes) -> bool {
+    match value {
+        LiteralTypes::Nil => false,
+        LiteralTypes::Bool(b) => *b,
+        _ => true,
+    }
+}
+
+// Mirrors the interpreter's binary operators, returning `None` where it would report an
+// error so the error still happens at runtime
+fn fold_binary(
+    operator: &TokenType,
+    left: &LiteralTypes,
+    right: &LiteralTypes,
+) -> Option<LiteralTypes> {
+    use LiteralTypes::{Bool, Nil, Number, String};
+
+    let value = match (operator, left, right) {
+        (TokenType::Minus, Number(l), Number(r)) => Number(l - r),
+        (TokenType::Slash, Number(l), Number(r)) => Number(l / r),
+        (TokenType::Star, Number(l), Number(r)) => Number(l * r),
+        (TokenType::Plus, Number(l), Number(r)) => Number(l + r),
+        (TokenType::Plus, String(l), String(r)) => String(format!("{}{}", l, r)),
+        (TokenType::Greater, Number(l), Number(r)) => Bool(l > r),
+        (TokenType::Greater, String(l), String(r)) => Bool(l > r),
+        (TokenType::GreaterEqual, Number(l), Number(r)) => Bool(l >= r),
+        (TokenType::GreaterEqual, String(l), String(r)) => Bool(l >= r),
+        (TokenType::Less, Number(l), Number(r)) => Bool(l < r),
+        (TokenType::Less, String(l), String(r)) => Bool(l < r),
+        (TokenType::LessEqual, Number(l), Number(r)) => Bool(l <= r),
+        (TokenType::LessEqual, String(l), String(r)) => Bool(l <= r),
+        (
+            TokenType::Greater | TokenType::GreaterEqual | TokenType::Less | TokenType::LessEqual,
+            _,
+            _,
+        ) => Bool(false),
+        (TokenType::EqualEqual | TokenType::BangEqual, _, _) => {
+            let equal = match (left, right) {
+                (Nil, Nil) => true,
+                (Number(l), Number(r)) => l == r,
+                (String(l), String(r)) => l == r,
+                (Bool(l), Bool(r)) => l == r,
+                _ => false,
+            };
+            Bool(equal == (*operator == TokenType::EqualEqual))
+        }
+        _ => return None,
+    };
+    Some(value)
+}
+
+impl stmt::Visitor<Stmt> for Optimizer {
+    fn visit_expression(&mut self, stmt: &Expression) -> Stmt {
+        Stmt::Expression(Expression {
+            line: stmt.line,
+            expression: self.optimize_expr(&stmt.expression),
+        })
+    }
+
+    fn visit_print(&mut self, stmt: &Print) -> Stmt {
+        Stmt::Print(Print {
+            keyword: stmt.keyword.clone(),
+            expression: self.optimize_expr(&stmt.expression),
+        })
+    }
+
+    fn visit_var(&mut self, stmt: &Var) -> Stmt {
+        Stmt::Var(Var {
+            uuid: stmt.uuid,
+            name: stmt.name.clone(),
+            initializer: stmt.initializer.as_ref().map(|e| self.optimize_expr(e)),
+        })
+    }
+
+    fn visit_block(&mut self, stmt: &Block) -> Stmt {
+        Stmt::Block(Block {
+            brace: stmt.brace.clone(),
+            statements: self.optimize(&stmt.statements),
+        })
+    }
+
+    // A constant condition leaves only the branch that is taken
+    fn visit_if(&mut self, stmt: &If) -> Stmt {
+        let condition = self.optimize_expr(&stmt.condition);
+        if let Some(value) = constant(&condition).filter(|value| self.foldable_condition(value)) {
+            if is_truthy(value) {
+                if stmt.else_branch.is_some() {
+                    warn(
+                        stmt.keyword.line,
+                        "Condition is always true, else branch is unreachable.",
+                    );
+                }
+                return self.optimize_stmt(&stmt.then_branch);
+            }
+
+            warn(
+                stmt.keyword.line,
+                "Condition is always false, branch is unreachable.",
+            );
+            return match &stmt.else_branch {
+                Some(else_branch) => self.optimize_stmt(else_branch),
+                None => Stmt::Block(Block {
+                    brace: stmt.keyword.clone(),
+                    statements: Vec::new(),
+                }),
+            };
+        }
+
+        Stmt::If(If {
+            keyword: stmt.keyword.clone(),
+            condition,
+            then_branch: Box::new(self.optimize_stmt(&stmt.then_branch)),
+            else_branch: stmt
+                .else_branch
+                .as_ref()
+                .map(|s| Box::new(self.optimize_stmt(s))),
+        })
+    }
+
+    fn visit_while(&mut self, stmt: &While) -> Stmt {
+        Stmt::While(While {
+            keyword: stmt.keyword.clone(),
+            condition: self.optimize_expr(&stmt.condition),
+            body: Box::new(self.optimize_stmt(&stmt.body)),
+            increment: stmt
+                .increment
+                .as_ref()
+                .map(|s| Box::new(self.optimize_stmt(s))),
+        })
+    }
+
+    fn visit_break(&mut self, stmt: &Break) -> Stmt {
+        Stmt::Break(stmt.clone())
+    }
+
+    fn visit_continue(&mut self, stmt: &Continue) -> Stmt {
+        Stmt::Continue(stmt.clone())
+    }
+
+    fn visit_function(&mut self, stmt: &Rc<Function>) -> Stmt {
+        Stmt::Function(Rc::new(Function {
+            uuid: stmt.uuid,
+            name: stmt.name.clone(),
+            doc: stmt.doc.clone(),
+            params: stmt.params.clone(),
+            body: self.optimize(&stmt.body),
+            is_async: stmt.is_async,
+        }))
+    }
+
+    fn visit_return(&mut self, stmt: &Return) -> Stmt {
+        Stmt::Return(Return {
+            keyword: stmt.keyword.clone(),
+            value: self.optimize_expr(&stmt.value),
+        })
+    }
+
+    fn visit_class(&mut self, stmt: &Class) -> Stmt {
+        Stmt::Class(Class {
+            uuid: stmt.uuid,
+            name: stmt.name.clone(),
+            doc: stmt.doc.clone(),
+            super_class: stmt.super_class.clone(),
+            methods: self.optimize(&stmt.methods),
+        })
+    }
+}
+
+impl expr::Visitor<Expr> for Optimizer {
+    fn visit_assignment(&mut self, expr: &Assignment) -> Expr {
+        Expr::Assignment(Assignment {
+            uuid: expr.uuid,
+            name: expr.name.clone(),
+            value: self.optimize_expr(&expr.value),
+        })
+    }
+
+    fn visit_binary(&mut self, expr: &Binary) -> Expr {
+        let left = self.optimize_expr(&expr.left);
+        let right = self.optimize_expr(&expr.right);
+        if let (Some(l), Some(r)) = (constant(&left), constant(&right)) {
+            let equality = matches!(
+                expr.operator.ttype,
+                TokenType::EqualEqual | TokenType::BangEqual
+            );
+            let nil = *l == LiteralTypes::Nil || *r == LiteralTypes::Nil;
+            if self.strict && !equality && nil {
+                return Expr::Binary(Binary {
+                    uuid: expr.uuid,
+                    left,
+                    operator: expr.operator.clone(),
+                    right,
+                });
+            }
+            if let Some(value) = fold_binary(&expr.operator.ttype, l, r) {
+                return literal(expr.uuid, value);
+            }
+        }
+
+        Expr::Binary(Binary {
+            uuid: expr.uuid,
+            left,
+            operator: expr.operator.clone(),
+            right,
+        })
+    }
+
+    fn visit_grouping(&mut self, expr: &Grouping) -> Expr {
+        let inner = self.optimize_expr(&expr.expr);
+        if constant(&inner).is_some() {
+            return *inner;
+        }
+
+        Expr::Grouping(Grouping {
+            uuid: expr.uuid,
+            expr: inner,
+        })
+    }
+
+    fn visit_literal(&self, expr: &Literal) -> Expr {
+        Expr::Literal(expr.clone())
+    }
+
+    // A constant left operand decides which operand the expression evaluates to
+    fn visit_logical(&mut self, expr: &Logical) -> Expr {
+        let left = self.optimize_expr(&expr.left);
+        let right = self.optimize_expr(&expr.right);
+        if let Some(l) = constant(&left) {
+            let short_circuits = match expr.operator.ttype {
+                TokenType::Or => is_truthy(l),
+                _ => !is_truthy(l),
+            };
+            return if short_circuits { *left } else { *right };
+        }
+
+        Expr::Logical(Logical {
+            uuid: expr.uuid,
+            left,
+            operator: expr.operator.clone(),
+            right,
+        })
+    }
+
+    fn visit_unary(&mut self, expr: &Unary) -> Expr {
+        let right = self.optimize_expr(&expr.right);
+        match (&expr.operator.ttype, constant(&right)) {
+            (TokenType::Minus, Some(LiteralTypes::Number(n))) => {
+                literal(expr.uuid, LiteralTypes::Number(-n))
+            }
+            (TokenType::Bang, Some(value)) if self.foldable_condition(value) => {
+                literal(expr.uuid, LiteralTypes::Bool(!is_truthy(value)))
+            }
+            _ => Expr::Unary(Unary {
+                uuid: expr.uuid,
+                operator: expr.operator.clone(),
+                right,
+            }),
+        }
+    }
+
+    fn visit_variable(&mut self, expr: &Variable) -> Expr {
+        Expr::Variable(expr.clone())
+    }
+
+    fn visit_call(&mut self, expr: &Call) -> Expr {
+        Expr::Call(Call {
+            uuid: expr.uuid,
+            callee: self.optimize_expr(&expr.callee),
+            paren: expr.paren.clone(),
+            arguments: expr
+                .arguments
+                .iter()
+                .map(|a| *self.optimize_expr(a))
+                .collect(),
+        })
+    }
+
+    fn visit_get(&mut self, expr: &Get) -> Expr {
+        Expr::Get(Get {
+            uuid: expr.uuid,
+            object: self.optimize_expr(&expr.object),
+            name: expr.name.clone(),
+        })
+    }
+
+    fn visit_set(&mut self, expr: &Set) -> Expr {
+        Expr::Set(Set {
+            uuid: expr.uuid,
+            object: self.optimize_expr(&expr.object),
+            name: expr.name.clone(),
+            value: self.optimize_expr(&expr.value),
+        })
+    }
+
+    fn visit_this(&mut self, expr: &This) -> Expr {
+        Expr::This(expr.clone())
+    }
+
+    fn visit_super(&mut self, expr: &Super) -> Expr {
+        Expr::Super(expr.clone())
+    }
+}
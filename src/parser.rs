@@ -1,57 +1,205 @@
+use std::collections::HashMap;
+use std::ops::Range;
+use std::rc::Rc;
+
 use crate::{
     expr::*,
-    stmt::{Block, Class, Expression, Function, If, Print, Return, Stmt, Var, While},
+    query::Span,
+    stmt::{
+        Block, Break, Class, Continue, Expression, Function, If, Print, Return, Stmt, Var, While,
+    },
+    symbol::Symbol,
     token::{
         LiteralTypes, Token,
         TokenType::{self, *},
     },
 };
 
-static mut UUID: usize = 0;
-
-pub fn uuid_next() -> usize {
-    unsafe {
-        UUID += 1;
-        UUID
-    }
+pub struct Parser<'a> {
+    // Tokens not looked at yet, pulled one at a time so the whole stream is never held
+    tokens: Box<dyn Iterator<Item = Token> + 'a>,
+    // The token being looked at and the one consumed before it
+    current: Token,
+    previous: Token,
+    // How many tokens have been consumed
+    consumed: usize,
+    // Text of the `///` comments right before a token, by the token's byte offset
+    docs: HashMap<usize, std::string::String>,
+    // Id given to the next node that needs one
+    next_id: usize,
+    // Byte span of every node given an id, indexed from the first id.
+    // `None` for nodes the parser makes up, like the `true` of `for (;;)`.
+    spans: Vec<Option<Span>>,
+    // Span and ids of every top-level statement parsed
+    top_level: Vec<(Span, Range<usize>)>,
+    // How many nested expressions and statements are being parsed, bounded by `max_depth`
+    // so deeply nested input is an error rather than a stack overflow
+    depth: usize,
+    max_depth: usize,
+    // Blocks and class bodies being parsed, whose `}` ends error recovery
+    blocks: usize,
+    // Whether an error has been reported, errors inside a statement being recovered from
+    // without failing the statement
+    had_error: bool,
+    // Set once the rest of the input is skipped, after which nothing more is reported
+    abandoned: bool,
 }
 
-pub struct Parser {
-    tokens: Vec<Token>,
-    current: usize,
-}
+pub const DEFAULT_MAX_DEPTH: usize = 200;
+
+// Binary operators from the loosest binding to the tightest: `or`, `and`, equality,
+// comparison, terms and factors
+const BINARY_LEVELS: [&[TokenType]; 6] = [
+    &[Or],
+    &[And],
+    &[BangEqual, EqualEqual],
+    &[Greater, GreaterEqual, Less, LessEqual],
+    &[Minus, Plus],
+    &[Slash, Star],
+];
 
 #[derive(Debug)]
 pub struct ParserError {}
 
-impl Parser {
-    pub fn new(tokens: Vec<Token>) -> Self {
-        Parser { tokens, current: 0 }
+impl<'a> Parser<'a> {
+    // Numbers nodes from `first_id`, so trees run by the same interpreter never share ids
+    pub fn new<I>(tokens: I, first_id: usize) -> Self
+    where
+        I: IntoIterator<Item = Token>,
+        I::IntoIter: 'a,
+    {
+        let end = end_of_input(None);
+        let mut parser = Parser {
+            tokens: Box::new(tokens.into_iter()),
+            current: end.clone(),
+            previous: end,
+            consumed: 0,
+            docs: HashMap::new(),
+            next_id: first_id,
+            spans: Vec::new(),
+            top_level: Vec::new(),
+            depth: 0,
+            max_depth: DEFAULT_MAX_DEPTH,
+            blocks: 0,
+            had_error: false,
+            abandoned: false,
+        };
+        parser.current = parser.next_token();
+        parser.previous = parser.current.clone();
+        parser
     }
 
-    pub fn parse(&mut self) -> Result<Vec<Stmt>, ParserError> {
-        let mut statements: Vec<Stmt> = Vec::new();
-        let mut error = false;
-        while !self.is_at_end() {
-            let s = self.declaration();
-            match &s {
-                Ok(_) => statements.push(s.unwrap()),
-                Err(_) => error = true,
+    // The next token that isn't a doc comment, keeping the text of those right before it.
+    // Input ending without an `Eof` ends where the last token did.
+    fn next_token(&mut self) -> Token {
+        let mut lines = Vec::new();
+        loop {
+            match self.tokens.next() {
+                Some(token) if token.ttype == DocComment => {
+                    if let LiteralTypes::String(text) = token.literal {
+                        lines.push(text);
+                    }
+                }
+                Some(token) => {
+                    if !lines.is_empty() {
+                        self.docs.insert(token.start, lines.join("\n"));
+                    }
+                    return token;
+                }
+                None => return end_of_input(Some(&self.current)),
             }
         }
+    }
+
+    pub fn set_max_depth(&mut self, depth: usize) {
+        self.max_depth = depth;
+    }
+
+    // First id not given to any node parsed so far
+    pub fn end_id(&self) -> usize {
+        self.next_id
+    }
+
+    // The `///` comments right before a declaration, whose first token is at `first`
+    fn doc_before(&mut self, first: usize) -> Option<std::string::String> {
+        self.docs.remove(&first)
+    }
+
+    // Byte span of a node parsed from the source
+    pub fn span_of(&self, uuid: usize) -> Option<Span> {
+        let first_id = self.next_id - self.spans.len();
+        *self.spans.get(uuid.checked_sub(first_id)?)?
+    }
+
+    // Spans of the nodes numbered in the range, kept for after the parser is gone
+    pub fn node_spans(&self, ids: Range<usize>) -> HashMap<usize, Span> {
+        ids.filter_map(|uuid| Some((uuid, self.span_of(uuid)?)))
+            .collect()
+    }
+
+    // Span and ids of every top-level statement, in order
+    pub fn top_level(&self) -> impl Iterator<Item = (Span, Range<usize>)> + '_ {
+        self.top_level.iter().cloned()
+    }
+
+    fn next_id(&mut self) -> usize {
+        self.next_id += 1;
+        self.spans.push(None);
+        self.next_id - 1
+    }
+
+    // Id for a node made of the tokens from the byte offset `first` up to the end of the
+    // last one consumed
+    fn node_id(&mut self, first: usize) -> usize {
+        let id = self.next_id();
+        let span = self.span_from(first);
+        if let Some(last) = self.spans.last_mut() {
+            *last = Some(span);
+        }
+        id
+    }
+
+    fn span_from(&self, first: usize) -> Span {
+        Span {
+            start: first,
+            end: self.previous.end.max(first),
+        }
+    }
 
-        if error {
+    pub fn parse(&mut self) -> Result<Vec<Stmt>, ParserError> {
+        let statements = self.parse_recovering();
+        if self.had_error {
             Err(ParserError {})
         } else {
             Ok(statements)
         }
     }
 
+    // Every statement that parses, skipping over the ones with errors, for tools that work
+    // on code as it's being written
+    pub fn parse_recovering(&mut self) -> Vec<Stmt> {
+        let mut statements: Vec<Stmt> = Vec::new();
+        while !self.is_at_end() {
+            let (first, first_id) = (self.peek().start, self.next_id);
+            if let Ok(statement) = self.declaration() {
+                statements.push(statement);
+                let span = self.span_from(first);
+                self.top_level.push((span, first_id..self.next_id));
+            }
+        }
+        statements
+    }
+
     fn declaration(&mut self) -> Result<Stmt, ParserError> {
+        let depth = self.depth;
+        let first = self.peek().start;
         let res = if self.token_match(&[Var]) {
             self.var_declaration()
         } else if self.token_match(&[Fun]) {
-            self.function("function")
+            self.function("function", false, first)
+        } else if self.token_match(&[Async]) {
+            self.consume(Fun, "Expect 'fun' after 'async'.")?;
+            self.function("function", true, first)
         } else if self.token_match(&[Class]) {
             self.class_declaration()
         } else {
@@ -61,13 +209,19 @@ impl Parser {
         match &res {
             Ok(_) => res,
             Err(_) => {
+                // Levels entered before the error was found are never left
+                self.depth = depth;
+                self.had_error = true;
                 self.synchronize();
                 Err(ParserError {})
             }
         }
     }
 
-    fn function(&mut self, kind: &str) -> Result<Stmt, ParserError> {
+    // Functions start at `fun`, methods at their name, or at `async` before either, which
+    // `first` is the byte offset of
+    fn function(&mut self, kind: &str, is_async: bool, first: usize) -> Result<Stmt, ParserError> {
+        let doc = self.doc_before(first);
         let name = self.consume(Identifier, &format!("Expect {} name.", kind))?;
         self.consume(LeftParen, &format!("Expect '(' after {} name.", kind))?;
 
@@ -87,24 +241,30 @@ impl Parser {
         self.consume(RightParen, "Expect ')' after parameters.")?;
 
         self.consume(LeftBrace, &format!("Expect '{{' before {} body.", kind))?;
-        let body = self.block()?;
+        // Declarations aren't statements, so functions nested in functions count here
+        let body = self.nested(Self::block)?;
 
-        Ok(Stmt::Function(Function {
+        Ok(Stmt::Function(Rc::new(Function {
+            uuid: self.node_id(first),
             name,
+            doc,
             params: parameters,
             body,
-        }))
+            is_async,
+        })))
     }
 
     fn class_declaration(&mut self) -> Result<Stmt, ParserError> {
+        let first = self.previous().start;
+        let doc = self.doc_before(first);
         let name = self.consume(Identifier, "Expect class name.")?;
 
         let super_class = if self.token_match(&[Less]) {
             self.consume(Identifier, "Expect superclass name.")?;
-            Some(Expr::Variable(Variable {
-                uuid: uuid_next(),
-                name: self.previous(),
-            }))
+            Some(Box::new(Expr::Variable(Variable {
+                uuid: self.node_id(self.previous().start),
+                name: self.previous().clone(),
+            })))
         } else {
             None
         };
@@ -112,42 +272,58 @@ impl Parser {
         self.consume(LeftBrace, "Expect '{' before class body.")?;
 
         let mut methods = Vec::new();
+        self.blocks += 1;
         while !self.check(&RightBrace) && !self.is_at_end() {
-            methods.push(self.function("method")?);
+            let first = self.peek().start;
+            let is_async = self.token_match(&[Async]);
+            match self.function("method", is_async, first) {
+                Ok(method) => methods.push(method),
+                Err(_) => {
+                    self.had_error = true;
+                    self.synchronize();
+                }
+            }
         }
+        self.blocks -= 1;
 
         self.consume(RightBrace, "Expect '}' after class body.")?;
 
         Ok(Stmt::Class(Class {
+            uuid: self.node_id(first),
             name,
+            doc,
             super_class,
             methods,
         }))
     }
 
     fn var_declaration(&mut self) -> Result<Stmt, ParserError> {
+        let first = self.previous().start;
         let name = self.consume(Identifier, "Expect variable name.")?;
 
-        let mut initializer = Expr::Literal(Literal {
-            uuid: uuid_next(),
-            value: LiteralTypes::Nil,
-        });
+        let mut initializer = None;
         if self.token_match(&[Equal]) {
-            initializer = self.expression()?;
+            initializer = Some(Box::new(self.expression()?));
         }
 
         self.consume(Semicolon, "Expect ';' after value.")?;
         Ok(Stmt::Var(Var {
+            uuid: self.node_id(first),
             name,
-            initializer: Box::new(initializer),
+            initializer,
         }))
     }
 
     fn statement(&mut self) -> Result<Stmt, ParserError> {
+        self.nested(Self::statement_inner)
+    }
+
+    fn statement_inner(&mut self) -> Result<Stmt, ParserError> {
         if self.token_match(&[Print]) {
             return self.print_statement();
         } else if self.token_match(&[LeftBrace]) {
             return Ok(Stmt::Block(Block {
+                brace: self.previous().clone(),
                 statements: self.block()?,
             }));
         } else if self.token_match(&[If]) {
@@ -158,6 +334,14 @@ impl Parser {
             return self.for_statement();
         } else if self.token_match(&[Return]) {
             return self.return_statement();
+        } else if self.token_match(&[Break]) {
+            let keyword = self.previous().clone();
+            self.consume(Semicolon, "Expect ';' after 'break'.")?;
+            return Ok(Stmt::Break(Break { keyword }));
+        } else if self.token_match(&[Continue]) {
+            let keyword = self.previous().clone();
+            self.consume(Semicolon, "Expect ';' after 'continue'.")?;
+            return Ok(Stmt::Continue(Continue { keyword }));
         }
 
         self.expression_statement()
@@ -166,23 +350,31 @@ impl Parser {
     fn block(&mut self) -> Result<Vec<Stmt>, ParserError> {
         let mut statements: Vec<Stmt> = Vec::new();
 
+        self.blocks += 1;
         while !self.check(&RightBrace) && !self.is_at_end() {
-            statements.push(self.declaration()?);
+            // Statements that fail have been reported, the rest of the block still parses
+            if let Ok(statement) = self.declaration() {
+                statements.push(statement);
+            }
         }
+        self.blocks -= 1;
 
         self.consume(RightBrace, "Expect '}' after block.")?;
         Ok(statements)
     }
 
     fn print_statement(&mut self) -> Result<Stmt, ParserError> {
+        let keyword = self.previous().clone();
         let value = self.expression()?;
         self.consume(Semicolon, "Expect ';' after value.")?;
         Ok(Stmt::Print(Print {
+            keyword,
             expression: Box::new(value),
         }))
     }
 
     fn if_statement(&mut self) -> Result<Stmt, ParserError> {
+        let keyword = self.previous().clone();
         self.consume(LeftParen, "Expect '(' after if.")?;
         let condition = self.expression()?;
         self.consume(RightParen, "Expect ')' after if condition.")?;
@@ -195,6 +387,7 @@ impl Parser {
         };
 
         Ok(Stmt::If(If {
+            keyword,
             condition: Box::new(condition),
             then_branch: Box::new(then_branch),
             else_branch: else_branch.map(Box::new),
@@ -202,18 +395,22 @@ impl Parser {
     }
 
     fn while_statement(&mut self) -> Result<Stmt, ParserError> {
+        let keyword = self.previous().clone();
         self.consume(LeftParen, "Expect '(' after while.")?;
         let condition = self.expression()?;
         self.consume(RightParen, "Expect ')' after while condition.")?;
         let body = self.statement()?;
 
         Ok(Stmt::While(While {
+            keyword,
             condition: Box::new(condition),
             body: Box::new(body),
+            increment: None,
         }))
     }
 
     fn for_statement(&mut self) -> Result<Stmt, ParserError> {
+        let keyword = self.previous().clone();
         self.consume(LeftParen, "Expect '(' after 'For'.")?;
 
         let initializer = if self.token_match(&[Semicolon]) {
@@ -228,12 +425,13 @@ impl Parser {
             self.expression()?
         } else {
             Expr::Literal(Literal {
-                uuid: uuid_next(),
+                uuid: self.next_id(),
                 value: LiteralTypes::Bool(true),
             })
         };
         self.consume(Semicolon, "Expect ';' after loop condition.")?;
 
+        let increment_line = self.peek().line;
         let increment = if !self.check(&RightParen) {
             Some(self.expression()?)
         } else {
@@ -241,26 +439,23 @@ impl Parser {
         };
         self.consume(RightParen, "Expect ')' after for clauses.")?;
 
-        let mut body = self.statement()?;
-
-        if let Some(inc) = increment {
-            body = Stmt::Block(Block {
-                statements: Vec::from([
-                    body,
-                    Stmt::Expression(Expression {
-                        expression: Box::new(inc),
-                    }),
-                ]),
-            });
-        };
+        let body = self.statement()?;
 
-        body = Stmt::While(While {
+        let mut body = Stmt::While(While {
+            keyword: keyword.clone(),
             condition: Box::new(condition),
             body: Box::new(body),
+            increment: increment.map(|inc| {
+                Box::new(Stmt::Expression(Expression {
+                    line: increment_line,
+                    expression: Box::new(inc),
+                }))
+            }),
         });
 
         if let Some(init) = initializer {
             body = Stmt::Block(Block {
+                brace: keyword,
                 statements: Vec::from([init, body]),
             })
         };
@@ -269,13 +464,13 @@ impl Parser {
     }
 
     fn return_statement(&mut self) -> Result<Stmt, ParserError> {
-        let keyword = self.previous();
+        let keyword = self.previous().clone();
 
         let value = if !self.check(&Semicolon) {
             self.expression()?
         } else {
             Expr::Literal(Literal {
-                uuid: uuid_next(),
+                uuid: self.next_id(),
                 value: LiteralTypes::Nil,
             })
         };
@@ -287,172 +482,151 @@ impl Parser {
     }
 
     fn expression_statement(&mut self) -> Result<Stmt, ParserError> {
+        let line = self.peek().line;
         let expr = self.expression()?;
         self.consume(Semicolon, "Expect ';' after value.")?;
         Ok(Stmt::Expression(Expression {
+            line,
             expression: Box::new(expr),
         }))
     }
 
+    // Parenthesized expressions recurse through `assignment`, `binary`, `unary`, `call` and
+    // `primary`, which leave what follows their operand to functions of their own so each
+    // level takes little of the stack
     fn expression(&mut self) -> Result<Expr, ParserError> {
-        self.assignment()
+        self.nested(Self::assignment)
     }
 
     fn assignment(&mut self) -> Result<Expr, ParserError> {
-        let expr = self.or()?;
+        let first = self.peek().start;
+        let expr = self.binary(0)?;
 
         if self.token_match(&[Equal]) {
-            let equals = self.previous();
-            let value = self.assignment()?;
-
-            if let Expr::Variable(v) = expr {
-                return Ok(Expr::Assignment(Assignment {
-                    uuid: uuid_next(),
-                    name: v.name,
-                    value: Box::new(value),
-                }));
-            } else if let Expr::Get(g) = expr {
-                return Ok(Expr::Set(Set {
-                    uuid: uuid_next(),
-                    object: g.object,
-                    name: g.name,
-                    value: Box::new(value),
-                }));
-            } else {
-                self.error(&equals, "Invalid assignment target.");
-                return Err(ParserError {});
-            }
+            return self.finish_assignment(expr, first);
         }
 
         Ok(expr)
     }
 
-    fn or(&mut self) -> Result<Expr, ParserError> {
-        let mut expr = self.and()?;
-
-        while self.token_match(&[Or]) {
-            let operator = self.previous();
-            let right = self.and()?;
-            expr = Expr::Logical(Logical {
-                uuid: uuid_next(),
-                left: Box::new(expr),
-                operator,
-                right: Box::new(right),
-            })
-        }
+    fn finish_assignment(&mut self, target: Expr, first: usize) -> Result<Expr, ParserError> {
+        let equals = self.previous().clone();
+        let value = self.nested(Self::assignment)?;
 
-        Ok(expr)
-    }
-
-    fn and(&mut self) -> Result<Expr, ParserError> {
-        let mut expr = self.equality()?;
-
-        while self.token_match(&[And]) {
-            let operator = self.previous();
-            let right = self.equality()?;
-            expr = Expr::Logical(Logical {
-                uuid: uuid_next(),
-                left: Box::new(expr),
-                operator,
-                right: Box::new(right),
-            })
-        }
-
-        Ok(expr)
-    }
-
-    fn equality(&mut self) -> Result<Expr, ParserError> {
-        let mut expr = self.comparison();
-
-        while self.token_match(&[BangEqual, EqualEqual]) {
-            let operator = self.previous();
-            let right = self.comparison()?;
-            expr = Ok(Expr::Binary(Binary {
-                uuid: uuid_next(),
-                left: Box::new(expr?),
-                operator,
-                right: Box::new(right),
+        if let Expr::Variable(v) = target {
+            Ok(Expr::Assignment(Assignment {
+                uuid: self.node_id(first),
+                name: v.name,
+                value: Box::new(value),
             }))
-        }
-
-        expr
-    }
-
-    fn comparison(&mut self) -> Result<Expr, ParserError> {
-        let mut expr = self.term();
-
-        while self.token_match(&[Greater, GreaterEqual, Less, LessEqual]) {
-            let operator = self.previous();
-            let right = self.term()?;
-            expr = Ok(Expr::Binary(Binary {
-                uuid: uuid_next(),
-                left: Box::new(expr?),
-                operator,
-                right: Box::new(right),
+        } else if let Expr::Get(g) = target {
+            Ok(Expr::Set(Set {
+                uuid: self.node_id(first),
+                object: g.object,
+                name: g.name,
+                value: Box::new(value),
             }))
+        } else {
+            self.error(&equals, "Invalid assignment target.");
+            Err(ParserError {})
         }
-
-        expr
     }
 
-    fn term(&mut self) -> Result<Expr, ParserError> {
-        let mut expr = self.factor();
-
-        while self.token_match(&[Minus, Plus]) {
-            let operator = self.previous();
-            let right = self.factor()?;
-            expr = Ok(Expr::Binary(Binary {
-                uuid: uuid_next(),
-                left: Box::new(expr?),
-                operator,
-                right: Box::new(right),
-            }))
+    // Parses binary operators binding at least as tightly as `level` of `BINARY_LEVELS` in one
+    // loop, recursing only for right operands. A parenthesized expression then costs one
+    // frame here instead of one per level, which matters on small stacks.
+    fn binary(&mut self, level: usize) -> Result<Expr, ParserError> {
+        let first = self.peek().start;
+        let expr = self.unary()?;
+        self.finish_binary(expr, first, level)
+    }
+
+    fn finish_binary(
+        &mut self,
+        mut expr: Expr,
+        first: usize,
+        level: usize,
+    ) -> Result<Expr, ParserError> {
+        let depth = self.depth;
+
+        while let Some(operator_level) = self.binary_operator(level) {
+            self.deepen()?;
+            self.advance();
+            let operator = self.previous().clone();
+            let right = self.binary(operator_level + 1)?;
+            let (left, right) = (Box::new(expr), Box::new(right));
+            let uuid = self.node_id(first);
+            expr = match operator.ttype {
+                Or | And => Expr::Logical(Logical {
+                    uuid,
+                    left,
+                    operator,
+                    right,
+                }),
+                _ => Expr::Binary(Binary {
+                    uuid,
+                    left,
+                    operator,
+                    right,
+                }),
+            };
         }
 
-        expr
+        self.depth = depth;
+        Ok(expr)
     }
 
-    fn factor(&mut self) -> Result<Expr, ParserError> {
-        let mut expr = self.unary();
-
-        while self.token_match(&[Slash, Star]) {
-            let operator = self.previous();
-            let right = self.unary()?;
-            expr = Ok(Expr::Binary(Binary {
-                uuid: uuid_next(),
-                left: Box::new(expr?),
-                operator,
-                right: Box::new(right),
-            }))
+    // Level of the binary operator coming next, if it binds at least as tightly as `level`
+    fn binary_operator(&self, level: usize) -> Option<usize> {
+        if self.is_at_end() {
+            return None;
         }
-
-        expr
+        let ttype = &self.peek().ttype;
+        BINARY_LEVELS
+            .iter()
+            .position(|operators| operators.contains(ttype))
+            .filter(|operator_level| *operator_level >= level)
     }
 
     fn unary(&mut self) -> Result<Expr, ParserError> {
-        if self.token_match(&[Bang, Minus]) {
-            let operator = self.previous();
-            let right = self.unary()?;
-            return Ok(Expr::Unary(Unary {
-                uuid: uuid_next(),
-                operator,
-                right: Box::new(right),
-            }));
+        let first = self.peek().start;
+        if self.token_match(&[Bang, Minus, Await]) {
+            return self.finish_unary(first);
         }
 
         self.call()
     }
 
+    fn finish_unary(&mut self, first: usize) -> Result<Expr, ParserError> {
+        let operator = self.previous().clone();
+        let right = self.nested(Self::unary)?;
+        Ok(Expr::Unary(Unary {
+            uuid: self.node_id(first),
+            operator,
+            right: Box::new(right),
+        }))
+    }
+
     fn call(&mut self) -> Result<Expr, ParserError> {
-        let mut expr = self.primary()?;
+        let first = self.peek().start;
+        let expr = self.primary()?;
+        self.postfix(expr, first)
+    }
+
+    // Calls and property accesses following `expr`
+    fn postfix(&mut self, mut expr: Expr, first: usize) -> Result<Expr, ParserError> {
+        let depth = self.depth;
 
         loop {
+            if self.check(&LeftParen) || self.check(&Dot) {
+                self.deepen()?;
+            }
             if self.token_match(&[LeftParen]) {
-                expr = self.finish_call(expr)?;
+                expr = self.finish_call(expr, first)?;
             } else if self.token_match(&[Dot]) {
                 let name = self.consume(Identifier, "Expect property name after '.'")?;
                 expr = Expr::Get(Get {
-                    uuid: uuid_next(),
+                    uuid: self.node_id(first),
                     object: Box::new(expr),
                     name,
                 });
@@ -461,18 +635,29 @@ impl Parser {
             }
         }
 
+        self.depth = depth;
         Ok(expr)
     }
 
-    fn finish_call(&mut self, callee: Expr) -> Result<Expr, ParserError> {
+    fn finish_call(&mut self, callee: Expr, first: usize) -> Result<Expr, ParserError> {
         let mut arguments = Vec::new();
+        let depth = self.depth;
 
         if !self.check(&RightParen) {
             loop {
                 if arguments.len() >= 255 {
                     self.error(self.peek(), "Can't have more than 255 arguments.");
                 }
-                arguments.push(self.expression()?);
+                match self.expression() {
+                    Ok(argument) => arguments.push(argument),
+                    Err(_) => {
+                        self.had_error = true;
+                        self.depth = depth;
+                        if !self.skip_argument() {
+                            return Err(ParserError {});
+                        }
+                    }
+                }
                 if !self.token_match(&[Comma]) {
                     break;
                 }
@@ -482,7 +667,7 @@ impl Parser {
         let paren = self.consume(RightParen, "Expect ')' after arguments.")?;
 
         Ok(Expr::Call(Call {
-            uuid: uuid_next(),
+            uuid: self.node_id(first),
             callee: Box::new(callee),
             paren,
             arguments,
@@ -490,42 +675,61 @@ impl Parser {
     }
 
     fn primary(&mut self) -> Result<Expr, ParserError> {
+        if self.token_match(&[LeftParen]) {
+            return self.grouping();
+        }
+        self.atom()
+    }
+
+    fn grouping(&mut self) -> Result<Expr, ParserError> {
+        let first = self.previous().start;
+        let expr = self.expression()?;
+        self.consume(RightParen, "Expect ')' after expression.")?;
+        Ok(Expr::Grouping(Grouping {
+            uuid: self.node_id(first),
+            expr: Box::new(expr),
+        }))
+    }
+
+    // Any primary expression but a parenthesized one
+    fn atom(&mut self) -> Result<Expr, ParserError> {
+        let first = self.peek().start;
         match self.peek().ttype {
             False => {
                 self.advance();
                 Ok(Expr::Literal(Literal {
-                    uuid: uuid_next(),
+                    uuid: self.node_id(first),
                     value: LiteralTypes::Bool(false),
                 }))
             }
             True => {
                 self.advance();
                 Ok(Expr::Literal(Literal {
-                    uuid: uuid_next(),
+                    uuid: self.node_id(first),
                     value: LiteralTypes::Bool(true),
                 }))
             }
             Nil => {
                 self.advance();
                 Ok(Expr::Literal(Literal {
-                    uuid: uuid_next(),
+                    uuid: self.node_id(first),
                     value: LiteralTypes::Nil,
                 }))
             }
             Number | String => {
                 self.advance();
                 Ok(Expr::Literal(Literal {
-                    uuid: uuid_next(),
-                    value: self.previous().literal,
+                    uuid: self.node_id(first),
+                    value: self.previous().literal.clone(),
                 }))
             }
             TokenType::Super => {
                 self.advance();
-                let keyword = self.previous();
+                let keyword = self.previous().clone();
                 self.consume(Dot, "Expect '.' after 'super'.")?;
                 let method = self.consume(Identifier, "Expect superclass method name.")?;
                 Ok(Expr::Super(crate::expr::Super {
-                    uuid: uuid_next(),
+                    uuid: self.node_id(first),
                     keyword,
                     method,
                 }))
@@ -533,29 +737,19 @@ impl Parser {
             TokenType::This => {
                 self.advance();
                 Ok(Expr::This(crate::expr::This {
-                    uuid: uuid_next(),
-                    keyword: self.previous(),
+                    uuid: self.node_id(first),
+                    keyword: self.previous().clone(),
                 }))
             }
             Identifier => {
                 self.advance();
                 Ok(Expr::Variable(Variable {
-                    uuid: uuid_next(),
-                    name: self.previous(),
-                }))
-            }
-            LeftParen => {
-                self.advance();
-                let expr = self.expression()?;
-                self.consume(RightParen, "Expect ')' after expression.")?;
-                Ok(Expr::Grouping(Grouping {
-                    uuid: uuid_next(),
-                    expr: Box::new(expr),
+                    uuid: self.node_id(first),
+                    name: self.previous().clone(),
                 }))
             }
             _ => {
                 self.error(self.peek(), "Expect expression.");
-                self.advance();
                 Err(ParserError {})
             }
         }
@@ -586,44 +780,121 @@ impl Parser {
 
     fn advance(&mut self) {
         if !self.is_at_end() {
-            self.current += 1;
+            let next = self.next_token();
+            self.previous = std::mem::replace(&mut self.current, next);
+            self.consumed += 1;
         }
     }
 
     fn peek(&self) -> &Token {
-        &self.tokens[self.current]
+        &self.current
     }
 
-    fn previous(&self) -> Token {
-        self.tokens[self.current - 1].clone()
+    fn previous(&self) -> &Token {
+        &self.previous
     }
 
     fn consume(&mut self, ttype: TokenType, message: &str) -> Result<Token, ParserError> {
         if !self.check(&ttype) {
-            self.error(&self.previous(), message);
+            self.error(self.previous(), message);
             return Err(ParserError {});
         }
 
         self.advance();
-        Ok(self.previous())
+        Ok(self.previous().clone())
+    }
+
+    fn nested<T>(
+        &mut self,
+        parse: impl FnOnce(&mut Self) -> Result<T, ParserError>,
+    ) -> Result<T, ParserError> {
+        self.deepen()?;
+        let result = parse(self);
+        self.depth -= 1;
+        result
+    }
+
+    // Enters one more level of nesting, which loops building left-nested trees also count.
+    // Past the limit the rest of the input is skipped, as there's no sensible place to
+    // resume inside the nesting.
+    fn deepen(&mut self) -> Result<(), ParserError> {
+        if self.depth >= self.max_depth {
+            self.error(self.peek(), "Too deeply nested.");
+            self.abandoned = true;
+            while !self.is_at_end() {
+                self.advance();
+            }
+            return Err(ParserError {});
+        }
+        self.depth += 1;
+        Ok(())
     }
 
     fn error(&self, token: &Token, message: &str) {
-        crate::error(token.clone(), message);
+        if !self.abandoned {
+            crate::error(token.clone(), message);
+        }
     }
 
+    // Skips to the start of the next statement. Braced code is skipped whole, and the `}`
+    // ending the enclosing block or class body is left for it to consume.
     fn synchronize(&mut self) {
-        self.advance();
-
+        let start = self.consumed;
+        let mut open = 0;
         while !self.is_at_end() {
-            if self.previous().ttype == Semicolon {
+            match self.peek().ttype {
+                RightBrace if open == 0 && self.blocks > 0 => return,
+                Class | Fun | Async | Var | For | If | While | Print | Return | Break
+                | Continue
+                    if open == 0 && self.consumed > start =>
+                {
+                    return
+                }
+                LeftBrace => open += 1,
+                RightBrace if open > 0 => open -= 1,
+                _ => {}
+            }
+            self.advance();
+            if open == 0 && matches!(self.previous().ttype, Semicolon | RightBrace) {
                 return;
             }
+        }
+    }
 
+    // Skips the rest of an argument that failed to parse, up to the `,` or `)` after it.
+    // Returns false if the statement ends first, leaving it to `synchronize`.
+    fn skip_argument(&mut self) -> bool {
+        let mut open = 0;
+        while !self.is_at_end() {
             match self.peek().ttype {
-                Class | Fun | Var | For | If | While | Print | Return => return,
-                _ => self.advance(),
+                Comma | RightParen if open == 0 => return true,
+                LeftParen => open += 1,
+                RightParen => open -= 1,
+                Semicolon | LeftBrace | RightBrace => return false,
+                _ => {}
             }
+            self.advance();
         }
+        false
     }
 }
+
+// `Eof` just past the token, or at the start of the input without one
+fn end_of_input(after: Option<&Token>) -> Token {
+    let (line, column, end) = after.map_or((1, 1, 0), |token| {
+        (
+            token.line,
+            token.column + token.lexeme.chars().count(),
+            token.end,
+        )
+    });
+    Token::new(
+        Eof,
+        Symbol::intern(""),
+        LiteralTypes::Nil,
+        line,
+        column,
+        end,
+        end,
+    )
+}
@@ -0,0 +1,78 @@
+/// Profiling through the interpreter's hook: every statement run is a sample of the calls
+/// active at the time. Samples are written as folded stacks, one line per stack like
+/// `script;main;fib 120`, which flamegraph.pl, inferno and speedscope all read.
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::rc::Rc;
+
+use crate::interpreter::{Exit, Hook, Interpreter};
+use crate::token::Token;
+
+// Statements run with each stack of calls active, keyed by the folded stack
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Profile {
+    pub stacks: BTreeMap<String, usize>,
+}
+
+impl Profile {
+    pub fn samples(&self) -> usize {
+        self.stacks.values().sum()
+    }
+
+    pub fn folded(&self) -> String {
+        self.stacks
+            .iter()
+            .map(|(stack, count)| format!("{} {}\n", stack, count))
+            .collect()
+    }
+}
+
+// Samples every statement run into a profile shared with whoever reads it afterwards
+pub(crate) struct ProfileHook {
+    pub profile: Rc<RefCell<Profile>>,
+    // Folded stack of the calls active now, starting with the script
+    stack: String,
+    // Where each active call's name starts in `stack`
+    starts: Vec<usize>,
+}
+
+impl ProfileHook {
+    pub(crate) fn new(profile: Rc<RefCell<Profile>>) -> Self {
+        ProfileHook {
+            profile,
+            stack: "script".to_string(),
+            starts: Vec::new(),
+        }
+    }
+}
+
+impl Hook for ProfileHook {
+    fn statement(&mut self, _interpreter: &mut Interpreter, _line: usize) -> Result<(), Exit> {
+        let mut profile = self.profile.borrow_mut();
+        match profile.stacks.get_mut(&self.stack) {
+            Some(count) => *count += 1,
+            None => {
+                profile.stacks.insert(self.stack.clone(), 1);
+            }
+        }
+        Ok(())
+    }
+
+    fn call(
+        &mut self,
+        _interpreter: &mut Interpreter,
+        function: &Token,
+        _line: usize,
+    ) -> Result<(), Exit> {
+        self.starts.push(self.stack.len());
+        self.stack.push(';');
+        self.stack.push_str(function.lexeme.as_str());
+        Ok(())
+    }
+
+    fn returned(&mut self, _interpreter: &mut Interpreter) {
+        if let Some(start) = self.starts.pop() {
+            self.stack.truncate(start);
+        }
+    }
+}
@@ -0,0 +1,380 @@
+/// Read-only view of a parsed script for tools. Every statement and expression is a `Node`
+/// with its kind, source span, parent and children, so nodes can be found by position or
+/// kind and their surroundings inspected without writing a visitor.
+use crate::expr::Expr;
+use crate::parser::Parser;
+use crate::stmt::Stmt;
+use crate::token::Token;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum NodeKind {
+    // Statements
+    Expression,
+    Print,
+    Var,
+    Block,
+    If,
+    While,
+    Function,
+    Return,
+    Class,
+    Break,
+    Continue,
+
+    // Expressions
+    Assignment,
+    Binary,
+    Call,
+    Get,
+    Grouping,
+    Literal,
+    Logical,
+    Set,
+    Super,
+    This,
+    Unary,
+    Variable,
+}
+
+// Index of a node in its `Ast`
+pub type NodeId = usize;
+
+// Byte offsets into the source, `end` exclusive
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+}
+
+impl Span {
+    pub fn contains(&self, offset: usize) -> bool {
+        self.start <= offset && offset < self.end
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Node {
+    pub kind: NodeKind,
+    // Empty for nodes the parser makes up, like the `true` of `for (;;)`
+    pub span: Span,
+    // Name declared or referred to, operator, or literal as written, for nodes that have one
+    pub text: Option<String>,
+    pub parent: Option<NodeId>,
+    // Id the parser gave the node, which a `Document` keeps through edits elsewhere.
+    // `None` for statements, which the parser doesn't number.
+    pub uuid: Option<usize>,
+    // In source order, apart from the increment of a `for` loop, which follows its body
+    pub children: Vec<NodeId>,
+}
+
+// Byte spans of the nodes the parser numbered
+pub(crate) trait NodeSpans {
+    fn span_of(&self, uuid: usize) -> Option<Span>;
+}
+
+impl NodeSpans for Parser<'_> {
+    fn span_of(&self, uuid: usize) -> Option<Span> {
+        Parser::span_of(self, uuid)
+    }
+}
+
+pub struct Ast {
+    // Parents come before their children
+    nodes: Vec<Node>,
+    roots: Vec<NodeId>,
+}
+
+impl Ast {
+    pub(crate) fn new<'s>(
+        source: &str,
+        spans: &dyn NodeSpans,
+        statements: impl IntoIterator<Item = &'s Stmt>,
+    ) -> Self {
+        let mut builder = Builder {
+            source,
+            spans,
+            nodes: Vec::new(),
+        };
+        let roots = statements
+            .into_iter()
+            .map(|statement| builder.stmt(statement, None))
+            .collect();
+        Ast {
+            nodes: builder.nodes,
+            roots,
+        }
+    }
+
+    // Top-level statements
+    pub fn roots(&self) -> &[NodeId] {
+        &self.roots
+    }
+
+    pub fn node(&self, id: NodeId) -> &Node {
+        &self.nodes[id]
+    }
+
+    // Every node, parents before their children
+    pub fn nodes(&self) -> &[Node] {
+        &self.nodes
+    }
+
+    // The innermost node covering the byte offset
+    pub fn find_node_at(&self, offset: usize) -> Option<NodeId> {
+        let mut found = None;
+        let mut candidates = &self.roots;
+        while let Some(&id) = candidates
+            .iter()
+            .find(|&&id| self.nodes[id].span.contains(offset))
+        {
+            found = Some(id);
+            candidates = &self.nodes[id].children;
+        }
+        found
+    }
+
+    // Every node of the kind, in the order they appear
+    pub fn nodes_of_kind(&self, kind: NodeKind) -> Vec<NodeId> {
+        (0..self.nodes.len())
+            .filter(|&id| self.nodes[id].kind == kind)
+            .collect()
+    }
+
+    pub fn parent(&self, id: NodeId) -> Option<NodeId> {
+        self.nodes[id].parent
+    }
+
+    // The node's parent, then its parent's parent and so on up to a top-level statement
+    pub fn ancestors(&self, id: NodeId) -> impl Iterator<Item = NodeId> + '_ {
+        std::iter::successors(self.parent(id), |&id| self.parent(id))
+    }
+}
+
+// Byte offset every line starts at
+pub(crate) fn line_starts(source: &str) -> Vec<usize> {
+    let newlines = source.match_indices('\n').map(|(i, _)| i + 1);
+    std::iter::once(0).chain(newlines).collect()
+}
+
+struct Builder<'a> {
+    source: &'a str,
+    spans: &'a dyn NodeSpans,
+    nodes: Vec<Node>,
+}
+
+impl<'a> Builder<'a> {
+    fn span_of(&self, token: &Token) -> Span {
+        Span {
+            start: token.start,
+            end: token.end,
+        }
+    }
+
+    // Span of a node the parser recorded
+    fn parsed_span(&self, uuid: usize) -> Option<Span> {
+        self.spans.span_of(uuid)
+    }
+
+    // Adds a node before its children, so they can refer to it as their parent
+    fn add(&mut self, kind: NodeKind, text: Option<String>, parent: Option<NodeId>) -> NodeId {
+        let id = self.nodes.len();
+        self.nodes.push(Node {
+            kind,
+            span: Span::default(),
+            text,
+            parent,
+            uuid: None,
+            children: Vec::new(),
+        });
+        if let Some(parent) = parent {
+            self.nodes[parent].children.push(id);
+        }
+        id
+    }
+
+    // Gives a node its parser id and the span it was parsed from, or failing that the span
+    // from its own token to the end of its last child
+    fn finish(&mut self, id: NodeId, uuid: Option<usize>, token: Option<&Token>) -> NodeId {
+        let parsed = uuid.and_then(|uuid| self.parsed_span(uuid));
+        let span = parsed.unwrap_or_else(|| {
+            let own = token.map(|token| self.span_of(token));
+            let children = self.nodes[id]
+                .children
+                .iter()
+                .map(|&child| self.nodes[child].span)
+                .filter(|span| span.end > span.start);
+            own.into_iter()
+                .chain(children)
+                .reduce(|l, r| Span {
+                    start: l.start.min(r.start),
+                    end: l.end.max(r.end),
+                })
+                .unwrap_or_default()
+        });
+        self.nodes[id].span = span;
+        self.nodes[id].uuid = uuid;
+        id
+    }
+
+    fn stmt(&mut self, stmt: &Stmt, parent: Option<NodeId>) -> NodeId {
+        match stmt {
+            Stmt::Expression(s) => {
+                let id = self.add(NodeKind::Expression, None, parent);
+                self.expr(&s.expression, id);
+                self.finish(id, None, None)
+            }
+            Stmt::Print(s) => {
+                let id = self.add(NodeKind::Print, None, parent);
+                self.expr(&s.expression, id);
+                self.finish(id, None, Some(&s.keyword))
+            }
+            Stmt::Var(s) => {
+                let id = self.add(NodeKind::Var, Some(s.name.lexeme.to_string()), parent);
+                if let Some(initializer) = &s.initializer {
+                    self.expr(initializer, id);
+                }
+                self.finish(id, Some(s.uuid), Some(&s.name))
+            }
+            Stmt::Block(s) => {
+                let id = self.add(NodeKind::Block, None, parent);
+                for statement in s.statements.iter() {
+                    self.stmt(statement, Some(id));
+                }
+                self.finish(id, None, Some(&s.brace))
+            }
+            Stmt::If(s) => {
+                let id = self.add(NodeKind::If, None, parent);
+                self.expr(&s.condition, id);
+                self.stmt(&s.then_branch, Some(id));
+                if let Some(else_branch) = &s.else_branch {
+                    self.stmt(else_branch, Some(id));
+                }
+                self.finish(id, None, Some(&s.keyword))
+            }
+            Stmt::While(s) => {
+                let id = self.add(NodeKind::While, None, parent);
+                self.expr(&s.condition, id);
+                self.stmt(&s.body, Some(id));
+                if let Some(increment) = &s.increment {
+                    self.stmt(increment, Some(id));
+                }
+                self.finish(id, None, Some(&s.keyword))
+            }
+            Stmt::Break(s) => {
+                let id = self.add(NodeKind::Break, None, parent);
+                self.finish(id, None, Some(&s.keyword))
+            }
+            Stmt::Continue(s) => {
+                let id = self.add(NodeKind::Continue, None, parent);
+                self.finish(id, None, Some(&s.keyword))
+            }
+            Stmt::Function(s) => {
+                let id = self.add(NodeKind::Function, Some(s.name.lexeme.to_string()), parent);
+                for statement in s.body.iter() {
+                    self.stmt(statement, Some(id));
+                }
+                self.finish(id, Some(s.uuid), Some(&s.name))
+            }
+            Stmt::Return(s) => {
+                let id = self.add(NodeKind::Return, None, parent);
+                self.expr(&s.value, id);
+                self.finish(id, None, Some(&s.keyword))
+            }
+            Stmt::Class(s) => {
+                let id = self.add(NodeKind::Class, Some(s.name.lexeme.to_string()), parent);
+                if let Some(super_class) = &s.super_class {
+                    self.expr(super_class, id);
+                }
+                for method in s.methods.iter() {
+                    self.stmt(method, Some(id));
+                }
+                self.finish(id, Some(s.uuid), Some(&s.name))
+            }
+        }
+    }
+
+    fn expr(&mut self, expr: &Expr, parent: NodeId) -> NodeId {
+        let parent = Some(parent);
+        let (id, uuid) = match expr {
+            Expr::Assignment(e) => {
+                let id = self.add(
+                    NodeKind::Assignment,
+                    Some(e.name.lexeme.to_string()),
+                    parent,
+                );
+                self.expr(&e.value, id);
+                (id, e.uuid)
+            }
+            Expr::Binary(e) => {
+                let id = self.add(
+                    NodeKind::Binary,
+                    Some(e.operator.lexeme.to_string()),
+                    parent,
+                );
+                self.expr(&e.left, id);
+                self.expr(&e.right, id);
+                (id, e.uuid)
+            }
+            Expr::Call(e) => {
+                let id = self.add(NodeKind::Call, None, parent);
+                self.expr(&e.callee, id);
+                for argument in e.arguments.iter() {
+                    self.expr(argument, id);
+                }
+                (id, e.uuid)
+            }
+            Expr::Get(e) => {
+                let id = self.add(NodeKind::Get, Some(e.name.lexeme.to_string()), parent);
+                self.expr(&e.object, id);
+                (id, e.uuid)
+            }
+            Expr::Grouping(e) => {
+                let id = self.add(NodeKind::Grouping, None, parent);
+                self.expr(&e.expr, id);
+                (id, e.uuid)
+            }
+            Expr::Literal(e) => {
+                // As written, so `1.50` isn't shown as `1.5`
+                let text = self
+                    .parsed_span(e.uuid)
+                    .and_then(|span| self.source.get(span.start..span.end))
+                    .map(str::to_string);
+                (self.add(NodeKind::Literal, text, parent), e.uuid)
+            }
+            Expr::Logical(e) => {
+                let id = self.add(
+                    NodeKind::Logical,
+                    Some(e.operator.lexeme.to_string()),
+                    parent,
+                );
+                self.expr(&e.left, id);
+                self.expr(&e.right, id);
+                (id, e.uuid)
+            }
+            Expr::Set(e) => {
+                let id = self.add(NodeKind::Set, Some(e.name.lexeme.to_string()), parent);
+                self.expr(&e.object, id);
+                self.expr(&e.value, id);
+                (id, e.uuid)
+            }
+            Expr::Super(e) => (
+                self.add(NodeKind::Super, Some(e.method.lexeme.to_string()), parent),
+                e.uuid,
+            ),
+            Expr::This(e) => (
+                self.add(NodeKind::This, Some("this".to_string()), parent),
+                e.uuid,
+            ),
+            Expr::Unary(e) => {
+                let id = self.add(NodeKind::Unary, Some(e.operator.lexeme.to_string()), parent);
+                self.expr(&e.right, id);
+                (id, e.uuid)
+            }
+            Expr::Variable(e) => (
+                self.add(NodeKind::Variable, Some(e.name.lexeme.to_string()), parent),
+                e.uuid,
+            ),
+        };
+        self.finish(id, Some(uuid), None)
+    }
+}
@@ -0,0 +1,406 @@
+/// Execution traces: every statement run, call made and returned from, and variable written,
+/// recorded through the interpreter's hook as the script runs. `rlox replay` then steps
+/// through a trace, forwards and backwards, without running the script again.
+use std::collections::BTreeSet;
+use std::fmt;
+use std::io::{self, BufRead, Write};
+use std::rc::Rc;
+
+use crate::debugger::describe;
+use crate::interpreter::{ErrorKind, Exit, Hook, Interpreter, RuntimeError};
+use crate::token::{LiteralTypes, Token};
+
+const HEADER: &str = "rlox trace 1";
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Event {
+    // A statement about to run
+    Statement {
+        line: usize,
+    },
+    // A function starting, called from the line
+    Call {
+        function: String,
+        line: usize,
+    },
+    // The function that started last returning
+    Return,
+    // A variable given a value, shown as the debugger shows values
+    Write {
+        line: usize,
+        name: String,
+        value: String,
+    },
+}
+
+// One event per line: `s line`, `c line function`, `r` or `w line name value`
+impl fmt::Display for Event {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Event::Statement { line } => write!(f, "s {}", line),
+            Event::Call { function, line } => write!(f, "c {} {}", line, function),
+            Event::Return => write!(f, "r"),
+            Event::Write { line, name, value } => write!(f, "w {} {} {}", line, name, value),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Trace {
+    // Path of the script that ran, to show its source while replaying
+    pub script: String,
+    pub events: Vec<Event>,
+}
+
+impl Trace {
+    pub fn parse(text: &str) -> Result<Trace, String> {
+        let mut lines = text.lines().enumerate();
+        if lines.next().map(|(_, line)| line) != Some(HEADER) {
+            return Err("Not an rlox trace.".to_string());
+        }
+        let mut trace = Trace::default();
+        for (i, line) in lines {
+            let bad = || format!("Bad trace event on line {}: '{}'.", i + 1, line);
+            let mut fields = line.splitn(4, ' ');
+            let kind = fields.next().unwrap_or("");
+            if kind == "script" {
+                trace.script = line["script ".len().min(line.len())..].to_string();
+                continue;
+            }
+            let line_number = match kind {
+                "r" => 0,
+                _ => fields.next().and_then(|n| n.parse().ok()).ok_or_else(bad)?,
+            };
+            let event = match (kind, fields.next(), fields.next()) {
+                ("s", None, None) => Event::Statement { line: line_number },
+                ("c", Some(function), None) => Event::Call {
+                    function: function.to_string(),
+                    line: line_number,
+                },
+                ("r", None, None) => Event::Return,
+                ("w", Some(name), Some(value)) => Event::Write {
+                    line: line_number,
+                    name: name.to_string(),
+                    value: value.to_string(),
+                },
+                _ => return Err(bad()),
+            };
+            trace.events.push(event);
+        }
+        Ok(trace)
+    }
+}
+
+// Writes every event to `output` as it happens, so a trace survives the script crashing
+// the host. Failing to write stops the script.
+pub struct Recorder {
+    output: Box<dyn Write>,
+}
+
+impl Recorder {
+    pub fn new(mut output: Box<dyn Write>, script: &str) -> io::Result<Self> {
+        writeln!(output, "{}\nscript {}", HEADER, script)?;
+        Ok(Recorder { output })
+    }
+
+    fn record(&mut self, event: Event) -> Result<(), Exit> {
+        writeln!(self.output, "{}", event).map_err(|err| {
+            Exit::RuntimeError(Box::new(RuntimeError {
+                token: None,
+                message: format!("Error writing trace: {}.", err),
+                kind: ErrorKind::Other,
+                backtrace: Vec::new(),
+            }))
+        })
+    }
+}
+
+impl Hook for Recorder {
+    fn statement(&mut self, _interpreter: &mut Interpreter, line: usize) -> Result<(), Exit> {
+        self.record(Event::Statement { line })
+    }
+
+    fn call(
+        &mut self,
+        _interpreter: &mut Interpreter,
+        function: &Token,
+        line: usize,
+    ) -> Result<(), Exit> {
+        self.record(Event::Call {
+            function: function.lexeme.to_string(),
+            line,
+        })
+    }
+
+    fn returned(&mut self, _interpreter: &mut Interpreter) {
+        // Nothing can be stopped from here, a write failing shows on the next event
+        let _ = self.record(Event::Return);
+    }
+
+    fn write(
+        &mut self,
+        interpreter: &mut Interpreter,
+        name: &Token,
+        value: &LiteralTypes,
+    ) -> Result<(), Exit> {
+        // Strings are quoted, so the value stays on one line
+        self.record(Event::Write {
+            line: name.line,
+            name: name.lexeme.to_string(),
+            value: describe(interpreter, value),
+        })
+    }
+}
+
+impl Drop for Recorder {
+    fn drop(&mut self) {
+        let _ = self.output.flush();
+    }
+}
+
+// A call active at some point of the trace and the variables written while it ran
+struct Frame {
+    function: String,
+    line: usize,
+    variables: Vec<(String, String)>,
+}
+
+// Line-based viewer for `rlox replay`, reading commands from stdin
+pub struct Replay {
+    trace: Trace,
+    source: Option<Rc<str>>,
+    // Call depth at every event
+    depths: Vec<usize>,
+    breakpoints: BTreeSet<usize>,
+    // Index of the statement event being shown
+    position: usize,
+    last_command: String,
+}
+
+const HELP: &str = "\
+step                go to the next statement, stepping into calls
+next                go to the next statement, stepping over calls
+back                go to the previous statement
+continue            go forwards to the next breakpoint
+reverse             go backwards to the previous breakpoint
+break [line]        stop at a line, or list breakpoints without one
+delete line         remove a breakpoint
+locals              show the variables written in every active call, innermost first
+print name          show the value last written to a variable
+backtrace           show the active calls
+quit                stop replaying";
+
+impl Replay {
+    // `source` is the script's text, if it can still be read
+    pub fn new(trace: Trace, source: Option<&str>) -> Self {
+        let mut depth: usize = 0;
+        let depths = trace
+            .events
+            .iter()
+            .map(|event| {
+                match event {
+                    Event::Call { .. } => depth += 1,
+                    Event::Return => depth = depth.saturating_sub(1),
+                    _ => {}
+                }
+                depth
+            })
+            .collect();
+        let mut replay = Replay {
+            trace,
+            source: source.map(Rc::from),
+            depths,
+            breakpoints: BTreeSet::new(),
+            position: 0,
+            last_command: String::new(),
+        };
+        replay.position = replay.next_statement(0, |_| true).unwrap_or(0);
+        replay
+    }
+
+    fn line(&self, position: usize) -> usize {
+        match self.trace.events.get(position) {
+            Some(Event::Statement { line }) => *line,
+            _ => 0,
+        }
+    }
+
+    // First statement from `start` on that `stop` accepts
+    fn next_statement(&self, start: usize, stop: impl Fn(usize) -> bool) -> Option<usize> {
+        (start..self.trace.events.len())
+            .find(|&i| matches!(self.trace.events[i], Event::Statement { .. }) && stop(i))
+    }
+
+    // Last statement before `end` that `stop` accepts
+    fn previous_statement(&self, end: usize, stop: impl Fn(usize) -> bool) -> Option<usize> {
+        (0..end)
+            .rev()
+            .find(|&i| matches!(self.trace.events[i], Event::Statement { .. }) && stop(i))
+    }
+
+    // Calls active at the current statement, the script first
+    fn frames(&self) -> Vec<Frame> {
+        let mut frames = vec![Frame {
+            function: "script".to_string(),
+            line: 0,
+            variables: Vec::new(),
+        }];
+        for event in self.trace.events[..self.position].iter() {
+            match event {
+                Event::Call { function, line } => frames.push(Frame {
+                    function: function.clone(),
+                    line: *line,
+                    variables: Vec::new(),
+                }),
+                Event::Return if frames.len() > 1 => {
+                    frames.pop();
+                }
+                Event::Write { name, value, .. } => {
+                    let Some(frame) = frames.last_mut() else {
+                        continue;
+                    };
+                    match frame.variables.iter_mut().find(|(n, _)| n == name) {
+                        Some(variable) => variable.1 = value.clone(),
+                        None => frame.variables.push((name.clone(), value.clone())),
+                    }
+                }
+                _ => {}
+            }
+        }
+        frames
+    }
+
+    fn show(&self) {
+        let line = self.line(self.position);
+        let text = self
+            .source
+            .as_deref()
+            .and_then(|source| source.lines().nth(line.saturating_sub(1)))
+            .unwrap_or("");
+        println!(
+            "[{}/{}] {:>4} | {}",
+            self.position + 1,
+            self.trace.events.len(),
+            line,
+            text.trim_end()
+        );
+    }
+
+    fn go(&mut self, position: Option<usize>, end: &str) {
+        match position {
+            Some(position) => {
+                self.position = position;
+                if self.breakpoints.contains(&self.line(position)) {
+                    println!("Breakpoint at line {}", self.line(position));
+                }
+                self.show();
+            }
+            None => println!("{}", end),
+        }
+    }
+
+    pub fn run(&mut self) {
+        if self.trace.events.is_empty() {
+            println!("The trace is empty.");
+            return;
+        }
+        self.show();
+        loop {
+            print!("(replay) ");
+            let _ = io::stdout().flush();
+            let mut line = String::new();
+            if io::stdin().lock().read_line(&mut line).unwrap_or(0) == 0 {
+                return;
+            }
+            let command = match line.trim() {
+                "" => self.last_command.clone(),
+                command => command.to_string(),
+            };
+            self.last_command = command.clone();
+
+            let (name, argument) = command.split_once(' ').unwrap_or((&command, ""));
+            let argument = argument.trim();
+            let depth = self.depths[self.position];
+            let after = self.position + 1;
+            match name {
+                "s" | "step" => {
+                    let next = self.next_statement(after, |_| true);
+                    self.go(next, "End of trace.");
+                }
+                "n" | "next" => {
+                    let next = self.next_statement(after, |i| self.depths[i] <= depth);
+                    self.go(next, "End of trace.");
+                }
+                "k" | "back" => {
+                    let previous = self.previous_statement(self.position, |_| true);
+                    self.go(previous, "Start of trace.");
+                }
+                "c" | "continue" => {
+                    let next =
+                        self.next_statement(after, |i| self.breakpoints.contains(&self.line(i)));
+                    self.go(
+                        next,
+                        "No breakpoint is reached before the end of the trace.",
+                    );
+                }
+                "r" | "reverse" => {
+                    let previous = self.previous_statement(self.position, |i| {
+                        self.breakpoints.contains(&self.line(i))
+                    });
+                    self.go(
+                        previous,
+                        "No breakpoint is reached before the start of the trace.",
+                    );
+                }
+                "b" | "break" if argument.is_empty() => {
+                    for line in self.breakpoints.iter() {
+                        println!("{}:{}", self.trace.script, line);
+                    }
+                }
+                "b" | "break" | "d" | "delete" => match argument.parse::<usize>() {
+                    Ok(line) if name.starts_with('b') => {
+                        self.breakpoints.insert(line);
+                        println!("Breakpoint set at line {}", line);
+                    }
+                    Ok(line) if self.breakpoints.remove(&line) => {
+                        println!("Breakpoint at line {} deleted", line)
+                    }
+                    Ok(line) => println!("No breakpoint at line {}.", line),
+                    Err(_) => println!("Expected a line number, found '{}'.", argument),
+                },
+                "l" | "locals" => {
+                    for frame in self.frames().iter().rev() {
+                        println!("{}:", frame.function);
+                        for (name, value) in frame.variables.iter() {
+                            println!("  {} = {}", name, value);
+                        }
+                    }
+                }
+                "p" | "print" => {
+                    let frames = self.frames();
+                    let value = frames.iter().rev().find_map(|frame| {
+                        frame
+                            .variables
+                            .iter()
+                            .find(|(name, _)| name == argument)
+                            .map(|(_, value)| value)
+                    });
+                    match value {
+                        Some(value) => println!("{}", value),
+                        None => println!("No value written to '{}' yet.", argument),
+                    }
+                }
+                "bt" | "backtrace" => {
+                    let mut line = self.line(self.position);
+                    for (i, frame) in self.frames().iter().rev().enumerate() {
+                        println!("#{} {} at line {}", i, frame.function, line);
+                        line = frame.line;
+                    }
+                }
+                "q" | "quit" => return,
+                "h" | "help" => println!("{}", HELP),
+                _ => println!("Unknown command '{}', 'help' lists them.", name),
+            }
+        }
+    }
+}
@@ -0,0 +1,110 @@
+/// Reflection natives, for serializers and pretty-printers written in Lox: `fields(object)`
+/// lists the fields an instance has set, `methods(class)` the methods a class or an instance's
+/// class has, inherited ones included, and `hasField(object, name)` tells whether a field is
+/// set. Maps work like instances, with their keys as the fields. `getattr(object, name)` and
+/// `setattr(object, name, value)` are `object.name` and `object.name = value` for a name
+/// computed while the script runs.
+use crate::interpreter::{Exit, Interpreter};
+use crate::lox_callable::Callable;
+use crate::symbol::Symbol;
+use crate::token::LiteralTypes;
+
+fn names(names: impl IntoIterator<Item = String>) -> LiteralTypes {
+    names
+        .into_iter()
+        .map(LiteralTypes::String)
+        .collect::<Vec<_>>()
+        .into()
+}
+
+fn name(value: &LiteralTypes) -> Result<&str, Exit> {
+    match value {
+        LiteralTypes::String(name) => Ok(name),
+        _ => Err(Exit::message("Property name must be a string.")),
+    }
+}
+
+// Defines `fields`, `methods`, `hasField`, `getattr` and `setattr`
+pub(crate) fn define_natives(interpreter: &mut Interpreter) {
+    // In the order the class's instances first set them
+    interpreter.define_native("fields", 1, |_, arguments| match &arguments[0] {
+        LiteralTypes::Callable(Callable::Instance(instance)) => {
+            let fields = instance.borrow().field_values();
+            Ok(names(fields.into_iter().map(|(name, _)| name.to_string())))
+        }
+        LiteralTypes::Map(map) => Ok(names(map.borrow().keys().cloned())),
+        _ => Err(Exit::message("Only instances and maps have fields.")),
+    });
+
+    // Sorted by name
+    interpreter.define_native("methods", 1, |_, arguments| {
+        let class = match &arguments[0] {
+            LiteralTypes::Callable(Callable::Class(class)) => class.clone(),
+            LiteralTypes::Callable(Callable::Instance(instance)) => instance.borrow().class.clone(),
+            _ => return Err(Exit::message("Only classes and instances have methods.")),
+        };
+        let mut methods: Vec<String> = class.methods.keys().map(|name| name.to_string()).collect();
+        methods.sort();
+        Ok(names(methods))
+    });
+
+    interpreter.define_native("hasField", 2, |_, arguments| {
+        let LiteralTypes::String(name) = &arguments[1] else {
+            return Err(Exit::message("Field name must be a string."));
+        };
+        match &arguments[0] {
+            LiteralTypes::Callable(Callable::Instance(instance)) => {
+                let field = instance.borrow().get_field(&Symbol::intern(name));
+                Ok(field.is_some().into())
+            }
+            LiteralTypes::Map(map) => Ok(map.borrow().contains_key(name).into()),
+            _ => Err(Exit::message("Only instances and maps have fields.")),
+        }
+    });
+
+    // Fields shadow methods, which come back bound to the instance, as with `object.name`
+    interpreter.define_native("getattr", 2, |_, arguments| {
+        let name = name(&arguments[1])?;
+        let value = match &arguments[0] {
+            LiteralTypes::Callable(Callable::Instance(instance)) => {
+                let symbol = Symbol::intern(name);
+                let this = instance.borrow();
+                match this.get_field(&symbol) {
+                    Some(value) => Some(value),
+                    None => this.class.find_method(&symbol).map(|method| {
+                        LiteralTypes::Callable(Callable::Function(method.bind(instance.clone())))
+                    }),
+                }
+            }
+            LiteralTypes::Map(map) => match map.borrow().get(name) {
+                Some(value) => Some(value.clone()),
+                None => return Err(Exit::message(format!("Undefined key {}.", name))),
+            },
+            LiteralTypes::Callable(Callable::Host(host)) => host.borrow().get(name),
+            _ => return Err(Exit::message("Only instances have properties.")),
+        };
+        value.ok_or_else(|| Exit::message(format!("Undefined property {}.", name)))
+    });
+
+    interpreter.define_native("setattr", 3, |_, arguments| {
+        let name = name(&arguments[1])?;
+        let value = arguments[2].clone();
+        match &arguments[0] {
+            LiteralTypes::Callable(Callable::Instance(instance)) => {
+                let mut this = instance.borrow_mut();
+                let slot = this.class.add_field(&Symbol::intern(name));
+                this.set_field(slot, value.clone());
+            }
+            LiteralTypes::Map(map) => {
+                map.borrow_mut().insert(name.to_string(), value.clone());
+            }
+            LiteralTypes::Callable(Callable::Host(host)) => {
+                host.borrow_mut()
+                    .set(name, value.clone())
+                    .map_err(Exit::message)?;
+            }
+            _ => return Err(Exit::message("Only instances have fields.")),
+        }
+        Ok(value)
+    });
+}
@@ -1,19 +1,52 @@
 use std::collections::HashMap;
+use std::rc::Rc;
 
+use crate::environment::Binding;
 use crate::expr::Expr;
 use crate::expr::*;
 use crate::interpreter::Interpreter;
 use crate::parser::ParserError;
 use crate::stmt::*;
+use crate::symbol::Symbol;
 use crate::token::Token;
 
 pub struct Resolver<'a> {
     interpreter: &'a mut Interpreter,
-    scopes: Vec<HashMap<String, bool>>,
+    scopes: Vec<HashMap<Symbol, Local>>,
+    // The function being resolved and every function enclosing it, the script first
+    functions: Vec<FunctionScope>,
     current_function: FunctionType,
     current_class: ClassType,
 }
 
+// A local's slot in its function's frame, and whether its initializer has finished
+struct Local {
+    slot: usize,
+    defined: bool,
+}
+
+struct FunctionScope {
+    // Index of the function's outermost scope in `scopes`
+    first_scope: usize,
+    // Slot for the next local, slots are reused once their scope ends
+    next_slot: usize,
+    // Variables of enclosing functions the function captures
+    upvalues: Vec<Binding>,
+    // Loops around the statement being resolved, which `break` and `continue` need one of
+    loops: usize,
+}
+
+impl FunctionScope {
+    fn new(first_scope: usize) -> Self {
+        FunctionScope {
+            first_scope,
+            next_slot: 0,
+            upvalues: Vec::new(),
+            loops: 0,
+        }
+    }
+}
+
 #[derive(Clone, Copy, PartialEq)]
 enum FunctionType {
     None,
@@ -34,6 +67,7 @@ impl<'a> Resolver<'a> {
         Resolver {
             interpreter,
             scopes: Vec::new(),
+            functions: vec![FunctionScope::new(0)],
             current_function: FunctionType::None,
             current_class: ClassType::None,
         }
@@ -51,8 +85,8 @@ impl<'a> Resolver<'a> {
         Ok(())
     }
 
-    fn resolve_expr(&mut self, expression: &Expr) {
-        let _ = expression.accept(self);
+    fn resolve_expr(&mut self, expression: &Expr) -> Result<(), ParserError> {
+        expression.accept(self)
     }
 
     fn begin_scope(&mut self) {
@@ -60,33 +94,100 @@ impl<'a> Resolver<'a> {
     }
 
     fn end_scope(&mut self) {
-        self.scopes.pop();
+        if let Some(scope) = self.scopes.pop() {
+            self.functions.last_mut().unwrap().next_slot -= scope.len();
+        }
     }
 
-    fn declare(&mut self, name: Token) -> Result<(), ParserError> {
-        if !self.scopes.is_empty() {
-            if self.scopes.last().unwrap().contains_key(&name.lexeme) {
-                crate::error(name, "Already a variable with this name in this scope.");
-                return Err(ParserError {});
-            }
-            self.scopes.last_mut().unwrap().insert(name.lexeme, false);
-        }
+    fn next_slot(&mut self) -> usize {
+        let function = self.functions.last_mut().unwrap();
+        function.next_slot += 1;
+        function.next_slot - 1
+    }
 
-        Ok(())
+    // Returns the frame slot of the new local, or `None` for a global
+    fn declare(&mut self, name: Token) -> Result<Option<usize>, ParserError> {
+        if self.scopes.is_empty() {
+            return Ok(None);
+        }
+        if self.scopes.last().unwrap().contains_key(&name.lexeme) {
+            crate::error(name, "Already a variable with this name in this scope.");
+            return Err(ParserError {});
+        }
+        let slot = self.next_slot();
+        self.scopes.last_mut().unwrap().insert(
+            name.lexeme,
+            Local {
+                slot,
+                defined: false,
+            },
+        );
+
+        Ok(Some(slot))
     }
 
     fn define(&mut self, name: Token) {
-        if !self.scopes.is_empty() {
-            self.scopes.last_mut().unwrap().insert(name.lexeme, true);
+        if let Some(local) = self
+            .scopes
+            .last_mut()
+            .and_then(|scope| scope.get_mut(&name.lexeme))
+        {
+            local.defined = true;
         }
     }
 
-    fn resolve_local(&mut self, expr: &Expr, name: Token) {
-        for (i, scope) in self.scopes.iter().enumerate().rev() {
-            if scope.contains_key(&name.lexeme) {
-                self.interpreter.resolve(expr, self.scopes.len() - 1 - i);
+    // Declares a name the interpreter defines implicitly, such as `this` and `super`
+    fn declare_implicit(&mut self, name: &str) -> usize {
+        let slot = self.next_slot();
+        self.scopes.last_mut().unwrap().insert(
+            Symbol::intern(name),
+            Local {
+                slot,
+                defined: true,
+            },
+        );
+        slot
+    }
+
+    fn resolve_local(&mut self, uuid: usize, name: &Token) {
+        match self.binding(&name.lexeme) {
+            Some(binding) => self.interpreter.resolve(uuid, binding),
+            None => self.interpreter.resolve_global(uuid, &name.lexeme),
+        }
+    }
+
+    // Finds the innermost local with the name, as seen from the current function
+    fn binding(&mut self, name: &Symbol) -> Option<Binding> {
+        for i in (0..self.scopes.len()).rev() {
+            if let Some(local) = self.scopes[i].get(name) {
+                let slot = local.slot;
+                let owner = self
+                    .functions
+                    .iter()
+                    .rposition(|f| f.first_scope <= i)
+                    .unwrap();
+                return Some(self.capture(self.functions.len() - 1, owner, slot));
             }
         }
+        None
+    }
+
+    // Threads a local of the `owner` function through the upvalues of every function
+    // between it and `function`
+    fn capture(&mut self, function: usize, owner: usize, slot: usize) -> Binding {
+        if function == owner {
+            return Binding::Local(slot);
+        }
+        let outer = self.capture(function - 1, owner, slot);
+        let upvalues = &mut self.functions[function].upvalues;
+        let index = match upvalues.iter().position(|u| *u == outer) {
+            Some(index) => index,
+            None => {
+                upvalues.push(outer);
+                upvalues.len() - 1
+            }
+        };
+        Binding::Upvalue(index)
     }
 
     fn resolve_function(
@@ -96,16 +197,46 @@ impl<'a> Resolver<'a> {
     ) -> Result<(), ParserError> {
         let enclosing_fn = self.current_function;
         self.current_function = ftype;
+        self.functions.push(FunctionScope::new(self.scopes.len()));
         self.begin_scope();
-        for param in function.params.iter() {
+        // Methods receive `this` in the slot before their parameters
+        if ftype == FunctionType::Method || ftype == FunctionType::Initializer {
+            self.declare_implicit("this");
+        }
+        for (i, param) in function.params.iter().enumerate() {
+            if function.params[..i]
+                .iter()
+                .any(|p| p.lexeme == param.lexeme)
+            {
+                crate::error(
+                    param.clone(),
+                    "Already a parameter with this name in this function.",
+                );
+                return Err(ParserError {});
+            }
             self.declare(param.clone())?;
             self.define(param.clone());
         }
         self.resolve_each(&function.body)?;
         self.end_scope();
+        let scope = self.functions.pop().unwrap();
+        self.interpreter
+            .resolve_captures(function.uuid, scope.upvalues);
         self.current_function = enclosing_fn;
         Ok(())
     }
+
+    // Loops don't reach into the functions declared in them
+    fn in_loop(&self, keyword: &Token) -> Result<(), ParserError> {
+        if self.functions.last().unwrap().loops == 0 {
+            crate::error(
+                keyword.clone(),
+                &format!("Can't use '{}' outside of a loop.", keyword.lexeme),
+            );
+            return Err(ParserError {});
+        }
+        Ok(())
+    }
 }
 
 impl<'a> crate::stmt::Visitor<Result<(), ParserError>> for Resolver<'a> {
@@ -118,15 +249,21 @@ impl<'a> crate::stmt::Visitor<Result<(), ParserError>> for Resolver<'a> {
     }
 
     fn visit_var(&mut self, stmt: &Var) -> Result<(), ParserError> {
-        self.declare(stmt.name.clone())?;
-        self.resolve_expr(stmt.initializer.as_ref());
+        if let Some(slot) = self.declare(stmt.name.clone())? {
+            self.interpreter.resolve_declaration(stmt.uuid, slot);
+        }
+        if let Some(initializer) = &stmt.initializer {
+            self.resolve_expr(initializer)?;
+        }
         self.define(stmt.name.clone());
 
         Ok(())
     }
 
-    fn visit_function(&mut self, stmt: &Function) -> Result<(), ParserError> {
-        self.declare(stmt.name.clone())?;
+    fn visit_function(&mut self, stmt: &Rc<Function>) -> Result<(), ParserError> {
+        if let Some(slot) = self.declare(stmt.name.clone())? {
+            self.interpreter.resolve_declaration(stmt.uuid, slot);
+        }
         self.define(stmt.name.clone());
 
         self.resolve_function(stmt, FunctionType::Function)?;
@@ -135,22 +272,22 @@ impl<'a> crate::stmt::Visitor<Result<(), ParserError>> for Resolver<'a> {
     }
 
     fn visit_expression(&mut self, stmt: &Expression) -> Result<(), ParserError> {
-        self.resolve_expr(&stmt.expression);
+        self.resolve_expr(&stmt.expression)?;
         Ok(())
     }
 
     fn visit_if(&mut self, stmt: &If) -> Result<(), ParserError> {
-        self.resolve_expr(&stmt.condition);
+        self.resolve_expr(&stmt.condition)?;
         self.resolve_stmt(&stmt.then_branch)?;
-        if stmt.else_branch.is_some() {
-            self.resolve_stmt(stmt.else_branch.as_ref().unwrap())?;
+        if let Some(else_branch) = &stmt.else_branch {
+            self.resolve_stmt(else_branch)?;
         }
 
         Ok(())
     }
 
     fn visit_print(&mut self, stmt: &Print) -> Result<(), ParserError> {
-        self.resolve_expr(&stmt.expression);
+        self.resolve_expr(&stmt.expression)?;
         Ok(())
     }
 
@@ -166,46 +303,76 @@ impl<'a> crate::stmt::Visitor<Result<(), ParserError>> for Resolver<'a> {
             return Err(ParserError {});
         }
 
-        self.resolve_expr(&stmt.value);
+        self.resolve_expr(&stmt.value)?;
         Ok(())
     }
 
     fn visit_while(&mut self, stmt: &While) -> Result<(), ParserError> {
-        self.resolve_expr(&stmt.condition);
-        self.resolve_stmt(&stmt.body)?;
+        self.resolve_expr(&stmt.condition)?;
+        self.functions.last_mut().unwrap().loops += 1;
+        let body = self.resolve_stmt(&stmt.body);
+        self.functions.last_mut().unwrap().loops -= 1;
+        body?;
+        if let Some(increment) = &stmt.increment {
+            self.resolve_stmt(increment)?;
+        }
         Ok(())
     }
 
+    fn visit_break(&mut self, stmt: &Break) -> Result<(), ParserError> {
+        self.in_loop(&stmt.keyword)
+    }
+
+    fn visit_continue(&mut self, stmt: &Continue) -> Result<(), ParserError> {
+        self.in_loop(&stmt.keyword)
+    }
+
     fn visit_class(&mut self, stmt: &Class) -> Result<(), ParserError> {
         let enclosing_class = self.current_class;
         self.current_class = ClassType::Class;
 
-        self.declare(stmt.name.clone())?;
+        if let Some(slot) = self.declare(stmt.name.clone())? {
+            self.interpreter.resolve_declaration(stmt.uuid, slot);
+        }
         self.define(stmt.name.clone());
 
-        if let Some(Expr::Variable(sc)) = &stmt.super_class {
-            if stmt.name.lexeme.eq(&sc.name.lexeme) {
+        if let Some(Expr::Variable(sc)) = stmt.super_class.as_deref() {
+            if stmt.name.lexeme == sc.name.lexeme {
                 crate::error(sc.name.clone(), "A class can't inherit from itself.");
                 return Err(ParserError {});
             }
             self.current_class = ClassType::SubClass;
-            self.resolve_expr(&Expr::Variable(sc.clone()));
+            self.resolve_expr(&Expr::Variable(sc.clone()))?;
             self.begin_scope();
-            self.scopes
-                .last_mut()
-                .unwrap()
-                .insert("super".to_string(), true);
+            let slot = self.declare_implicit("super");
+            self.interpreter.resolve_declaration(sc.uuid, slot);
         }
 
-        self.begin_scope();
-        self.scopes
-            .last_mut()
-            .unwrap()
-            .insert("this".to_string(), true);
-
-        for method in stmt.methods.iter() {
+        for (i, method) in stmt.methods.iter().enumerate() {
             if let Stmt::Function(m) = method {
-                let declaration = if m.name.lexeme.eq("init") {
+                // The later method would otherwise replace the earlier one without a word
+                let duplicate = stmt.methods[..i].iter().any(
+                    |earlier| matches!(earlier, Stmt::Function(e) if e.name.lexeme == m.name.lexeme),
+                );
+                if duplicate {
+                    crate::error(
+                        m.name.clone(),
+                        "Already a method with this name in this class.",
+                    );
+                    return Err(ParserError {});
+                }
+                if m.name.lexeme == "init" && m.is_async {
+                    crate::error(m.name.clone(), "Can't make an initializer async.");
+                    return Err(ParserError {});
+                }
+                if m.name.lexeme == "deinit" && (m.is_async || !m.params.is_empty()) {
+                    crate::error(
+                        m.name.clone(),
+                        "A deinit method can't be async or take parameters.",
+                    );
+                    return Err(ParserError {});
+                }
+                let declaration = if m.name.lexeme == "init" {
                     FunctionType::Initializer
                 } else {
                     FunctionType::Method
@@ -214,9 +381,7 @@ impl<'a> crate::stmt::Visitor<Result<(), ParserError>> for Resolver<'a> {
             }
         }
 
-        self.end_scope();
-
-        if let Some(Expr::Variable(_sc)) = &stmt.super_class {
+        if let Some(Expr::Variable(_sc)) = stmt.super_class.as_deref() {
             self.end_scope();
         }
 
@@ -228,8 +393,10 @@ impl<'a> crate::stmt::Visitor<Result<(), ParserError>> for Resolver<'a> {
 
 impl<'a> crate::expr::Visitor<Result<(), ParserError>> for Resolver<'a> {
     fn visit_variable(&mut self, expr: &Variable) -> Result<(), ParserError> {
-        if !self.scopes.is_empty()
-            && self.scopes.last().unwrap().get(&expr.name.lexeme) == Some(&false)
+        if let Some(Local { defined: false, .. }) = self
+            .scopes
+            .last()
+            .and_then(|scope| scope.get(&expr.name.lexeme))
         {
             crate::error(
                 expr.name.clone(),
@@ -237,33 +404,33 @@ impl<'a> crate::expr::Visitor<Result<(), ParserError>> for Resolver<'a> {
             );
             return Err(ParserError {});
         }
-        self.resolve_local(&Expr::Variable(expr.clone()), expr.name.clone());
+        self.resolve_local(expr.uuid, &expr.name);
         Ok(())
     }
 
     fn visit_assignment(&mut self, expr: &Assignment) -> Result<(), ParserError> {
-        self.resolve_expr(&expr.value);
-        self.resolve_local(&Expr::Assignment(expr.clone()), expr.name.clone());
+        self.resolve_expr(&expr.value)?;
+        self.resolve_local(expr.uuid, &expr.name);
         Ok(())
     }
 
     fn visit_binary(&mut self, expr: &Binary) -> Result<(), ParserError> {
-        self.resolve_expr(&expr.left);
-        self.resolve_expr(&expr.right);
+        self.resolve_expr(&expr.left)?;
+        self.resolve_expr(&expr.right)?;
         Ok(())
     }
 
     fn visit_call(&mut self, expr: &Call) -> Result<(), ParserError> {
-        self.resolve_expr(&expr.callee);
+        self.resolve_expr(&expr.callee)?;
 
         for argument in expr.arguments.iter() {
-            self.resolve_expr(argument);
+            self.resolve_expr(argument)?;
         }
         Ok(())
     }
 
     fn visit_grouping(&mut self, expr: &Grouping) -> Result<(), ParserError> {
-        self.resolve_expr(&expr.expr);
+        self.resolve_expr(&expr.expr)?;
         Ok(())
     }
 
@@ -272,24 +439,24 @@ impl<'a> crate::expr::Visitor<Result<(), ParserError>> for Resolver<'a> {
     }
 
     fn visit_logical(&mut self, expr: &Logical) -> Result<(), ParserError> {
-        self.resolve_expr(&expr.left);
-        self.resolve_expr(&expr.right);
+        self.resolve_expr(&expr.left)?;
+        self.resolve_expr(&expr.right)?;
         Ok(())
     }
 
     fn visit_unary(&mut self, expr: &Unary) -> Result<(), ParserError> {
-        self.resolve_expr(&expr.right);
+        self.resolve_expr(&expr.right)?;
         Ok(())
     }
 
     fn visit_get(&mut self, expr: &Get) -> Result<(), ParserError> {
-        self.resolve_expr(&expr.object);
+        self.resolve_expr(&expr.object)?;
         Ok(())
     }
 
     fn visit_set(&mut self, expr: &Set) -> Result<(), ParserError> {
-        self.resolve_expr(&expr.value);
-        self.resolve_expr(&expr.object);
+        self.resolve_expr(&expr.value)?;
+        self.resolve_expr(&expr.object)?;
         Ok(())
     }
 
@@ -299,7 +466,7 @@ impl<'a> crate::expr::Visitor<Result<(), ParserError>> for Resolver<'a> {
             return Err(ParserError {});
         }
 
-        self.resolve_local(&Expr::This(expr.clone()), expr.keyword.clone());
+        self.resolve_local(expr.uuid, &expr.keyword);
         Ok(())
     }
 
@@ -317,7 +484,10 @@ impl<'a> crate::expr::Visitor<Result<(), ParserError>> for Resolver<'a> {
             );
             return Err(ParserError {});
         }
-        self.resolve_local(&Expr::Super(expr.clone()), expr.keyword.clone());
+        self.resolve_local(expr.uuid, &expr.keyword);
+        if let Some(binding) = self.binding(&Symbol::intern("this")) {
+            self.interpreter.resolve_receiver(expr.uuid, binding);
+        }
         Ok(())
     }
 }
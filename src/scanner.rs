@@ -1,129 +1,225 @@
 /// Scanner class contains all the methods needed to recognize each token
+use std::fmt;
+
 use crate::{
-    report,
+    report_at,
+    symbol::Symbol,
     token::{LiteralTypes, Token, TokenType},
 };
 
-pub struct Scanner {
-    source: String,
-    tokens: Vec<Token>,
+// Reads the source in place, only lexemes are copied out, once per distinct name.
+// Tokens are scanned one at a time as the scanner is iterated, ending with `Eof`.
+pub struct Scanner<'a> {
+    source: &'a str,
+    // Where the source starts in the script it's taken from, added to token offsets
+    offset: usize,
     start: usize,
     current: usize,
     line: usize,
+    // Where the current line starts in the source
+    line_start: usize,
+    // Line and column the token being scanned starts at
+    start_line: usize,
+    start_column: usize,
+    // Line of the last token scanned, 0 before the first
+    last_line: usize,
+    // Set once `Eof` has been produced
+    done: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ScanErrorKind {
+    UnexpectedCharacter,
+    // No token is produced, the parser then reports whatever the string cut short
+    UnterminatedString,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ScanError {
+    pub kind: ScanErrorKind,
+    pub line: usize,
+    pub column: usize,
+}
+
+impl ScanError {
+    pub fn message(&self) -> &'static str {
+        match self.kind {
+            ScanErrorKind::UnexpectedCharacter => "Unexpected Character",
+            ScanErrorKind::UnterminatedString => "Unterminated string.",
+        }
+    }
+
+    pub(crate) fn report(&self) {
+        report_at(self.line, self.column, 1, self.message());
+    }
+}
+
+impl fmt::Display for ScanError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "[Line {}, Column {}] Error: {}",
+            self.line,
+            self.column,
+            self.message()
+        )
+    }
 }
 
-impl Scanner {
-    pub fn new(source: String) -> Self {
+impl std::error::Error for ScanError {}
+
+impl<'a> Scanner<'a> {
+    pub fn new(source: &'a str) -> Self {
         Scanner {
             source,
-            tokens: Vec::new(),
+            offset: 0,
             start: 0,
             current: 0,
             line: 1,
+            line_start: 0,
+            start_line: 1,
+            start_column: 1,
+            last_line: 0,
+            done: false,
         }
     }
 
-    //For each entity, it calls scan token function and return final vector of tokens
-    pub fn scan_tokens(&mut self) -> Vec<Token> {
-        while !self.is_at_end() {
-            self.start = self.current;
-            self.scan_token();
+    // Scans source taken from the middle of a script, numbering its lines from `line` and
+    // its bytes from `offset`
+    pub fn from_line(source: &'a str, line: usize, offset: usize) -> Self {
+        Scanner {
+            offset,
+            line,
+            start_line: line,
+            ..Scanner::new(source)
         }
+    }
 
-        self.tokens.push(Token::new(
-            TokenType::Eof,
-            String::new(),
-            LiteralTypes::Nil,
-            self.line,
-        ));
+    //Scans every token up front, reporting errors as it goes
+    pub fn scan_tokens(self) -> Vec<Token> {
+        self.reported().collect()
+    }
 
-        self.tokens.clone()
+    // The tokens, with errors reported instead of returned
+    pub(crate) fn reported(self) -> impl Iterator<Item = Token> + 'a {
+        self.filter_map(|token| token.map_err(|error| error.report()).ok())
     }
 
-    //Contains all the tokens we need to recognize
-    fn scan_token(&mut self) {
+    //Contains all the tokens we need to recognize. `None` for whitespace and comments.
+    fn scan_token(&mut self) -> Option<Result<Token, ScanError>> {
         let c: u8 = self.advance();
-        match c {
-            b'(' => self.add_token(TokenType::LeftParen, LiteralTypes::Nil),
-            b')' => self.add_token(TokenType::RightParen, LiteralTypes::Nil),
-            b'{' => self.add_token(TokenType::LeftBrace, LiteralTypes::Nil),
-            b'}' => self.add_token(TokenType::RightBrace, LiteralTypes::Nil),
-            b',' => self.add_token(TokenType::Comma, LiteralTypes::Nil),
-            b'.' => self.add_token(TokenType::Dot, LiteralTypes::Nil),
-            b'-' => self.add_token(TokenType::Minus, LiteralTypes::Nil),
-            b'+' => self.add_token(TokenType::Plus, LiteralTypes::Nil),
-            b';' => self.add_token(TokenType::Semicolon, LiteralTypes::Nil),
-            b'*' => self.add_token(TokenType::Star, LiteralTypes::Nil),
+        let token = match c {
+            b'(' => self.make_token(TokenType::LeftParen, LiteralTypes::Nil),
+            b')' => self.make_token(TokenType::RightParen, LiteralTypes::Nil),
+            b'{' => self.make_token(TokenType::LeftBrace, LiteralTypes::Nil),
+            b'}' => self.make_token(TokenType::RightBrace, LiteralTypes::Nil),
+            b',' => self.make_token(TokenType::Comma, LiteralTypes::Nil),
+            b'.' => self.make_token(TokenType::Dot, LiteralTypes::Nil),
+            b'-' => self.make_token(TokenType::Minus, LiteralTypes::Nil),
+            b'+' => self.make_token(TokenType::Plus, LiteralTypes::Nil),
+            b';' => self.make_token(TokenType::Semicolon, LiteralTypes::Nil),
+            b'*' => self.make_token(TokenType::Star, LiteralTypes::Nil),
 
             b'!' => {
                 let is_equal = self.is_next_expected(b'=');
-                self.add_token(
+                self.make_token(
                     if is_equal {
                         TokenType::BangEqual
                     } else {
                         TokenType::Bang
                     },
                     LiteralTypes::Nil,
-                );
+                )
             }
             b'=' => {
                 let is_equal = self.is_next_expected(b'=');
-                self.add_token(
+                self.make_token(
                     if is_equal {
                         TokenType::EqualEqual
                     } else {
                         TokenType::Equal
                     },
                     LiteralTypes::Nil,
-                );
+                )
             }
             b'<' => {
                 let is_equal = self.is_next_expected(b'=');
-                self.add_token(
+                self.make_token(
                     if is_equal {
                         TokenType::LessEqual
                     } else {
                         TokenType::Less
                     },
                     LiteralTypes::Nil,
-                );
+                )
             }
             b'>' => {
                 let is_equal = self.is_next_expected(b'=');
-                self.add_token(
+                self.make_token(
                     if is_equal {
                         TokenType::GreaterEqual
                     } else {
                         TokenType::Greater
                     },
                     LiteralTypes::Nil,
-                );
+                )
             }
             b'/' => {
                 let slash = self.is_next_expected(b'/');
-                if slash {
-                    while self.peek() != b'\n' && !self.is_at_end() {
-                        self.current += 1;
-                    }
-                } else {
-                    self.add_token(TokenType::Slash, LiteralTypes::Nil)
+                if !slash {
+                    return Some(Ok(self.make_token(TokenType::Slash, LiteralTypes::Nil)));
+                }
+                // Exactly three slashes on a line of their own, since longer runs are
+                // often used as separators
+                let doc =
+                    self.peek() == b'/' && self.peek_next() != b'/' && self.last_line != self.line;
+                while self.peek() != b'\n' && !self.is_at_end() {
+                    self.current += 1;
+                }
+                if !doc {
+                    return None;
                 }
+                let text = &self.source[self.start + 3..self.current];
+                let text = text.strip_prefix(' ').unwrap_or(text).trim_end();
+                self.make_token(TokenType::DocComment, LiteralTypes::String(text.into()))
             }
 
-            b'\r' | b' ' | b'\t' => {}
-            b'\n' => self.line += 1,
-            b'"' => self.string(),
+            b'\r' | b' ' | b'\t' => return None,
+            b'\n' => {
+                self.new_line();
+                return None;
+            }
+            b'"' => return Some(self.string()),
 
             _ => {
                 if c.is_ascii_digit() {
-                    self.number();
+                    self.number()
                 } else if self.is_alpha(c) {
-                    self.identifier();
+                    self.identifier()
                 } else {
-                    report(self.line, "Unexpected Character");
+                    // Skip the rest of a multi-byte character so it's reported once
+                    while !self.source.is_char_boundary(self.current) {
+                        self.current += 1;
+                    }
+                    return Some(Err(ScanError {
+                        kind: ScanErrorKind::UnexpectedCharacter,
+                        line: self.line,
+                        column: self.start_column,
+                    }));
                 }
             }
-        }
+        };
+        Some(Ok(token))
+    }
+
+    fn column(&self) -> usize {
+        self.source[self.line_start..self.current].chars().count() + 1
+    }
+
+    // Called once the newline has been consumed
+    fn new_line(&mut self) {
+        self.line += 1;
+        self.line_start = self.current;
     }
 
     fn is_at_end(&self) -> bool {
@@ -136,10 +232,18 @@ impl Scanner {
         c
     }
 
-    fn add_token(&mut self, ttype: TokenType, literal: LiteralTypes) {
-        let lexeme = self.source[self.start..self.current].to_string();
-        self.tokens
-            .push(Token::new(ttype, lexeme, literal, self.line))
+    fn make_token(&mut self, ttype: TokenType, literal: LiteralTypes) -> Token {
+        let lexeme = Symbol::intern(&self.source[self.start..self.current]);
+        self.last_line = self.start_line;
+        Token::new(
+            ttype,
+            lexeme,
+            literal,
+            self.start_line,
+            self.start_column,
+            self.offset + self.start,
+            self.offset + self.current,
+        )
     }
 
     fn is_next_expected(&mut self, expected: u8) -> bool {
@@ -159,30 +263,39 @@ impl Scanner {
         if self.is_at_end() {
             return b'\0';
         }
-        return self.source.as_bytes()[self.current];
+        self.source.as_bytes()[self.current]
     }
 
     fn peek_next(&self) -> u8 {
-        if self.is_at_end() {
+        if self.current + 1 >= self.source.len() {
             return b'\0';
         }
-        return self.source.as_bytes()[self.current + 1];
+        self.source.as_bytes()[self.current + 1]
     }
 
-    fn string(&mut self) {
+    fn string(&mut self) -> Result<Token, ScanError> {
         while self.peek() != b'"' && !self.is_at_end() {
-            if self.peek() == b'\n' {
-                self.line += 1;
-            }
             self.current += 1;
+            if self.source.as_bytes()[self.current - 1] == b'\n' {
+                self.new_line();
+            }
+        }
+
+        if self.is_at_end() {
+            // Reported where the opening quote is, the scanner may be several lines further on
+            return Err(ScanError {
+                kind: ScanErrorKind::UnterminatedString,
+                line: self.start_line,
+                column: self.start_column,
+            });
         }
         self.current += 1;
 
         let value: String = self.source[self.start + 1..self.current - 1].to_string();
-        self.add_token(TokenType::String, LiteralTypes::String(value));
+        Ok(self.make_token(TokenType::String, LiteralTypes::String(value)))
     }
 
-    fn number(&mut self) {
+    fn number(&mut self) -> Token {
         while self.peek().is_ascii_digit() {
             self.current += 1;
         }
@@ -196,11 +309,11 @@ impl Scanner {
         }
 
         let value: f64 = self.source[self.start..self.current].parse().unwrap();
-        self.add_token(TokenType::Number, LiteralTypes::Number(value))
+        self.make_token(TokenType::Number, LiteralTypes::Number(value))
     }
 
     // Check for either identifier or keywords
-    fn identifier(&mut self) {
+    fn identifier(&mut self) -> Token {
         while self.is_alpha(self.peek()) || self.peek().is_ascii_digit() {
             self.current += 1;
         }
@@ -210,14 +323,11 @@ impl Scanner {
 
         match ttype {
             Some(t) => match &t {
-                TokenType::True => self.add_token(t, LiteralTypes::Bool(true)),
-                TokenType::False => self.add_token(t, LiteralTypes::Bool(false)),
-                _ => self.add_token(t, LiteralTypes::Nil),
+                TokenType::True => self.make_token(t, LiteralTypes::Bool(true)),
+                TokenType::False => self.make_token(t, LiteralTypes::Bool(false)),
+                _ => self.make_token(t, LiteralTypes::Nil),
             },
-            None => self.add_token(
-                TokenType::Identifier,
-                LiteralTypes::String(text.to_string()),
-            ),
+            None => self.make_token(TokenType::Identifier, LiteralTypes::Nil),
         }
     }
 
@@ -228,7 +338,11 @@ impl Scanner {
     fn get_keyword(&self, word: &str) -> Option<TokenType> {
         match word {
             "and" => Some(TokenType::And),
+            "async" => Some(TokenType::Async),
+            "await" => Some(TokenType::Await),
+            "break" => Some(TokenType::Break),
             "class" => Some(TokenType::Class),
+            "continue" => Some(TokenType::Continue),
             "else" => Some(TokenType::Else),
             "false" => Some(TokenType::False),
             "for" => Some(TokenType::For),
@@ -247,3 +361,32 @@ impl Scanner {
         }
     }
 }
+
+impl Iterator for Scanner<'_> {
+    type Item = Result<Token, ScanError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while !self.is_at_end() {
+            self.start = self.current;
+            self.start_line = self.line;
+            self.start_column = self.column();
+            if let Some(token) = self.scan_token() {
+                return Some(token);
+            }
+        }
+
+        if self.done {
+            return None;
+        }
+        self.done = true;
+        Some(Ok(Token::new(
+            TokenType::Eof,
+            Symbol::intern(""),
+            LiteralTypes::Nil,
+            self.line,
+            self.column(),
+            self.offset + self.current,
+            self.offset + self.current,
+        )))
+    }
+}
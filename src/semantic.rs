@@ -0,0 +1,277 @@
+/// Semantic tokens for syntax highlighting: every keyword, name, literal, operator and
+/// comment of a script with its source range and what it is. Names are told apart by what
+/// they were declared as, following the same scoping rules as the resolver.
+use std::collections::HashMap;
+
+use crate::expr::Expr;
+use crate::query::{self, Span};
+use crate::stmt::{Function, Stmt};
+use crate::symbol::Symbol;
+use crate::token::{Token, TokenType};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum SemanticKind {
+    Keyword,
+    Variable,
+    Parameter,
+    // Field of an instance
+    Property,
+    Function,
+    Method,
+    Class,
+    String,
+    Number,
+    Operator,
+    Comment,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SemanticToken {
+    pub kind: SemanticKind,
+    pub span: Span,
+    // Where the span starts, columns counted in characters from 1
+    pub line: usize,
+    pub column: usize,
+}
+
+// Classifies the scanned tokens using the statements parsed from them, which may be fewer
+// than the tokens when the source has errors. `globals` are the names the interpreter
+// defines before running a script.
+pub(crate) fn classify(
+    source: &str,
+    tokens: &[Token],
+    statements: &[Stmt],
+    globals: HashMap<Symbol, SemanticKind>,
+) -> Vec<SemanticToken> {
+    let mut names = Names {
+        scopes: vec![globals],
+        kinds: HashMap::new(),
+    };
+    // Top-level functions and classes can be used above their declaration
+    for statement in statements.iter() {
+        match statement {
+            Stmt::Function(function) => names.declare(&function.name, SemanticKind::Function),
+            Stmt::Class(class) => names.declare(&class.name, SemanticKind::Class),
+            _ => {}
+        }
+    }
+    for statement in statements.iter() {
+        names.stmt(statement);
+    }
+
+    let line_starts = query::line_starts(source);
+    let position = |offset: usize| {
+        let line = line_starts.partition_point(|&start| start <= offset);
+        let column = source[line_starts[line - 1]..offset].chars().count() + 1;
+        (line, column)
+    };
+    let mut semantic: Vec<SemanticToken> = tokens
+        .iter()
+        .filter_map(|token| {
+            let kind = names
+                .kinds
+                .get(&(token.line, token.column))
+                .copied()
+                .or_else(|| lexical_kind(&token.ttype))?;
+            Some(SemanticToken {
+                kind,
+                span: Span {
+                    start: token.start,
+                    end: token.end,
+                },
+                line: token.line,
+                column: token.column,
+            })
+        })
+        .collect();
+
+    // Other comments aren't scanned, they're whatever follows `//` between two tokens
+    let gap_starts = std::iter::once(0).chain(semantic.iter().map(|token| token.span.end));
+    let gap_ends = semantic.iter().map(|token| token.span.start);
+    let gaps: Vec<(usize, usize)> = gap_starts.zip(gap_ends.chain([source.len()])).collect();
+    for (gap_start, gap_end) in gaps {
+        let mut offset = gap_start;
+        while let Some(found) = source[offset..gap_end].find("//") {
+            let start = offset + found;
+            let end = line_end(source, start).min(gap_end);
+            let (line, column) = position(start);
+            semantic.push(SemanticToken {
+                kind: SemanticKind::Comment,
+                span: Span { start, end },
+                line,
+                column,
+            });
+            offset = end;
+        }
+    }
+    semantic.sort_by_key(|token| token.span.start);
+    semantic
+}
+
+fn line_end(source: &str, offset: usize) -> usize {
+    source[offset..]
+        .find('\n')
+        .map_or(source.len(), |i| offset + i)
+}
+
+// What a token is by itself, `None` for punctuation
+fn lexical_kind(ttype: &TokenType) -> Option<SemanticKind> {
+    use TokenType::*;
+    match ttype {
+        And | Async | Await | Break | Class | Continue | Else | False | Fun | For | If | Nil
+        | Or | Print | Return | Super | This | True | Var | While => Some(SemanticKind::Keyword),
+        String => Some(SemanticKind::String),
+        Number => Some(SemanticKind::Number),
+        Minus | Plus | Slash | Star | Bang | BangEqual | Equal | EqualEqual | Greater
+        | GreaterEqual | Less | LessEqual => Some(SemanticKind::Operator),
+        DocComment => Some(SemanticKind::Comment),
+        // Unless the names show what it was declared as
+        Identifier => Some(SemanticKind::Variable),
+        LeftParen | RightParen | LeftBrace | RightBrace | Comma | Dot | Semicolon | Eof => None,
+    }
+}
+
+// Works out what every name in the tree refers to
+struct Names {
+    // Innermost last, starting with the globals
+    scopes: Vec<HashMap<Symbol, SemanticKind>>,
+    // Kind of every identifier token, by line and column
+    kinds: HashMap<(usize, usize), SemanticKind>,
+}
+
+impl Names {
+    fn mark(&mut self, token: &Token, kind: SemanticKind) {
+        self.kinds.insert((token.line, token.column), kind);
+    }
+
+    fn declare(&mut self, name: &Token, kind: SemanticKind) {
+        self.mark(name, kind);
+        if let Some(scope) = self.scopes.last_mut() {
+            scope.insert(name.lexeme.clone(), kind);
+        }
+    }
+
+    // A name used as a variable is whatever it was declared as
+    fn refer(&mut self, name: &Token) {
+        let kind = self
+            .scopes
+            .iter()
+            .rev()
+            .find_map(|scope| scope.get(&name.lexeme))
+            .copied()
+            .unwrap_or(SemanticKind::Variable);
+        self.mark(name, kind);
+    }
+
+    fn scoped(&mut self, f: impl FnOnce(&mut Self)) {
+        self.scopes.push(HashMap::new());
+        f(self);
+        self.scopes.pop();
+    }
+
+    fn function(&mut self, function: &Function, kind: SemanticKind) {
+        self.mark(&function.name, kind);
+        self.scoped(|names| {
+            for param in function.params.iter() {
+                names.declare(param, SemanticKind::Parameter);
+            }
+            for statement in function.body.iter() {
+                names.stmt(statement);
+            }
+        });
+    }
+
+    fn stmt(&mut self, stmt: &Stmt) {
+        match stmt {
+            Stmt::Expression(s) => self.expr(&s.expression),
+            Stmt::Print(s) => self.expr(&s.expression),
+            Stmt::Var(s) => {
+                if let Some(initializer) = &s.initializer {
+                    self.expr(initializer);
+                }
+                self.declare(&s.name, SemanticKind::Variable);
+            }
+            Stmt::Block(s) => self.scoped(|names| {
+                for statement in s.statements.iter() {
+                    names.stmt(statement);
+                }
+            }),
+            Stmt::If(s) => {
+                self.expr(&s.condition);
+                self.stmt(&s.then_branch);
+                if let Some(else_branch) = &s.else_branch {
+                    self.stmt(else_branch);
+                }
+            }
+            Stmt::While(s) => {
+                self.expr(&s.condition);
+                self.stmt(&s.body);
+                if let Some(increment) = &s.increment {
+                    self.stmt(increment);
+                }
+            }
+            Stmt::Break(_) | Stmt::Continue(_) => {}
+            Stmt::Function(function) => {
+                // Declared first, so it can call itself
+                self.declare(&function.name, SemanticKind::Function);
+                self.function(function, SemanticKind::Function);
+            }
+            Stmt::Return(s) => self.expr(&s.value),
+            Stmt::Class(class) => {
+                self.declare(&class.name, SemanticKind::Class);
+                if let Some(super_class) = &class.super_class {
+                    self.expr(super_class);
+                }
+                for method in class.methods.iter() {
+                    if let Stmt::Function(method) = method {
+                        self.function(method, SemanticKind::Method);
+                    }
+                }
+            }
+        }
+    }
+
+    fn expr(&mut self, expr: &Expr) {
+        match expr {
+            Expr::Assignment(e) => {
+                self.expr(&e.value);
+                self.refer(&e.name);
+            }
+            Expr::Binary(e) => {
+                self.expr(&e.left);
+                self.expr(&e.right);
+            }
+            Expr::Call(e) => {
+                match e.callee.as_ref() {
+                    // Calling a property is calling a method
+                    Expr::Get(get) => {
+                        self.expr(&get.object);
+                        self.mark(&get.name, SemanticKind::Method);
+                    }
+                    callee => self.expr(callee),
+                }
+                for argument in e.arguments.iter() {
+                    self.expr(argument);
+                }
+            }
+            Expr::Get(e) => {
+                self.expr(&e.object);
+                self.mark(&e.name, SemanticKind::Property);
+            }
+            Expr::Grouping(e) => self.expr(&e.expr),
+            Expr::Literal(_) | Expr::This(_) => {}
+            Expr::Logical(e) => {
+                self.expr(&e.left);
+                self.expr(&e.right);
+            }
+            Expr::Set(e) => {
+                self.expr(&e.object);
+                self.expr(&e.value);
+                self.mark(&e.name, SemanticKind::Property);
+            }
+            Expr::Super(e) => self.mark(&e.method, SemanticKind::Method),
+            Expr::Unary(e) => self.expr(&e.right),
+            Expr::Variable(e) => self.refer(&e.name),
+        }
+    }
+}
@@ -0,0 +1,563 @@
+/// Conversions between Lox values and Rust types implementing `Serialize`/`Deserialize`.
+/// Structs and maps become Lox maps, sequences and tuples become lists, and every numeric
+/// type is a Lox number.
+use std::{collections::BTreeMap, fmt};
+
+use serde::de::{
+    self, value::MapDeserializer, value::SeqDeserializer, DeserializeOwned, EnumAccess,
+    IntoDeserializer, VariantAccess, Visitor,
+};
+use serde::ser::{self, Serialize};
+
+use crate::lox_callable::Callable;
+use crate::token::LiteralTypes;
+
+#[derive(Debug)]
+pub struct Error(String);
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl ser::Error for Error {
+    fn custom<T: fmt::Display>(msg: T) -> Self {
+        Error(msg.to_string())
+    }
+}
+
+impl de::Error for Error {
+    fn custom<T: fmt::Display>(msg: T) -> Self {
+        Error(msg.to_string())
+    }
+}
+
+// Converts a Lox value, e.g. one produced by a script, into a Rust type
+pub fn lox_value_to<T: DeserializeOwned>(value: LiteralTypes) -> Result<T, Error> {
+    T::deserialize(value)
+}
+
+// Evaluates a configuration script with `Lox::eval_config` and converts its value into a
+// Rust type. The script's errors become the error's message.
+pub fn eval_config_to<T: DeserializeOwned>(content: &str) -> Result<T, Error> {
+    let value = crate::Lox::new()
+        .eval_config(content)
+        .map_err(|error| Error(error.to_string()))?;
+    lox_value_to(value)
+}
+
+// Converts a Rust value into a Lox value that can be handed to scripts
+pub fn to_lox_value<T: Serialize + ?Sized>(value: &T) -> Result<LiteralTypes, Error> {
+    value.serialize(Serializer)
+}
+
+impl Serialize for LiteralTypes {
+    fn serialize<S: ser::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        use ser::{SerializeMap, SerializeSeq};
+
+        match self {
+            LiteralTypes::Nil | LiteralTypes::Unassigned => serializer.serialize_unit(),
+            LiteralTypes::Bool(b) => serializer.serialize_bool(*b),
+            LiteralTypes::Number(n) => serializer.serialize_f64(*n),
+            LiteralTypes::String(s) => serializer.serialize_str(s),
+            LiteralTypes::List(list) => {
+                let list = list.borrow();
+                let mut seq = serializer.serialize_seq(Some(list.len()))?;
+                for item in list.iter() {
+                    seq.serialize_element(item)?;
+                }
+                seq.end()
+            }
+            LiteralTypes::Map(map) => {
+                let map = map.borrow();
+                let mut entries = serializer.serialize_map(Some(map.len()))?;
+                for (key, value) in map.iter() {
+                    entries.serialize_entry(key, value)?;
+                }
+                entries.end()
+            }
+            LiteralTypes::Callable(_) => Err(ser::Error::custom("can't serialize a callable")),
+        }
+    }
+}
+
+pub struct Serializer;
+
+pub struct SerializeList {
+    items: Vec<LiteralTypes>,
+    // Set for tuple variants, which become `{ variant: [items] }`
+    variant: Option<&'static str>,
+}
+
+pub struct SerializeMap {
+    entries: BTreeMap<String, LiteralTypes>,
+    next_key: Option<String>,
+    // Set for struct variants, which become `{ variant: { fields } }`
+    variant: Option<&'static str>,
+}
+
+fn wrap_variant(variant: Option<&'static str>, value: LiteralTypes) -> LiteralTypes {
+    match variant {
+        Some(name) => BTreeMap::from([(name.to_string(), value)]).into(),
+        None => value,
+    }
+}
+
+impl ser::Serializer for Serializer {
+    type Ok = LiteralTypes;
+    type Error = Error;
+    type SerializeSeq = SerializeList;
+    type SerializeTuple = SerializeList;
+    type SerializeTupleStruct = SerializeList;
+    type SerializeTupleVariant = SerializeList;
+    type SerializeMap = SerializeMap;
+    type SerializeStruct = SerializeMap;
+    type SerializeStructVariant = SerializeMap;
+
+    fn serialize_bool(self, v: bool) -> Result<LiteralTypes, Error> {
+        Ok(LiteralTypes::Bool(v))
+    }
+
+    fn serialize_i8(self, v: i8) -> Result<LiteralTypes, Error> {
+        self.serialize_f64(v as f64)
+    }
+
+    fn serialize_i16(self, v: i16) -> Result<LiteralTypes, Error> {
+        self.serialize_f64(v as f64)
+    }
+
+    fn serialize_i32(self, v: i32) -> Result<LiteralTypes, Error> {
+        self.serialize_f64(v as f64)
+    }
+
+    fn serialize_i64(self, v: i64) -> Result<LiteralTypes, Error> {
+        self.serialize_f64(v as f64)
+    }
+
+    fn serialize_u8(self, v: u8) -> Result<LiteralTypes, Error> {
+        self.serialize_f64(v as f64)
+    }
+
+    fn serialize_u16(self, v: u16) -> Result<LiteralTypes, Error> {
+        self.serialize_f64(v as f64)
+    }
+
+    fn serialize_u32(self, v: u32) -> Result<LiteralTypes, Error> {
+        self.serialize_f64(v as f64)
+    }
+
+    fn serialize_u64(self, v: u64) -> Result<LiteralTypes, Error> {
+        self.serialize_f64(v as f64)
+    }
+
+    fn serialize_f32(self, v: f32) -> Result<LiteralTypes, Error> {
+        self.serialize_f64(v as f64)
+    }
+
+    fn serialize_f64(self, v: f64) -> Result<LiteralTypes, Error> {
+        Ok(LiteralTypes::Number(v))
+    }
+
+    fn serialize_char(self, v: char) -> Result<LiteralTypes, Error> {
+        Ok(LiteralTypes::String(v.to_string()))
+    }
+
+    fn serialize_str(self, v: &str) -> Result<LiteralTypes, Error> {
+        Ok(LiteralTypes::String(v.to_string()))
+    }
+
+    fn serialize_bytes(self, v: &[u8]) -> Result<LiteralTypes, Error> {
+        let items: Vec<LiteralTypes> = v.iter().map(|b| LiteralTypes::Number(*b as f64)).collect();
+        Ok(items.into())
+    }
+
+    fn serialize_none(self) -> Result<LiteralTypes, Error> {
+        Ok(LiteralTypes::Nil)
+    }
+
+    fn serialize_some<T: Serialize + ?Sized>(self, value: &T) -> Result<LiteralTypes, Error> {
+        value.serialize(self)
+    }
+
+    fn serialize_unit(self) -> Result<LiteralTypes, Error> {
+        Ok(LiteralTypes::Nil)
+    }
+
+    fn serialize_unit_struct(self, _name: &'static str) -> Result<LiteralTypes, Error> {
+        Ok(LiteralTypes::Nil)
+    }
+
+    fn serialize_unit_variant(
+        self,
+        _name: &'static str,
+        _index: u32,
+        variant: &'static str,
+    ) -> Result<LiteralTypes, Error> {
+        Ok(LiteralTypes::String(variant.to_string()))
+    }
+
+    fn serialize_newtype_struct<T: Serialize + ?Sized>(
+        self,
+        _name: &'static str,
+        value: &T,
+    ) -> Result<LiteralTypes, Error> {
+        value.serialize(self)
+    }
+
+    fn serialize_newtype_variant<T: Serialize + ?Sized>(
+        self,
+        _name: &'static str,
+        _index: u32,
+        variant: &'static str,
+        value: &T,
+    ) -> Result<LiteralTypes, Error> {
+        Ok(wrap_variant(Some(variant), value.serialize(self)?))
+    }
+
+    fn serialize_seq(self, len: Option<usize>) -> Result<SerializeList, Error> {
+        Ok(SerializeList {
+            items: Vec::with_capacity(len.unwrap_or(0)),
+            variant: None,
+        })
+    }
+
+    fn serialize_tuple(self, len: usize) -> Result<SerializeList, Error> {
+        self.serialize_seq(Some(len))
+    }
+
+    fn serialize_tuple_struct(
+        self,
+        _name: &'static str,
+        len: usize,
+    ) -> Result<SerializeList, Error> {
+        self.serialize_seq(Some(len))
+    }
+
+    fn serialize_tuple_variant(
+        self,
+        _name: &'static str,
+        _index: u32,
+        variant: &'static str,
+        len: usize,
+    ) -> Result<SerializeList, Error> {
+        Ok(SerializeList {
+            items: Vec::with_capacity(len),
+            variant: Some(variant),
+        })
+    }
+
+    fn serialize_map(self, _len: Option<usize>) -> Result<SerializeMap, Error> {
+        Ok(SerializeMap {
+            entries: BTreeMap::new(),
+            next_key: None,
+            variant: None,
+        })
+    }
+
+    fn serialize_struct(self, _name: &'static str, len: usize) -> Result<SerializeMap, Error> {
+        self.serialize_map(Some(len))
+    }
+
+    fn serialize_struct_variant(
+        self,
+        _name: &'static str,
+        _index: u32,
+        variant: &'static str,
+        _len: usize,
+    ) -> Result<SerializeMap, Error> {
+        Ok(SerializeMap {
+            entries: BTreeMap::new(),
+            next_key: None,
+            variant: Some(variant),
+        })
+    }
+}
+
+impl ser::SerializeSeq for SerializeList {
+    type Ok = LiteralTypes;
+    type Error = Error;
+
+    fn serialize_element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), Error> {
+        self.items.push(value.serialize(Serializer)?);
+        Ok(())
+    }
+
+    fn end(self) -> Result<LiteralTypes, Error> {
+        Ok(wrap_variant(self.variant, self.items.into()))
+    }
+}
+
+impl ser::SerializeTuple for SerializeList {
+    type Ok = LiteralTypes;
+    type Error = Error;
+
+    fn serialize_element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), Error> {
+        ser::SerializeSeq::serialize_element(self, value)
+    }
+
+    fn end(self) -> Result<LiteralTypes, Error> {
+        ser::SerializeSeq::end(self)
+    }
+}
+
+impl ser::SerializeTupleStruct for SerializeList {
+    type Ok = LiteralTypes;
+    type Error = Error;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), Error> {
+        ser::SerializeSeq::serialize_element(self, value)
+    }
+
+    fn end(self) -> Result<LiteralTypes, Error> {
+        ser::SerializeSeq::end(self)
+    }
+}
+
+impl ser::SerializeTupleVariant for SerializeList {
+    type Ok = LiteralTypes;
+    type Error = Error;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), Error> {
+        ser::SerializeSeq::serialize_element(self, value)
+    }
+
+    fn end(self) -> Result<LiteralTypes, Error> {
+        ser::SerializeSeq::end(self)
+    }
+}
+
+impl ser::SerializeMap for SerializeMap {
+    type Ok = LiteralTypes;
+    type Error = Error;
+
+    fn serialize_key<T: Serialize + ?Sized>(&mut self, key: &T) -> Result<(), Error> {
+        let key = match key.serialize(Serializer)? {
+            LiteralTypes::String(s) => s,
+            LiteralTypes::Number(n) => n.to_string(),
+            LiteralTypes::Bool(b) => b.to_string(),
+            _ => {
+                return Err(Error(
+                    "map keys must be strings, numbers or booleans".into(),
+                ))
+            }
+        };
+        self.next_key = Some(key);
+        Ok(())
+    }
+
+    fn serialize_value<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), Error> {
+        let key = self
+            .next_key
+            .take()
+            .ok_or_else(|| Error("map value serialized before its key".into()))?;
+        self.entries.insert(key, value.serialize(Serializer)?);
+        Ok(())
+    }
+
+    fn end(self) -> Result<LiteralTypes, Error> {
+        Ok(wrap_variant(self.variant, self.entries.into()))
+    }
+}
+
+impl ser::SerializeStruct for SerializeMap {
+    type Ok = LiteralTypes;
+    type Error = Error;
+
+    fn serialize_field<T: Serialize + ?Sized>(
+        &mut self,
+        key: &'static str,
+        value: &T,
+    ) -> Result<(), Error> {
+        self.entries
+            .insert(key.to_string(), value.serialize(Serializer)?);
+        Ok(())
+    }
+
+    fn end(self) -> Result<LiteralTypes, Error> {
+        ser::SerializeMap::end(self)
+    }
+}
+
+impl ser::SerializeStructVariant for SerializeMap {
+    type Ok = LiteralTypes;
+    type Error = Error;
+
+    fn serialize_field<T: Serialize + ?Sized>(
+        &mut self,
+        key: &'static str,
+        value: &T,
+    ) -> Result<(), Error> {
+        ser::SerializeStruct::serialize_field(self, key, value)
+    }
+
+    fn end(self) -> Result<LiteralTypes, Error> {
+        ser::SerializeMap::end(self)
+    }
+}
+
+impl<'de> IntoDeserializer<'de, Error> for LiteralTypes {
+    type Deserializer = LiteralTypes;
+
+    fn into_deserializer(self) -> LiteralTypes {
+        self
+    }
+}
+
+// Integers are only accepted from numbers without a fractional part that the target type can
+// hold, anything else being an error rather than a value saturated into range
+macro_rules! deserialize_integer {
+    ($($method:ident: $target:ty => $visit:ident as $ty:ty),*) => {
+        $(
+            fn $method<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+                match self {
+                    LiteralTypes::Number(n) if n.fract() == 0.0 => {
+                        // `MAX + 1` is exact as a float even where `MAX` itself isn't
+                        if n >= <$target>::MIN as f64 && n < <$target>::MAX as f64 + 1.0 {
+                            visitor.$visit(n as $ty)
+                        } else {
+                            Err(de::Error::invalid_value(de::Unexpected::Float(n), &visitor))
+                        }
+                    }
+                    other => other.deserialize_any(visitor),
+                }
+            }
+        )*
+    };
+}
+
+impl<'de> de::Deserializer<'de> for LiteralTypes {
+    type Error = Error;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        match self {
+            LiteralTypes::Nil | LiteralTypes::Unassigned => visitor.visit_unit(),
+            LiteralTypes::Bool(b) => visitor.visit_bool(b),
+            LiteralTypes::Number(n) => visitor.visit_f64(n),
+            LiteralTypes::String(s) => visitor.visit_string(s),
+            LiteralTypes::List(list) => {
+                let items = list.borrow().clone();
+                let mut seq = SeqDeserializer::new(items.into_iter());
+                let value = visitor.visit_seq(&mut seq)?;
+                seq.end()?;
+                Ok(value)
+            }
+            LiteralTypes::Map(map) => {
+                let entries = map.borrow().clone();
+                let mut map = MapDeserializer::new(entries.into_iter());
+                let value = visitor.visit_map(&mut map)?;
+                map.end()?;
+                Ok(value)
+            }
+            // Fields of an instance, so configuration scripts can build their values as objects
+            LiteralTypes::Callable(Callable::Instance(instance)) => {
+                let fields = instance.borrow().field_values();
+                let entries = fields
+                    .into_iter()
+                    .map(|(name, value)| (name.to_string(), value));
+                let mut map = MapDeserializer::new(entries);
+                let value = visitor.visit_map(&mut map)?;
+                map.end()?;
+                Ok(value)
+            }
+            LiteralTypes::Callable(_) => Err(Error("can't deserialize a callable".into())),
+        }
+    }
+
+    deserialize_integer! {
+        deserialize_i8: i8 => visit_i64 as i64,
+        deserialize_i16: i16 => visit_i64 as i64,
+        deserialize_i32: i32 => visit_i64 as i64,
+        deserialize_i64: i64 => visit_i64 as i64,
+        deserialize_u8: u8 => visit_u64 as u64,
+        deserialize_u16: u16 => visit_u64 as u64,
+        deserialize_u32: u32 => visit_u64 as u64,
+        deserialize_u64: u64 => visit_u64 as u64
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        match self {
+            LiteralTypes::Nil => visitor.visit_none(),
+            other => visitor.visit_some(other),
+        }
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        _variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        match self {
+            LiteralTypes::String(variant) => visitor.visit_enum(variant.into_deserializer()),
+            LiteralTypes::Map(map) if map.borrow().len() == 1 => {
+                let (variant, value) = map.borrow().clone().into_iter().next().unwrap();
+                visitor.visit_enum(Variant { variant, value })
+            }
+            _ => Err(Error(
+                "expected a variant name or a map with a single entry".into(),
+            )),
+        }
+    }
+
+    serde::forward_to_deserialize_any! {
+        bool f32 f64 char str string bytes byte_buf unit unit_struct seq tuple
+        tuple_struct map struct identifier ignored_any
+    }
+}
+
+// Enum variant with data, written as `{ variant: value }`
+struct Variant {
+    variant: String,
+    value: LiteralTypes,
+}
+
+impl<'de> EnumAccess<'de> for Variant {
+    type Error = Error;
+    type Variant = LiteralTypes;
+
+    fn variant_seed<V: de::DeserializeSeed<'de>>(
+        self,
+        seed: V,
+    ) -> Result<(V::Value, LiteralTypes), Error> {
+        let variant = seed.deserialize(self.variant.into_deserializer())?;
+        Ok((variant, self.value))
+    }
+}
+
+impl<'de> VariantAccess<'de> for LiteralTypes {
+    type Error = Error;
+
+    fn unit_variant(self) -> Result<(), Error> {
+        match self {
+            LiteralTypes::Nil => Ok(()),
+            _ => Err(Error("expected a unit variant".into())),
+        }
+    }
+
+    fn newtype_variant_seed<T: de::DeserializeSeed<'de>>(self, seed: T) -> Result<T::Value, Error> {
+        seed.deserialize(self)
+    }
+
+    fn tuple_variant<V: Visitor<'de>>(self, _len: usize, visitor: V) -> Result<V::Value, Error> {
+        de::Deserializer::deserialize_seq(self, visitor)
+    }
+
+    fn struct_variant<V: Visitor<'de>>(
+        self,
+        _fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        de::Deserializer::deserialize_map(self, visitor)
+    }
+}
@@ -0,0 +1,68 @@
+/// Runs source a cell at a time, as notebooks, playgrounds and "run selection" in editors
+/// do. Cells share one interpreter, so later cells see what earlier ones defined, and each
+/// cell's output, value and errors are returned apart.
+use crate::interpreter::Interpreter;
+use crate::shared_buffer::SharedBuffer;
+use crate::token::LiteralTypes;
+use crate::LoxError;
+
+pub struct CellResult {
+    // Cells are numbered from 1 in the order they run
+    pub id: usize,
+    // What the cell printed
+    pub output: String,
+    // Value of the cell's last statement if that's an expression, or the error as `run`
+    // returns it
+    pub result: Result<Option<LiteralTypes>, LoxError>,
+    // Errors as reported, tagged with the cell, e.g. `[Cell 2, Line 1, Column 5] Error: ..`
+    pub diagnostics: Vec<String>,
+}
+
+pub struct Session {
+    interpreter: Interpreter,
+    // What the cell running prints, collected until it has run
+    output: SharedBuffer,
+    cells: usize,
+}
+
+impl Default for Session {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Session {
+    pub fn new() -> Self {
+        let output = SharedBuffer::default();
+        Session {
+            interpreter: Interpreter::with_output(Box::new(output.clone())),
+            output,
+            cells: 0,
+        }
+    }
+
+    // The interpreter cells run on, e.g. to define host values or set a step limit
+    pub fn interpreter(&mut self) -> &mut Interpreter {
+        &mut self.interpreter
+    }
+
+    pub fn execute_cell(&mut self, source: &str) -> CellResult {
+        self.cells += 1;
+        let (result, diagnostics) =
+            crate::capture_diagnostics(|| crate::run_for_value(&mut self.interpreter, source));
+        let output = self.output.take_text();
+        let diagnostics = diagnostics
+            .into_iter()
+            .map(|diagnostic| match diagnostic.strip_prefix('[') {
+                Some(rest) => format!("[Cell {}, {}", self.cells, rest),
+                None => format!("[Cell {}] {}", self.cells, diagnostic),
+            })
+            .collect();
+        CellResult {
+            id: self.cells,
+            output,
+            result,
+            diagnostics,
+        }
+    }
+}
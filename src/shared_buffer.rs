@@ -0,0 +1,27 @@
+/// A writer that collects what's written to it, for hosts that hand an interpreter its output
+/// and read it back afterwards.
+use std::cell::RefCell;
+use std::io::{self, Write};
+use std::rc::Rc;
+
+// Every clone writes to the same bytes
+#[derive(Clone, Default)]
+pub(crate) struct SharedBuffer(Rc<RefCell<Vec<u8>>>);
+
+impl SharedBuffer {
+    // What has been written so far, leaving the buffer empty
+    pub(crate) fn take_text(&self) -> String {
+        String::from_utf8_lossy(&self.0.take()).into_owned()
+    }
+}
+
+impl Write for SharedBuffer {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.borrow_mut().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
@@ -0,0 +1,51 @@
+/// Signal handling for scripts: `onSignal("int", fn)` and `onSignal("term", fn)` call `fn`
+/// when the process gets SIGINT or SIGTERM, instead of it ending there. The handler runs
+/// before the next statement, so a long run can finish what it's doing and `exit`. Only
+/// allowed once the host has called `Interpreter::set_allow_signals`.
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::sync::atomic::AtomicBool;
+use std::sync::Arc;
+
+use signal_hook::consts::{SIGINT, SIGTERM};
+
+use crate::interpreter::{Exit, Interpreter};
+use crate::lox_callable::Callable;
+use crate::token::LiteralTypes;
+
+// Defines `onSignal`
+pub fn define_natives(interpreter: &mut Interpreter) {
+    // Flag each signal sets, registered the first time a handler is given for it
+    let flags: RefCell<HashMap<i32, Arc<AtomicBool>>> = RefCell::default();
+    interpreter.define_native("onSignal", 2, move |interpreter, arguments| {
+        if !interpreter.allow_signals() {
+            return Err(Exit::message("Handling signals isn't allowed."));
+        }
+        let signal = match &arguments[0] {
+            LiteralTypes::String(name) if name == "int" => SIGINT,
+            LiteralTypes::String(name) if name == "term" => SIGTERM,
+            _ => return Err(Exit::message("Signal must be \"int\" or \"term\".")),
+        };
+        if !matches!(
+            arguments[1],
+            LiteralTypes::Callable(
+                Callable::Function(_) | Callable::Class(_) | Callable::Native(_)
+            )
+        ) {
+            return Err(Exit::message("Signal handler must be a function."));
+        }
+        let mut flags = flags.borrow_mut();
+        let flag = match flags.get(&signal) {
+            Some(flag) => Arc::clone(flag),
+            None => {
+                let flag = Arc::new(AtomicBool::new(false));
+                signal_hook::flag::register(signal, Arc::clone(&flag))
+                    .map_err(|error| Exit::message(format!("Error handling signal: {}.", error)))?;
+                flags.insert(signal, Arc::clone(&flag));
+                flag
+            }
+        };
+        interpreter.on_interrupt(flag, arguments[1].clone());
+        Ok(LiteralTypes::Nil)
+    });
+}
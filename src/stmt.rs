@@ -1,3 +1,5 @@
+use std::rc::Rc;
+
 use crate::{expr::Expr, token::Token};
 
 #[derive(Clone)]
@@ -8,34 +10,45 @@ pub enum Stmt {
     Block(Block),
     If(If),
     While(While),
-    Function(Function),
+    // Shared with the functions created from it, so declaring one doesn't copy its body
+    Function(Rc<Function>),
     Return(Return),
     Class(Class),
+    Break(Break),
+    Continue(Continue),
 }
 
 #[derive(Clone)]
 pub struct Expression {
+    // Line the statement starts on, which the expression itself doesn't record
+    pub line: usize,
     pub expression: Box<Expr>,
 }
 
 #[derive(Clone)]
 pub struct Print {
+    pub keyword: Token,
     pub expression: Box<Expr>,
 }
 
 #[derive(Clone)]
 pub struct Var {
+    pub uuid: usize,
     pub name: Token,
-    pub initializer: Box<Expr>,
+    // `None` for `var x;`, which leaves the variable unassigned
+    pub initializer: Option<Box<Expr>>,
 }
 
 #[derive(Clone)]
 pub struct Block {
+    // The `{` opening the block, or the `for` keyword for blocks a `for` loop becomes
+    pub brace: Token,
     pub statements: Vec<Stmt>,
 }
 
 #[derive(Clone)]
 pub struct If {
+    pub keyword: Token,
     pub condition: Box<Expr>,
     pub then_branch: Box<Stmt>,
     pub else_branch: Option<Box<Stmt>>,
@@ -43,15 +56,23 @@ pub struct If {
 
 #[derive(Clone)]
 pub struct While {
+    pub keyword: Token,
     pub condition: Box<Expr>,
     pub body: Box<Stmt>,
+    // Expression statement a `for` loop runs after its body, even when `continue` ends it
+    pub increment: Option<Box<Stmt>>,
 }
 
 #[derive(Clone)]
 pub struct Function {
+    pub uuid: usize,
     pub name: Token,
+    // Text of the `///` comments right above the declaration, one line each
+    pub doc: Option<String>,
     pub params: Vec<Token>,
     pub body: Vec<Stmt>,
+    // Declared `async`, so calling it schedules the body and returns a promise
+    pub is_async: bool,
 }
 
 #[derive(Clone)]
@@ -62,11 +83,23 @@ pub struct Return {
 
 #[derive(Clone)]
 pub struct Class {
+    pub uuid: usize,
     pub name: Token,
-    pub super_class: Option<Expr>,
+    pub doc: Option<String>,
+    pub super_class: Option<Box<Expr>>,
     pub methods: Vec<Stmt>,
 }
 
+#[derive(Clone)]
+pub struct Break {
+    pub keyword: Token,
+}
+
+#[derive(Clone)]
+pub struct Continue {
+    pub keyword: Token,
+}
+
 pub trait Visitor<T> {
     fn visit_expression(&mut self, stmt: &Expression) -> T;
     fn visit_print(&mut self, stmt: &Print) -> T;
@@ -74,9 +107,11 @@ pub trait Visitor<T> {
     fn visit_block(&mut self, stmt: &Block) -> T;
     fn visit_if(&mut self, stmt: &If) -> T;
     fn visit_while(&mut self, stmt: &While) -> T;
-    fn visit_function(&mut self, stmt: &Function) -> T;
+    fn visit_function(&mut self, stmt: &Rc<Function>) -> T;
     fn visit_return(&mut self, stmt: &Return) -> T;
     fn visit_class(&mut self, stmt: &Class) -> T;
+    fn visit_break(&mut self, stmt: &Break) -> T;
+    fn visit_continue(&mut self, stmt: &Continue) -> T;
 }
 
 impl Stmt {
@@ -91,6 +126,27 @@ impl Stmt {
             Stmt::Function(fun) => visitor.visit_function(fun),
             Stmt::Return(r) => visitor.visit_return(r),
             Stmt::Class(class) => visitor.visit_class(class),
+            Stmt::Break(stmt) => visitor.visit_break(stmt),
+            Stmt::Continue(stmt) => visitor.visit_continue(stmt),
+        }
+    }
+}
+
+impl Stmt {
+    // Line the statement starts on, where a debugger stops before running it
+    pub fn line(&self) -> usize {
+        match self {
+            Stmt::Expression(expression) => expression.line,
+            Stmt::Print(print) => print.keyword.line,
+            Stmt::Var(var) => var.name.line,
+            Stmt::Block(block) => block.brace.line,
+            Stmt::If(stmt) => stmt.keyword.line,
+            Stmt::While(stmt) => stmt.keyword.line,
+            Stmt::Function(fun) => fun.name.line,
+            Stmt::Return(r) => r.keyword.line,
+            Stmt::Class(class) => class.name.line,
+            Stmt::Break(stmt) => stmt.keyword.line,
+            Stmt::Continue(stmt) => stmt.keyword.line,
         }
     }
 }
@@ -0,0 +1,66 @@
+/// `StringBuilder()`, for building a long string out of many pieces. Strings are immutable,
+/// so `s = s + piece` in a loop copies everything built so far each time; `append(piece)`
+/// adds to the end of a growing buffer instead, and `toString()` gives what's been built.
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use crate::interpreter::Interpreter;
+use crate::lox_callable::{Callable, HostObject};
+use crate::token::LiteralTypes;
+
+#[derive(Default)]
+pub struct StringBuilder {
+    text: String,
+}
+
+impl HostObject for StringBuilder {
+    fn type_name(&self) -> &str {
+        "StringBuilder"
+    }
+
+    // Counted in characters, like columns
+    fn get(&self, name: &str) -> Option<LiteralTypes> {
+        match name {
+            "length" => Some(LiteralTypes::Number(self.text.chars().count() as f64)),
+            _ => None,
+        }
+    }
+
+    fn set(&mut self, _name: &str, _value: LiteralTypes) -> Result<(), String> {
+        Err("Can't add properties to a string builder.".to_string())
+    }
+
+    fn call_method(
+        &mut self,
+        name: &str,
+        arguments: &[LiteralTypes],
+    ) -> Result<LiteralTypes, String> {
+        match (name, arguments) {
+            ("append", [LiteralTypes::String(piece)]) => {
+                self.text.push_str(piece);
+                Ok(LiteralTypes::Nil)
+            }
+            ("append", [_]) => Err("Can only append strings.".to_string()),
+            ("toString", []) => Ok(LiteralTypes::String(self.text.clone())),
+            ("clear", []) => {
+                self.text.clear();
+                Ok(LiteralTypes::Nil)
+            }
+            ("append", _) => Err(format!("Expected 1 arguments but got {}.", arguments.len())),
+            ("toString" | "clear", _) => {
+                Err(format!("Expected 0 arguments but got {}.", arguments.len()))
+            }
+            _ => Err(format!("Undefined method {}.", name)),
+        }
+    }
+}
+
+// Defines `StringBuilder`
+pub(crate) fn define_natives(interpreter: &mut Interpreter) {
+    interpreter.define_native("StringBuilder", 0, |_, _| {
+        let builder = StringBuilder::default();
+        Ok(LiteralTypes::Callable(Callable::Host(Rc::new(
+            RefCell::new(builder),
+        ))))
+    });
+}
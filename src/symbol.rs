@@ -0,0 +1,99 @@
+/// Interned strings used for lexemes and variable, field and method names
+use std::{
+    cell::RefCell,
+    collections::HashSet,
+    fmt,
+    hash::{Hash, Hasher},
+    ops::Deref,
+    rc::Rc,
+};
+
+// Strings no longer referenced outside the pool are dropped once it reaches this size
+const PURGE_THRESHOLD: usize = 4096;
+
+thread_local! {
+    static POOL: RefCell<Pool> = RefCell::new(Pool {
+        strings: HashSet::new(),
+        purge_at: PURGE_THRESHOLD,
+    });
+}
+
+struct Pool {
+    strings: HashSet<Rc<str>>,
+    purge_at: usize,
+}
+
+// Equal text always shares one allocation, so symbols are compared and hashed by address
+// and cloning one never copies the text.
+#[derive(Clone)]
+pub struct Symbol(Rc<str>);
+
+impl Symbol {
+    pub fn intern(text: &str) -> Symbol {
+        POOL.with(|pool| {
+            let mut pool = pool.borrow_mut();
+            if let Some(existing) = pool.strings.get(text) {
+                return Symbol(Rc::clone(existing));
+            }
+
+            if pool.strings.len() >= pool.purge_at {
+                pool.strings.retain(|s| Rc::strong_count(s) > 1);
+                pool.purge_at = PURGE_THRESHOLD.max(pool.strings.len() * 2);
+            }
+
+            let interned: Rc<str> = Rc::from(text);
+            pool.strings.insert(Rc::clone(&interned));
+            Symbol(interned)
+        })
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl PartialEq for Symbol {
+    fn eq(&self, other: &Self) -> bool {
+        Rc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+impl Eq for Symbol {}
+
+impl PartialEq<str> for Symbol {
+    fn eq(&self, other: &str) -> bool {
+        &*self.0 == other
+    }
+}
+
+impl PartialEq<&str> for Symbol {
+    fn eq(&self, other: &&str) -> bool {
+        &*self.0 == *other
+    }
+}
+
+impl Hash for Symbol {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        (Rc::as_ptr(&self.0) as *const u8 as usize).hash(state);
+    }
+}
+
+impl Deref for Symbol {
+    type Target = str;
+
+    fn deref(&self) -> &str {
+        &self.0
+    }
+}
+
+impl fmt::Display for Symbol {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl fmt::Debug for Symbol {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?}", &*self.0)
+    }
+}
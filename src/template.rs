@@ -0,0 +1,161 @@
+/// Templates: text with embedded Lox, `{{ expression }}` writing the value of the expression
+/// and `{% statements %}` running statements, which can wrap text in loops and conditionals.
+/// A template runs as the tokens of a script, every token keeping its line, column and offset
+/// in the template, so errors point into it.
+use crate::query;
+use crate::scanner::Scanner;
+use crate::symbol::Symbol;
+use crate::token::{LiteralTypes, Token, TokenType};
+
+// Globals writing a piece of the template's text by its index, and the value of an
+// expression. Scripts can't refer to them, as neither is an identifier.
+pub(crate) const WRITE_TEXT: &str = "{text}";
+pub(crate) const WRITE_VALUE: &str = "{value}";
+
+pub(crate) struct Template {
+    pub tokens: Vec<Token>,
+    // Text between the tags, in order
+    pub texts: Vec<String>,
+}
+
+impl Template {
+    // Reports errors in the embedded code as the scanner does. Returns `None`, after
+    // reporting it, if a tag is never closed or `{{ }}` holds nothing.
+    pub(crate) fn scan(template: &str) -> Option<Template> {
+        let line_starts = query::line_starts(template);
+        let position = |offset: usize| {
+            let line = line_starts.partition_point(|&start| start <= offset);
+            let column = template[line_starts[line - 1]..offset].chars().count() + 1;
+            (line, column, offset)
+        };
+        let mut scanned = Template {
+            tokens: Vec::new(),
+            texts: Vec::new(),
+        };
+
+        let mut offset = 0;
+        let mut empty = false;
+        loop {
+            let tag = ["{{", "{%"]
+                .iter()
+                .filter_map(|open| Some(offset + template[offset..].find(open)?))
+                .min();
+            let text_end = tag.unwrap_or(template.len());
+            if text_end > offset {
+                let start = position(offset);
+                let index = scanned.texts.len();
+                scanned.texts.push(template[offset..text_end].to_string());
+                let argument = Token::new(
+                    TokenType::Number,
+                    Symbol::intern(&index.to_string()),
+                    LiteralTypes::Number(index as f64),
+                    start.0,
+                    start.1,
+                    offset,
+                    offset,
+                );
+                scanned.call(WRITE_TEXT, start, start, vec![argument]);
+            }
+            let Some(tag) = tag else {
+                break;
+            };
+
+            let open = position(tag);
+            let value = &template[tag..tag + 2] == "{{";
+            let close = if value { "}}" } else { "%}" };
+            let Some(end) = template[tag + 2..].find(close).map(|i| tag + 2 + i) else {
+                crate::report_at(open.0, open.1, 2, "Unterminated template tag.");
+                return None;
+            };
+            let code = scan_code(&template[tag + 2..end], position(tag + 2));
+            if value && code.is_empty() {
+                crate::report_at(open.0, open.1, 2, "Expect expression in '{{ }}'.");
+                empty = true;
+            } else if value {
+                scanned.call(WRITE_VALUE, open, position(end), code);
+            } else {
+                scanned.tokens.extend(code);
+            }
+            offset = end + 2;
+        }
+
+        let (line, column, offset) = position(template.len());
+        scanned.tokens.push(Token::new(
+            TokenType::Eof,
+            Symbol::intern(""),
+            LiteralTypes::Nil,
+            line,
+            column,
+            offset,
+            offset,
+        ));
+        (!empty).then_some(scanned)
+    }
+
+    // Adds a call to one of the globals writing output, its name and opening parenthesis at
+    // `open` and the rest at `close`
+    fn call(
+        &mut self,
+        name: &str,
+        open: (usize, usize, usize),
+        close: (usize, usize, usize),
+        arguments: Vec<Token>,
+    ) {
+        let (line, column, offset) = open;
+        let name = Token::new(
+            TokenType::Identifier,
+            Symbol::intern(name),
+            LiteralTypes::Nil,
+            line,
+            column,
+            offset,
+            offset,
+        );
+        self.tokens.push(name);
+        self.tokens.push(punctuation(TokenType::LeftParen, open));
+        self.tokens.extend(arguments);
+        self.tokens.push(punctuation(TokenType::RightParen, close));
+        self.tokens.push(punctuation(TokenType::Semicolon, close));
+    }
+}
+
+// Scans the code of a tag starting at the line, column and offset, reporting errors as it goes
+fn scan_code(code: &str, (line, column, offset): (usize, usize, usize)) -> Vec<Token> {
+    // Columns after the first line already count from the start of their line
+    let shift = |token_line: usize, token_column: usize| match token_line == line {
+        true => token_column + column - 1,
+        false => token_column,
+    };
+    Scanner::from_line(code, line, offset)
+        .filter_map(|token| match token {
+            Ok(token) if token.ttype == TokenType::Eof => None,
+            Ok(mut token) => {
+                token.column = shift(token.line, token.column);
+                Some(token)
+            }
+            Err(mut error) => {
+                error.column = shift(error.line, error.column);
+                error.report();
+                None
+            }
+        })
+        .collect()
+}
+
+// Made up, so its span is empty
+fn punctuation(ttype: TokenType, (line, column, offset): (usize, usize, usize)) -> Token {
+    let lexeme = match ttype {
+        TokenType::LeftParen => "(",
+        TokenType::RightParen => ")",
+        _ => ";",
+    };
+    Token::new(
+        ttype,
+        Symbol::intern(lexeme),
+        LiteralTypes::Nil,
+        line,
+        column,
+        offset,
+        offset,
+    )
+}
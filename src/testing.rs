@@ -0,0 +1,94 @@
+/// Unit tests written in Lox: `test(name, fn)` runs a test and records whether it passed,
+/// `expectEq(actual, expected)` and `expectError(fn)` fail the test they're called from.
+/// `rlox test --native` runs every script under a path with these natives and adds up the
+/// results.
+use std::cell::RefCell;
+use std::fmt;
+use std::rc::Rc;
+
+use crate::debugger::describe;
+use crate::interpreter::{Exit, Interpreter};
+use crate::token::LiteralTypes;
+
+// Results of the tests run so far
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct TestReport {
+    pub passed: usize,
+    // Name of every failed test with why it failed
+    pub failed: Vec<(String, String)>,
+}
+
+impl TestReport {
+    pub fn add(&mut self, other: &TestReport) {
+        self.passed += other.passed;
+        self.failed.extend(other.failed.iter().cloned());
+    }
+}
+
+// e.g. "test result: FAILED. 4 passed; 1 failed"
+impl fmt::Display for TestReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let result = match self.failed.is_empty() {
+            true => "ok",
+            false => "FAILED",
+        };
+        write!(
+            f,
+            "test result: {}. {} passed; {} failed",
+            result,
+            self.passed,
+            self.failed.len()
+        )
+    }
+}
+
+// Defines the testing natives, which record into the returned report and write a line per
+// test to the interpreter's output
+pub fn define_natives(interpreter: &mut Interpreter) -> Rc<RefCell<TestReport>> {
+    let report = Rc::new(RefCell::new(TestReport::default()));
+
+    let results = Rc::clone(&report);
+    interpreter.define_native("test", 2, move |interpreter, arguments| {
+        let name = interpreter.stringify(&arguments[0]);
+        let failure = match interpreter.call_value(&arguments[1], &[]) {
+            Ok(_) => None,
+            Err(Exit::RuntimeError(error)) => Some(error.to_string()),
+            Err(exit) => return Err(exit),
+        };
+        match failure {
+            None => {
+                results.borrow_mut().passed += 1;
+                interpreter.write_output(&format!("test {} ... ok\n", name))?;
+            }
+            Some(message) => {
+                interpreter
+                    .write_output(&format!("test {} ... FAILED\n    {}\n", name, message))?;
+                results.borrow_mut().failed.push((name, message));
+            }
+        }
+        Ok(LiteralTypes::Nil)
+    });
+
+    interpreter.define_native("expectEq", 2, |interpreter, arguments| {
+        let (actual, expected) = (&arguments[0], &arguments[1]);
+        if interpreter.is_equal(actual, expected) {
+            return Ok(LiteralTypes::Nil);
+        }
+        Err(Exit::message(format!(
+            "Expected {} but got {}.",
+            describe(interpreter, expected),
+            describe(interpreter, actual)
+        )))
+    });
+
+    interpreter.define_native("expectError", 1, |interpreter, arguments| {
+        let result = interpreter.call_value(&arguments[0], &[]);
+        match result {
+            Ok(_) => Err(Exit::message("Expected an error.")),
+            Err(Exit::RuntimeError(_)) => Ok(LiteralTypes::Nil),
+            Err(exit) => Err(exit),
+        }
+    });
+
+    report
+}
@@ -0,0 +1,526 @@
+/// Threads for scripts: `spawn(fn)` runs a function on a thread of its own and returns a
+/// handle whose `join()` waits for the value it returns, and `channel()` makes a queue that
+/// threads `send(value)` to and `receive()` from. Every thread runs in an interpreter of its
+/// own, so nothing mutable is shared: the function, the variables it captures and the globals
+/// it uses are copied when it's spawned, and values are copied again as they're sent.
+use std::any::Any;
+use std::cell::RefCell;
+use std::collections::{BTreeMap, VecDeque};
+use std::io::{self, Write};
+use std::rc::Rc;
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread::{self, JoinHandle};
+
+use crate::bytes::Bytes;
+use crate::environment::Upvalue;
+use crate::interpreter::{Exit, Interpreter};
+use crate::lox_callable::{Callable, HostObject, LoxCallable, LoxFunction};
+use crate::loxc::{self, LoadError, Place};
+use crate::stmt::Stmt;
+use crate::token::LiteralTypes;
+
+// The stack the CLI runs scripts on, leaving room for the call depth it allows
+const STACK_SIZE: usize = 64 * 1024 * 1024;
+
+// A value copied out of one interpreter, to be rebuilt in another
+enum Value {
+    Nil,
+    Bool(bool),
+    Number(f64),
+    String(String),
+    List(Vec<Value>),
+    Map(BTreeMap<String, Value>),
+    Channel(Channel),
+    Bytes(Bytes),
+    // Index into the functions copied along with the value
+    Function(usize),
+}
+
+impl Value {
+    fn into_lox(self, functions: &[LiteralTypes]) -> LiteralTypes {
+        match self {
+            Value::Nil => LiteralTypes::Nil,
+            Value::Bool(value) => LiteralTypes::Bool(value),
+            Value::Number(value) => LiteralTypes::Number(value),
+            Value::String(value) => LiteralTypes::String(value),
+            Value::List(items) => {
+                let items = items.into_iter().map(|item| item.into_lox(functions));
+                LiteralTypes::List(Rc::new(RefCell::new(items.collect())))
+            }
+            Value::Map(entries) => {
+                let entries = entries
+                    .into_iter()
+                    .map(|(key, value)| (key, value.into_lox(functions)));
+                LiteralTypes::Map(Rc::new(RefCell::new(entries.collect())))
+            }
+            Value::Channel(channel) => host(channel),
+            Value::Bytes(bytes) => bytes.into_value(),
+            Value::Function(index) => functions[index].clone(),
+        }
+    }
+}
+
+fn host(object: impl HostObject + 'static) -> LiteralTypes {
+    LiteralTypes::Callable(Callable::Host(Rc::new(RefCell::new(object))))
+}
+
+// A function compiled as `rlox compile` stores scripts, with the variables it captures
+struct SentFunction {
+    code: Vec<u8>,
+    // Index of each captured variable among the copied ones
+    upvalues: Vec<usize>,
+}
+
+// Copies values out of an interpreter. Functions are only copied when spawning, since
+// that needs the interpreter that resolved them.
+struct Copier<'a> {
+    interpreter: Option<&'a Interpreter>,
+    functions: Vec<SentFunction>,
+    copied_functions: Vec<(LoxFunction, usize)>,
+    // Captured variables, copied once however many closures share them
+    cells: Vec<Value>,
+    copied_cells: Vec<(Upvalue, usize)>,
+    globals: Vec<(String, Value)>,
+    // Lists and maps being copied, to refuse ones that contain themselves
+    containers: Vec<*const ()>,
+}
+
+impl<'a> Copier<'a> {
+    fn new(interpreter: Option<&'a Interpreter>) -> Self {
+        Copier {
+            interpreter,
+            functions: Vec::new(),
+            copied_functions: Vec::new(),
+            cells: Vec::new(),
+            copied_cells: Vec::new(),
+            globals: Vec::new(),
+            containers: Vec::new(),
+        }
+    }
+
+    fn copy(&mut self, value: &LiteralTypes) -> Result<Value, String> {
+        let refused = |what: String| Err(format!("Can't send {} to another thread.", what));
+        Ok(match value {
+            LiteralTypes::Nil | LiteralTypes::Unassigned => Value::Nil,
+            LiteralTypes::Bool(value) => Value::Bool(*value),
+            LiteralTypes::Number(value) => Value::Number(*value),
+            LiteralTypes::String(value) => Value::String(value.clone()),
+            LiteralTypes::List(list) => {
+                let container = Rc::as_ptr(list) as *const ();
+                if self.containers.contains(&container) {
+                    return refused("a list that contains itself".to_string());
+                }
+                self.containers.push(container);
+                let items: Result<_, String> =
+                    list.borrow().iter().map(|item| self.copy(item)).collect();
+                self.containers.pop();
+                Value::List(items?)
+            }
+            LiteralTypes::Map(map) => {
+                let container = Rc::as_ptr(map) as *const ();
+                if self.containers.contains(&container) {
+                    return refused("a map that contains itself".to_string());
+                }
+                self.containers.push(container);
+                let entries: Result<_, String> = map
+                    .borrow()
+                    .iter()
+                    .map(|(key, value)| Ok((key.clone(), self.copy(value)?)))
+                    .collect();
+                self.containers.pop();
+                Value::Map(entries?)
+            }
+            LiteralTypes::Callable(Callable::Host(object)) => {
+                let object = object.borrow();
+                let any = object.as_any();
+                if let Some(channel) = any.and_then(|any| any.downcast_ref::<Channel>()) {
+                    Value::Channel(channel.clone())
+                } else if let Some(bytes) = any.and_then(|any| any.downcast_ref::<Bytes>()) {
+                    Value::Bytes(bytes.clone())
+                } else {
+                    return refused(format!("{} instance", object.type_name()));
+                }
+            }
+            LiteralTypes::Callable(Callable::Function(function)) => {
+                match (&function.receiver, self.interpreter) {
+                    (None, Some(interpreter)) => {
+                        Value::Function(self.function(interpreter, function)?)
+                    }
+                    (None, None) => return refused(function.to_string()),
+                    (Some(_), _) => {
+                        return refused(format!("{} method", function.declaration.name.lexeme))
+                    }
+                }
+            }
+            LiteralTypes::Callable(Callable::Class(class)) => {
+                return refused(format!("{} class", class.name))
+            }
+            LiteralTypes::Callable(Callable::Instance(instance)) => {
+                return refused(instance.borrow().to_string())
+            }
+            LiteralTypes::Callable(Callable::Native(native)) => return refused(native.to_string()),
+        })
+    }
+
+    fn function(
+        &mut self,
+        interpreter: &'a Interpreter,
+        function: &LoxFunction,
+    ) -> Result<usize, String> {
+        if let Some((_, index)) = self.copied_functions.iter().find(|(f, _)| f == function) {
+            return Ok(*index);
+        }
+        let statements = [Stmt::Function(Rc::clone(&function.declaration))];
+        let ids = loxc::node_ids(&statements);
+        let resolution = interpreter.resolution(ids.clone());
+        let globals: Vec<String> = resolution
+            .locals
+            .iter()
+            .chain(resolution.receivers.iter())
+            .filter_map(|(_, place)| match place {
+                Place::Global(name) => Some(name.as_str().to_string()),
+                _ => None,
+            })
+            .collect();
+
+        // Recorded before what it uses is copied, so recursive functions are copied once
+        let index = self.functions.len();
+        self.functions.push(SentFunction {
+            code: loxc::encode(&statements, &resolution, ids.start, ids.end),
+            upvalues: Vec::new(),
+        });
+        self.copied_functions.push((function.clone(), index));
+        for cell in function.upvalues.iter() {
+            let cell = self.cell(cell)?;
+            self.functions[index].upvalues.push(cell);
+        }
+        for name in globals {
+            self.global(interpreter, name)?;
+        }
+        Ok(index)
+    }
+
+    fn cell(&mut self, cell: &Upvalue) -> Result<usize, String> {
+        if let Some((_, index)) = self.copied_cells.iter().find(|(c, _)| Rc::ptr_eq(c, cell)) {
+            return Ok(*index);
+        }
+        let index = self.cells.len();
+        self.cells.push(Value::Nil);
+        self.copied_cells.push((Rc::clone(cell), index));
+        let value = cell.borrow().clone();
+        self.cells[index] = self.copy(&value)?;
+        Ok(index)
+    }
+
+    // Globals that aren't defined are left for the thread to report if it reads them, and
+    // natives for it to use its own
+    fn global(&mut self, interpreter: &'a Interpreter, name: String) -> Result<(), String> {
+        if self.globals.iter().any(|(copied, _)| *copied == name) {
+            return Ok(());
+        }
+        let value = match interpreter.global(&name) {
+            None | Some(LiteralTypes::Callable(Callable::Native(_))) => return Ok(()),
+            Some(value) => value,
+        };
+        let index = self.globals.len();
+        self.globals.push((name, Value::Nil));
+        self.globals[index].1 = self.copy(&value)?;
+        Ok(())
+    }
+}
+
+// Which of the spawner's writers relayed output goes to
+#[derive(Clone, Copy)]
+enum Stream {
+    Output,
+    Errors,
+}
+
+// Writer a thread's output and errors go through on their way back to the spawner, which
+// writes them out where its own go
+struct Relay {
+    stream: Stream,
+    sender: Sender<(Stream, Vec<u8>)>,
+}
+
+impl Write for Relay {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        // Once the handle is dropped nobody collects the output, so it's discarded
+        let _ = self.sender.send((self.stream, buf.to_vec()));
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+// A spawned function with everything it uses, ready to be rebuilt on its thread, along with
+// the spawner's settings so the thread can do no more than the spawner could
+struct Snapshot {
+    functions: Vec<SentFunction>,
+    cells: Vec<Value>,
+    globals: Vec<(String, Value)>,
+    function: Value,
+    max_call_depth: usize,
+    max_nesting: usize,
+    strict: bool,
+    allow_net: bool,
+    allow_fs: bool,
+    allow_signals: bool,
+    allow_extensions: bool,
+    // Drawn from the spawner's sequence in deterministic mode, so every thread gets its own
+    seed: Option<u64>,
+    relay: Sender<(Stream, Vec<u8>)>,
+}
+
+impl Snapshot {
+    fn take(
+        interpreter: &mut Interpreter,
+        function: &LiteralTypes,
+        relay: Sender<(Stream, Vec<u8>)>,
+    ) -> Result<Snapshot, String> {
+        let mut copier = Copier::new(Some(interpreter));
+        let function = copier.copy(function)?;
+        let (functions, cells, globals) = (copier.functions, copier.cells, copier.globals);
+        let seed = match interpreter.deterministic() {
+            true => Some(interpreter.random().to_bits()),
+            false => None,
+        };
+        Ok(Snapshot {
+            functions,
+            cells,
+            globals,
+            function,
+            max_call_depth: interpreter.max_call_depth(),
+            max_nesting: interpreter.max_nesting(),
+            strict: interpreter.strict(),
+            allow_net: interpreter.allow_net(),
+            allow_fs: interpreter.allow_fs(),
+            allow_signals: interpreter.allow_signals(),
+            allow_extensions: interpreter.allow_extensions(),
+            seed,
+            relay,
+        })
+    }
+
+    fn rebuild(self, isolate: &mut Interpreter) -> Result<LiteralTypes, LoadError> {
+        isolate.set_max_call_depth(self.max_call_depth);
+        isolate.set_max_nesting(self.max_nesting);
+        isolate.set_strict(self.strict);
+        isolate.set_allow_net(self.allow_net);
+        isolate.set_allow_fs(self.allow_fs);
+        isolate.set_allow_signals(self.allow_signals);
+        isolate.set_allow_extensions(self.allow_extensions);
+        isolate.set_deterministic(self.seed);
+        let cells: Vec<Upvalue> = self
+            .cells
+            .iter()
+            .map(|_| Rc::new(RefCell::new(LiteralTypes::Nil)))
+            .collect();
+        let mut functions = Vec::new();
+        for function in self.functions.iter() {
+            let max_depth = isolate.max_nesting().saturating_mul(4);
+            let program = loxc::decode(&function.code, isolate.next_id(), max_depth)?;
+            isolate.set_next_id(program.end_id);
+            isolate.restore(program.resolution);
+            let Some(Stmt::Function(declaration)) = program.statements.into_iter().next() else {
+                return Err(LoadError::Corrupt);
+            };
+            let upvalues = function.upvalues.iter().map(|&i| Rc::clone(&cells[i]));
+            let function = LoxFunction::new(declaration, upvalues.collect(), false);
+            functions.push(LiteralTypes::Callable(Callable::Function(function)));
+        }
+        for (cell, value) in cells.iter().zip(self.cells) {
+            *cell.borrow_mut() = value.into_lox(&functions);
+        }
+        for (name, value) in self.globals {
+            isolate.define(&name, value.into_lox(&functions));
+        }
+        Ok(self.function.into_lox(&functions))
+    }
+
+    // Runs the function in an interpreter of its own, reporting any error there. Returns
+    // what the function returned, or `None` if it failed.
+    fn run(self) -> Option<Value> {
+        let mut isolate = Interpreter::with_output(Box::new(Relay {
+            stream: Stream::Output,
+            sender: self.relay.clone(),
+        }));
+        isolate.set_error_output(Box::new(Relay {
+            stream: Stream::Errors,
+            sender: self.relay.clone(),
+        }));
+        define_natives(&mut isolate);
+        let errors = Rc::clone(isolate.error_output());
+        let _errors = crate::ErrorsGuard::set(&errors);
+        let result = self
+            .rebuild(&mut isolate)
+            .map_err(|error| Exit::message(format!("Error: {}", error)))
+            .and_then(|function| isolate.call_value(&function, &[]))
+            .and_then(|value| Copier::new(None).copy(&value).map_err(Exit::message));
+        match result {
+            Ok(value) => Some(value),
+            Err(Exit::RuntimeError(error)) => {
+                crate::interpreter::report_error(&error);
+                None
+            }
+            Err(_) => None,
+        }
+    }
+}
+
+// Queue of values sent between threads. Every copy of a channel is the same queue.
+#[derive(Clone, Default)]
+pub struct Channel {
+    queue: Arc<(Mutex<VecDeque<Value>>, Condvar)>,
+}
+
+impl HostObject for Channel {
+    fn type_name(&self) -> &str {
+        "Channel"
+    }
+
+    fn get(&self, _name: &str) -> Option<LiteralTypes> {
+        None
+    }
+
+    fn set(&mut self, _name: &str, _value: LiteralTypes) -> Result<(), String> {
+        Err("Can't add properties to a channel.".to_string())
+    }
+
+    fn call_method(
+        &mut self,
+        name: &str,
+        arguments: &[LiteralTypes],
+    ) -> Result<LiteralTypes, String> {
+        let (queue, ready) = &*self.queue;
+        match (name, arguments) {
+            ("send", [value]) => {
+                let value = Copier::new(None).copy(value)?;
+                // A thread that panicked while holding the lock left the queue intact
+                let mut queue = queue
+                    .lock()
+                    .unwrap_or_else(|poisoned| poisoned.into_inner());
+                queue.push_back(value);
+                ready.notify_one();
+                Ok(LiteralTypes::Nil)
+            }
+            // Waits for a value if none has been sent yet
+            ("receive", []) => {
+                let mut queue = queue
+                    .lock()
+                    .unwrap_or_else(|poisoned| poisoned.into_inner());
+                loop {
+                    if let Some(value) = queue.pop_front() {
+                        return Ok(value.into_lox(&[]));
+                    }
+                    queue = ready
+                        .wait(queue)
+                        .unwrap_or_else(|poisoned| poisoned.into_inner());
+                }
+            }
+            ("send", _) => Err(format!("Expected 1 arguments but got {}.", arguments.len())),
+            ("receive", _) => Err(format!("Expected 0 arguments but got {}.", arguments.len())),
+            _ => Err(format!("Undefined method {}.", name)),
+        }
+    }
+
+    fn as_any(&self) -> Option<&dyn Any> {
+        Some(self)
+    }
+}
+
+// Handle of a spawned thread, which can be joined once
+struct Thread {
+    handle: Option<JoinHandle<Option<Value>>>,
+    relayed: Receiver<(Stream, Vec<u8>)>,
+    // The spawner's output and error output
+    output: crate::Sink,
+    errors: crate::Sink,
+}
+
+impl Thread {
+    // Writes out what the thread has printed so far
+    fn forward(&self) -> io::Result<()> {
+        for (stream, bytes) in self.relayed.try_iter() {
+            let sink = match stream {
+                Stream::Output => &self.output,
+                Stream::Errors => &self.errors,
+            };
+            sink.borrow_mut().write_all(&bytes)?;
+        }
+        Ok(())
+    }
+}
+
+impl Drop for Thread {
+    fn drop(&mut self) {
+        let _ = self.forward();
+    }
+}
+
+impl HostObject for Thread {
+    fn type_name(&self) -> &str {
+        "Thread"
+    }
+
+    fn get(&self, _name: &str) -> Option<LiteralTypes> {
+        None
+    }
+
+    fn set(&mut self, _name: &str, _value: LiteralTypes) -> Result<(), String> {
+        Err("Can't add properties to a thread.".to_string())
+    }
+
+    fn call_method(
+        &mut self,
+        name: &str,
+        arguments: &[LiteralTypes],
+    ) -> Result<LiteralTypes, String> {
+        match (name, arguments) {
+            ("join", []) => {
+                let handle = self.handle.take().ok_or("Thread already joined.")?;
+                let result = handle.join();
+                self.forward()
+                    .map_err(|_| "Failed to write output.".to_string())?;
+                match result {
+                    Ok(Some(value)) => Ok(value.into_lox(&[])),
+                    _ => Err("Thread failed.".to_string()),
+                }
+            }
+            ("join", _) => Err(format!("Expected 0 arguments but got {}.", arguments.len())),
+            _ => Err(format!("Undefined method {}.", name)),
+        }
+    }
+}
+
+// Defines `spawn` and `channel`. Threads get them too, so they can spawn threads of their own.
+pub fn define_natives(interpreter: &mut Interpreter) {
+    interpreter.define_native("spawn", 1, |interpreter, arguments| {
+        match &arguments[0] {
+            LiteralTypes::Callable(Callable::Function(function)) if function.arity() == 0 => {}
+            LiteralTypes::Callable(Callable::Function(_)) => {
+                return Err(Exit::message(
+                    "Can only spawn functions without parameters.",
+                ))
+            }
+            _ => return Err(Exit::message("Can only spawn functions.")),
+        }
+        let (relay, relayed) = mpsc::channel();
+        let snapshot = Snapshot::take(interpreter, &arguments[0], relay).map_err(Exit::message)?;
+        let handle = thread::Builder::new()
+            .stack_size(STACK_SIZE)
+            .spawn(move || snapshot.run())
+            .map_err(|error| Exit::message(format!("Error starting thread: {}.", error)))?;
+        Ok(host(Thread {
+            handle: Some(handle),
+            relayed,
+            output: Rc::clone(interpreter.output()),
+            errors: Rc::clone(interpreter.error_output()),
+        }))
+    });
+
+    interpreter.define_native("channel", 0, |_, _| Ok(host(Channel::default())));
+}
@@ -1,11 +1,22 @@
-use crate::lox_callable::Callable;
+use std::{cell::RefCell, collections::BTreeMap, rc::Rc};
+
+use crate::{lox_callable::Callable, symbol::Symbol};
 
 #[derive(Debug, Clone)]
 pub struct Token {
     pub ttype: TokenType,
-    pub lexeme: String,
+    // Interned rather than borrowed from the source, so tokens outlive it, as they do in
+    // functions the REPL keeps and in compiled scripts, which have no source. Each distinct
+    // lexeme is copied out once, not once per token.
+    pub lexeme: Symbol,
     pub literal: LiteralTypes,
     pub line: usize,
+    // Counted in characters from 1
+    pub column: usize,
+    // Byte offsets of the lexeme in the source, `end` exclusive. Tokens that aren't in the
+    // source have an empty span where they'd be.
+    pub start: usize,
+    pub end: usize,
 }
 
 #[derive(Debug, Clone, PartialEq)]
@@ -15,27 +26,119 @@ pub enum LiteralTypes {
     Bool(bool),
     Nil,
     Callable(Callable),
+    List(Rc<RefCell<Vec<LiteralTypes>>>),
+    Map(Rc<RefCell<BTreeMap<String, LiteralTypes>>>),
+    // Value of a variable declared without an initializer, which can't be read until
+    // something is assigned to it
+    Unassigned,
+}
+
+impl From<f64> for LiteralTypes {
+    fn from(value: f64) -> Self {
+        LiteralTypes::Number(value)
+    }
+}
+
+impl From<bool> for LiteralTypes {
+    fn from(value: bool) -> Self {
+        LiteralTypes::Bool(value)
+    }
+}
+
+impl From<String> for LiteralTypes {
+    fn from(value: String) -> Self {
+        LiteralTypes::String(value)
+    }
+}
+
+impl From<&str> for LiteralTypes {
+    fn from(value: &str) -> Self {
+        LiteralTypes::String(value.to_string())
+    }
+}
+
+impl From<()> for LiteralTypes {
+    fn from(_: ()) -> Self {
+        LiteralTypes::Nil
+    }
+}
+
+impl From<Vec<LiteralTypes>> for LiteralTypes {
+    fn from(value: Vec<LiteralTypes>) -> Self {
+        LiteralTypes::List(Rc::new(RefCell::new(value)))
+    }
+}
+
+impl From<BTreeMap<String, LiteralTypes>> for LiteralTypes {
+    fn from(value: BTreeMap<String, LiteralTypes>) -> Self {
+        LiteralTypes::Map(Rc::new(RefCell::new(value)))
+    }
+}
+
+impl TryFrom<LiteralTypes> for f64 {
+    type Error = String;
+
+    fn try_from(value: LiteralTypes) -> Result<Self, Self::Error> {
+        match value {
+            LiteralTypes::Number(n) => Ok(n),
+            _ => Err("Expected a number.".to_string()),
+        }
+    }
+}
+
+impl TryFrom<LiteralTypes> for bool {
+    type Error = String;
+
+    fn try_from(value: LiteralTypes) -> Result<Self, Self::Error> {
+        match value {
+            LiteralTypes::Bool(b) => Ok(b),
+            _ => Err("Expected a boolean.".to_string()),
+        }
+    }
+}
+
+impl TryFrom<LiteralTypes> for String {
+    type Error = String;
+
+    fn try_from(value: LiteralTypes) -> Result<Self, Self::Error> {
+        match value {
+            LiteralTypes::String(s) => Ok(s),
+            _ => Err("Expected a string.".to_string()),
+        }
+    }
 }
 
 impl Token {
-    pub fn new(ttype: TokenType, lexeme: String, literal: LiteralTypes, line: usize) -> Self {
+    pub fn new(
+        ttype: TokenType,
+        lexeme: Symbol,
+        literal: LiteralTypes,
+        line: usize,
+        column: usize,
+        start: usize,
+        end: usize,
+    ) -> Self {
         Token {
             ttype,
             lexeme,
             literal,
             line,
+            column,
+            start,
+            end,
         }
     }
 
     pub fn show(&self) -> String {
         format!(
-            "line:{} ttype:{:?} lexeme:{} literal:{:?}",
-            self.line, self.ttype, self.lexeme, self.literal
+            "line:{} column:{} ttype:{:?} lexeme:{} literal:{:?}",
+            self.line, self.column, self.ttype, self.lexeme, self.literal
         )
     }
 }
 
 #[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "fuzz", derive(arbitrary::Arbitrary))]
 pub enum TokenType {
     // Single-character tokens.
     LeftParen,
@@ -67,7 +170,11 @@ pub enum TokenType {
 
     // Keywords.
     And,
+    Async,
+    Await,
+    Break,
     Class,
+    Continue,
     Else,
     False,
     Fun,
@@ -83,5 +190,9 @@ pub enum TokenType {
     Var,
     While,
 
+    // `///` comment, documenting the declaration below it. Its literal is the text after the
+    // slashes. The parser takes these out of the token stream before parsing.
+    DocComment,
+
     Eof,
 }
@@ -0,0 +1,104 @@
+/// JavaScript-facing API used when building for `wasm32-unknown-unknown`
+use wasm_bindgen::prelude::*;
+
+use crate::interpreter::Interpreter;
+use crate::shared_buffer::SharedBuffer;
+use crate::token::LiteralTypes;
+
+#[wasm_bindgen]
+pub struct RunResult {
+    stdout: String,
+    values: Vec<String>,
+    diagnostics: Vec<String>,
+    duration: f64,
+    steps: usize,
+}
+
+#[wasm_bindgen]
+impl RunResult {
+    // Everything the program printed
+    #[wasm_bindgen(getter)]
+    pub fn stdout(&self) -> String {
+        self.stdout.clone()
+    }
+
+    // Same as `stdout`, for pages written before it was added
+    #[wasm_bindgen(getter)]
+    pub fn output(&self) -> String {
+        self.stdout.clone()
+    }
+
+    // Value of every top-level expression statement that isn't nil, in the order they ran
+    #[wasm_bindgen(getter)]
+    pub fn values(&self) -> Vec<String> {
+        self.values.clone()
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn diagnostics(&self) -> Vec<String> {
+        self.diagnostics.clone()
+    }
+
+    // Milliseconds spent scanning, parsing, resolving and running the program
+    #[wasm_bindgen(getter)]
+    pub fn duration(&self) -> f64 {
+        self.duration
+    }
+
+    // Statements run
+    #[wasm_bindgen(getter)]
+    pub fn steps(&self) -> usize {
+        self.steps
+    }
+}
+
+#[wasm_bindgen]
+extern "C" {
+    #[wasm_bindgen(js_namespace = Date)]
+    fn now() -> f64;
+}
+
+// Runs a Lox program and returns everything it printed along with any errors
+#[wasm_bindgen]
+pub fn run(source: &str) -> RunResult {
+    run_capture(source)
+}
+
+// Runs a Lox program for a playground, keeping what it printed, the values of its
+// expression statements, its errors and how long it ran apart
+#[wasm_bindgen]
+pub fn run_capture(source: &str) -> RunResult {
+    let buffer = SharedBuffer::default();
+    let mut interpreter = Interpreter::with_output(Box::new(buffer.clone()));
+    let started = now();
+    let (values, diagnostics) = crate::capture_diagnostics(|| values_of(&mut interpreter, source));
+    let duration = now() - started;
+
+    let stdout = buffer.take_text();
+    RunResult {
+        stdout,
+        values,
+        diagnostics,
+        duration,
+        steps: interpreter.steps(),
+    }
+}
+
+// Runs the program, keeping the value of each expression statement. Like `run`, carries on
+// after a runtime error and runs async calls once the program has.
+fn values_of(interpreter: &mut Interpreter, source: &str) -> Vec<String> {
+    let _source = crate::SourceGuard::set(source);
+    let Ok(statements) = crate::parse_for(interpreter, source) else {
+        return Vec::new();
+    };
+    if crate::resolve(interpreter, &statements).is_err() {
+        return Vec::new();
+    }
+    let mut values = Vec::new();
+    let _ = interpreter.interpret_values(&statements, &mut values);
+    values
+        .iter()
+        .filter(|value| **value != LiteralTypes::Nil)
+        .map(|value| interpreter.stringify(value))
+        .collect()
+}
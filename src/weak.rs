@@ -0,0 +1,81 @@
+/// `weakRef(object)`, a reference to an instance, list or map that doesn't keep it alive.
+/// The handle's `get()` gives the object back while something else still holds it, and `nil`
+/// once it's been freed, whether when its last reference went or by the cycle collector.
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::rc::{Rc, Weak};
+
+use crate::interpreter::{Exit, Interpreter};
+use crate::lox_callable::{Callable, HostObject, LoxInstance};
+use crate::token::LiteralTypes;
+
+enum Target {
+    Instance(Weak<RefCell<LoxInstance>>),
+    List(Weak<RefCell<Vec<LiteralTypes>>>),
+    Map(Weak<RefCell<BTreeMap<String, LiteralTypes>>>),
+}
+
+pub struct WeakRef {
+    target: Target,
+}
+
+impl WeakRef {
+    fn get(&self) -> LiteralTypes {
+        let value = match &self.target {
+            Target::Instance(instance) => instance
+                .upgrade()
+                .map(|instance| LiteralTypes::Callable(Callable::Instance(instance))),
+            Target::List(list) => list.upgrade().map(LiteralTypes::List),
+            Target::Map(map) => map.upgrade().map(LiteralTypes::Map),
+        };
+        value.unwrap_or(LiteralTypes::Nil)
+    }
+}
+
+impl HostObject for WeakRef {
+    fn type_name(&self) -> &str {
+        "WeakRef"
+    }
+
+    fn get(&self, _name: &str) -> Option<LiteralTypes> {
+        None
+    }
+
+    fn set(&mut self, _name: &str, _value: LiteralTypes) -> Result<(), String> {
+        Err("Can't add properties to a weak reference.".to_string())
+    }
+
+    fn call_method(
+        &mut self,
+        name: &str,
+        arguments: &[LiteralTypes],
+    ) -> Result<LiteralTypes, String> {
+        match (name, arguments) {
+            ("get", []) => Ok(WeakRef::get(self)),
+            ("get", _) => Err(format!("Expected 0 arguments but got {}.", arguments.len())),
+            _ => Err(format!("Undefined method {}.", name)),
+        }
+    }
+}
+
+// Defines `weakRef`
+pub(crate) fn define_natives(interpreter: &mut Interpreter) {
+    interpreter.define_native("weakRef", 1, |_, arguments| {
+        let target = match &arguments[0] {
+            LiteralTypes::Callable(Callable::Instance(instance)) => {
+                Target::Instance(Rc::downgrade(instance))
+            }
+            LiteralTypes::List(list) => Target::List(Rc::downgrade(list)),
+            LiteralTypes::Map(map) => Target::Map(Rc::downgrade(map)),
+            _ => {
+                return Err(Exit::message(
+                    "Can only make weak references to instances, lists and maps.",
+                ))
+            }
+        };
+        let weak = WeakRef { target };
+        Ok(LiteralTypes::Callable(Callable::Host(Rc::new(
+            RefCell::new(weak),
+        ))))
+    });
+}
@@ -0,0 +1,155 @@
+// Snapshots of `rlox --ast` output in both forms, for a script using every kind of statement
+// and expression
+use rlox::ast_printer::Form;
+use rlox::{print_ast, LoxError};
+
+const SCRIPT: &str = r#"
+var empty;
+var a = 1;
+print a + 2 * (3 - 4) / 5;
+a = -a;
+print !true == false != nil;
+print 1 < 2 and 2 <= 3 or 4 > 5 and 5 >= 6;
+print "text";
+{
+  var b = a;
+  print b;
+}
+if (a > 0) print "positive"; else print "not positive";
+if (a) print a;
+while (a < 10) a = a + 1;
+for (var i = 0; i < 3; i = i + 1) {
+  if (i == 1) continue;
+  if (i == 2) break;
+  print i;
+}
+fun add(x, y) {
+  return x + y;
+}
+fun nothing() {
+  return;
+}
+async fun later() {
+  return await add(1, 2);
+}
+class Base {
+  init(name) {
+    this.name = name;
+  }
+  greet() {
+    return "hi " + this.name;
+  }
+}
+class Derived < Base {
+  greet() {
+    return super.greet() + "!";
+  }
+}
+var d = Derived("lox");
+d.name = "rlox";
+print d.greet();
+"#;
+
+// Syntax trees print without the first newline the snapshots start with
+fn snapshot(text: &str) -> &str {
+    text.strip_prefix('\n').unwrap()
+}
+
+#[test]
+fn lisp_form() {
+    let expected = r#"
+(var empty)
+(var a = 1)
+(print (+ a (/ (* 2 (group (- 3 4))) 5)))
+(; (= a (- a)))
+(print (!= (== (! true) false) nil))
+(print (or (and (< 1 2) (<= 2 3)) (and (> 4 5) (>= 5 6))))
+(print "text")
+(block (var b = a) (print b))
+(if-else (> a 0) (print "positive") (print "not positive"))
+(if a (print a))
+(while (< a 10) (; (= a (+ a 1))))
+(block (var i = 0) (for (< i 3) (= i (+ i 1)) (block (if (== i 1) (continue)) (if (== i 2) (break)) (print i))))
+(fun add(x y) (return (+ x y)))
+(fun nothing() (return nil))
+(async fun later() (return (await (call add 1 2))))
+(class Base (fun init(name) (; (= (. this name) name))) (fun greet() (return (+ "hi " (. this name)))))
+(class Derived < Base (fun greet() (return (+ (call (super greet)) "!"))))
+(var d = (call Derived "lox"))
+(; (= (. d name) "rlox"))
+(print (call (. d greet)))
+"#;
+    assert_eq!(print_ast(SCRIPT, Form::Lisp).unwrap(), snapshot(expected));
+}
+
+#[test]
+fn source_form() {
+    let expected = r#"
+var empty;
+var a = 1;
+print a + 2 * (3 - 4) / 5;
+a = -a;
+print !true == false != nil;
+print 1 < 2 and 2 <= 3 or 4 > 5 and 5 >= 6;
+print "text";
+{
+  var b = a;
+  print b;
+}
+if (a > 0) print "positive"; else print "not positive";
+if (a) print a;
+while (a < 10) a = a + 1;
+{
+  var i = 0;
+  for (; i < 3; i = i + 1) {
+    if (i == 1) continue;
+    if (i == 2) break;
+    print i;
+  }
+}
+fun add(x, y) {
+  return x + y;
+}
+fun nothing() {
+  return;
+}
+async fun later() {
+  return await add(1, 2);
+}
+class Base {
+  init(name) {
+    this.name = name;
+  }
+  greet() {
+    return "hi " + this.name;
+  }
+}
+class Derived < Base {
+  greet() {
+    return super.greet() + "!";
+  }
+}
+var d = Derived("lox");
+d.name = "rlox";
+print d.greet();
+"#;
+    assert_eq!(print_ast(SCRIPT, Form::Source).unwrap(), snapshot(expected));
+}
+
+// Printed source parses back into the tree it was printed from
+#[test]
+fn source_form_round_trips() {
+    let source = print_ast(SCRIPT, Form::Source).unwrap();
+    assert_eq!(
+        print_ast(&source, Form::Lisp).unwrap(),
+        print_ast(SCRIPT, Form::Lisp).unwrap()
+    );
+}
+
+#[test]
+fn syntax_errors_print_nothing() {
+    assert!(matches!(
+        print_ast("print (1;", Form::Lisp),
+        Err(LoxError::Parse(_))
+    ));
+}
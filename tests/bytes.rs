@@ -0,0 +1,29 @@
+// Byte buffers too large to allocate are runtime errors, not out-of-memory aborts
+use std::io;
+
+use rlox::interpreter::ErrorKind;
+use rlox::{Lox, LoxError};
+
+fn error_kind(source: &str) -> Option<ErrorKind> {
+    match Lox::with_output(Box::new(io::sink())).run_source(source) {
+        Err(LoxError::Runtime(error, _)) => Some(error.kind),
+        _ => None,
+    }
+}
+
+#[test]
+fn huge_lengths_are_runtime_errors() {
+    assert_eq!(
+        error_kind("bytes(1000000000000000000);"),
+        Some(ErrorKind::Native)
+    );
+    assert_eq!(error_kind("bytes(1073741825);"), Some(ErrorKind::Native));
+}
+
+#[test]
+fn lengths_within_the_limit_make_zeroed_buffers() {
+    assert_eq!(
+        error_kind("var b = bytes(16); if (b.length != 16 or b.get(15) != 0) undefined();"),
+        None
+    );
+}
@@ -0,0 +1,41 @@
+// Commands of the `rlox` binary that print what they make of a script
+use std::fs;
+use std::path::PathBuf;
+use std::process::{Command, Stdio};
+
+fn script(name: &str, source: &str) -> PathBuf {
+    let path = PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join(name);
+    fs::write(&path, source).unwrap();
+    path
+}
+
+#[test]
+fn tokens_are_printed_one_a_line() {
+    let path = script("tokens.lox", "print 1;");
+    let output = Command::new(env!("CARGO_BIN_EXE_rlox"))
+        .arg("--tokens")
+        .arg(&path)
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8(output.stdout).unwrap().lines().count(), 4);
+}
+
+#[test]
+fn a_reader_closing_early_ends_the_output() {
+    // Far more than a pipe holds, so the reader is gone before it's all written
+    let path = script("closed.lox", &"print 1;\n".repeat(50_000));
+    for flag in ["--tokens", "--ast"] {
+        let mut child = Command::new(env!("CARGO_BIN_EXE_rlox"))
+            .arg(flag)
+            .arg(&path)
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .unwrap();
+        drop(child.stdout.take());
+        let output = child.wait_with_output().unwrap();
+        assert!(output.status.success(), "{}: {:?}", flag, output);
+        assert!(output.stderr.is_empty(), "{}: {:?}", flag, output);
+    }
+}
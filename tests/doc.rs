@@ -0,0 +1,19 @@
+// Doc comments belong to the declaration they come right before
+use rlox::document;
+
+#[test]
+fn methods_on_the_class_line_have_no_doc_of_their_own() {
+    let script = document("/// A class.\nclass A { m() {} }", "a.lox").unwrap();
+    let class = &script.items[0];
+    assert_eq!(class.doc.as_deref(), Some("A class."));
+    assert_eq!(class.methods[0].doc, None);
+}
+
+#[test]
+fn doc_comments_run_over_several_lines() {
+    let source = "/// Adds.\n/// Twice.\nasync fun add() {}\nclass B {\n  /// Gets.\n  get() {}\n}";
+    let script = document(source, "b.lox").unwrap();
+    assert_eq!(script.items[0].doc.as_deref(), Some("Adds.\nTwice."));
+    assert_eq!(script.items[1].doc, None);
+    assert_eq!(script.items[1].methods[0].doc.as_deref(), Some("Gets."));
+}
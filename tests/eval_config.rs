@@ -0,0 +1,33 @@
+// Configuration scripts evaluated with `Lox::eval_config`
+use std::io;
+
+use rlox::token::LiteralTypes;
+use rlox::{Lox, LoxError};
+
+fn lox() -> Lox {
+    Lox::with_output(Box::new(io::sink()))
+}
+
+#[test]
+fn the_last_expression_is_the_value() {
+    let value = lox().eval_config("var port = 80; port + 1;").unwrap();
+    assert_eq!(value, LiteralTypes::Number(81.0));
+}
+
+#[test]
+fn the_config_global_is_the_value_otherwise() {
+    let value = lox().eval_config("var config = \"debug\";").unwrap();
+    assert_eq!(value, LiteralTypes::String("debug".to_string()));
+    assert_eq!(lox().eval_config("var a = 1;").unwrap(), LiteralTypes::Nil);
+}
+
+#[test]
+fn errors_carry_their_diagnostics() {
+    let error = lox().eval_config("var a = ;").unwrap_err();
+    assert!(matches!(error, LoxError::Parse(_)));
+    assert!(!error.diagnostics().is_empty());
+
+    let error = lox().eval_config("1 + nil;").unwrap_err();
+    assert!(matches!(error, LoxError::Runtime(..)));
+    assert!(error.to_string().contains("Operands"));
+}
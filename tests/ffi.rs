@@ -0,0 +1,58 @@
+// Natives registered through the C ABI, called from scripts
+use std::ffi::{c_void, CStr};
+use std::ptr;
+
+use rlox::ffi::{rlox_free, rlox_new, rlox_register_native, rlox_run, RloxValue, RloxValueType};
+
+fn value(kind: RloxValueType, string: &'static CStr) -> RloxValue {
+    RloxValue {
+        kind,
+        boolean: false,
+        number: 0.0,
+        string: string.as_ptr(),
+    }
+}
+
+extern "C" fn greet(_: *mut c_void, _: *const RloxValue, _: usize) -> RloxValue {
+    value(RloxValueType::String, c"hi")
+}
+
+extern "C" fn fail(_: *mut c_void, _: *const RloxValue, _: usize) -> RloxValue {
+    value(RloxValueType::Error, c"Native failed on purpose.")
+}
+
+extern "C" fn fail_silently(_: *mut c_void, _: *const RloxValue, _: usize) -> RloxValue {
+    RloxValue {
+        string: ptr::null(),
+        ..value(RloxValueType::Error, c"")
+    }
+}
+
+fn run(source: &CStr) -> i32 {
+    unsafe {
+        let vm = rlox_new();
+        rlox_register_native(vm, c"greet".as_ptr(), 0, greet, ptr::null_mut());
+        rlox_register_native(vm, c"fail".as_ptr(), 0, fail, ptr::null_mut());
+        rlox_register_native(
+            vm,
+            c"failSilently".as_ptr(),
+            0,
+            fail_silently,
+            ptr::null_mut(),
+        );
+        let code = rlox_run(vm, source.as_ptr());
+        rlox_free(vm);
+        code
+    }
+}
+
+#[test]
+fn natives_return_values() {
+    assert_eq!(run(c"if (greet() != \"hi\") fail();"), 0);
+}
+
+#[test]
+fn natives_raise_runtime_errors() {
+    assert_eq!(run(c"fail();"), 70);
+    assert_eq!(run(c"failSilently();"), 70);
+}
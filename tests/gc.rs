@@ -0,0 +1,92 @@
+// Values only reachable through reference cycles are freed by the cycle collector
+use std::io;
+
+use rlox::Lox;
+
+fn lox() -> Lox {
+    Lox::with_output(Box::new(io::sink()))
+}
+
+#[test]
+fn instance_cycles_are_freed() {
+    let mut lox = lox();
+    lox.run_source(
+        "class Node {}
+         fun pair() { var a = Node(); var b = Node(); a.other = b; b.other = a; }
+         for (var i = 0; i < 10; i = i + 1) pair();",
+    )
+    .unwrap();
+    assert_eq!(lox.interpreter().heap_report().instances, 20);
+
+    assert!(lox.interpreter().collect_garbage() >= 20);
+    assert_eq!(lox.interpreter().heap_report().instances, 0);
+}
+
+#[test]
+fn closure_cycles_are_freed() {
+    let mut lox = lox();
+    lox.run_source(
+        "fun cycle() { var f; fun g() { return f; } f = g; }
+         for (var i = 0; i < 10; i = i + 1) cycle();",
+    )
+    .unwrap();
+    let before = lox.interpreter().heap_report();
+    assert!(before.captured >= 10);
+
+    assert!(lox.interpreter().collect_garbage() >= 10);
+    let after = lox.interpreter().heap_report();
+    assert_eq!(after.captured, 0);
+    assert!(after.closures < before.closures);
+}
+
+#[test]
+fn reachable_values_are_kept() {
+    let mut lox = lox();
+    lox.run_source(
+        "class Node {}
+         var a = Node(); var b = Node(); a.other = b; b.other = a;
+         fun counter() { var n = 0; fun next() { n = n + 1; return n; } return next; }
+         var next = counter();",
+    )
+    .unwrap();
+    lox.interpreter().collect_garbage();
+    let report = lox.interpreter().heap_report();
+    assert_eq!(report.instances, 2);
+    assert_eq!(report.captured, 1);
+
+    // Still usable after a collection
+    lox.run_source("if (next() != 1 or a.other.other != a) undefined();")
+        .unwrap();
+}
+
+// Instances whose class has a `deinit` are freed with their last reference, like any other
+#[test]
+fn finalizable_instances_are_freed_when_dropped() {
+    let mut lox = lox();
+    lox.run_source(
+        "var finalized = 0;
+         class Resource { deinit() { finalized = finalized + 1; } }
+         var r = Resource(); var weak = weakRef(r); r = nil;",
+    )
+    .unwrap();
+    lox.run_source("if (weak.get() != nil or finalized != 1) undefined();")
+        .unwrap();
+}
+
+#[test]
+fn finalizable_cycles_are_finalized_then_freed() {
+    let mut lox = lox();
+    lox.run_source(
+        "var finalized = 0;
+         class Resource { deinit() { finalized = finalized + 1; } }
+         fun pair() { var a = Resource(); var b = Resource(); a.other = b; b.other = a; }
+         pair();",
+    )
+    .unwrap();
+    assert_eq!(lox.interpreter().heap_report().instances, 2);
+    lox.interpreter().collect_garbage();
+    lox.run_source("if (finalized != 2) undefined();").unwrap();
+    lox.interpreter().collect_garbage();
+    assert_eq!(lox.interpreter().heap_report().instances, 0);
+    lox.run_source("if (finalized != 2) undefined();").unwrap();
+}
@@ -0,0 +1,106 @@
+// A document edited piece by piece parses to the same tree as its final source parsed afresh
+use rlox::incremental::{Document, TextEdit};
+use rlox::query::{Ast, NodeKind, Span};
+use rlox::{parse_ast, LoxError};
+
+const SOURCE: &str = "var a = 1;
+fun add(x, y) {
+  return x + y;
+}
+
+/// Greets
+class Greeter {
+  greet() { print \"hi\"; }
+}
+print add(a, 2);
+";
+
+// Everything about a node but the id the parser gave it, which edits keep
+type Shape = (NodeKind, Span, Option<String>, Option<usize>, Vec<usize>);
+
+fn shape(ast: &Ast) -> Vec<Shape> {
+    ast.nodes()
+        .iter()
+        .map(|node| {
+            (
+                node.kind,
+                node.span,
+                node.text.clone(),
+                node.parent,
+                node.children.clone(),
+            )
+        })
+        .collect()
+}
+
+fn replace(document: &Document, old: &str, new: &str) -> TextEdit {
+    let start = document.source().find(old).unwrap();
+    TextEdit {
+        start,
+        end: start + old.len(),
+        text: new.to_string(),
+    }
+}
+
+fn assert_matches_fresh_parse(document: &Document) {
+    let fresh = parse_ast(document.source()).unwrap();
+    assert_eq!(shape(&document.ast()), shape(&fresh));
+}
+
+#[test]
+fn edits_match_a_fresh_parse() {
+    let mut document = Document::new(SOURCE);
+    assert_matches_fresh_parse(&document);
+
+    let edits = [
+        // Within a statement
+        ("x + y", "x * y + 1"),
+        // Adding lines, which moves every statement after it
+        ("var a = 1;\n", "var a = 1;\nvar b = a;\nvar c = b;\n"),
+        // Joining two statements onto one line
+        ("var b = a;\nvar c", "var b = a; var c"),
+        // Into a doc comment
+        ("Greets", "Greets people"),
+        // Removing a whole declaration
+        ("fun add(x, y) {\n  return x * y + 1;\n}\n", ""),
+        ("print add(a, 2);", "print a;"),
+    ];
+    for (old, new) in edits {
+        let edit = replace(&document, old, new);
+        document.edit(&edit).unwrap();
+        assert_matches_fresh_parse(&document);
+    }
+}
+
+#[test]
+fn broken_edits_recover_once_fixed() {
+    let mut document = Document::new(SOURCE);
+
+    let edit = replace(&document, "return x + y;", "return x +;");
+    assert!(matches!(document.edit(&edit), Err(LoxError::Parse(_))));
+    assert!(document.has_errors());
+
+    let edit = replace(&document, "return x +;", "return x + y;");
+    document.edit(&edit).unwrap();
+    assert!(!document.has_errors());
+    assert_eq!(document.source(), SOURCE);
+    assert_matches_fresh_parse(&document);
+}
+
+#[test]
+fn untouched_statements_keep_their_ids() {
+    let mut document = Document::new(SOURCE);
+    let ids = |document: &Document| -> Vec<Option<usize>> {
+        let ast = document.ast();
+        let last = *ast.roots().last().unwrap();
+        let mut ids = vec![ast.node(last).uuid];
+        ids.extend(ast.node(last).children.iter().map(|&c| ast.node(c).uuid));
+        ids
+    };
+    let before = ids(&document);
+
+    let edit = replace(&document, "var a = 1;", "var a = 10;\n");
+    document.edit(&edit).unwrap();
+    assert_eq!(ids(&document), before);
+    assert_matches_fresh_parse(&document);
+}
@@ -0,0 +1,110 @@
+// Compiled scripts run as their source does, and damaged files are refused
+use std::cell::RefCell;
+use std::io::{self, Write};
+use std::rc::Rc;
+
+use rlox::interpreter::Interpreter;
+use rlox::{compile, run, run_compiled, LoxError};
+
+const SCRIPT: &str = "
+class Greeter {
+  init(name) { this.name = name; }
+  greet() { return \"hi \" + this.name; }
+}
+fun count(n) {
+  var total = 0;
+  for (var i = 1; i <= n; i = i + 1) {
+    if (i == 3) continue;
+    total = total + i;
+  }
+  return total;
+}
+var counter = 0;
+fun increment() { counter = counter + 1; return counter; }
+increment();
+print Greeter(\"lox\").greet();
+print count(5);
+print increment();
+";
+
+#[derive(Clone, Default)]
+struct Output(Rc<RefCell<Vec<u8>>>);
+
+impl Write for Output {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.borrow_mut().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Output {
+    fn text(&self) -> String {
+        String::from_utf8(self.0.borrow().clone()).unwrap()
+    }
+}
+
+fn run_source(source: &str) -> String {
+    let output = Output::default();
+    run(source, Box::new(output.clone())).unwrap();
+    output.text()
+}
+
+fn run_bytes(compiled: &[u8]) -> Result<String, LoxError> {
+    let output = Output::default();
+    let mut interpreter = Interpreter::with_output(Box::new(output.clone()));
+    run_compiled(&mut interpreter, compiled)?;
+    Ok(output.text())
+}
+
+#[test]
+fn compiled_scripts_run_like_their_source() {
+    let compiled = compile(SCRIPT).unwrap();
+    assert_eq!(run_bytes(&compiled).unwrap(), run_source(SCRIPT));
+    assert_eq!(run_source(SCRIPT), "hi lox\n12\n2\n");
+}
+
+#[test]
+fn compiling_is_deterministic() {
+    assert_eq!(compile(SCRIPT).unwrap(), compile(SCRIPT).unwrap());
+}
+
+#[test]
+fn scripts_with_errors_are_not_compiled() {
+    assert!(matches!(compile("print (1;"), Err(LoxError::Parse(_))));
+    assert!(matches!(compile("return 1;"), Err(LoxError::Resolve(_))));
+}
+
+#[test]
+fn damaged_files_are_refused() {
+    let compiled = compile(SCRIPT).unwrap();
+
+    // Not compiled at all
+    assert!(matches!(
+        run_bytes(SCRIPT.as_bytes()),
+        Err(LoxError::Load(_))
+    ));
+    // Cut short
+    assert!(matches!(
+        run_bytes(&compiled[..compiled.len() / 2]),
+        Err(LoxError::Load(_))
+    ));
+    assert!(matches!(run_bytes(&compiled[..3]), Err(LoxError::Load(_))));
+    // Another format version
+    let mut version = compiled.clone();
+    version[4] = version[4].wrapping_add(1);
+    assert!(matches!(run_bytes(&version), Err(LoxError::Load(_))));
+    // Any byte of the payload changed
+    for i in (22..compiled.len()).step_by(7) {
+        let mut corrupt = compiled.clone();
+        corrupt[i] ^= 0x55;
+        assert!(
+            matches!(run_bytes(&corrupt), Err(LoxError::Load(_))),
+            "byte {} was changed",
+            i
+        );
+    }
+}
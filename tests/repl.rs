@@ -0,0 +1,43 @@
+// Lines typed at the prompt of the `rlox` binary
+use std::io::Write;
+use std::process::{Command, Stdio};
+
+// What the prompt printed for the lines, without its `>> ` and `.. ` prompts
+fn repl(input: &str) -> String {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_rlox"))
+        // Away from any `.loxrc` of whoever runs the tests
+        .env_remove("RLOX_INIT")
+        .env("HOME", env!("CARGO_TARGET_TMPDIR"))
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(input.as_bytes())
+        .unwrap();
+    let output = child.wait_with_output().unwrap();
+    String::from_utf8(output.stdout)
+        .unwrap()
+        .replace(">> ", "")
+        .replace(".. ", "")
+}
+
+#[test]
+fn bare_expressions_print_their_value() {
+    assert_eq!(repl("1 + 2\n"), "3\n");
+}
+
+#[test]
+fn a_comment_ending_the_line_keeps_the_semicolon_out() {
+    assert_eq!(repl("1 + 2 // note\n"), "3\n");
+    assert_eq!(repl("var a = 4 // four\na\n"), "4\n");
+}
+
+#[test]
+fn statements_continue_over_open_braces() {
+    assert_eq!(repl("if (true) {\nprint 5;\n}\n"), "5\n");
+}
@@ -0,0 +1,108 @@
+use std::collections::BTreeMap;
+use std::fmt::Debug;
+
+use rlox::token::LiteralTypes;
+use rlox::{lox_value_to, to_lox_value};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+struct Player {
+    name: String,
+    level: u8,
+    score: i64,
+    guild: Option<String>,
+    position: (f64, f64),
+    items: Vec<Item>,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+enum Item {
+    Potion,
+    Gold(u32),
+    Weapon { name: String, damage: u16 },
+    Pair(i8, i8),
+}
+
+fn round_trip<T: Serialize + DeserializeOwned + Debug + PartialEq>(value: T) {
+    let lox = to_lox_value(&value).unwrap();
+    assert_eq!(lox_value_to::<T>(lox).unwrap(), value);
+}
+
+#[test]
+fn structs_round_trip() {
+    round_trip(Player {
+        name: "ada".to_string(),
+        level: 7,
+        score: -1200,
+        guild: None,
+        position: (1.5, -2.0),
+        items: vec![Item::Potion, Item::Gold(30)],
+    });
+}
+
+#[test]
+fn options_round_trip() {
+    round_trip(Some("guild".to_string()));
+    round_trip(None::<String>);
+    round_trip(vec![Some(1u16), None, Some(3)]);
+}
+
+#[test]
+fn enums_round_trip() {
+    round_trip(Item::Potion);
+    round_trip(Item::Gold(u32::MAX));
+    round_trip(Item::Weapon {
+        name: "sword".to_string(),
+        damage: 12,
+    });
+    round_trip(Item::Pair(-128, 127));
+}
+
+#[test]
+fn maps_round_trip() {
+    let scores = BTreeMap::from([("ada".to_string(), 3i32), ("bob".to_string(), -4)]);
+    round_trip(scores);
+}
+
+#[test]
+fn structs_become_maps() {
+    let lox = to_lox_value(&Item::Weapon {
+        name: "axe".to_string(),
+        damage: 3,
+    })
+    .unwrap();
+    let LiteralTypes::Map(map) = lox else {
+        panic!("expected a map, got {:?}", lox);
+    };
+    assert!(map.borrow().contains_key("Weapon"));
+}
+
+#[test]
+fn integers_at_the_limits() {
+    assert_eq!(lox_value_to::<u8>(255.0.into()).unwrap(), 255);
+    assert_eq!(lox_value_to::<i8>((-128.0).into()).unwrap(), -128);
+    assert_eq!(lox_value_to::<u32>(0.0.into()).unwrap(), 0);
+    assert_eq!(
+        lox_value_to::<i64>(9_007_199_254_740_992.0.into()).unwrap(),
+        9_007_199_254_740_992
+    );
+}
+
+#[test]
+fn out_of_range_integers_are_rejected() {
+    assert!(lox_value_to::<u32>((-5.0).into()).is_err());
+    assert!(lox_value_to::<u8>(256.0.into()).is_err());
+    assert!(lox_value_to::<i8>((-129.0).into()).is_err());
+    assert!(lox_value_to::<i32>(2_147_483_648.0.into()).is_err());
+    assert!(lox_value_to::<i64>(1e30.into()).is_err());
+    assert!(lox_value_to::<i64>(9_223_372_036_854_775_808.0.into()).is_err());
+    assert!(lox_value_to::<u64>(18_446_744_073_709_551_616.0.into()).is_err());
+    assert!(lox_value_to::<u64>(f64::NAN.into()).is_err());
+}
+
+#[test]
+fn fractions_are_not_integers() {
+    assert!(lox_value_to::<i32>(1.5.into()).is_err());
+    assert_eq!(lox_value_to::<f64>(1.5.into()).unwrap(), 1.5);
+}
@@ -0,0 +1,100 @@
+// Scripts hitting the call depth or nesting limits on a thread with the default stack must
+// fail with an error instead of overflowing the thread's stack
+use std::io;
+use std::thread;
+
+use rlox::interpreter::ErrorKind;
+use rlox::{Lox, LoxError};
+
+#[derive(Debug, PartialEq)]
+enum Outcome {
+    Ran,
+    SyntaxError,
+    RuntimeError(ErrorKind),
+    OtherError,
+}
+
+// Runs the source on a new thread with the default stack size
+fn run_on_thread(source: String) -> Outcome {
+    thread::spawn(
+        move || match Lox::with_output(Box::new(io::sink())).run_source(&source) {
+            Ok(()) => Outcome::Ran,
+            Err(LoxError::Parse(_)) => Outcome::SyntaxError,
+            Err(LoxError::Runtime(error, _)) => Outcome::RuntimeError(error.kind),
+            Err(_) => Outcome::OtherError,
+        },
+    )
+    .join()
+    .expect("the script overflowed the thread's stack")
+}
+
+const STACK_OVERFLOW: Outcome = Outcome::RuntimeError(ErrorKind::StackOverflow);
+
+fn recursion(depth: usize) -> String {
+    format!(
+        "fun f(n) {{ if (n == 0) return 0; return 1 + f(n - 1); }} print f({});",
+        depth
+    )
+}
+
+#[test]
+fn recursion_within_the_limit_runs() {
+    assert_eq!(run_on_thread(recursion(100)), Outcome::Ran);
+}
+
+#[test]
+fn deep_recursion_is_a_stack_overflow() {
+    assert_eq!(run_on_thread(recursion(200)), STACK_OVERFLOW);
+    assert_eq!(run_on_thread(recursion(100_000)), STACK_OVERFLOW);
+}
+
+// Calls in tail position replace the caller's frame instead of adding one
+#[test]
+fn deep_tail_recursion_runs() {
+    let source = "fun count(n) { if (n == 0) return 0; return count(n - 1); } count(100000);";
+    assert_eq!(run_on_thread(source.to_string()), Outcome::Ran);
+
+    let source = "fun even(n) { if (n == 0) return true; return odd(n - 1); }
+                  fun odd(n) { if (n == 0) return false; return even(n - 1); }
+                  if (!even(100000)) undefined();";
+    assert_eq!(run_on_thread(source.to_string()), Outcome::Ran);
+}
+
+#[test]
+fn deep_method_recursion_is_a_stack_overflow() {
+    let source = "class A { f(n) { return 1 + this.f(n - 1); } } A().f(0);".to_string();
+    assert_eq!(run_on_thread(source), STACK_OVERFLOW);
+}
+
+#[test]
+fn deep_initializer_recursion_is_a_stack_overflow() {
+    let source = "class A { init(n) { A(n + 1); } } A(0);".to_string();
+    assert_eq!(run_on_thread(source), STACK_OVERFLOW);
+}
+
+#[test]
+fn nested_parentheses_within_the_limit_run() {
+    let source = format!("print {}1{};", "(".repeat(100), ")".repeat(100));
+    assert_eq!(run_on_thread(source), Outcome::Ran);
+}
+
+#[test]
+fn deeply_nested_source_is_a_syntax_error() {
+    let sources = [
+        format!("print {}1{};", "(".repeat(10_000), ")".repeat(10_000)),
+        format!("print {}1;", "-".repeat(10_000)),
+        format!("var a; {}1;", "a = ".repeat(10_000)),
+        format!("{}{}", "{".repeat(10_000), "}".repeat(10_000)),
+        format!("{}print 1;", "if (true) ".repeat(10_000)),
+        format!("{}{}", "fun f() {".repeat(10_000), "}".repeat(10_000)),
+        format!(
+            "fun f(x) {{ return x; }} {}1{};",
+            "f(".repeat(10_000),
+            ")".repeat(10_000)
+        ),
+        format!("print 1{};", " + 1".repeat(10_000)),
+    ];
+    for source in sources {
+        assert_eq!(run_on_thread(source), Outcome::SyntaxError);
+    }
+}
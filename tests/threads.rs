@@ -0,0 +1,71 @@
+// Threads write where the interpreter that spawned them does, and run with its settings
+use std::cell::RefCell;
+use std::io::{self, Write};
+use std::rc::Rc;
+
+use rlox::interpreter::Interpreter;
+use rlox::{run_with, threads};
+
+#[derive(Clone, Default)]
+struct Output(Rc<RefCell<Vec<u8>>>);
+
+impl Write for Output {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.borrow_mut().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Output {
+    fn text(&self) -> String {
+        String::from_utf8(self.0.borrow().clone()).unwrap()
+    }
+}
+
+// Runs the script, returning what it printed and what went to its error output
+fn run_captured(source: &str, seed: Option<u64>) -> (String, String) {
+    let (output, errors) = (Output::default(), Output::default());
+    let mut interpreter = Interpreter::with_output(Box::new(output.clone()));
+    interpreter.set_error_output(Box::new(errors.clone()));
+    interpreter.set_deterministic(seed);
+    threads::define_natives(&mut interpreter);
+    let _ = run_with(&mut interpreter, source);
+    (output.text(), errors.text())
+}
+
+#[test]
+fn threads_write_to_the_spawners_output() {
+    let (output, errors) = run_captured(
+        "fun work() { print \"from thread\"; eprint(\"warning\"); }
+         spawn(work).join();
+         print \"done\";",
+        None,
+    );
+    assert_eq!(output, "from thread\ndone\n");
+    assert!(errors.contains("warning"), "{}", errors);
+}
+
+#[test]
+fn thread_errors_go_to_the_spawners_error_output() {
+    let (_, errors) = run_captured("fun work() { nil(); } spawn(work).join();", None);
+    assert!(
+        errors.contains("Can only call functions and classes."),
+        "{}",
+        errors
+    );
+    assert!(errors.contains("Thread failed."), "{}", errors);
+}
+
+#[test]
+fn threads_follow_the_spawners_seed() {
+    let script = "fun roll() { return random(); } print spawn(roll).join();";
+    let (first, _) = run_captured(script, Some(7));
+    let (second, _) = run_captured(script, Some(7));
+    let (other, _) = run_captured(script, Some(8));
+    assert_eq!(first, second);
+    assert_ne!(first, other);
+}
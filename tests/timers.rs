@@ -0,0 +1,43 @@
+// Timers with delays that could panic or starve the other timers
+use std::io;
+use std::sync::mpsc;
+use std::thread;
+use std::time::Duration;
+
+use rlox::{Lox, LoxError};
+
+// Whether the script ran without errors, `None` if it's still running after a few seconds
+fn run_with_deadline(source: &'static str) -> Option<Result<(), String>> {
+    let (sender, receiver) = mpsc::channel();
+    thread::spawn(move || {
+        let result = Lox::with_output(Box::new(io::sink()))
+            .run_source(source)
+            .map_err(|error| match error {
+                LoxError::Runtime(error, _) => error.message,
+                _ => "not a runtime error".to_string(),
+            });
+        let _ = sender.send(result);
+    });
+    receiver.recv_timeout(Duration::from_secs(5)).ok()
+}
+
+#[test]
+fn infinite_delays_are_runtime_errors() {
+    let result = run_with_deadline("fun f() {} setTimeout(f, 1/0);");
+    assert_eq!(
+        result,
+        Some(Err("Expected a delay in milliseconds.".to_string()))
+    );
+    let result = run_with_deadline("fun f() {} setInterval(f, 0/0);");
+    assert!(matches!(result, Some(Err(_))));
+}
+
+#[test]
+fn zero_intervals_let_other_timers_run() {
+    let source = "var count = 0;
+                  fun tick() { count = count + 1; }
+                  var id = setInterval(tick, 0);
+                  fun stop() { clearInterval(id); }
+                  setTimeout(stop, 5);";
+    assert_eq!(run_with_deadline(source), Some(Ok(())));
+}